@@ -809,6 +809,14 @@ impl BuiltinFuncArgs {
             }
         })
     }
+    fn get_as_tcp(&self, i: usize) -> BuiltInResult<socket::TcpClient> {
+        self.get_arg(i, |obj| {
+            match obj {
+                Object::Socket(socket::USocket::Tcp(client)) => Ok(client),
+                o => Err(BuiltinFuncError::new(UErrorMessage::BuiltinArgInvalid(o))),
+            }
+        })
+    }
     fn get_as_websocket(&self, i: usize) -> BuiltInResult<socket::WebSocket> {
         self.get_arg(i, |obj| {
             match obj {
@@ -817,6 +825,14 @@ impl BuiltinFuncArgs {
             }
         })
     }
+    fn get_as_mqtt(&self, i: usize) -> BuiltInResult<socket::MqttClient> {
+        self.get_arg(i, |obj| {
+            match obj {
+                Object::Socket(socket::USocket::Mqtt(client)) => Ok(client),
+                o => Err(BuiltinFuncError::new(UErrorMessage::BuiltinArgInvalid(o))),
+            }
+        })
+    }
     fn get_as_ascii(&self, i: usize) -> BuiltInResult<Option<u8>> {
         self.get_arg_with_required_flag(i, false, |obj| {
             match obj {
@@ -1071,6 +1087,7 @@ fn init_builtin_consts() -> BuiltinConsts {
     sets.append(BuiltinConsts::new::<system_controls::SetHotKey>());
 
     // math
+    sets.append(BuiltinConsts::new::<math::RoundConst>());
 
     // key codes
     sets.append(BuiltinConsts::new::<key_codes::VirtualKeyCode>());
@@ -1080,6 +1097,7 @@ fn init_builtin_consts() -> BuiltinConsts {
     sets.append(BuiltinConsts::new::<com_object::ExcelConst>());
     // browser_control
     sets.append(BuiltinConsts::new::<browser_control::BcEnum>());
+    sets.append(BuiltinConsts::new::<browser_control::WebMatchConst>());
     // array_control
     sets.append(BuiltinConsts::new::<array_control::QsrtConst>());
     sets.append(BuiltinConsts::new::<array_control::CalcConst>());
@@ -1092,6 +1110,10 @@ fn init_builtin_consts() -> BuiltinConsts {
 
     // socket
     sets.append(BuiltinConsts::new::<socket::WebSocketConst>());
+    sets.append(BuiltinConsts::new::<socket::TlsConst>());
+    sets.append(BuiltinConsts::new::<socket::MailProtocolConst>());
+    sets.append(BuiltinConsts::new::<socket::MailSecurityConst>());
+    sets.append(BuiltinConsts::new::<socket::MqttConst>());
 
     // SLCT_* 定数
     let slcts = BuiltinConsts {
@@ -1113,6 +1135,7 @@ fn init_builtin_consts() -> BuiltinConsts {
     // file_control
     sets.append(BuiltinConsts::new::<file_control::FileConst>());
     sets.append(BuiltinConsts::new::<file_control::FileOrderConst>());
+    sets.append(BuiltinConsts::new::<file_control::CsvTypeConst>());
 
     // 特殊変数
     let special = special_variables();
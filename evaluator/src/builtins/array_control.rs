@@ -6,6 +6,7 @@ use crate::Evaluator;
 
 use strum_macros::{EnumString, VariantNames};
 use num_derive::{ToPrimitive, FromPrimitive};
+use std::sync::{Arc, Mutex};
 
 pub fn builtin_func_sets() -> BuiltinFunctionSets {
     let mut sets = BuiltinFunctionSets::new();
@@ -18,6 +19,19 @@ pub fn builtin_func_sets() -> BuiltinFunctionSets {
     sets.add("calcarray", calcarray, get_desc!(calcarray));
     sets.add("setclear", setclear, get_desc!(setclear));
     sets.add("shiftarray", shiftarray, get_desc!(shiftarray));
+    sets.add("map", map, get_desc!(map));
+    sets.add("filter", filter, get_desc!(filter));
+    sets.add("reduce", reduce, get_desc!(reduce));
+    sets.add("find", find, get_desc!(find));
+    sets.add("findindex", findindex, get_desc!(findindex));
+    sets.add("any", any, get_desc!(any));
+    sets.add("all", all, get_desc!(all));
+    sets.add("groupby", groupby, get_desc!(groupby));
+    sets.add("uniq", uniq, get_desc!(uniq));
+    sets.add("flatten", flatten, get_desc!(flatten));
+    sets.add("ziparray", ziparray, get_desc!(ziparray));
+    sets.add("sort", sort, get_desc!(sort));
+    sets.add("chunk", chunk, get_desc!(chunk));
     sets
 }
 
@@ -218,6 +232,7 @@ pub fn resize(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncRe
         } else {
             n + 1
         } as usize;
+        evaluator.check_array_len(new_len)?;
         arr.resize(new_len, default);
         let i = arr.len() as isize - 1;
 
@@ -462,4 +477,335 @@ pub fn shiftarray(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFu
         .map_err(BuiltinFuncError::UError)?;
 
     Ok(Object::Empty)
-}
\ No newline at end of file
+}
+/// 各要素に関数を適用する、deepなら多次元配列の末端要素に適用する
+fn map_elements(evaluator: &mut Evaluator, func: &Function, arr: Vec<Object>, deep: bool) -> BuiltInResult<Vec<Object>> {
+    arr.into_iter().enumerate()
+        .map(|(i, o)| match o {
+            Object::Array(inner) if deep => Ok(Object::Array(map_elements(evaluator, func, inner, deep)?)),
+            o => Ok(func.invoke_with_values(evaluator, vec![o, Object::Num(i as f64)])?),
+        })
+        .collect()
+}
+
+#[builtin_func_desc(
+    desc="配列の各要素に関数を適用した新しい配列を返す"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="関数",t="関数",d="関数(要素, インデックス)、戻り値が新しい要素になる"},
+        {n="多次元",t="真偽値",d="TRUEなら多次元配列の末端の要素に関数を適用し形を保つ、FALSEなら1次元目の要素に適用",o},
+    ],
+    rtype={desc="変換後の配列",types="配列"}
+)]
+pub fn map(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let func = args.get_as_user_function(1)?;
+    let deep = args.get_as_bool(2, Some(false))?;
+    let mapped = map_elements(evaluator, &func, arr, deep)?;
+    Ok(Object::Array(mapped))
+}
+
+#[builtin_func_desc(
+    desc="関数がTRUEを返した要素のみの配列を返す"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="関数",t="関数",d="関数(要素, インデックス)"},
+    ],
+    rtype={desc="条件を満たす要素の配列",types="配列"}
+)]
+pub fn filter(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let func = args.get_as_user_function(1)?;
+    let mut filtered = vec![];
+    for (i, o) in arr.into_iter().enumerate() {
+        if func.invoke_with_values(evaluator, vec![o.clone(), Object::Num(i as f64)])?.is_truthy() {
+            filtered.push(o);
+        }
+    }
+    Ok(Object::Array(filtered))
+}
+
+#[builtin_func_desc(
+    desc="関数で配列を畳み込む"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="関数",t="関数",d="関数(累積値, 要素, インデックス)、戻り値が次の累積値になる"},
+        {n="初期値",t="値",d="累積値の初期値、省略時は先頭要素を初期値とし2番目の要素から処理する",o},
+    ],
+    rtype={desc="最終的な累積値、空配列かつ初期値省略時はEMPTY",types="値"}
+)]
+pub fn reduce(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let func = args.get_as_user_function(1)?;
+    let initial = args.get_as_object_or_empty(2)?;
+    let mut iter = arr.into_iter().enumerate();
+    let mut acc = match initial {
+        Some(o) => o,
+        None => match iter.next() {
+            Some((_, o)) => o,
+            None => return Ok(Object::Empty),
+        },
+    };
+    for (i, o) in iter {
+        acc = func.invoke_with_values(evaluator, vec![acc, o, Object::Num(i as f64)])?;
+    }
+    Ok(acc)
+}
+
+/// 関数がTRUEを返す最初の要素のインデックスと値
+fn find_first(evaluator: &mut Evaluator, func: &Function, arr: Vec<Object>) -> BuiltInResult<Option<(usize, Object)>> {
+    for (i, o) in arr.into_iter().enumerate() {
+        if func.invoke_with_values(evaluator, vec![o.clone(), Object::Num(i as f64)])?.is_truthy() {
+            return Ok(Some((i, o)));
+        }
+    }
+    Ok(None)
+}
+
+#[builtin_func_desc(
+    desc="関数がTRUEを返す最初の要素を返す"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="関数",t="関数",d="関数(要素, インデックス)"},
+    ],
+    rtype={desc="見つかった要素、なければEMPTY",types="値"}
+)]
+pub fn find(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let func = args.get_as_user_function(1)?;
+    let found = find_first(evaluator, &func, arr)?;
+    Ok(found.map(|(_, o)| o).unwrap_or_default())
+}
+
+#[builtin_func_desc(
+    desc="関数がTRUEを返す最初の要素のインデックスを返す"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="関数",t="関数",d="関数(要素, インデックス)"},
+    ],
+    rtype={desc="見つかった要素のインデックス、なければ-1",types="数値"}
+)]
+pub fn findindex(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let func = args.get_as_user_function(1)?;
+    let found = find_first(evaluator, &func, arr)?;
+    Ok(found.map_or(Object::Num(-1.0), |(i, _)| Object::Num(i as f64)))
+}
+
+#[builtin_func_desc(
+    desc="関数がTRUEを返す要素がひとつでもあるかどうか"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="関数",t="関数",d="関数(要素, インデックス)"},
+    ],
+    rtype={desc="ひとつでもTRUEならTRUE、空配列はFALSE",types="真偽値"}
+)]
+pub fn any(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let func = args.get_as_user_function(1)?;
+    let found = find_first(evaluator, &func, arr)?;
+    Ok(Object::Bool(found.is_some()))
+}
+
+#[builtin_func_desc(
+    desc="すべての要素で関数がTRUEを返すかどうか"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="関数",t="関数",d="関数(要素, インデックス)"},
+    ],
+    rtype={desc="すべてTRUEならTRUE、空配列はTRUE",types="真偽値"}
+)]
+pub fn all(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let func = args.get_as_user_function(1)?;
+    for (i, o) in arr.into_iter().enumerate() {
+        if ! func.invoke_with_values(evaluator, vec![o, Object::Num(i as f64)])?.is_truthy() {
+            return Ok(Object::Bool(false));
+        }
+    }
+    Ok(Object::Bool(true))
+}
+
+#[builtin_func_desc(
+    desc="関数の戻り値ごとに要素をまとめた連想配列を返す"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="関数",t="関数",d="関数(要素, インデックス)、戻り値がキーになる(文字列、数値、真偽値)"},
+    ],
+    rtype={desc="キーと要素の配列の連想配列 (大文字小文字を区別、最初に現れた順)",types="連想配列"}
+)]
+pub fn groupby(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let func = args.get_as_user_function(1)?;
+    let mut groups = HashTbl::new(false, true);
+    for (i, o) in arr.into_iter().enumerate() {
+        let key = func.invoke_with_values(evaluator, vec![o.clone(), Object::Num(i as f64)])?;
+        let Some(name) = HashTbl::key_to_string(&key) else {
+            return Err(builtin_func_error(UErrorMessage::InvalidIndex(key)));
+        };
+        let group = match groups.get(&name) {
+            Object::Array(mut group) => {
+                group.push(o);
+                group
+            },
+            _ => vec![o],
+        };
+        groups.insert_key(key, Object::Array(group));
+    }
+    Ok(Object::HashTbl(Arc::new(Mutex::new(groups))))
+}
+
+#[builtin_func_desc(
+    desc="重複する要素を除いた配列を返す"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="関数",t="関数",d="関数(要素, インデックス)、戻り値が同じ要素を重複とみなす、省略時は要素そのものを比較",o},
+    ],
+    rtype={desc="最初に現れた要素のみを残した配列",types="配列"}
+)]
+pub fn uniq(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let func = match args.get_as_object_or_empty(1)? {
+        Some(_) => Some(args.get_as_user_function(1)?),
+        None => None,
+    };
+    let mut seen = vec![];
+    let mut unique = vec![];
+    for (i, o) in arr.into_iter().enumerate() {
+        let key = match &func {
+            Some(func) => func.invoke_with_values(evaluator, vec![o.clone(), Object::Num(i as f64)])?,
+            None => o.clone(),
+        };
+        if ! seen.contains(&key) {
+            seen.push(key);
+            unique.push(o);
+        }
+    }
+    Ok(Object::Array(unique))
+}
+
+fn flatten_array(arr: Vec<Object>, depth: Option<usize>) -> Vec<Object> {
+    let mut flat = vec![];
+    for o in arr {
+        match o {
+            Object::Array(inner) if depth != Some(0) => {
+                flat.extend(flatten_array(inner, depth.map(|d| d - 1)));
+            },
+            o => flat.push(o),
+        }
+    }
+    flat
+}
+
+#[builtin_func_desc(
+    desc="多次元配列を平坦化する"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="深さ",t="数値",d="平坦化する階層数、省略時はすべて平坦化する",o},
+    ],
+    rtype={desc="平坦化した配列",types="配列"}
+)]
+pub fn flatten(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let depth = args.get_as_int_or_empty::<usize>(1)?;
+    Ok(Object::Array(flatten_array(arr, depth)))
+}
+
+#[builtin_func_desc(
+    desc="複数の配列の同じ位置の要素をまとめる"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="配列2-10",t="配列",d="対象の配列",v=9},
+    ],
+    rtype={desc="各配列の同じ位置の要素を持つ配列の配列、長さは最も短い配列に合わせる",types="配列"}
+)]
+pub fn ziparray(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let mut arrays = vec![args.get_as_array(0, None)?];
+    for i in 1..10 {
+        if let Some(arr) = args.get_as_array_or_empty(i)? {
+            arrays.push(arr);
+        }
+    }
+    let len = arrays.iter().map(|a| a.len()).min().unwrap_or_default();
+    let zipped = (0..len)
+        .map(|i| Object::Array(arrays.iter().map(|a| a[i].clone()).collect()))
+        .collect();
+    Ok(Object::Array(zipped))
+}
+
+/// 比較関数の戻り値を順序にする
+fn compare_by(evaluator: &mut Evaluator, func: &Option<Function>, a: &Object, b: &Object) -> BuiltInResult<std::cmp::Ordering> {
+    let Some(func) = func else {
+        return Ok(a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    };
+    let result = func.invoke_with_values(evaluator, vec![a.clone(), b.clone()])?;
+    match result.as_f64(true) {
+        Some(n) => Ok(n.partial_cmp(&0.0).unwrap_or(std::cmp::Ordering::Equal)),
+        None => Err(builtin_func_error(UErrorMessage::NotANumber(result))),
+    }
+}
+/// 比較関数がエラーを返す場合があるため安定なマージソートを行う
+fn merge_sort(evaluator: &mut Evaluator, func: &Option<Function>, arr: Vec<Object>, desc: bool) -> BuiltInResult<Vec<Object>> {
+    if arr.len() <= 1 {
+        return Ok(arr);
+    }
+    let mut left = arr;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(evaluator, func, left, desc)?;
+    let right = merge_sort(evaluator, func, right, desc)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        let ord = compare_by(evaluator, func, l, r)?;
+        let take_right = if desc { ord.is_lt() } else { ord.is_gt() };
+        if take_right {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+#[builtin_func_desc(
+    desc="比較関数で並び替えた新しい配列を返す"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="比較関数",t="関数",d="関数(a, b)、aを前にするなら負数、bを前にするなら正数、同順なら0を返す、省略時は値の大小で比較",o},
+        {n="降順",t="真偽値",d="TRUEなら比較結果を逆順にする",o},
+    ],
+    rtype={desc="並び替えた配列 (安定ソート)",types="配列"}
+)]
+pub fn sort(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let func = match args.get_as_object_or_empty(1)? {
+        Some(_) => Some(args.get_as_user_function(1)?),
+        None => None,
+    };
+    let desc = args.get_as_bool(2, Some(false))?;
+    let sorted = merge_sort(evaluator, &func, arr, desc)?;
+    Ok(Object::Array(sorted))
+}
+
+#[builtin_func_desc(
+    desc="配列を指定サイズごとに分割する"
+    args=[
+        {n="配列",t="配列",d="対象の配列"},
+        {n="サイズ",t="数値",d="分割後の各配列の要素数 (1以上)、最後の配列は要素数が少ない場合がある"},
+    ],
+    rtype={desc="分割された配列の配列",types="配列"}
+)]
+pub fn chunk(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let arr = args.get_as_array(0, None)?;
+    let size = args.get_as_int::<usize>(1, None)?;
+    if size == 0 {
+        return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(Object::Num(0.0))));
+    }
+    let chunks = arr.chunks(size)
+        .map(|c| Object::Array(c.to_vec()))
+        .collect();
+    Ok(Object::Array(chunks))
+}
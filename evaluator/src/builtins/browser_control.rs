@@ -43,6 +43,17 @@ pub enum BcEnum {
     BC_VIVALDI = 11,
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Debug, EnumString, EnumProperty, VariantNames, ToPrimitive, FromPrimitive)]
+pub enum WebMatchConst {
+    #[strum[props(desc="記録の再生時にメソッドを照合する")]]
+    REPLAY_MATCH_METHOD = 1,
+    #[strum[props(desc="記録の再生時にURLを照合する")]]
+    REPLAY_MATCH_URL    = 2,
+    #[strum[props(desc="記録の再生時に本文を照合する")]]
+    REPLAY_MATCH_BODY   = 4,
+}
+
 #[builtin_func_desc(
     desc="ブラウザを起動しBrowserオブジェクトを返す",
     args=[
@@ -117,9 +128,9 @@ pub fn remote_object_type(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFu
     ],
     rtype={desc="レスポンスオブジェクト",types="WebResponse"}
 )]
-pub fn webrequest(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+pub fn webrequest(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let uri = args.get_as_string(0, None)?;
-    let req = WebRequest::new();
+    let mut req = WebRequest::new().with_sandbox(evaluator.sandbox());
     let res = req.get(&uri)?;
     Ok(Object::WebResponse(res))
 }
@@ -129,8 +140,8 @@ pub fn webrequest(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult
     args=[],
     rtype={desc="リクエストオブジェクト",types="WebRequest"}
 )]
-pub fn webrequest_builder(_: &mut Evaluator, _: BuiltinFuncArgs) -> BuiltinFuncResult {
-    let req = WebRequest::new();
+pub fn webrequest_builder(evaluator: &mut Evaluator, _: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let req = WebRequest::new().with_sandbox(evaluator.sandbox());
     Ok(Object::WebRequest(Arc::new(Mutex::new(req))))
 }
 
@@ -3,7 +3,9 @@ use crate::builtins::*;
 use crate::object::Object;
 use crate::error::UErrorMessage::UWindowError;
 use crate::gui::*;
+use crate::embed::HostDialog;
 use util::settings::USETTINGS;
+use util::winapi::is_headless;
 
 use std::sync::{Mutex, LazyLock};
 use std::rc::Rc;
@@ -154,13 +156,18 @@ fn set_dlg_point(x: i32, y: i32, point: &LazyLock<Mutex<(Option<i32>, Option<i32
     ],
     rtype={desc="押されたボタンを示す定数",types="定数"}
 )]
-pub fn msgbox(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+pub fn msgbox(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let message = args.get_as_string(0, None)?;
     let btns = args.get_as_int::<i32>(1, Some(BtnConst::BTN_OK as i32))?;
     let (x, y) = get_dlg_point(&args, (2, 3), &MSGBOX_POINT)?;
     let focus = args.get_as_int_or_empty(4)?;
     let enable_link = args.get_as_bool(5, Some(false))?;
 
+    let dialog = HostDialog::MsgBox { message: message.clone(), buttons: btns, focus };
+    if let Some(result) = evaluator.host_dialog(dialog) {
+        return Ok(result);
+    }
+
     let font = Some(DIALOG_FONT_FAMILY.clone());
     let defbtn = focus.map(MsgBoxButton);
     let title = DIALOG_TITLE.as_str();
@@ -190,7 +197,7 @@ pub fn msgbox(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     ],
     rtype={desc="入力値",types="文字列または配列"}
 )]
-pub fn input(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+pub fn input(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let mut msg = args.get_as_string_array(0)?;
     let mut label = match msg.len() {
         0 => return Err(builtin_func_error(UErrorMessage::EmptyArrayNotAllowed)),
@@ -209,6 +216,16 @@ pub fn input(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     mask_flags.resize(label.len(), false);
     let (x, y) = get_dlg_point(&args, (3, 4), &INPUT_POINT)?;
 
+    let dialog = HostDialog::Input {
+        message: msg.last().cloned().unwrap_or_default(),
+        labels: label.clone(),
+        defaults: default_values.clone(),
+        masks: mask_flags.clone(),
+    };
+    if let Some(result) = evaluator.host_dialog(dialog) {
+        return Ok(result);
+    }
+
     let fields = label.into_iter()
         .zip(default_values)
         .zip(mask_flags)
@@ -307,7 +324,7 @@ pub enum SlctConst {
     ],
     rtype={desc="選択した項目に該当する値、複数選択の場合配列",types="値または配列"}
 )]
-pub fn slctbox(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+pub fn slctbox(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     // 第一引数: 種別と戻り値型
     let n = args.get_as_int(0, None)?;
     let r#type = SlctType::new(n);
@@ -327,6 +344,11 @@ pub fn slctbox(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     // 残りの引数を文字列の配列として受ける
     let items = args.get_rest_as_string_array(msg_index + 1, 0)?;
 
+    let dialog = HostDialog::Slctbox { message: message.clone().unwrap_or_default(), kind: n, items: items.clone(), timeout: progress };
+    if let Some(result) = evaluator.host_dialog(dialog) {
+        return Ok(result);
+    }
+
     // 表示位置の決定
     let pos_x = match x {
         Some(-1) => SLCTBOX_POINT.lock().unwrap().0,
@@ -437,6 +459,14 @@ pub enum BalloonFlag {
     ],
 )]
 pub fn balloon(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    if is_headless() {
+        // ヘッドレスモードでは吹き出しの代わりに標準エラー出力へ
+        if args.len() > 0 {
+            let message = args.get_as_string(0, None)?;
+            eprintln!("{message}");
+        }
+        return Ok(Object::Empty);
+    }
     let balloon = if args.len() == 0 {
         // balloon消す
         None
@@ -3,13 +3,15 @@ mod drop;
 
 use crate::Evaluator;
 use crate::builtins::*;
-use crate::object::{Object, Fopen, Csv, CsvValue, FopenMode, FGetType, FPutType};
+use crate::object::{Object, Fopen, Csv, CsvValue, CsvStream, FopenMode, FGetType, FPutType, HashTbl, UObject, IniSections, ZipArchive};
+use crate::object::csvstream::rows_to_csv;
 use crate::error::UErrorMessage::FopenError;
 
 use std::io::{Write, Read};
 use std::sync::{Arc, Mutex, RwLock};
 use std::path::{PathBuf, Path};
 use std::sync::LazyLock;
+use serde_json::Value as JsonValue;
 
 use strum_macros::{EnumString, VariantNames};
 use num_derive::{ToPrimitive, FromPrimitive};
@@ -25,16 +27,30 @@ pub fn builtin_func_sets() -> BuiltinFunctionSets {
     sets.add("readini", readini, get_desc!(readini));
     sets.add("writeini", writeini, get_desc!(writeini));
     sets.add("deleteini", deleteini, get_desc!(deleteini));
+    sets.add("loadini", loadini, get_desc!(loadini));
+    sets.add("saveini", saveini, get_desc!(saveini));
     sets.add("deletefile", deletefile, get_desc!(deletefile));
     sets.add("getdir", getdir, get_desc!(getdir));
     sets.add("dropfile", dropfile, get_desc!(dropfile));
     sets.add("zipitems", zipitems, get_desc!(zipitems));
     sets.add("unzip", unzip, get_desc!(unzip));
     sets.add("zip", zip, get_desc!(zip));
+    sets.add("ziparchive", ziparchive, get_desc!(ziparchive));
+    sets.add("taritems", taritems, get_desc!(taritems));
+    sets.add("untar", untar, get_desc!(untar));
+    sets.add("tar", tar, get_desc!(tar));
+    sets.add("gzip", gzip, get_desc!(gzip));
+    sets.add("gunzip", gunzip, get_desc!(gunzip));
+    sets.add("deflate", deflate, get_desc!(deflate));
+    sets.add("inflate", inflate, get_desc!(inflate));
     sets.add("csvopen", csvopen, get_desc!(csvopen));
     sets.add("csvclose", csvclose, get_desc!(csvclose));
     sets.add("csvread", csvread, get_desc!(csvread));
     sets.add("csvwrite", csvwrite, get_desc!(csvwrite));
+    sets.add("csvreader", csvreader, get_desc!(csvreader));
+    sets.add("csvwriter", csvwriter, get_desc!(csvwriter));
+    sets.add("fromcsv", fromcsv, get_desc!(fromcsv));
+    sets.add("tocsv", tocsv, get_desc!(tocsv));
     sets
 }
 
@@ -70,6 +86,19 @@ pub enum FileConst {
     F_ALLTEXT   = -2
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, EnumString, EnumProperty, VariantNames, ToPrimitive, FromPrimitive)]
+pub enum CsvTypeConst {
+    #[strum[props(desc="CSVの値を文字列として扱う")]]
+    CSV_STR  = 0,
+    #[strum[props(desc="CSVの値を数値に変換する")]]
+    CSV_NUM  = 1,
+    #[strum[props(desc="CSVの値を真偽値に変換する")]]
+    CSV_BOOL = 2,
+    #[strum[props(desc="CSVの値をDateTimeオブジェクトに変換する")]]
+    CSV_DATE = 3,
+}
+
 #[builtin_func_desc(
     desc="テキストファイルを開く"
     args=[
@@ -334,6 +363,123 @@ pub fn deleteini(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult
     Ok(Object::default())
 }
 
+#[builtin_func_desc(
+    desc="iniファイル全体を読み取る"
+    args=[
+        {o,n="ファイル",t="文字列またはファイルID",d="対象ファイルを指定"},
+        {o,n="連想配列",t="真偽値",d="TRUEなら連想配列、FALSEならUObjectで返す"},
+    ],
+    rtype={desc="セクション名をキーとし、キーと値の組を値とするUObjectまたは連想配列",types="UObjectまたは連想配列"}
+)]
+pub fn loadini(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let path_or_fopen = args.get_as_string_or_fopen(0)?;
+    let as_hashtbl = args.get_as_bool(1, Some(false))?;
+    let sections = match path_or_fopen {
+        TwoTypeArg::T(path) => {
+            let path = path.unwrap_or(DEFAULT_INI_NAME.to_string());
+            Fopen::ini_to_sections_from_path(&path)
+                .map_err(|e| builtin_func_error(FopenError(e)))?
+        },
+        TwoTypeArg::U(arc) => {
+            let fopen = arc.lock().unwrap();
+            fopen.ini_to_sections()
+        },
+    };
+    let obj = if as_hashtbl {
+        let mut hashtbl = HashTbl::new(false, false);
+        for (section, keys) in sections {
+            let mut keytbl = HashTbl::new(false, false);
+            for (key, value) in keys {
+                keytbl.insert(key, value.into());
+            }
+            hashtbl.insert(section, Object::HashTbl(Arc::new(Mutex::new(keytbl))));
+        }
+        Object::HashTbl(Arc::new(Mutex::new(hashtbl)))
+    } else {
+        let map = sections.into_iter()
+            .map(|(section, keys)| {
+                let keys = keys.into_iter()
+                    .map(|(key, value)| (key, JsonValue::String(value)))
+                    .collect();
+                (section, JsonValue::Object(keys))
+            })
+            .collect();
+        Object::UObject(UObject::from(JsonValue::Object(map)))
+    };
+    Ok(obj)
+}
+
+/// セクションの値をキーと値の組にする
+fn to_ini_keys(section: &str, obj: Object) -> BuiltInResult<Vec<(String, String)>> {
+    match obj {
+        Object::HashTbl(arc) => {
+            let hashtbl = arc.lock().unwrap();
+            let keys = hashtbl.map().into_iter()
+                .map(|(key, value)| (key, value.to_string()))
+                .collect();
+            Ok(keys)
+        },
+        obj @ Object::UObject(_) => json_to_ini_keys(section, JsonValue::try_from(obj)?),
+        _ => Err(builtin_func_error(UErrorMessage::InvalidIniSection(section.to_string()))),
+    }
+}
+fn json_to_ini_keys(section: &str, value: JsonValue) -> BuiltInResult<Vec<(String, String)>> {
+    match value {
+        JsonValue::Object(map) => {
+            let keys = map.into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        JsonValue::Null => String::new(),
+                        JsonValue::String(s) => s,
+                        value => value.to_string(),
+                    };
+                    (key, value)
+                })
+                .collect();
+            Ok(keys)
+        },
+        _ => Err(builtin_func_error(UErrorMessage::InvalidIniSection(section.to_string()))),
+    }
+}
+
+#[builtin_func_desc(
+    desc="UObjectまたは連想配列の内容でiniファイルを書き換える、コメントは保持される"
+    args=[
+        {n="値",t="UObjectまたは連想配列",d="セクション名をキーとし、キーと値の組(UObjectまたは連想配列)を値とする"},
+        {o,n="ファイル",t="文字列またはファイルID",d="対象ファイル"},
+    ],
+)]
+pub fn saveini(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let sections = match args.get_as_object(0, None)? {
+        Object::HashTbl(arc) => {
+            let hashtbl = arc.lock().unwrap();
+            hashtbl.map().into_iter()
+                .map(|(section, obj)| Ok((section.clone(), to_ini_keys(&section, obj)?)))
+                .collect::<BuiltInResult<IniSections>>()?
+        },
+        obj @ Object::UObject(_) => match JsonValue::try_from(obj.clone())? {
+            JsonValue::Object(map) => map.into_iter()
+                .map(|(section, value)| Ok((section.clone(), json_to_ini_keys(&section, value)?)))
+                .collect::<BuiltInResult<IniSections>>()?,
+            _ => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(obj))),
+        },
+        obj => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(obj))),
+    };
+    let path_or_fopen = args.get_as_string_or_fopen(1)?;
+    match path_or_fopen {
+        TwoTypeArg::T(path) => {
+            let path = path.unwrap_or(DEFAULT_INI_NAME.to_string());
+            Fopen::ini_update_from_path(&path, &sections)
+                .map_err(|e| builtin_func_error(FopenError(e)))?;
+        },
+        TwoTypeArg::U(arc) => {
+            let mut fopen = arc.lock().unwrap();
+            fopen.ini_update(&sections);
+        },
+    }
+    Ok(Object::default())
+}
+
 #[builtin_func_desc(
     desc="ファイルを削除",
     args=[
@@ -508,7 +654,7 @@ impl Zip {
         } else {
             zip::ZipWriter::new(file)
         };
-        let options = zip::write::FileOptions::default()
+        let options = zip::write::SimpleFileOptions::default()
             .compression_method(zip::CompressionMethod::Deflated);
 
         let paths = files.into_iter().map(PathBuf::from);
@@ -612,6 +758,206 @@ pub fn zip(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     Ok(Object::Bool(result))
 }
 
+#[builtin_func_desc(
+    desc="zipファイルを読み書きするZipArchiveオブジェクトを得る",
+    args=[
+        {o,n="zip",t="文字列またはバイト配列",d="zipファイルのパス、またはzipのバイト配列; 省略時やファイルがない場合は新規作成"},
+        {o,n="パスワード",t="文字列",d="暗号化されたエントリの読み出し、および追加するエントリの暗号化に使うパスワード"},
+    ],
+    rtype={desc="ZipArchiveオブジェクト",types="ZipArchive"}
+)]
+pub fn ziparchive(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let password = args.get_as_string_or_empty(1)?;
+    let archive = match args.get_as_object_or_empty(0)? {
+        Some(Object::ByteArray(bytes)) => ZipArchive::from_bytes(bytes, password)?,
+        Some(path) => ZipArchive::open(&path.to_string(), password)?,
+        None => ZipArchive::from_bytes(vec![], password)?,
+    };
+    let archive = archive.with_sandbox(evaluator.sandbox());
+    Ok(Object::ZipArchive(Arc::new(Mutex::new(archive))))
+}
+
+struct Tar {
+    path: PathBuf,
+}
+
+impl Tar {
+    fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path)
+        }
+    }
+    /// 拡張子が.gzまたは.tgzならgzip圧縮されたtarとして扱う
+    fn is_gzip(&self) -> bool {
+        self.path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("gz") || ext.eq_ignore_ascii_case("tgz"))
+    }
+    fn archive(&self) -> std::io::Result<tar::Archive<Box<dyn Read>>> {
+        let file = std::fs::File::open(&self.path)?;
+        let reader: Box<dyn Read> = if self.is_gzip() {
+            Box::new(flate2::read::GzDecoder::new(std::io::BufReader::new(file)))
+        } else {
+            Box::new(std::io::BufReader::new(file))
+        };
+        Ok(tar::Archive::new(reader))
+    }
+
+    fn list(&self) -> std::io::Result<Vec<String>> {
+        let mut archive = self.archive()?;
+        let mut names = vec![];
+        for entry in archive.entries()? {
+            let entry = entry?;
+            names.push(entry.path()?.to_string_lossy().to_string());
+        }
+        Ok(names)
+    }
+
+    fn extract(&self, out: &str) -> std::io::Result<()> {
+        let mut archive = self.archive()?;
+        archive.unpack(out)
+    }
+
+    fn compress(&self, files: Vec<String>) -> std::io::Result<()> {
+        let file = std::fs::File::create(&self.path)?;
+        let writer: Box<dyn Write> = if self.is_gzip() {
+            Box::new(flate2::write::GzEncoder::new(file, flate2::Compression::default()))
+        } else {
+            Box::new(file)
+        };
+        let mut builder = tar::Builder::new(writer);
+        for path in files.into_iter().map(PathBuf::from) {
+            let name = path.file_name().unwrap_or_default().to_os_string();
+            if path.is_dir() {
+                builder.append_dir_all(name, &path)?;
+            } else if path.is_file() {
+                builder.append_path_with_name(&path, name)?;
+            }
+        }
+        // gzipの場合はここで圧縮ストリームも閉じられる
+        builder.into_inner()?.flush()
+    }
+}
+
+#[builtin_func_desc(
+    desc="tarファイル内のファイル一覧を得る",
+    args=[
+        {n="tar",t="文字列",d="tarファイルのパス、拡張子が.gzまたは.tgzならgzip圧縮されたtarとして扱う"},
+    ],
+    rtype={desc="ファイル名の配列",types="配列"}
+)]
+pub fn taritems(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let path = args.get_as_string(0, None)?;
+    let tar = Tar::new(&path);
+    let array = tar.list()?.into_iter()
+        .map(|s| s.into())
+        .collect();
+    Ok(Object::Array(array))
+}
+
+#[builtin_func_desc(
+    desc="tarファイルを展開",
+    args=[
+        {n="tar",t="文字列",d="tarファイルのパス、拡張子が.gzまたは.tgzならgzip圧縮されたtarとして扱う"},
+        {n="展開先",t="文字列",d="展開先フォルダのパス"},
+    ],
+    rtype={desc="成功時TRUE",types="真偽値"}
+)]
+pub fn untar(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let path = args.get_as_string(0, None)?;
+    let out = args.get_as_string(1, None)?;
+    let tar = Tar::new(&path);
+    let result = tar.extract(&out).is_ok();
+    Ok(Object::Bool(result))
+}
+
+#[builtin_func_desc(
+    desc="tarファイルを作成",
+    args=[
+        {n="tar",t="文字列",d="作成するtarファイルのパス、拡張子が.gzまたは.tgzならgzip圧縮する"},
+        {v=10,n="ファイル1-10",t="文字列または配列",d="tarに含めるファイルまたはフォルダのパス (配列可)"},
+    ],
+    rtype={desc="成功時TRUE",types="真偽値"}
+)]
+pub fn tar(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let path = args.get_as_string(0, None)?;
+    let files = args.get_rest_as_string_array(1, 1)?;
+    let tar = Tar::new(&path);
+    let result = tar.compress(files).is_ok();
+    Ok(Object::Bool(result))
+}
+
+/// 圧縮レベルを得る、省略時は既定値
+fn get_compression(args: &BuiltinFuncArgs, i: usize) -> BuiltInResult<flate2::Compression> {
+    let compression = match args.get_as_int_or_empty::<u32>(i)? {
+        Some(level) if level <= 9 => flate2::Compression::new(level),
+        Some(level) => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(level.into()))),
+        None => flate2::Compression::default(),
+    };
+    Ok(compression)
+}
+
+#[builtin_func_desc(
+    desc="データをgzip形式で圧縮する",
+    args=[
+        {n="データ",t="文字列またはバイト配列",d="圧縮するデータ、文字列はUTF-8として扱う"},
+        {o,n="圧縮レベル",t="数値",d="0-9、省略時は6"},
+    ],
+    rtype={desc="圧縮されたデータ",types="バイト配列"}
+)]
+pub fn gzip(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let data = args.get_as_bytearray(0)?;
+    let compression = get_compression(&args, 1)?;
+    let mut encoder = flate2::write::GzEncoder::new(vec![], compression);
+    encoder.write_all(&data)?;
+    Ok(Object::ByteArray(encoder.finish()?))
+}
+
+#[builtin_func_desc(
+    desc="gzip形式のデータを展開する",
+    args=[
+        {n="データ",t="バイト配列",d="gzip形式のデータ"},
+    ],
+    rtype={desc="展開されたデータ",types="バイト配列"}
+)]
+pub fn gunzip(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let data = args.get_as_bytearray(0)?;
+    let mut decoder = flate2::read::MultiGzDecoder::new(data.as_slice());
+    let mut buf = vec![];
+    decoder.read_to_end(&mut buf)?;
+    Ok(Object::ByteArray(buf))
+}
+
+#[builtin_func_desc(
+    desc="データをdeflate形式で圧縮する",
+    args=[
+        {n="データ",t="文字列またはバイト配列",d="圧縮するデータ、文字列はUTF-8として扱う"},
+        {o,n="圧縮レベル",t="数値",d="0-9、省略時は6"},
+    ],
+    rtype={desc="圧縮されたデータ",types="バイト配列"}
+)]
+pub fn deflate(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let data = args.get_as_bytearray(0)?;
+    let compression = get_compression(&args, 1)?;
+    let mut encoder = flate2::write::DeflateEncoder::new(vec![], compression);
+    encoder.write_all(&data)?;
+    Ok(Object::ByteArray(encoder.finish()?))
+}
+
+#[builtin_func_desc(
+    desc="deflate形式のデータを展開する",
+    args=[
+        {n="データ",t="バイト配列",d="deflate形式のデータ"},
+    ],
+    rtype={desc="展開されたデータ",types="バイト配列"}
+)]
+pub fn inflate(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let data = args.get_as_bytearray(0)?;
+    let mut decoder = flate2::read::DeflateDecoder::new(data.as_slice());
+    let mut buf = vec![];
+    decoder.read_to_end(&mut buf)?;
+    Ok(Object::ByteArray(buf))
+}
+
 #[builtin_func_desc(
     desc="csvファイルを開く",
     args=[
@@ -624,22 +970,7 @@ pub fn zip(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
 pub fn csvopen(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let file = args.get_as_string(0, None)?;
     let header = args.get_as_bool(1, Some(false))?;
-    let delimiter = match args.get_as_string_or_bool(2, Some(TwoTypeArg::U(false)))? {
-        TwoTypeArg::T(d) => match d.len() {
-            1 => {
-                let ch = d.chars().next().unwrap_or(',');
-                if ch.is_ascii() {
-                    ch as u8
-                } else {
-                    return Err(builtin_func_error(UErrorMessage::ShouldBeAsciiCharacter(d)));
-                }
-            },
-            _ => {
-                return Err(builtin_func_error(UErrorMessage::ShouldBeAsciiCharacter(d)));
-            }
-        },
-        TwoTypeArg::U(b) => if b {b'\t'} else {b','},
-    };
+    let delimiter = get_csv_delimiter(&args, 2)?;
     let csv = Csv::open(&file, header, delimiter)
         .map_err(|e| builtin_func_error(UErrorMessage::FopenError(e)))?;
     Ok(Object::Csv(Arc::new(RwLock::new(csv))))
@@ -734,4 +1065,104 @@ pub fn csvwrite(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult
         TwoTypeArg::U(column) => w.write(value, row, column),
     };
     Ok(succeed.into())
-}
\ No newline at end of file
+}
+
+/// 区切り文字の引数、TRUEならタブ文字
+fn get_csv_delimiter(args: &BuiltinFuncArgs, i: usize) -> BuiltInResult<u8> {
+    let delimiter = match args.get_as_string_or_bool(i, Some(TwoTypeArg::U(false)))? {
+        TwoTypeArg::T(d) => match d.len() {
+            1 => {
+                let ch = d.chars().next().unwrap_or(',');
+                if ch.is_ascii() {
+                    ch as u8
+                } else {
+                    return Err(builtin_func_error(UErrorMessage::ShouldBeAsciiCharacter(d)));
+                }
+            },
+            _ => {
+                return Err(builtin_func_error(UErrorMessage::ShouldBeAsciiCharacter(d)));
+            }
+        },
+        TwoTypeArg::U(b) => if b {b'\t'} else {b','},
+    };
+    Ok(delimiter)
+}
+
+#[builtin_func_desc(
+    desc="csvファイルを1行ずつ読むCsvReaderオブジェクトを返す",
+    args=[
+        {n="csv",t="文字列",d="csvファイルのパス"},
+        {o, n="ヘッダ",t="真偽値",d="csvファイルにヘッダ行があるかどうか"},
+        {o, n="区切り文字",t="真偽値またはASCII文字",d="区切り文字、TRUEならタブ文字"},
+    ]
+    rtype={desc="ファイルはreadやfor-inで読み始めた時点で開かれる", types="CsvReader"}
+)]
+pub fn csvreader(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let path = args.get_as_string(0, None)?;
+    let header = args.get_as_bool(1, Some(false))?;
+    let delimiter = get_csv_delimiter(&args, 2)?;
+    let mut csv = CsvStream::reader(PathBuf::from(path));
+    csv.set_header(header)?;
+    csv.set_delimiter(delimiter)?;
+    Ok(Object::CsvStream(Arc::new(Mutex::new(csv))))
+}
+
+#[builtin_func_desc(
+    desc="csvファイルに1行ずつ書き込むCsvWriterオブジェクトを返す",
+    args=[
+        {n="csv",t="文字列",d="csvファイルのパス"},
+        {o, n="追記",t="真偽値",d="TRUEなら既存ファイルの末尾に追記する、FALSEなら上書き"},
+        {o, n="ヘッダ",t="真偽値",d="ヘッダ行を書き込むかどうか"},
+        {o, n="区切り文字",t="真偽値またはASCII文字",d="区切り文字、TRUEならタブ文字"},
+    ]
+    rtype={desc="ファイルは最初に書き込んだ時点で開かれる", types="CsvWriter"}
+)]
+pub fn csvwriter(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let path = args.get_as_string(0, None)?;
+    let append = args.get_as_bool(1, Some(false))?;
+    let header = args.get_as_bool(2, Some(false))?;
+    let delimiter = get_csv_delimiter(&args, 3)?;
+    let mut csv = CsvStream::writer(PathBuf::from(path), append);
+    csv.set_header(header)?;
+    csv.set_delimiter(delimiter)?;
+    Ok(Object::CsvStream(Arc::new(Mutex::new(csv))))
+}
+
+#[builtin_func_desc(
+    desc="csv文字列を配列にする",
+    args=[
+        {n="csv文字列",t="文字列",d="変換するcsv文字列"},
+        {o, n="ヘッダ",t="真偽値",d="TRUEなら1行目をヘッダとし各行を連想配列にする、FALSEなら各行を配列にする"},
+        {o, n="区切り文字",t="真偽値またはASCII文字",d="区切り文字、TRUEならタブ文字"},
+        {o, n="UObject",t="真偽値",d="TRUEなら配列ではなくUObjectを返す"},
+    ]
+    rtype={desc="各行を要素とする配列またはUObject", types="配列またはUObject"}
+)]
+pub fn fromcsv(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let text = args.get_as_string(0, None)?;
+    let header = args.get_as_bool(1, Some(true))?;
+    let delimiter = get_csv_delimiter(&args, 2)?;
+    let as_uobject = args.get_as_bool(3, Some(false))?;
+    let mut csv = CsvStream::from_text(text);
+    csv.set_header(header)?;
+    csv.set_delimiter(delimiter)?;
+    let rows = csv.read_all(as_uobject)?;
+    Ok(rows)
+}
+
+#[builtin_func_desc(
+    desc="配列をcsv文字列にする",
+    args=[
+        {n="配列",t="配列またはUObject",d="各行を要素とする配列、各行は配列、連想配列またはUObject"},
+        {o, n="ヘッダ",t="真偽値",d="TRUEなら最初の連想配列のキーをヘッダ行として出力する"},
+        {o, n="区切り文字",t="真偽値またはASCII文字",d="区切り文字、TRUEならタブ文字"},
+    ]
+    rtype={desc="csv文字列", types="文字列"}
+)]
+pub fn tocsv(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let rows = args.get_as_object(0, None)?;
+    let header = args.get_as_bool(1, Some(true))?;
+    let delimiter = get_csv_delimiter(&args, 2)?;
+    let csv = rows_to_csv(rows, header, delimiter)?;
+    Ok(csv.into())
+}
@@ -1,12 +1,22 @@
+mod stats;
+mod exact;
+
 use crate::builtins::*;
+use crate::object::{Object, UObject, URng};
+use crate::object::rng::with_default_rng;
 use crate::Evaluator;
 
 use rand::Rng;
+use rust_decimal::RoundingStrategy;
+use strum_macros::{EnumString, VariantNames};
+use num_derive::{ToPrimitive, FromPrimitive};
 
 pub fn builtin_func_sets() -> BuiltinFunctionSets {
     let mut sets = BuiltinFunctionSets::new();
     sets.add("isnan", isnan, get_desc!(isnan));
     sets.add("random", random, get_desc!(random));
+    sets.add("rng", rng, get_desc!(rng));
+    sets.add("randseed", randseed, get_desc!(randseed));
     sets.add("abs", abs, get_desc!(abs));
     sets.add("zcut", zcut, get_desc!(zcut));
     sets.add("int", int, get_desc!(int));
@@ -23,6 +33,17 @@ pub fn builtin_func_sets() -> BuiltinFunctionSets {
     sets.add("arcsin", arcsin, get_desc!(arcsin));
     sets.add("arccos", arccos, get_desc!(arccos));
     sets.add("arctan", arctan, get_desc!(arctan));
+    sets.add("median", median, get_desc!(median));
+    sets.add("mode", mode, get_desc!(mode));
+    sets.add("variance", variance, get_desc!(variance));
+    sets.add("stddev", stddev, get_desc!(stddev));
+    sets.add("percentile", percentile, get_desc!(percentile));
+    sets.add("histogram", histogram, get_desc!(histogram));
+    sets.add("linreg", linreg, get_desc!(linreg));
+    sets.add("movavg", movavg, get_desc!(movavg));
+    sets.add("cumsum", cumsum, get_desc!(cumsum));
+    sets.add("bigcalc", bigcalc, get_desc!(bigcalc));
+    sets.add("deccalc", deccalc, get_desc!(deccalc));
     sets
 }
 
@@ -50,10 +71,34 @@ pub fn isnan(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
 )]
 pub fn random(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let n = args.get_as_int(0, None)?;
-    let r = rand::thread_rng().gen_range(0..n);
+    let r = with_default_rng(|rng| rng.gen_range(0..n));
     Ok(r.into())
 }
 
+#[builtin_func_desc(
+    desc="シードを指定して乱数生成器を作る",
+    args=[
+        {o,n="シード",t="数値",d="0以上の整数、省略時はランダムなシードを用いる"},
+    ],
+    rtype={desc="乱数生成器",types="Rng"}
+)]
+pub fn rng(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let seed = args.get_as_int_or_empty::<u64>(0)?;
+    Ok(Object::Rng(URng::new(seed)))
+}
+
+#[builtin_func_desc(
+    desc="randomが使う乱数生成器のシードを設定する",
+    args=[
+        {o,n="シード",t="数値",d="0以上の整数、省略時はシードの指定を解除する"},
+    ],
+)]
+pub fn randseed(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let seed = args.get_as_int_or_empty::<u64>(0)?;
+    crate::object::rng::set_default_seed(seed);
+    Ok(Object::Empty)
+}
+
 #[builtin_func_desc(
     desc="絶対値を得る",
     args=[
@@ -105,17 +150,64 @@ pub fn ceil(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     Ok(n.ceil().into())
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Debug, EnumString, EnumProperty, VariantNames, ToPrimitive, FromPrimitive, PartialEq, Default)]
+pub enum RoundConst {
+    #[default]
+    #[strum[props(desc="四捨五入 (0から遠い方へ)")]]
+    RND_HALF_AWAY = 0,
+    #[strum[props(desc="偶数丸め (銀行丸め)")]]
+    RND_HALF_EVEN = 1,
+    #[strum[props(desc="五捨六入 (0に近い方へ)")]]
+    RND_HALF_DOWN = 2,
+    #[strum[props(desc="切り捨て (0方向)")]]
+    RND_DOWN = 3,
+    #[strum[props(desc="切り上げ (0から遠い方へ)")]]
+    RND_UP = 4,
+    #[strum[props(desc="負の無限大方向")]]
+    RND_FLOOR = 5,
+    #[strum[props(desc="正の無限大方向")]]
+    RND_CEIL = 6,
+}
+impl From<RoundConst> for RoundingStrategy {
+    fn from(val: RoundConst) -> Self {
+        match val {
+            RoundConst::RND_HALF_AWAY => RoundingStrategy::MidpointAwayFromZero,
+            RoundConst::RND_HALF_EVEN => RoundingStrategy::MidpointNearestEven,
+            RoundConst::RND_HALF_DOWN => RoundingStrategy::MidpointTowardZero,
+            RoundConst::RND_DOWN => RoundingStrategy::ToZero,
+            RoundConst::RND_UP => RoundingStrategy::AwayFromZero,
+            RoundConst::RND_FLOOR => RoundingStrategy::ToNegativeInfinity,
+            RoundConst::RND_CEIL => RoundingStrategy::ToPositiveInfinity,
+        }
+    }
+}
+
 #[builtin_func_desc(
     desc="指定桁数で丸め",
     args=[
         {n="n",t="数値",d="入力値"},
         {o,n="桁",t="数値",d="丸め桁、マイナスなら小数点以下の桁数"},
+        {o,n="丸め方法",t="定数",d=r#"指定時は十進数として誤差なく丸める
+- RND_HALF_AWAY: 四捨五入
+- RND_HALF_EVEN: 偶数丸め (銀行丸め)
+- RND_HALF_DOWN: 五捨六入
+- RND_DOWN: 切り捨て
+- RND_UP: 切り上げ
+- RND_FLOOR: 負の無限大方向
+- RND_CEIL: 正の無限大方向
+"#},
     ],
     rtype={desc="丸め値",types="数値"}
 )]
 pub fn round(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let n = args.get_as_f64(0, None)?;
     let d = args.get_as_int(1, Some(0i32))?;
+    if let Some(mode) = args.get_as_const::<RoundConst>(2, false)?
+        && let Some(rounded) = exact::round_f64(n, d, mode.into())
+    {
+        return Ok(rounded.into());
+    }
     let rounded = if d == 0 {
         n.round()
     } else {
@@ -274,4 +366,208 @@ pub fn arctan(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let n = args.get_as_f64(0, None)?;
     let r = n.atan();
     Ok(r.into())
-}
\ No newline at end of file
+}
+/// 配列内の数値を得る、数値以外は無視する
+fn get_numbers(args: &BuiltinFuncArgs, i: usize) -> BuiltInResult<Vec<f64>> {
+    let arr = args.get_as_array(i, None)?;
+    let nums = arr.into_iter()
+        .filter_map(|o| if let Object::Num(n) = o {Some(n)} else {None})
+        .collect();
+    Ok(nums)
+}
+fn num_or_empty(n: Option<f64>) -> Object {
+    n.map(Object::Num).unwrap_or_default()
+}
+fn num_array(nums: Vec<f64>) -> Object {
+    Object::Array(nums.into_iter().map(Object::Num).collect())
+}
+
+#[builtin_func_desc(
+    desc="中央値",
+    args=[
+        {n="配列",t="配列",d="数値配列、数値以外は無視する"},
+    ],
+    rtype={desc="中央値、数値がなければEMPTY",types="数値"}
+)]
+pub fn median(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let nums = get_numbers(&args, 0)?;
+    Ok(num_or_empty(stats::median(&nums)))
+}
+
+#[builtin_func_desc(
+    desc="最頻値",
+    args=[
+        {n="配列",t="配列",d="数値配列、数値以外は無視する"},
+    ],
+    rtype={desc="最頻値の配列、同数の値は出現順にすべて含む",types="配列"}
+)]
+pub fn mode(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let nums = get_numbers(&args, 0)?;
+    Ok(num_array(stats::mode(&nums)))
+}
+
+#[builtin_func_desc(
+    desc="分散",
+    args=[
+        {n="配列",t="配列",d="数値配列、数値以外は無視する"},
+        {o,n="標本",t="真偽値",d="TRUEなら不偏分散 (n-1で割る)、FALSEなら母分散"},
+    ],
+    rtype={desc="分散、計算できない場合はEMPTY",types="数値"}
+)]
+pub fn variance(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let nums = get_numbers(&args, 0)?;
+    let sample = args.get_as_bool(1, Some(false))?;
+    Ok(num_or_empty(stats::variance(&nums, sample)))
+}
+
+#[builtin_func_desc(
+    desc="標準偏差",
+    args=[
+        {n="配列",t="配列",d="数値配列、数値以外は無視する"},
+        {o,n="標本",t="真偽値",d="TRUEなら不偏分散から、FALSEなら母分散から求める"},
+    ],
+    rtype={desc="標準偏差、計算できない場合はEMPTY",types="数値"}
+)]
+pub fn stddev(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let nums = get_numbers(&args, 0)?;
+    let sample = args.get_as_bool(1, Some(false))?;
+    Ok(num_or_empty(stats::variance(&nums, sample).map(f64::sqrt)))
+}
+
+#[builtin_func_desc(
+    desc="パーセンタイル",
+    args=[
+        {n="配列",t="配列",d="数値配列、数値以外は無視する"},
+        {n="p",t="数値",d="0から100の値、要素間は線形補間する"},
+    ],
+    rtype={desc="パーセンタイル値、計算できない場合はEMPTY",types="数値"}
+)]
+pub fn percentile(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let nums = get_numbers(&args, 0)?;
+    let p = args.get_as_f64(1, None)?;
+    Ok(num_or_empty(stats::percentile(&nums, p)))
+}
+
+#[builtin_func_desc(
+    desc="等幅の階級ごとの度数分布",
+    args=[
+        {n="配列",t="配列",d="数値配列、数値以外は無視する"},
+        {n="階級数",t="数値",d="階級の数"},
+        {o,n="最小",t="数値",d="範囲の下限、省略時は最小値"},
+        {o,n="最大",t="数値",d="範囲の上限、省略時は最大値"},
+    ],
+    rtype={desc="[下限, 上限, 度数] の配列、最後の階級のみ上限を含む",types="配列"}
+)]
+pub fn histogram(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let nums = get_numbers(&args, 0)?;
+    let bins = args.get_as_int::<usize>(1, None)?;
+    let min = args.get_as_int_or_empty::<f64>(2)?;
+    let max = args.get_as_int_or_empty::<f64>(3)?;
+    let range = match (min, max) {
+        (None, None) => None,
+        (min, max) => {
+            let min = min.unwrap_or_else(|| nums.iter().copied().reduce(f64::min).unwrap_or_default());
+            let max = max.unwrap_or_else(|| nums.iter().copied().reduce(f64::max).unwrap_or_default());
+            Some((min, max))
+        },
+    };
+    let bins = stats::histogram(&nums, bins, range).into_iter()
+        .map(|(lower, upper, count)| Object::Array(vec![lower.into(), upper.into(), count.into()]))
+        .collect();
+    Ok(Object::Array(bins))
+}
+
+#[builtin_func_desc(
+    desc="最小二乗法による単回帰",
+    args=[
+        {n="y",t="配列",d="目的変数の数値配列"},
+        {o,n="x",t="配列",d="説明変数の数値配列、省略時は0, 1, 2, ..."},
+    ],
+    rtype={desc="slope(傾き)、intercept(切片)、r2(決定係数)を持つUObject、計算できない場合はEMPTY",types="UObject"}
+)]
+pub fn linreg(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let ys = get_numbers(&args, 0)?;
+    let xs = match args.get_as_array_or_empty(1)? {
+        Some(_) => get_numbers(&args, 1)?,
+        None => (0..ys.len()).map(|i| i as f64).collect(),
+    };
+    let Some(reg) = stats::linear_regression(&xs, &ys) else {
+        return Ok(Object::Empty);
+    };
+    let json = serde_json::json!({
+        "slope": reg.slope,
+        "intercept": reg.intercept,
+        "r2": reg.r2,
+    });
+    Ok(Object::UObject(UObject::from(json)))
+}
+
+#[builtin_func_desc(
+    desc="単純移動平均",
+    args=[
+        {n="配列",t="配列",d="数値配列、数値以外は無視する"},
+        {n="期間",t="数値",d="平均をとる要素数"},
+    ],
+    rtype={desc="移動平均の配列、要素数は(配列長-期間+1)",types="配列"}
+)]
+pub fn movavg(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let nums = get_numbers(&args, 0)?;
+    let window = args.get_as_int::<usize>(1, None)?;
+    Ok(num_array(stats::moving_average(&nums, window)))
+}
+
+#[builtin_func_desc(
+    desc="累積和",
+    args=[
+        {n="配列",t="配列",d="数値配列、数値以外は無視する"},
+    ],
+    rtype={desc="累積和の配列",types="配列"}
+)]
+pub fn cumsum(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let nums = get_numbers(&args, 0)?;
+    Ok(num_array(stats::cumulative_sum(&nums)))
+}
+
+#[builtin_func_desc(
+    desc="多倍長整数の演算",
+    args=[
+        {n="a",t="文字列または数値",d="整数"},
+        {n="演算子",t="文字列",d="+ - * / % ^ のいずれか、/は0方向への切り捨て"},
+        {n="b",t="文字列または数値",d="整数"},
+    ],
+    rtype={desc="計算結果を表す文字列",types="文字列"}
+)]
+pub fn bigcalc(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let a = args.get_as_string(0, None)?;
+    let op = args.get_as_string(1, None)?;
+    let b = args.get_as_string(2, None)?;
+    let result = exact::bigint_calc(&a, &op, &b)
+        .map_err(builtin_func_error)?;
+    Ok(result.into())
+}
+
+#[builtin_func_desc(
+    desc="十進数の演算",
+    args=[
+        {n="a",t="文字列または数値",d="十進数"},
+        {n="演算子",t="文字列",d="+ - * / % のいずれか"},
+        {n="b",t="文字列または数値",d="十進数"},
+        {o,n="桁",t="数値",d="結果を丸める桁、マイナスなら小数点以下の桁数、省略時は丸めない"},
+        {o,n="丸め方法",t="定数",d="RND_HALF_AWAY (デフォルト), RND_HALF_EVEN, RND_HALF_DOWN, RND_DOWN, RND_UP, RND_FLOOR, RND_CEIL"},
+    ],
+    rtype={desc="計算結果を表す文字列 (有効桁数は28桁程度)",types="文字列"}
+)]
+pub fn deccalc(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let a = args.get_as_string(0, None)?;
+    let op = args.get_as_string(1, None)?;
+    let b = args.get_as_string(2, None)?;
+    let digits = args.get_as_int_or_empty::<i32>(3)?;
+    let mode = args.get_as_const::<RoundConst>(4, false)?.unwrap_or_default();
+    let mut result = exact::decimal_calc(&a, &op, &b)
+        .map_err(builtin_func_error)?;
+    if let Some(digits) = digits {
+        result = exact::round_decimal(result, digits, mode.into())
+            .map_err(builtin_func_error)?;
+    }
+    Ok(result.normalize().to_string().into())
+}
@@ -0,0 +1,107 @@
+//! 誤差のない整数・十進数演算
+
+use crate::error::UErrorMessage;
+
+use std::str::FromStr;
+use num_bigint::BigInt;
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal::prelude::ToPrimitive;
+
+pub type ExactResult<T> = Result<T, UErrorMessage>;
+
+pub fn parse_bigint(s: &str) -> ExactResult<BigInt> {
+    BigInt::from_str(s.trim())
+        .map_err(|_| UErrorMessage::InvalidNumberString(s.into(), "bigint".into()))
+}
+pub fn parse_decimal(s: &str) -> ExactResult<Decimal> {
+    let s = s.trim();
+    Decimal::from_str(s)
+        .or_else(|_| Decimal::from_scientific(s))
+        .map_err(|_| UErrorMessage::InvalidNumberString(s.into(), "decimal".into()))
+}
+
+/// 多倍長整数の演算
+pub fn bigint_calc(a: &str, op: &str, b: &str) -> ExactResult<String> {
+    let a = parse_bigint(a)?;
+    let b = parse_bigint(b)?;
+    let zero = BigInt::from(0);
+    let result = match op {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" | "%" if b == zero => return Err(UErrorMessage::DivZeroNotAllowed),
+        "/" => a / b,
+        "%" => a % b,
+        "^" => {
+            let exp = b.to_u32().ok_or(UErrorMessage::NumericOverflow)?;
+            a.pow(exp)
+        },
+        _ => return Err(UErrorMessage::InvalidArgument(op.into())),
+    };
+    Ok(result.to_string())
+}
+
+/// 十進数の演算
+pub fn decimal_calc(a: &str, op: &str, b: &str) -> ExactResult<Decimal> {
+    let a = parse_decimal(a)?;
+    let b = parse_decimal(b)?;
+    let result = match op {
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "/" | "%" if b.is_zero() => return Err(UErrorMessage::DivZeroNotAllowed),
+        "/" => a.checked_div(b),
+        "%" => a.checked_rem(b),
+        _ => return Err(UErrorMessage::InvalidArgument(op.into())),
+    };
+    result.ok_or(UErrorMessage::NumericOverflow)
+}
+
+/// 桁指定で丸める、digitsが負なら小数点以下の桁数、正なら10のdigits乗の位
+pub fn round_decimal(value: Decimal, digits: i32, strategy: RoundingStrategy) -> ExactResult<Decimal> {
+    if digits <= 0 {
+        return Ok(value.round_dp_with_strategy(digits.unsigned_abs(), strategy));
+    }
+    let factor = (0..digits).try_fold(Decimal::ONE, |f, _| f.checked_mul(Decimal::TEN))
+        .ok_or(UErrorMessage::NumericOverflow)?;
+    value.checked_div(factor)
+        .map(|d| d.round_dp_with_strategy(0, strategy))
+        .and_then(|d| d.checked_mul(factor))
+        .ok_or(UErrorMessage::NumericOverflow)
+}
+
+/// 数値を十進数として丸める
+/// 十進数で表せない値 (NaN、範囲外) はNoneを返す
+pub fn round_f64(n: f64, digits: i32, strategy: RoundingStrategy) -> Option<f64> {
+    let value = Decimal::from_str(&n.to_string()).ok()?;
+    round_decimal(value, digits, strategy).ok()?.to_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bigint() {
+        assert_eq!(bigint_calc("123456789012345678901234567890", "+", "1").unwrap(), "123456789012345678901234567891");
+        assert_eq!(bigint_calc("2", "^", "100").unwrap(), "1267650600228229401496703205376");
+        assert_eq!(bigint_calc("-7", "/", "2").unwrap(), "-3");
+        assert_eq!(bigint_calc("-7", "%", "2").unwrap(), "-1");
+        assert_eq!(bigint_calc("1", "/", "0"), Err(UErrorMessage::DivZeroNotAllowed));
+        assert!(bigint_calc("1.5", "+", "1").is_err());
+    }
+
+    #[test]
+    fn test_decimal() {
+        assert_eq!(decimal_calc("0.1", "+", "0.2").unwrap().to_string(), "0.3");
+        assert_eq!(decimal_calc("1", "/", "4").unwrap().to_string(), "0.25");
+        let half_even = RoundingStrategy::MidpointNearestEven;
+        let half_away = RoundingStrategy::MidpointAwayFromZero;
+        assert_eq!(round_f64(2.5, 0, half_even), Some(2.0));
+        assert_eq!(round_f64(3.5, 0, half_even), Some(4.0));
+        assert_eq!(round_f64(2.675, -2, half_away), Some(2.68));
+        assert_eq!(round_f64(1250.0, 2, half_even), Some(1200.0));
+        assert_eq!(round_f64(-1.5, 0, RoundingStrategy::ToZero), Some(-1.0));
+        assert_eq!(round_f64(f64::NAN, 0, half_even), None);
+    }
+}
@@ -0,0 +1,179 @@
+//! 数値配列の統計処理
+
+fn sorted(values: &[f64]) -> Vec<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted
+}
+
+pub fn mean(values: &[f64]) -> Option<f64> {
+    (! values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+pub fn median(values: &[f64]) -> Option<f64> {
+    percentile(values, 50.0)
+}
+
+/// 最頻値、同数の場合は最初に現れた順にすべて返す
+pub fn mode(values: &[f64]) -> Vec<f64> {
+    let mut counts: Vec<(f64, usize)> = vec![];
+    for v in values {
+        match counts.iter_mut().find(|(n, _)| n == v) {
+            Some((_, c)) => *c += 1,
+            None => counts.push((*v, 1)),
+        }
+    }
+    let max = counts.iter().map(|(_, c)| *c).max().unwrap_or_default();
+    counts.into_iter()
+        .filter(|(_, c)| *c == max)
+        .map(|(n, _)| n)
+        .collect()
+}
+
+/// 分散、sampleなら不偏分散 (n-1で割る)
+pub fn variance(values: &[f64], sample: bool) -> Option<f64> {
+    let mean = mean(values)?;
+    let n = if sample { values.len() - 1 } else { values.len() };
+    if n == 0 {
+        return None;
+    }
+    let sum = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>();
+    Some(sum / n as f64)
+}
+
+/// パーセンタイル (0-100)、要素間は線形補間する
+pub fn percentile(values: &[f64], p: f64) -> Option<f64> {
+    if values.is_empty() || ! (0.0..=100.0).contains(&p) {
+        return None;
+    }
+    let sorted = sorted(values);
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let fract = rank - lower as f64;
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * fract)
+}
+
+/// 等幅の階級ごとの度数 (下限, 上限, 度数)
+/// 最後の階級のみ上限を含む、範囲外の値は数えない
+pub fn histogram(values: &[f64], bins: usize, range: Option<(f64, f64)>) -> Vec<(f64, f64, usize)> {
+    if bins == 0 {
+        return vec![];
+    }
+    let (min, max) = match range {
+        Some(range) => range,
+        None => {
+            let min = values.iter().copied().reduce(f64::min).unwrap_or_default();
+            let max = values.iter().copied().reduce(f64::max).unwrap_or_default();
+            (min, max)
+        },
+    };
+    let width = (max - min) / bins as f64;
+    let mut counts = vec![0; bins];
+    for v in values.iter().filter(|v| (min..=max).contains(*v)) {
+        let i = if width > 0.0 {
+            (((v - min) / width) as usize).min(bins - 1)
+        } else {
+            0
+        };
+        counts[i] += 1;
+    }
+    counts.into_iter().enumerate()
+        .map(|(i, count)| {
+            let lower = min + width * i as f64;
+            let upper = if i == bins - 1 { max } else { min + width * (i + 1) as f64 };
+            (lower, upper, count)
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Regression {
+    pub slope: f64,
+    pub intercept: f64,
+    /// 決定係数
+    pub r2: f64,
+}
+
+/// 最小二乗法による単回帰
+pub fn linear_regression(xs: &[f64], ys: &[f64]) -> Option<Regression> {
+    let len = xs.len().min(ys.len());
+    let (xs, ys) = (&xs[..len], &ys[..len]);
+    if len < 2 {
+        return None;
+    }
+    let mean_x = mean(xs)?;
+    let mean_y = mean(ys)?;
+    let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+    for (x, y) in xs.iter().zip(ys) {
+        sxx += (x - mean_x).powi(2);
+        syy += (y - mean_y).powi(2);
+        sxy += (x - mean_x) * (y - mean_y);
+    }
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    let intercept = mean_y - slope * mean_x;
+    let r2 = if syy == 0.0 { 1.0 } else { sxy * sxy / (sxx * syy) };
+    Some(Regression { slope, intercept, r2 })
+}
+
+/// 単純移動平均、要素数がwindowに満たない場合は空
+pub fn moving_average(values: &[f64], window: usize) -> Vec<f64> {
+    if window == 0 {
+        return vec![];
+    }
+    values.windows(window)
+        .map(|w| w.iter().sum::<f64>() / window as f64)
+        .collect()
+}
+
+pub fn cumulative_sum(values: &[f64]) -> Vec<f64> {
+    values.iter()
+        .scan(0.0, |acc, v| {
+            *acc += v;
+            Some(*acc)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(mean(&values), Some(5.0));
+        assert_eq!(median(&values), Some(4.5));
+        assert_eq!(median(&[3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(mode(&values), vec![4.0]);
+        assert_eq!(mode(&[1.0, 2.0, 2.0, 1.0]), vec![1.0, 2.0]);
+        assert_eq!(variance(&values, false), Some(4.0));
+        assert_eq!(variance(&values, true), Some(32.0 / 7.0));
+        assert_eq!(variance(&[1.0], true), None);
+        assert_eq!(percentile(&[1.0, 2.0, 3.0, 4.0], 25.0), Some(1.75));
+        assert_eq!(percentile(&values, 100.0), Some(9.0));
+        assert_eq!(percentile(&values, 101.0), None);
+        assert!(median(&[]).is_none());
+    }
+
+    #[test]
+    fn test_histogram() {
+        let values = [0.0, 1.0, 2.5, 5.0, 9.9, 10.0];
+        assert_eq!(histogram(&values, 2, None), vec![(0.0, 5.0, 3), (5.0, 10.0, 3)]);
+        assert_eq!(histogram(&values, 2, Some((1.0, 3.0))), vec![(1.0, 2.0, 1), (2.0, 3.0, 1)]);
+        assert_eq!(histogram(&[1.0, 1.0], 3, None)[0], (1.0, 1.0, 2));
+    }
+
+    #[test]
+    fn test_regression_and_series() {
+        let reg = linear_regression(&[1.0, 2.0, 3.0], &[3.0, 5.0, 7.0]).unwrap();
+        assert_eq!(reg, Regression { slope: 2.0, intercept: 1.0, r2: 1.0 });
+        assert!(linear_regression(&[1.0, 1.0], &[1.0, 2.0]).is_none());
+        assert_eq!(moving_average(&[1.0, 2.0, 3.0, 4.0], 2), vec![1.5, 2.5, 3.5]);
+        assert!(moving_average(&[1.0], 2).is_empty());
+        assert_eq!(cumulative_sum(&[1.0, 2.0, 3.0]), vec![1.0, 3.0, 6.0]);
+    }
+}
@@ -3,17 +3,33 @@
 mod udp;
 mod tcp;
 mod websocket;
+mod http;
+mod mail;
+mod mqtt;
 
 use crate::error::{UError, UErrorKind, UErrorMessage, UErrorLine};
 use crate::builtins::*;
 use crate::Evaluator;
+use crate::object::Function;
 use num_derive::{FromPrimitive, ToPrimitive};
 use strum_macros::{EnumString, VariantNames};
+use std::time::Duration;
+use util::com::Com;
+use util::logging::LogType;
 pub use udp::UdpClient;
-pub use tcp::{TcpClient, TcpListener, EndOfData};
-pub use websocket::{WebSocket, Message};
+pub use tcp::{TcpClient, TcpServer, TcpListener, EndOfData, TlsOptions};
+pub use websocket::{WebSocket, WebSocketServer, Message};
+pub use http::{HttpServer, HttpRequest, HttpResponse, HttpLimits, Router, content_type};
+pub use mail::{MailClient, MailProtocol, MailSecurity, OutgoingMail, ReceivedMail, Attachment};
+pub use mqtt::{MqttClient, MqttMessage, MqttOptions};
 
 type SocketResult<T> = Result<T, UError>;
+/// tcprecvでサイズ省略時に一度に受信する最大サイズ
+const TCP_RECV_MAX: usize = 65536;
+/// udponrecvでサイズ省略時の受信バッファのサイズ (UDPの最大データサイズ)
+const UDP_RECV_MAX: usize = 65507;
+/// MQTTの接続と応答待ちのタイムアウト秒
+const MQTT_TIMEOUT: u64 = 30;
 pub(crate) const SOCKET_CLOSED_ERROR: UError = UError {
     kind: UErrorKind::SocketError,
     message: UErrorMessage::SocketHasBeenClosed,
@@ -27,11 +43,33 @@ pub fn builtin_func_sets() -> BuiltinFunctionSets {
     sets.add("udpclient", udp_client, get_desc!(udp_client));
     sets.add("udpsend", udp_send, get_desc!(udp_send));
     sets.add("udprecv", udp_recv, get_desc!(udp_recv));
+    sets.add("udpjoin", udp_join, get_desc!(udp_join));
+    sets.add("udpleave", udp_leave, get_desc!(udp_leave));
+    sets.add("udponrecv", udp_on_recv, get_desc!(udp_on_recv));
     sets.add("tcpsend", tcp_send, get_desc!(tcp_send));
     sets.add("tcplistener", tcp_listener, get_desc!(tcp_listener));
+    sets.add("tcpserver", tcp_server, get_desc!(tcp_server));
+    sets.add("tcpclient", tcp_client, get_desc!(tcp_client));
+    sets.add("httpserver", http_server, get_desc!(http_server));
+    sets.add("httpresponse", http_response, get_desc!(http_response));
+    sets.add("tcprecv", tcp_recv, get_desc!(tcp_recv));
+    sets.add("tcprecvuntil", tcp_recv_until, get_desc!(tcp_recv_until));
+    sets.add("tcprecvline", tcp_recv_line, get_desc!(tcp_recv_line));
+    sets.add("tcptimeout", tcp_timeout, get_desc!(tcp_timeout));
     sets.add("websocket", websocket, get_desc!(websocket));
     sets.add("wssend", ws_send, get_desc!(ws_send));
     sets.add("wsrecv", ws_recv, get_desc!(ws_recv));
+    sets.add("wsonmessage", ws_on_message, get_desc!(ws_on_message));
+    sets.add("wsserver", ws_server, get_desc!(ws_server));
+    sets.add("wsbroadcast", ws_broadcast, get_desc!(ws_broadcast));
+    sets.add("mailclient", mail_client, get_desc!(mail_client));
+    sets.add("mailsend", mail_send, get_desc!(mail_send));
+    sets.add("mailfetch", mail_fetch, get_desc!(mail_fetch));
+    sets.add("mqttclient", mqtt_client, get_desc!(mqtt_client));
+    sets.add("mqttpublish", mqtt_publish, get_desc!(mqtt_publish));
+    sets.add("mqttsubscribe", mqtt_subscribe, get_desc!(mqtt_subscribe));
+    sets.add("mqttunsubscribe", mqtt_unsubscribe, get_desc!(mqtt_unsubscribe));
+    sets.add("mqttrecv", mqtt_recv, get_desc!(mqtt_recv));
     sets
 }
 
@@ -40,14 +78,32 @@ pub fn builtin_func_sets() -> BuiltinFunctionSets {
 pub enum USocket {
     /// UDPクライアント
     Udp(udp::UdpClient),
+    /// TCPクライアント
+    Tcp(TcpClient),
+    /// TCPサーバー
+    TcpServer(TcpServer),
+    /// HTTPサーバー
+    HttpServer(HttpServer),
+    /// WebSocketサーバー
+    WebSocketServer(WebSocketServer),
     /// WebSocket
     WebSocket(WebSocket),
+    /// メールサーバーへの接続設定
+    Mail(MailClient),
+    /// MQTTクライアント
+    Mqtt(MqttClient),
 }
 impl std::fmt::Display for USocket {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             USocket::Udp(udp_client) => udp_client.fmt(f),
+            USocket::Tcp(tcp_client) => tcp_client.fmt(f),
+            USocket::TcpServer(tcp_server) => tcp_server.fmt(f),
+            USocket::HttpServer(http_server) => http_server.fmt(f),
+            USocket::WebSocketServer(ws_server) => ws_server.fmt(f),
             USocket::WebSocket(websocket) => websocket.fmt(f),
+            USocket::Mail(mail_client) => mail_client.fmt(f),
+            USocket::Mqtt(mqtt_client) => mqtt_client.fmt(f),
         }
     }
 }
@@ -62,7 +118,14 @@ pub fn sclose(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let socket = _args.get_as_socket(0)?;
     match socket {
         USocket::Udp(udp_client) => udp_client.close(),
+        USocket::Tcp(tcp_client) => tcp_client.close(),
+        USocket::TcpServer(tcp_server) => tcp_server.stop(),
+        USocket::HttpServer(http_server) => http_server.stop(),
+        USocket::WebSocketServer(ws_server) => ws_server.stop(),
         USocket::WebSocket(websocket) => websocket.close(),
+        // 送受信のたびに接続するので閉じるものはない
+        USocket::Mail(_) => {},
+        USocket::Mqtt(mqtt_client) => mqtt_client.close(),
     }
     Ok(Object::Empty)
 }
@@ -73,12 +136,17 @@ pub fn sclose(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
     args=[
         {n="IPアドレス",t="文字列",d="自身の待ち受けIPアドレス"},
         {n="ポート",t="数値",d="自身の待ち受けポート"},
+        {o,n="ブロードキャスト",t="真偽値",d="TRUEならブロードキャストアドレスへの送信を許可する"},
     ],
 )]
 pub fn udp_client(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let addr = args.get_as_string(0, None)?;
     let port = args.get_as_int(1, None)?;
+    let broadcast = args.get_as_bool(2, Some(false))?;
     let client = udp::UdpClient::new(&addr, port)?;
+    if broadcast {
+        client.set_broadcast(true)?;
+    }
     Ok(Object::Socket(USocket::Udp(client)))
 }
 
@@ -103,33 +171,123 @@ pub fn udp_send(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
 
 #[builtin_func_desc(
     desc="UDPによるデータ受信を行う",
-    rtype={desc="受信データを示すバイト配列、送信元IPアドレスを示す文字列、送信元ポートを示す数値の配列",types="[バイト配列, 文字列, 数値]"}
+    rtype={desc="受信データを示すバイト配列、送信元IPアドレスを示す文字列、送信元ポートを示す数値の配列、タイムアウトした場合はEMPTY",types="[バイト配列, 文字列, 数値]"}
     args=[
         {n="udp",t="UDPクライアント",d="データを送信するUDPクライアント"},
         {n="バッファサイズ",t="数値",d="受信バッファのサイズ"},
+        {o,n="タイムアウト秒",t="数値",d="受信を待つ秒数、省略時は受信するまで待つ"},
     ],
 )]
 pub fn udp_recv(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let client = args.get_as_udp(0)?;
     let size = args.get_as_int(1, None)?;
-    let (data, addr, port) = client.receive(size)?;
-    Ok(Object::Array(vec![
+    // 省略時は負の値として受信するまで待つ
+    let timeout = args.get_as_f64(2, Some(-1.0))?;
+    let timeout = (timeout >= 0.0).then(|| Duration::from_secs_f64(timeout));
+    let received = client.receive(size, timeout)?
+        .map(udp_datagram)
+        .unwrap_or_default();
+    Ok(received)
+}
+
+/// 受信したデータを [データ, 送信元IPアドレス, 送信元ポート] にする
+fn udp_datagram((data, addr, port): (Vec<u8>, String, u16)) -> Object {
+    Object::Array(vec![
         Object::ByteArray(data),
         Object::String(addr),
         Object::Num(port as _)
-    ]))
+    ])
+}
+
+#[builtin_func_desc(
+    desc="UDPクライアントをマルチキャストグループに参加させる",
+    args=[
+        {n="udp",t="UDPクライアント",d="udpclientで得たオブジェクト"},
+        {n="グループ",t="文字列",d="マルチキャストアドレス"},
+        {o,n="インターフェース",t="文字列または数値",d="IPv4なら受信するインターフェースのIPアドレス、IPv6ならインターフェース番号"},
+    ],
+)]
+pub fn udp_join(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = args.get_as_udp(0)?;
+    let group = args.get_as_string(1, None)?;
+    let interface = args.get_as_string_or_empty(2)?;
+    client.join_multicast(&group, interface.as_deref())?;
+    Ok(Object::Empty)
+}
+
+#[builtin_func_desc(
+    desc="UDPクライアントをマルチキャストグループから脱退させる",
+    args=[
+        {n="udp",t="UDPクライアント",d="udpclientで得たオブジェクト"},
+        {n="グループ",t="文字列",d="マルチキャストアドレス"},
+        {o,n="インターフェース",t="文字列または数値",d="参加時に指定したインターフェース"},
+    ],
+)]
+pub fn udp_leave(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = args.get_as_udp(0)?;
+    let group = args.get_as_string(1, None)?;
+    let interface = args.get_as_string_or_empty(2)?;
+    client.leave_multicast(&group, interface.as_deref())?;
+    Ok(Object::Empty)
+}
+
+#[builtin_func_desc(
+    desc="UDPの受信時に呼ばれる関数をバックグラウンドで実行する",
+    args=[
+        {n="udp",t="UDPクライアント",d="udpclientで得たオブジェクト"},
+        {o,n="関数",t="関数",d="受信データ, 送信元IPアドレス, 送信元ポート, UDPクライアントを受ける関数、省略時は登録済みの関数を解除する"},
+        {o,n="バッファサイズ",t="数値",d="受信バッファのサイズ"},
+    ],
+)]
+pub fn udp_on_recv(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = args.get_as_udp(0)?;
+    let func = match args.get_as_object_or_empty(1)? {
+        Some(Object::Function(func)) |
+        Some(Object::AnonFunc(func)) => Some(func),
+        Some(o) => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(o))),
+        None => None,
+    };
+    let size = args.get_as_int::<usize>(2, Some(UDP_RECV_MAX))?;
+    // 以前に登録された関数はこれで終了する
+    let id = client.new_listener();
+    if let Some(func) = func {
+        let mut evaluator = evaluator.new_thread();
+        std::thread::spawn(move || {
+            let Ok(_com) = Com::init() else {
+                return;
+            };
+            let socket = Object::Socket(USocket::Udp(client.clone()));
+            client.listen(id, size, |data, addr, port| {
+                let values = vec![
+                    Object::ByteArray(data),
+                    Object::String(addr),
+                    Object::Num(port as f64),
+                    socket.clone(),
+                ];
+                if let Err(e) = func.invoke_with_values(&mut evaluator, values) {
+                    evaluator.out_log(&e.errror_text_with_line(), LogType::Error);
+                }
+            });
+        });
+    }
+    Ok(Object::Empty)
 }
 
 #[builtin_func_desc(
     desc="サーバーにデータを送信し、そのレスポンスデータを返す",
-    rtype={desc="レスポンスデータを示すバイト配列",types="バイト配列"}
+    rtype={desc="レスポンスデータを示すバイト配列、TCPクライアントの場合は送信バイト数",types="バイト配列または数値"}
     args=[
-        {n="IPアドレス",t="文字列",d="接続先IPアドレス"},
-        {n="ポート",t="数値",d="接続先ポート"},
-        {n="送信データ",t="値",d="送信するデータ"},
+        {n="IPアドレス",t="文字列またはTCPクライアント",d="接続先IPアドレス、またはTCPクライアント"},
+        {n="ポート",t="数値",d="接続先ポート、TCPクライアントの場合は送信データ"},
+        {o,n="送信データ",t="値",d="送信するデータ"},
     ],
 )]
 pub fn tcp_send(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    if let Object::Socket(USocket::Tcp(client)) = _args.get_as_object(0, None)? {
+        let data = _args.get_as_bytearray(1)?;
+        let size = client.write(&data)?;
+        return Ok(size.into());
+    }
     let addr = _args.get_as_string(0, None)?;
     let port = _args.get_as_int(1, None)?;
     let data = _args.get_as_bytearray(2)?;
@@ -175,16 +333,284 @@ pub fn tcp_listener(evaluator: &mut Evaluator, _args: BuiltinFuncArgs) -> Builti
     Ok(Object::Empty)
 }
 
+#[builtin_func_desc(
+    desc="複数のクライアントを並行して処理するTCPサーバーをバックグラウンドで開始する",
+    rtype={desc="TCPサーバー",types="TCPサーバー"}
+    args=[
+        {n="IPアドレス",t="文字列",d="待ち受けIPアドレス"},
+        {n="ポート",t="数値",d="待ち受けポート、0なら空きポート"},
+        {n="ハンドラ",t="関数",d="接続ごとに別スレッドで呼ばれる関数、TCPクライアント, 接続元IPアドレス, 接続元ポートを受ける"},
+        {o,n="タイムアウト秒",t="数値",d="各接続の送受信タイムアウト秒、0なら無制限"},
+    ],
+)]
+pub fn tcp_server(evaluator: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let address = _args.get_as_string(0, None)?;
+    let port = _args.get_as_int(1, None)?;
+    let func = _args.get_as_user_function(2)?;
+    let timeout = _args.get_as_f64(3, Some(0.0))?;
+    let timeout = (timeout > 0.0).then(|| Duration::from_secs_f64(timeout));
+
+    let mut evaluator = evaluator.new_thread();
+    let factory = move || {
+        // 接続ごとに独立した評価環境を用意する
+        let mut evaluator = evaluator.new_thread();
+        let func = func.clone();
+        move |client: TcpClient, peer: std::net::SocketAddr| {
+            let Ok(_com) = Com::init() else {
+                return;
+            };
+            let values = vec![
+                Object::Socket(USocket::Tcp(client)),
+                Object::String(peer.ip().to_string()),
+                Object::Num(peer.port() as f64),
+            ];
+            if let Err(e) = func.invoke_with_values(&mut evaluator, values) {
+                evaluator.out_log(&e.errror_text_with_line(), LogType::Error);
+            }
+        }
+    };
+    let server = TcpServer::start(&address, port, timeout, factory)?;
+    Ok(Object::Socket(USocket::TcpServer(server)))
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, EnumString, EnumProperty, VariantNames, ToPrimitive, FromPrimitive)]
+pub enum TlsConst {
+    #[strum[props(desc="TLSで接続し、サーバー証明書を検証する")]]
+    TLS_VERIFY = 1,
+    #[strum[props(desc="TLSで接続し、サーバー証明書を検証しない")]]
+    TLS_INSECURE = 2,
+}
+
+#[builtin_func_desc(
+    desc="TCPサーバーに接続し、接続を維持するTCPクライアントを返す",
+    rtype={desc="TCPクライアント",types="TCPクライアント"}
+    args=[
+        {n="ホスト",t="文字列",d="接続先ホスト名またはIPアドレス"},
+        {n="ポート",t="数値",d="接続先ポート"},
+        {o,n="タイムアウト秒",t="数値",d="接続タイムアウト秒、送受信タイムアウトの初期値にもなる、0なら無制限"},
+        {o,n="TLS",t="真偽値または定数",d="TRUE(TLS_VERIFY)ならTLSで接続、TLS_INSECUREなら証明書を検証しない"},
+        {o,n="CA証明書",t="文字列",d="追加で信頼するCA証明書ファイルのパス (PEMまたはDER)"},
+    ],
+)]
+pub fn tcp_client(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let host = _args.get_as_string(0, None)?;
+    let port = _args.get_as_int(1, None)?;
+    let timeout = _args.get_as_f64(2, Some(10.0))?;
+    let tls = _args.get_as_bool_or_int::<u8>(3, Some(0))?;
+    let ca = _args.get_as_string_or_empty(4)?;
+
+    let timeout = (timeout > 0.0).then(|| Duration::from_secs_f64(timeout));
+    let tls = match tls {
+        0 => None,
+        n => {
+            let ca = match ca {
+                Some(path) => Some(std::fs::read(path)?),
+                None => None,
+            };
+            Some(TlsOptions { insecure: n == TlsConst::TLS_INSECURE as u8, ca })
+        },
+    };
+    let client = TcpClient::connect(&host, port, timeout, tls)?;
+    Ok(Object::Socket(USocket::Tcp(client)))
+}
+
+#[builtin_func_desc(
+    desc="TCPクライアントでデータを受信する",
+    rtype={desc="受信データ",types="バイト配列"}
+    args=[
+        {n="TCPクライアント",t="TCPクライアント",d="tcpclientで得たオブジェクト"},
+        {o,n="サイズ",t="数値",d="指定時はそのバイト数を受信するまで待つ、省略時は受信できたデータを返す"},
+    ],
+)]
+pub fn tcp_recv(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = _args.get_as_tcp(0)?;
+    let size = _args.get_as_int_or_empty::<usize>(1)?;
+    let data = match size {
+        Some(size) => client.recv_exact(size)?,
+        None => client.recv(TCP_RECV_MAX)?,
+    };
+    Ok(Object::ByteArray(data))
+}
+
+#[builtin_func_desc(
+    desc="TCPクライアントで区切りまでデータを受信する",
+    rtype={desc="区切りを含まない受信データ、受信データがないまま切断されたらEMPTY",types="バイト配列"}
+    args=[
+        {n="TCPクライアント",t="TCPクライアント",d="tcpclientで得たオブジェクト"},
+        {n="区切り",t="文字列またはバイト配列",d="受信データの区切り"},
+    ],
+)]
+pub fn tcp_recv_until(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = _args.get_as_tcp(0)?;
+    let delimiter = _args.get_as_bytearray(1)?;
+    if delimiter.is_empty() {
+        return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(Object::ByteArray(delimiter))));
+    }
+    let data = client.recv_until(&delimiter)?
+        .map(Object::ByteArray)
+        .unwrap_or_default();
+    Ok(data)
+}
+
+#[builtin_func_desc(
+    desc="TCPクライアントで1行受信する",
+    rtype={desc="改行を含まない受信文字列、受信データがないまま切断されたらEMPTY",types="文字列"}
+    args=[
+        {n="TCPクライアント",t="TCPクライアント",d="tcpclientで得たオブジェクト"},
+    ],
+)]
+pub fn tcp_recv_line(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = _args.get_as_tcp(0)?;
+    let line = client.recv_line()?
+        .map(Object::String)
+        .unwrap_or_default();
+    Ok(line)
+}
+
+#[builtin_func_desc(
+    desc="TCPクライアントの送受信タイムアウトを設定する",
+    args=[
+        {n="TCPクライアント",t="TCPクライアント",d="tcpclientで得たオブジェクト"},
+        {n="受信タイムアウト秒",t="数値",d="受信タイムアウト秒、0なら無制限"},
+        {o,n="送信タイムアウト秒",t="数値",d="送信タイムアウト秒、省略時は受信と同じ"},
+    ],
+)]
+pub fn tcp_timeout(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = _args.get_as_tcp(0)?;
+    let read = _args.get_as_f64(1, None)?;
+    let write = _args.get_as_f64(2, Some(read))?;
+    let to_duration = |sec: f64| (sec > 0.0).then(|| Duration::from_secs_f64(sec));
+    client.set_timeout(to_duration(read), to_duration(write))?;
+    Ok(Object::Empty)
+}
+
+#[builtin_func_desc(
+    desc="HTTPサーバーをバックグラウンドで開始する",
+    rtype={desc="HTTPサーバー",types="HTTPサーバー"}
+    args=[
+        {n="IPアドレス",t="文字列",d="待ち受けIPアドレス"},
+        {n="ポート",t="数値",d="待ち受けポート、0なら空きポート"},
+        {n="ルート",t="配列",d="[メソッド, パス, 関数] の配列、関数はHttpRequestを受けてレスポンスを返す"},
+        {o,n="静的ファイル",t="文字列",d="ルートに一致しないGETリクエストに対してファイルを返すディレクトリ"},
+        {o,n="最大ボディサイズ",t="数値",d="受け付けるリクエストボディの最大バイト数、超えたら413を返す、省略時は10MB"},
+        {o,n="最大ヘッダサイズ",t="数値",d="受け付けるリクエストヘッダの最大バイト数、超えたら431を返す、省略時は16KB"},
+    ],
+)]
+pub fn http_server(evaluator: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let address = _args.get_as_string(0, None)?;
+    let port = _args.get_as_int(1, None)?;
+    let routes = _args.get_as_array_or_empty(2)?.unwrap_or_default();
+    let static_dir = _args.get_as_string_or_empty(3)?.map(std::path::PathBuf::from);
+    let default = HttpLimits::default();
+    let limits = HttpLimits {
+        max_body: _args.get_as_int(4, Some(default.max_body))?,
+        max_header: _args.get_as_int(5, Some(default.max_header))?,
+    };
+
+    let mut router = Router::new();
+    for route in routes {
+        match route {
+            Object::Array(ref items) if items.len() == 3 => match &items[2] {
+                Object::Function(func) |
+                Object::AnonFunc(func) => {
+                    router.add(&items[0].to_string(), &items[1].to_string(), func.clone());
+                },
+                _ => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(route))),
+            },
+            _ => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(route))),
+        }
+    }
+
+    let mut evaluator = evaluator.new_thread();
+    let factory = move || {
+        // 接続ごとに独立した評価環境を用意する
+        let mut evaluator = evaluator.new_thread();
+        // COMは接続を処理するスレッドで初期化する
+        let mut com = None;
+        move |func: &Function, request: HttpRequest| {
+            if com.is_none() {
+                com = Com::init().ok();
+            }
+            match func.invoke_with_values(&mut evaluator, vec![Object::HttpRequest(request)]) {
+                Ok(obj) => HttpResponse::from_object(obj),
+                Err(e) => {
+                    evaluator.out_log(&e.errror_text_with_line(), LogType::Error);
+                    HttpResponse::status_only(500)
+                },
+            }
+        }
+    };
+    let server = HttpServer::start(&address, port, router, static_dir, limits, factory)?;
+    Ok(Object::Socket(USocket::HttpServer(server)))
+}
+
+#[builtin_func_desc(
+    desc="HTTPサーバーのハンドラが返すレスポンスを作る",
+    rtype={desc="レスポンス",types="HttpResponse"}
+    args=[
+        {n="ボディ",t="値",d="文字列、UObject、バイト配列のいずれか"},
+        {o,n="ステータス",t="数値",d="ステータスコード"},
+        {o,n="ヘッダ",t="連想配列またはUObject",d="レスポンスヘッダ"},
+    ],
+)]
+pub fn http_response(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let body = _args.get_as_object(0, Some(Object::Empty))?;
+    let status = _args.get_as_int::<u16>(1, Some(200))?;
+    let header = _args.get_as_object_or_empty(2)?;
+
+    let mut response = match body {
+        Object::Empty | Object::EmptyParam => HttpResponse::new(status, None, vec![]),
+        body => HttpResponse::from_object(body),
+    };
+    response.status = status;
+    for (key, value) in header_pairs(header)? {
+        response.set_header(&key, &value);
+    }
+    Ok(Object::HttpResponse(response))
+}
+
+/// 連想配列またはUObjectをヘッダ名と値の組にする
+fn header_pairs(header: Option<Object>) -> BuiltInResult<Vec<(String, String)>> {
+    let pairs = match header {
+        Some(Object::HashTbl(hash)) => {
+            let hash = hash.lock().unwrap();
+            hash.map().into_iter()
+                .map(|(key, value)| (key, value.to_string()))
+                .collect()
+        },
+        Some(Object::UObject(uo)) => {
+            let json = uo.to_json_string().map_err(UError::from)?;
+            match serde_json::from_str(&json) {
+                Ok(serde_json::Value::Object(map)) => map.into_iter()
+                    .map(|(key, value)| match value {
+                        serde_json::Value::String(s) => (key, s),
+                        v => (key, v.to_string()),
+                    })
+                    .collect(),
+                _ => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(Object::UObject(uo)))),
+            }
+        },
+        Some(o) => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(o))),
+        None => vec![],
+    };
+    Ok(pairs)
+}
+
 #[builtin_func_desc(
     desc="WebSocketセッションを張る",
     rtype={desc="WebSocketオブジェクト",types="WebSocket"}
     args=[
         {n="wsuri",t="文字列",d="ws:// から始まるURI"},
+        {o,n="ヘッダ",t="連想配列またはUObject",d="接続時に追加するリクエストヘッダ"},
+        {o,n="サブプロトコル",t="文字列または配列",d="要求するサブプロトコル"},
     ],
 )]
 pub fn websocket(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let uri = _args.get_as_string(0, None)?;
-    let ws = WebSocket::new(&uri)?;
+    let headers = header_pairs(_args.get_as_object_or_empty(1)?)?;
+    let protocols = _args.get_as_string_array_or_empty(2)?.unwrap_or_default();
+    let ws = WebSocket::connect(&uri, &headers, &protocols)?;
     Ok(Object::Socket(USocket::WebSocket(ws)))
 }
 
@@ -205,16 +631,9 @@ impl From<WebSocketConst> for Object {
     }
 }
 
-#[builtin_func_desc(
-    desc="WebSocketでデータを送信する",
-    args=[
-        {n="WebSocket",t="WebSocket",d="WebSocketオブジェクト"},
-        {n="送信データ",t="値",d="送信するデータ"},
-    ],
-)]
-pub fn ws_send(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
-    let ws = _args.get_as_websocket(0)?;
-    let message = match _args.get_as_object(1, None)? {
+/// 送信データをWebSocketのメッセージにする
+fn object_to_message(obj: Object) -> BuiltInResult<Message> {
+    let message = match obj {
         Object::Num(n) if [0, 1, 2].contains(&(n as i32)) => {
             match n as i32 {
                 0 => Message::Close(None),
@@ -232,26 +651,505 @@ pub fn ws_send(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
         }
         o => Message::Text(o.to_string()),
     };
+    Ok(message)
+}
+/// 受信したメッセージを値にする
+fn message_to_object(message: Message) -> Object {
+    match message {
+        Message::Text(s) => Object::String(s),
+        Message::Binary(bytes) => Object::ByteArray(bytes),
+        Message::Ping(_) => WebSocketConst::WS_PING.into(),
+        Message::Pong(_) => WebSocketConst::WS_PONG.into(),
+        Message::Close(_) => WebSocketConst::WS_CLOSE.into(),
+        Message::Frame(_) => Object::Empty,
+    }
+}
+
+#[builtin_func_desc(
+    desc="WebSocketでデータを送信する",
+    args=[
+        {n="WebSocket",t="WebSocket",d="WebSocketオブジェクト"},
+        {n="送信データ",t="値",d="送信するデータ"},
+    ],
+)]
+pub fn ws_send(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let ws = _args.get_as_websocket(0)?;
+    let message = object_to_message(_args.get_as_object(1, None)?)?;
     ws.send(message)?;
     Ok(Object::Empty)
 }
 
 #[builtin_func_desc(
-    desc="",
-    rtype={desc="受信データ",types="文字列、バイト配列、定数"}
+    desc="WebSocketでデータを受信する",
+    rtype={desc="受信データ、タイムアウトした場合はEMPTY",types="文字列、バイト配列、定数"}
     args=[
         {n="WebSocket",t="WebSocket",d="WebSocketオブジェクト"},
+        {o,n="タイムアウト秒",t="数値",d="受信を待つ秒数、0なら受信済みのデータのみ確認する、省略時は受信するまで待つ"},
     ],
 )]
 pub fn ws_recv(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let ws = _args.get_as_websocket(0)?;
-    let message = ws.receive()?;
-    match message {
-        Message::Text(s) => Ok(Object::String(s)),
-        Message::Binary(bytes) => Ok(Object::ByteArray(bytes)),
-        Message::Ping(_) => Ok(WebSocketConst::WS_PING.into()),
-        Message::Pong(_) => Ok(WebSocketConst::WS_PONG.into()),
-        Message::Close(_) => Ok(WebSocketConst::WS_CLOSE.into()),
-        Message::Frame(_) => Ok(Object::Empty),
+    // 省略時は負の値として受信するまで待つ
+    let timeout = _args.get_as_f64(1, Some(-1.0))?;
+    let timeout = (timeout >= 0.0).then(|| Duration::from_secs_f64(timeout));
+    let message = ws.receive(timeout)?
+        .map(message_to_object)
+        .unwrap_or_default();
+    Ok(message)
+}
+
+#[builtin_func_desc(
+    desc="WebSocketの受信時に呼ばれる関数をバックグラウンドで実行する",
+    args=[
+        {n="WebSocket",t="WebSocket",d="WebSocketオブジェクト"},
+        {o,n="関数",t="関数",d="受信データとWebSocketを受ける関数、省略時は登録済みの関数を解除する"},
+    ],
+)]
+pub fn ws_on_message(evaluator: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let ws = _args.get_as_websocket(0)?;
+    let func = match _args.get_as_object_or_empty(1)? {
+        Some(Object::Function(func)) |
+        Some(Object::AnonFunc(func)) => Some(func),
+        Some(o) => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(o))),
+        None => None,
+    };
+    // 以前に登録された関数はこれで終了する
+    let id = ws.new_listener();
+    if let Some(func) = func {
+        let mut evaluator = evaluator.new_thread();
+        std::thread::spawn(move || {
+            let Ok(_com) = Com::init() else {
+                return;
+            };
+            let socket = Object::Socket(USocket::WebSocket(ws.clone()));
+            ws.listen(id, |message| {
+                let values = vec![message_to_object(message), socket.clone()];
+                if let Err(e) = func.invoke_with_values(&mut evaluator, values) {
+                    evaluator.out_log(&e.errror_text_with_line(), LogType::Error);
+                }
+            });
+        });
+    }
+    Ok(Object::Empty)
+}
+
+#[builtin_func_desc(
+    desc="WebSocketサーバーをバックグラウンドで開始する",
+    rtype={desc="WebSocketサーバー",types="WebSocketサーバー"}
+    args=[
+        {n="IPアドレス",t="文字列",d="待ち受けIPアドレス"},
+        {n="ポート",t="数値",d="待ち受けポート、0なら空きポート"},
+        {n="ハンドラ",t="関数",d="接続ごとに別スレッドで呼ばれる関数、WebSocket, 接続元IPアドレス, 接続元ポートを受ける"},
+    ],
+)]
+pub fn ws_server(evaluator: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let address = _args.get_as_string(0, None)?;
+    let port = _args.get_as_int(1, None)?;
+    let func = _args.get_as_user_function(2)?;
+
+    let mut evaluator = evaluator.new_thread();
+    let factory = move || {
+        // 接続ごとに独立した評価環境を用意する
+        let mut evaluator = evaluator.new_thread();
+        let func = func.clone();
+        move |ws: WebSocket, peer: std::net::SocketAddr| {
+            let Ok(_com) = Com::init() else {
+                return;
+            };
+            let values = vec![
+                Object::Socket(USocket::WebSocket(ws)),
+                Object::String(peer.ip().to_string()),
+                Object::Num(peer.port() as f64),
+            ];
+            if let Err(e) = func.invoke_with_values(&mut evaluator, values) {
+                evaluator.out_log(&e.errror_text_with_line(), LogType::Error);
+            }
+        }
+    };
+    let server = WebSocketServer::start(&address, port, factory)?;
+    Ok(Object::Socket(USocket::WebSocketServer(server)))
+}
+
+#[builtin_func_desc(
+    desc="WebSocketサーバーに接続中のすべてのクライアントにデータを送信する",
+    rtype={desc="送信できたクライアント数",types="数値"}
+    args=[
+        {n="WebSocketサーバー",t="WebSocketサーバー",d="wsserverで得たオブジェクト"},
+        {n="送信データ",t="値",d="送信するデータ"},
+    ],
+)]
+pub fn ws_broadcast(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let server = match _args.get_as_socket(0)? {
+        USocket::WebSocketServer(server) => server,
+        socket => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(Object::Socket(socket)))),
+    };
+    let message = object_to_message(_args.get_as_object(1, None)?)?;
+    let count = server.broadcast(message);
+    Ok(count.into())
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, EnumString, EnumProperty, VariantNames, ToPrimitive, FromPrimitive)]
+pub enum MailProtocolConst {
+    #[strum[props(desc="SMTPでメールを送信する")]]
+    MAIL_SMTP = 1,
+    #[strum[props(desc="IMAPでメールを受信する")]]
+    MAIL_IMAP = 2,
+    #[strum[props(desc="POP3でメールを受信する")]]
+    MAIL_POP3 = 3,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, EnumString, EnumProperty, VariantNames, ToPrimitive, FromPrimitive)]
+pub enum MailSecurityConst {
+    #[strum[props(desc="暗号化しない")]]
+    MAIL_NOTLS = 0,
+    #[strum[props(desc="平文で接続後にSTARTTLSで暗号化する")]]
+    MAIL_STARTTLS = 1,
+    #[strum[props(desc="接続時からTLSで暗号化する")]]
+    MAIL_TLS = 2,
+    #[strum[props(desc="OR連結でサーバー証明書を検証しない")]]
+    MAIL_INSECURE = 4,
+}
+
+#[builtin_func_desc(
+    desc="メールサーバーへの接続設定を返す、接続は送受信のたびに行う",
+    rtype={desc="メールクライアント",types="メールクライアント"}
+    args=[
+        {n="プロトコル",t="定数",d="MAIL_SMTP, MAIL_IMAP, MAIL_POP3のいずれか"},
+        {n="ホスト",t="文字列",d="メールサーバーのホスト名"},
+        {o,n="ポート",t="数値",d="接続先ポート、0ならプロトコルと暗号化方式の既定ポート"},
+        {o,n="暗号化",t="定数",d="MAIL_TLS, MAIL_STARTTLS, MAIL_NOTLSのいずれか、MAIL_INSECUREをOR連結すると証明書を検証しない"},
+        {o,n="ユーザー",t="文字列",d="認証するユーザー名、省略時は認証しない"},
+        {o,n="パスワード",t="文字列",d="認証のパスワード"},
+        {o,n="タイムアウト秒",t="数値",d="接続と送受信のタイムアウト秒、0なら無制限"},
+    ],
+)]
+pub fn mail_client(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let protocol = match _args.get_as_int::<u8>(0, None)? {
+        n if n == MailProtocolConst::MAIL_SMTP as u8 => MailProtocol::Smtp,
+        n if n == MailProtocolConst::MAIL_IMAP as u8 => MailProtocol::Imap,
+        n if n == MailProtocolConst::MAIL_POP3 as u8 => MailProtocol::Pop3,
+        n => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(n.into()))),
+    };
+    let host = _args.get_as_string(1, None)?;
+    let port = _args.get_as_int::<u16>(2, Some(0))?;
+    let security = _args.get_as_int::<u8>(3, Some(MailSecurityConst::MAIL_TLS as u8))?;
+    let user = _args.get_as_string_or_empty(4)?;
+    let password = _args.get_as_string_or_empty(5)?;
+    let timeout = _args.get_as_f64(6, Some(30.0))?;
+
+    let insecure = (security & MailSecurityConst::MAIL_INSECURE as u8) > 0;
+    let security = match security & !(MailSecurityConst::MAIL_INSECURE as u8) {
+        n if n == MailSecurityConst::MAIL_TLS as u8 => MailSecurity::Tls,
+        n if n == MailSecurityConst::MAIL_STARTTLS as u8 => MailSecurity::StartTls,
+        _ => MailSecurity::None,
+    };
+    let mut client = MailClient::new(protocol, &host, port, security);
+    client.insecure = insecure;
+    client.user = user;
+    client.password = password;
+    client.timeout = (timeout > 0.0).then(|| Duration::from_secs_f64(timeout));
+    Ok(Object::Socket(USocket::Mail(client)))
+}
+
+/// 指定プロトコルのメールクライアントを受ける
+fn get_mail_client(args: &BuiltinFuncArgs, i: usize, smtp: bool) -> BuiltInResult<MailClient> {
+    match args.get_as_socket(i)? {
+        USocket::Mail(client) if (client.protocol == MailProtocol::Smtp) == smtp => Ok(client),
+        socket => Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(Object::Socket(socket)))),
+    }
+}
+/// 文字列をカンマで区切ってアドレスのリストにする
+fn mail_addresses(addresses: Vec<String>) -> Vec<String> {
+    addresses.iter()
+        .flat_map(|a| a.split(','))
+        .map(|a| a.trim().to_string())
+        .filter(|a| ! a.is_empty())
+        .collect()
+}
+/// ファイルパス、または [ファイル名, データ, MIMEタイプ] を添付ファイルにする
+fn mail_attachment(obj: Object) -> BuiltInResult<Attachment> {
+    match obj {
+        Object::Array(ref items) if (2..=3).contains(&items.len()) => {
+            let filename = items[0].to_string();
+            let Some(data) = items[1].as_bytearray() else {
+                return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(obj)));
+            };
+            let content_type = match items.get(2) {
+                Some(mime) => mime.to_string(),
+                None => content_type(std::path::Path::new(&filename)).to_string(),
+            };
+            Ok(Attachment { filename, content_type, data })
+        },
+        Object::Array(_) => Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(obj))),
+        obj => {
+            let path = std::path::PathBuf::from(obj.to_string());
+            let data = std::fs::read(&path)?;
+            let filename = path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            Ok(Attachment { filename, content_type: content_type(&path).to_string(), data })
+        },
+    }
+}
+
+#[builtin_func_desc(
+    desc="SMTPでメールを送信する",
+    args=[
+        {n="メールクライアント",t="メールクライアント",d="MAIL_SMTPを指定したmailclientで得たオブジェクト"},
+        {n="差出人",t="文字列",d="差出人のアドレス、名前 <アドレス> の形式も可"},
+        {n="宛先",t="文字列または配列",d="宛先のアドレス、文字列ならカンマ区切りで複数指定できる"},
+        {n="件名",t="文字列",d="件名"},
+        {n="本文",t="文字列",d="テキストの本文"},
+        {o,n="HTML本文",t="文字列",d="HTMLの本文、指定時はテキストの本文と両方を送る"},
+        {o,n="添付ファイル",t="配列",d="ファイルパス、または [ファイル名, バイト配列, MIMEタイプ(省略可)] の配列"},
+        {o,n="Cc",t="文字列または配列",d="Ccのアドレス"},
+        {o,n="Bcc",t="文字列または配列",d="Bccのアドレス"},
+    ],
+)]
+pub fn mail_send(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = get_mail_client(&_args, 0, true)?;
+    let from = _args.get_as_string(1, None)?;
+    let to = mail_addresses(_args.get_as_string_array(2)?);
+    let subject = _args.get_as_string(3, None)?;
+    let text = _args.get_as_string(4, None)?;
+    let html = _args.get_as_string_or_empty(5)?;
+    let attachments = _args.get_as_array_or_empty(6)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(mail_attachment)
+        .collect::<BuiltInResult<Vec<_>>>()?;
+    let cc = mail_addresses(_args.get_as_string_array_or_empty(7)?.unwrap_or_default());
+    let bcc = mail_addresses(_args.get_as_string_array_or_empty(8)?.unwrap_or_default());
+
+    let mail = OutgoingMail { from, to, cc, bcc, subject, text, html, attachments };
+    client.send(&mail)?;
+    Ok(Object::Empty)
+}
+
+#[builtin_func_desc(
+    desc="IMAPまたはPOP3でメールを受信する、受信したメールはサーバーに残る",
+    rtype={desc="新しい順のメールを示すUObjectの配列",types="配列"}
+    args=[
+        {n="メールクライアント",t="メールクライアント",d="MAIL_IMAPまたはMAIL_POP3を指定したmailclientで得たオブジェクト"},
+        {o,n="件数",t="数値",d="受信する最大件数、0なら全件"},
+        {o,n="メールボックス",t="文字列",d="IMAPで受信するメールボックス"},
+        {o,n="未読のみ",t="真偽値",d="TRUEなら未読のメールのみ受信する (IMAPのみ)、既読にはしない"},
+        {o,n="保存先",t="文字列",d="添付ファイルを保存するディレクトリ"},
+    ],
+)]
+pub fn mail_fetch(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = get_mail_client(&_args, 0, false)?;
+    let count = _args.get_as_int::<usize>(1, Some(10))?;
+    let mailbox = _args.get_as_string(2, Some("INBOX".into()))?;
+    let unseen_only = _args.get_as_bool(3, Some(false))?;
+    let save_dir = _args.get_as_string_or_empty(4)?.map(std::path::PathBuf::from);
+
+    let mut mails = vec![];
+    for (id, raw) in client.fetch(count, &mailbox, unseen_only)? {
+        let mail = ReceivedMail::parse(&raw);
+        let id = id.parse::<u64>()
+            .map(serde_json::Value::from)
+            .unwrap_or_else(|_| serde_json::Value::from(id));
+        let mut json = mail.to_json(id);
+        if let Some(dir) = &save_dir {
+            std::fs::create_dir_all(dir)?;
+            if let Some(items) = json["attachments"].as_array_mut() {
+                for (attachment, item) in mail.attachments.iter().zip(items) {
+                    let path = attachment.save(dir)?;
+                    item["path"] = path.to_string_lossy().into();
+                }
+            }
+        }
+        mails.push(Object::UObject(UObject::from(json)));
+    }
+    Ok(Object::Array(mails))
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, EnumString, EnumProperty, VariantNames, ToPrimitive, FromPrimitive)]
+pub enum MqttConst {
+    #[strum[props(desc="MQTT 3.1.1で接続する")]]
+    MQTT_V311 = 4,
+    #[strum[props(desc="MQTT 5で接続する")]]
+    MQTT_V5 = 5,
+}
+
+#[builtin_func_desc(
+    desc="MQTTブローカーに接続し、MQTTクライアントを返す",
+    rtype={desc="MQTTクライアント",types="MQTTクライアント"}
+    args=[
+        {n="ホスト",t="文字列",d="ブローカーのホスト名またはIPアドレス"},
+        {o,n="ポート",t="数値",d="接続先ポート、0ならTLSの有無により1883または8883"},
+        {o,n="クライアントID",t="文字列",d="クライアントを識別するID、省略時はランダムに決める"},
+        {o,n="ユーザー",t="文字列",d="認証するユーザー名"},
+        {o,n="パスワード",t="文字列",d="認証のパスワード"},
+        {o,n="TLS",t="真偽値または定数",d="TRUE(TLS_VERIFY)ならTLSで接続、TLS_INSECUREなら証明書を検証しない"},
+        {o,n="バージョン",t="定数",d="MQTT_V311またはMQTT_V5"},
+        {o,n="キープアライブ秒",t="数値",d="無通信時にPINGを送る間隔、0なら送らない"},
+    ],
+)]
+pub fn mqtt_client(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let host = _args.get_as_string(0, None)?;
+    let port = _args.get_as_int::<u16>(1, Some(0))?;
+    let client_id = _args.get_as_string(2, Some(String::new()))?;
+    let user = _args.get_as_string_or_empty(3)?;
+    let password = _args.get_as_string_or_empty(4)?;
+    let tls = _args.get_as_bool_or_int::<u8>(5, Some(0))?;
+    let version = match _args.get_as_int::<u8>(6, Some(MqttConst::MQTT_V311 as u8))? {
+        n if n == MqttConst::MQTT_V311 as u8 || n == MqttConst::MQTT_V5 as u8 => n,
+        n => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(n.into()))),
+    };
+    let keep_alive = _args.get_as_int::<u16>(7, Some(60))?;
+
+    let tls = (tls > 0).then(|| TlsOptions { insecure: tls == TlsConst::TLS_INSECURE as u8, ca: None });
+    let port = match (port, &tls) {
+        (0, Some(_)) => 8883,
+        (0, None) => 1883,
+        (port, _) => port,
+    };
+    let options = MqttOptions {
+        client_id,
+        user,
+        password,
+        version,
+        keep_alive,
+        tls,
+        timeout: Some(Duration::from_secs(MQTT_TIMEOUT)),
+    };
+    let client = MqttClient::connect(&host, port, options)?;
+    Ok(Object::Socket(USocket::Mqtt(client)))
+}
+
+/// 送信する値をMQTTのペイロードにする
+fn mqtt_payload(obj: Object) -> BuiltInResult<Vec<u8>> {
+    let payload = match obj {
+        Object::String(s) => s.into_bytes(),
+        Object::UObject(uo) => uo.to_json_string().map_err(UError::from)?.into_bytes(),
+        Object::ByteArray(bytes) => bytes,
+        o if matches!(o, Object::Array(_)) => match o.as_bytearray() {
+            Some(bytes) => bytes,
+            None => Err(BuiltinFuncError::new(UErrorMessage::Any("can not convert array to byte array".into())))?,
+        },
+        o => o.to_string().into_bytes(),
+    };
+    Ok(payload)
+}
+/// 受信したペイロードを値にする、UTF-8でなければバイト配列
+fn mqtt_message_to_object(payload: Vec<u8>) -> Object {
+    match String::from_utf8(payload) {
+        Ok(s) => Object::String(s),
+        Err(e) => Object::ByteArray(e.into_bytes()),
+    }
+}
+
+#[builtin_func_desc(
+    desc="MQTTでメッセージを発行する、QoS 1以上ならブローカーの応答を待つ",
+    args=[
+        {n="MQTTクライアント",t="MQTTクライアント",d="mqttclientで得たオブジェクト"},
+        {n="トピック",t="文字列",d="発行先のトピック"},
+        {n="ペイロード",t="値",d="送信するデータ、文字列やUObjectはUTF-8で送る"},
+        {o,n="QoS",t="数値",d="0, 1, 2のいずれか"},
+        {o,n="保持",t="真偽値",d="TRUEならブローカーにメッセージを保持させる"},
+    ],
+)]
+pub fn mqtt_publish(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = _args.get_as_mqtt(0)?;
+    let topic = _args.get_as_string(1, None)?;
+    let payload = mqtt_payload(_args.get_as_object(2, None)?)?;
+    let qos = _args.get_as_int::<u8>(3, Some(0))?.min(2);
+    let retain = _args.get_as_bool(4, Some(false))?;
+    client.publish(&topic, &payload, qos, retain)?;
+    Ok(Object::Empty)
+}
+
+#[builtin_func_desc(
+    desc="MQTTのトピックを購読する、関数を省略した場合はmqttrecvで受信する",
+    rtype={desc="ブローカーが許可したQoSの配列",types="配列"}
+    args=[
+        {n="MQTTクライアント",t="MQTTクライアント",d="mqttclientで得たオブジェクト"},
+        {n="トピック",t="文字列または配列",d="購読するトピックフィルタ、+ と # のワイルドカードが使える"},
+        {o,n="QoS",t="数値",d="0, 1, 2のいずれか"},
+        {o,n="関数",t="関数",d="トピック, ペイロード, MQTTクライアントを受ける関数、受信ごとにバックグラウンドで呼ばれる"},
+    ],
+)]
+pub fn mqtt_subscribe(evaluator: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = _args.get_as_mqtt(0)?;
+    let filters = _args.get_as_string_array(1)?;
+    let qos = _args.get_as_int::<u8>(2, Some(0))?.min(2);
+    let func = match _args.get_as_object_or_empty(3)? {
+        Some(Object::Function(func)) |
+        Some(Object::AnonFunc(func)) => Some(func),
+        Some(o) => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(o))),
+        None => None,
+    };
+    // 購読直後に届く保持メッセージも受けられるよう先に登録する
+    if let Some(func) = func {
+        let receiver = client.route(&filters);
+        let mut evaluator = evaluator.new_thread();
+        // 強参照を持つとroutesの送り先と循環してクライアントが破棄されなくなる
+        let weak = client.downgrade();
+        std::thread::spawn(move || {
+            let Ok(_com) = Com::init() else {
+                return;
+            };
+            // 購読解除か切断、クライアントの破棄で終了する
+            for message in receiver {
+                let Some(client) = weak.upgrade() else {
+                    break;
+                };
+                let values = vec![
+                    Object::String(message.topic),
+                    mqtt_message_to_object(message.payload),
+                    Object::Socket(USocket::Mqtt(client)),
+                ];
+                if let Err(e) = func.invoke_with_values(&mut evaluator, values) {
+                    evaluator.out_log(&e.errror_text_with_line(), LogType::Error);
+                }
+            }
+        });
     }
-}
\ No newline at end of file
+    let granted = client.subscribe(&filters, qos)
+        .inspect_err(|_| client.unroute(&filters))?
+        .into_iter()
+        .map(Object::from)
+        .collect();
+    Ok(Object::Array(granted))
+}
+
+#[builtin_func_desc(
+    desc="MQTTのトピックの購読を解除する、登録した関数も終了する",
+    args=[
+        {n="MQTTクライアント",t="MQTTクライアント",d="mqttclientで得たオブジェクト"},
+        {n="トピック",t="文字列または配列",d="購読時に指定したトピックフィルタ"},
+    ],
+)]
+pub fn mqtt_unsubscribe(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = _args.get_as_mqtt(0)?;
+    let filters = _args.get_as_string_array(1)?;
+    client.unsubscribe(&filters)?;
+    Ok(Object::Empty)
+}
+
+#[builtin_func_desc(
+    desc="関数を指定せずに購読したトピックのメッセージを受信する",
+    rtype={desc="トピックとペイロードの配列、タイムアウトした場合はEMPTY",types="[文字列, 文字列またはバイト配列]"}
+    args=[
+        {n="MQTTクライアント",t="MQTTクライアント",d="mqttclientで得たオブジェクト"},
+        {o,n="タイムアウト秒",t="数値",d="受信を待つ秒数、0なら受信済みのメッセージのみ確認する、省略時は受信するまで待つ"},
+    ],
+)]
+pub fn mqtt_recv(_: &mut Evaluator, _args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let client = _args.get_as_mqtt(0)?;
+    // 省略時は負の値として受信するまで待つ
+    let timeout = _args.get_as_f64(1, Some(-1.0))?;
+    let timeout = (timeout >= 0.0).then(|| Duration::from_secs_f64(timeout));
+    let message = client.receive(timeout)?
+        .map(|message| Object::Array(vec![
+            Object::String(message.topic),
+            mqtt_message_to_object(message.payload),
+        ]))
+        .unwrap_or_default();
+    Ok(message)
+}
@@ -0,0 +1,676 @@
+//! スクリプトから利用する簡易HTTPサーバー
+//!
+//! TcpServerの上でHTTP/1.1の解析とルーティング、静的ファイルの配信を行う
+
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{SocketResult, TcpClient, TcpServer};
+use crate::object::{Object, UObject, HashTbl};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+
+/// キープアライブ中の接続を待つ時間
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(30);
+/// 受け付けるヘッダの最大数
+const MAX_HEADERS: usize = 64;
+/// チャンクサイズの行の最大長
+const MAX_CHUNK_LINE: usize = 1024;
+
+/// 受け付けるリクエストの大きさの上限
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HttpLimits {
+    /// リクエスト行とヘッダの合計バイト数、超えたら431を返す
+    pub max_header: usize,
+    /// ボディのバイト数、超えたら413を返す
+    pub max_body: usize,
+}
+impl Default for HttpLimits {
+    fn default() -> Self {
+        Self {
+            max_header: 16 * 1024,
+            max_body: 10 * 1024 * 1024,
+        }
+    }
+}
+
+fn bad_request(msg: impl std::fmt::Display) -> UError {
+    UError::new(UErrorKind::SocketError, UErrorMessage::Any(msg.to_string()))
+}
+
+/// 受信したHTTPリクエスト
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    /// デコード済みのパス
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// ルートのパターンで得たパラメータ
+    pub params: Vec<(String, String)>,
+    pub remote: SocketAddr,
+}
+impl std::fmt::Display for HttpRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HttpRequest[{} {}]", self.method, self.path)
+    }
+}
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    /// 応答後も接続を維持するかどうか
+    fn keep_alive(&self, version: u8) -> bool {
+        match self.header("connection") {
+            Some(value) if value.eq_ignore_ascii_case("close") => false,
+            Some(value) if value.eq_ignore_ascii_case("keep-alive") => true,
+            _ => version >= 1,
+        }
+    }
+    /// 接続からリクエストを一つ読む、リクエストが来る前に切断されたらNone
+    fn read(client: &TcpClient, remote: SocketAddr, limits: HttpLimits) -> SocketResult<Option<(Self, u8)>> {
+        let head = client.recv_until_limit(b"\r\n\r\n", limits.max_header)
+            .map_err(|e| match e.message {
+                UErrorMessage::SocketDataTooLarge => UError::new(UErrorKind::SocketError, UErrorMessage::HttpHeaderTooLarge(limits.max_header)),
+                _ => e,
+            })?;
+        let Some(mut head) = head else {
+            return Ok(None);
+        };
+        head.extend_from_slice(b"\r\n\r\n");
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut parsed = httparse::Request::new(&mut headers);
+        match parsed.parse(&head) {
+            Ok(httparse::Status::Complete(_)) => {},
+            Ok(httparse::Status::Partial) => return Err(bad_request("incomplete request")),
+            Err(e) => return Err(bad_request(e)),
+        }
+        let method = parsed.method.unwrap_or_default().to_ascii_uppercase();
+        let version = parsed.version.unwrap_or(1);
+        let target = parsed.path.unwrap_or("/");
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (target, ""),
+        };
+        let path = urlencoding::decode(path)
+            .map_err(bad_request)?
+            .to_string();
+        let query = url::form_urlencoded::parse(query.as_bytes())
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let headers = parsed.headers.iter()
+            .map(|h| (h.name.to_string(), String::from_utf8_lossy(h.value).to_string()))
+            .collect();
+        let mut request = Self { method, path, query, headers, body: vec![], params: vec![], remote };
+        request.body = request.read_body(client, limits.max_body)?;
+        Ok(Some((request, version)))
+    }
+    fn read_body(&self, client: &TcpClient, max_body: usize) -> SocketResult<Vec<u8>> {
+        let too_large = || UError::new(UErrorKind::SocketError, UErrorMessage::HttpBodyTooLarge(max_body));
+        let chunked = self.header("transfer-encoding")
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+        if chunked {
+            let mut body = vec![];
+            loop {
+                let line = client.recv_until_limit(b"\r\n", MAX_CHUNK_LINE)?
+                    .ok_or_else(|| bad_request("incomplete chunk"))?;
+                let line = String::from_utf8_lossy(&line);
+                let size = line.split(';').next().unwrap_or_default().trim();
+                let size = usize::from_str_radix(size, 16).map_err(bad_request)?;
+                if size == 0 {
+                    // トレーラーを読み捨てる
+                    while client.recv_until_limit(b"\r\n", MAX_CHUNK_LINE)?.is_some_and(|l| ! l.is_empty()) {}
+                    break;
+                }
+                if size > max_body - body.len() {
+                    return Err(too_large());
+                }
+                body.extend(client.recv_exact(size)?);
+                client.recv_exact(2)?;
+            }
+            Ok(body)
+        } else {
+            match self.header("content-length") {
+                Some(len) => {
+                    let len = len.trim().parse::<usize>().map_err(bad_request)?;
+                    if len > max_body {
+                        return Err(too_large());
+                    }
+                    client.recv_exact(len)
+                },
+                None => Ok(vec![]),
+            }
+        }
+    }
+
+    fn pairs_to_uobject(pairs: &[(String, String)]) -> Object {
+        let map = pairs.iter()
+            .map(|(key, value)| (key.clone(), serde_json::Value::String(value.clone())))
+            .collect::<serde_json::Map<_, _>>();
+        Object::UObject(UObject::from(serde_json::Value::Object(map)))
+    }
+    pub fn get_property(&self, name: &str) -> SocketResult<Object> {
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "method" => self.method.as_str().into(),
+            "path" => self.path.as_str().into(),
+            "params" => Self::pairs_to_uobject(&self.params),
+            "query" => Self::pairs_to_uobject(&self.query),
+            "header" => {
+                let mut header = HashTbl::new(false, false);
+                for (key, value) in &self.headers {
+                    header.insert(key.clone(), value.as_str().into());
+                }
+                Object::HashTbl(Arc::new(Mutex::new(header)))
+            },
+            "body" => String::from_utf8_lossy(&self.body).to_string().into(),
+            "bytes" => Object::ByteArray(self.body.clone()),
+            "json" => {
+                let body = String::from_utf8_lossy(&self.body);
+                UObject::from_json_str(&body)
+                    .map(Object::UObject)
+                    .unwrap_or_default()
+            },
+            "remote" => self.remote.to_string().into(),
+            _ => return Err(UError::new(UErrorKind::SocketError, UErrorMessage::InvalidMember(name.to_string()))),
+        };
+        Ok(obj)
+    }
+    pub fn invoke_method(&self, name: &str, _args: Vec<Object>) -> SocketResult<Object> {
+        Err(UError::new(UErrorKind::SocketError, UErrorMessage::InvalidMember(name.to_string())))
+    }
+}
+
+/// ハンドラが返すHTTPレスポンス
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+impl std::fmt::Display for HttpResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HttpResponse[{}]", self.status)
+    }
+}
+impl HttpResponse {
+    pub fn new(status: u16, content_type: Option<&str>, body: Vec<u8>) -> Self {
+        let headers = content_type
+            .map(|ct| vec![("Content-Type".to_string(), ct.to_string())])
+            .unwrap_or_default();
+        Self { status, headers, body }
+    }
+    fn text(status: u16, text: &str) -> Self {
+        Self::new(status, Some("text/plain; charset=utf-8"), text.as_bytes().to_vec())
+    }
+    /// ステータスコードのみのレスポンス
+    pub fn status_only(status: u16) -> Self {
+        let reason = reason_phrase(status);
+        match status {
+            204 | 304 => Self::new(status, None, vec![]),
+            _ => Self::text(status, reason),
+        }
+    }
+    /// 同名のヘッダがあれば置き換える
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.retain(|(key, _)| ! key.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+    fn has_header(&self, name: &str) -> bool {
+        self.headers.iter().any(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+    /// ハンドラの戻り値をレスポンスにする
+    /// - 文字列: text/plain
+    /// - UObject: application/json
+    /// - バイト配列: application/octet-stream
+    /// - 数値: ステータスコードのみ
+    /// - EMPTY: 204 No Content
+    pub fn from_object(obj: Object) -> Self {
+        match obj {
+            Object::HttpResponse(res) => res,
+            Object::String(s) => Self::text(200, &s),
+            Object::UObject(uo) => {
+                let json = uo.to_json_string().unwrap_or_default();
+                Self::new(200, Some("application/json; charset=utf-8"), json.into_bytes())
+            },
+            Object::ByteArray(bytes) => Self::new(200, Some("application/octet-stream"), bytes),
+            Object::Num(n) => Self::status_only(n as u16),
+            Object::Empty |
+            Object::EmptyParam |
+            Object::Null |
+            Object::Nothing => Self::status_only(204),
+            o => Self::text(200, &o.to_string()),
+        }
+    }
+    fn write(&self, client: &TcpClient, head_only: bool, keep_alive: bool) -> SocketResult<()> {
+        let mut data = format!("HTTP/1.1 {} {}\r\n", self.status, reason_phrase(self.status));
+        for (key, value) in &self.headers {
+            data.push_str(&format!("{key}: {value}\r\n"));
+        }
+        if ! self.has_header("content-length") {
+            data.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        let connection = if keep_alive {"keep-alive"} else {"close"};
+        data.push_str(&format!("Connection: {connection}\r\n\r\n"));
+        let mut data = data.into_bytes();
+        if ! head_only {
+            data.extend_from_slice(&self.body);
+        }
+        client.write(&data)?;
+        Ok(())
+    }
+
+    pub fn get_property(&self, name: &str) -> SocketResult<Object> {
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "status" => (self.status as f64).into(),
+            "header" => {
+                let mut header = HashTbl::new(false, false);
+                for (key, value) in &self.headers {
+                    header.insert(key.clone(), value.as_str().into());
+                }
+                Object::HashTbl(Arc::new(Mutex::new(header)))
+            },
+            "body" => String::from_utf8_lossy(&self.body).to_string().into(),
+            _ => return Err(UError::new(UErrorKind::SocketError, UErrorMessage::InvalidMember(name.to_string()))),
+        };
+        Ok(obj)
+    }
+    pub fn invoke_method(&self, name: &str, _args: Vec<Object>) -> SocketResult<Object> {
+        Err(UError::new(UErrorKind::SocketError, UErrorMessage::InvalidMember(name.to_string())))
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// `:name`
+    Param(String),
+    /// 末尾の `*`、残りのパスすべてに一致する
+    Rest,
+}
+
+#[derive(Debug, Clone)]
+struct Route<T> {
+    /// 大文字のメソッド名、`*` ならすべてのメソッド
+    method: String,
+    segments: Vec<Segment>,
+    target: T,
+}
+impl<T> Route<T> {
+    fn matches(&self, path: &[&str]) -> Option<Vec<(String, String)>> {
+        let mut params = vec![];
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Rest => {
+                    params.push(("*".to_string(), path.get(i..).unwrap_or_default().join("/")));
+                    return Some(params);
+                },
+                Segment::Literal(literal) => if path.get(i) != Some(&literal.as_str()) {
+                    return None;
+                },
+                Segment::Param(name) => params.push((name.clone(), path.get(i)?.to_string())),
+            }
+        }
+        (path.len() == self.segments.len()).then_some(params)
+    }
+}
+
+/// メソッドとパスのパターンからハンドラを探す
+#[derive(Debug, Clone)]
+pub struct Router<T> {
+    routes: Vec<Route<T>>,
+}
+impl<T> Default for Router<T> {
+    fn default() -> Self {
+        Self { routes: vec![] }
+    }
+}
+/// ルーティングの結果
+#[derive(Debug, PartialEq)]
+pub enum RouteMatch<'a, T> {
+    Found(&'a T, Vec<(String, String)>),
+    /// パスは一致したがメソッドが一致しない
+    MethodNotAllowed,
+    NotFound,
+}
+impl<T> Router<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn split_path(path: &str) -> Vec<&str> {
+        path.split('/').filter(|s| ! s.is_empty()).collect()
+    }
+    /// ルートを追加する、先に追加したものが優先される
+    /// - pattern: `/items/:id` のように `:名前` でパラメータ、末尾の `*` で残りすべてに一致
+    pub fn add(&mut self, method: &str, pattern: &str, target: T) {
+        let segments = Self::split_path(pattern).into_iter()
+            .map(|s| match s {
+                "*" => Segment::Rest,
+                s => match s.strip_prefix(':') {
+                    Some(name) => Segment::Param(name.to_string()),
+                    None => Segment::Literal(s.to_string()),
+                },
+            })
+            .collect();
+        self.routes.push(Route { method: method.to_ascii_uppercase(), segments, target });
+    }
+    pub fn find(&self, method: &str, path: &str) -> RouteMatch<'_, T> {
+        let path = Self::split_path(path);
+        let mut path_matched = false;
+        for route in &self.routes {
+            if let Some(params) = route.matches(&path) {
+                if route.method == "*" || route.method == method {
+                    return RouteMatch::Found(&route.target, params);
+                }
+                path_matched = true;
+            }
+        }
+        if path_matched {
+            RouteMatch::MethodNotAllowed
+        } else {
+            RouteMatch::NotFound
+        }
+    }
+}
+
+/// 拡張子からContent-Typeを得る
+pub fn content_type(path: &Path) -> &'static str {
+    let ext = path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json; charset=utf-8",
+        "txt" | "log" | "csv" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// 静的ファイルを返す、該当するファイルがなければNone
+fn serve_static(dir: &Path, path: &str) -> Option<HttpResponse> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    // ディレクトリ外を指すパスは拒否する
+    if ! relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let mut file = dir.join(relative);
+    if file.is_dir() {
+        file.push("index.html");
+    }
+    let body = std::fs::read(&file).ok()?;
+    Some(HttpResponse::new(200, Some(content_type(&file)), body))
+}
+
+/// バックグラウンドで動作するHTTPサーバー
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpServer {
+    server: TcpServer,
+}
+impl std::fmt::Display for HttpServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.server.is_stopped() {
+            write!(f, "HttpServer (Stopped)")
+        } else {
+            write!(f, "HttpServer[{}]", self.server.local_addr())
+        }
+    }
+}
+impl HttpServer {
+    /// 待ち受けを開始する
+    /// - router: メソッドとパスからハンドラを探すためのルーター
+    /// - static_dir: ルートに一致しないGET/HEADリクエストをこのディレクトリのファイルで応答する
+    /// - limits: 受け付けるリクエストの大きさの上限
+    /// - factory: 接続ごとに呼ばれ、その接続でリクエストを処理する関数を返す
+    pub fn start<T, F, H>(address: &str, port: u16, router: Router<T>, static_dir: Option<PathBuf>, limits: HttpLimits, mut factory: F) -> SocketResult<Self>
+    where
+        T: Clone + Send + 'static,
+        F: FnMut() -> H + Send + 'static,
+        H: FnMut(&T, HttpRequest) -> HttpResponse + Send + 'static,
+    {
+        let server = TcpServer::start(address, port, Some(KEEP_ALIVE_TIMEOUT), move || {
+            let mut handler = factory();
+            let router = router.clone();
+            let static_dir = static_dir.clone();
+            move |client: TcpClient, peer: SocketAddr| {
+                let _ = Self::serve(&client, peer, &router, static_dir.as_deref(), limits, &mut handler);
+            }
+        })?;
+        Ok(Self { server })
+    }
+    /// 一つの接続でリクエストを処理し続ける
+    fn serve<T, H>(client: &TcpClient, peer: SocketAddr, router: &Router<T>, static_dir: Option<&Path>, limits: HttpLimits, handler: &mut H) -> SocketResult<()>
+    where H: FnMut(&T, HttpRequest) -> HttpResponse,
+    {
+        loop {
+            let (mut request, version) = match HttpRequest::read(client, peer, limits) {
+                Ok(Some(read)) => read,
+                Ok(None) => break,
+                Err(e) if e.kind == UErrorKind::SocketError && e.message == UErrorMessage::SocketTimeout => break,
+                Err(e) => {
+                    let response = match e.message {
+                        UErrorMessage::HttpHeaderTooLarge(_) => HttpResponse::status_only(431),
+                        UErrorMessage::HttpBodyTooLarge(_) => HttpResponse::status_only(413),
+                        _ => HttpResponse::text(400, &e.to_string()),
+                    };
+                    let _ = response.write(client, false, false);
+                    break;
+                },
+            };
+            let keep_alive = request.keep_alive(version);
+            let head_only = request.method == "HEAD";
+            let response = match router.find(&request.method, &request.path) {
+                RouteMatch::Found(target, params) => {
+                    request.params = params;
+                    handler(target, request)
+                },
+                matched => {
+                    let is_get = head_only || request.method == "GET";
+                    let file = static_dir
+                        .filter(|_| is_get)
+                        .and_then(|dir| serve_static(dir, &request.path));
+                    match (file, matched) {
+                        (Some(file), _) => file,
+                        (None, RouteMatch::MethodNotAllowed) => HttpResponse::status_only(405),
+                        (None, _) => HttpResponse::status_only(404),
+                    }
+                },
+            };
+            response.write(client, head_only, keep_alive)?;
+            if ! keep_alive {
+                break;
+            }
+        }
+        Ok(())
+    }
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.local_addr()
+    }
+    pub fn stop(&self) {
+        self.server.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_router() {
+        let mut router = Router::new();
+        router.add("get", "/items", 1);
+        router.add("GET", "/items/:id", 2);
+        router.add("POST", "/items/:id", 3);
+        router.add("*", "/files/*", 4);
+
+        assert_eq!(router.find("GET", "/items/"), RouteMatch::Found(&1, vec![]));
+        assert_eq!(router.find("GET", "/items/42"), RouteMatch::Found(&2, vec![("id".into(), "42".into())]));
+        assert_eq!(router.find("POST", "/items/42"), RouteMatch::Found(&3, vec![("id".into(), "42".into())]));
+        assert_eq!(router.find("DELETE", "/items/42"), RouteMatch::MethodNotAllowed);
+        assert_eq!(router.find("PUT", "/files/a/b.txt"), RouteMatch::Found(&4, vec![("*".into(), "a/b.txt".into())]));
+        assert_eq!(router.find("GET", "/items/42/x"), RouteMatch::NotFound);
+    }
+
+    fn request(port: u16, data: &str) -> String {
+        let client = TcpClient::connect("127.0.0.1", port, Some(Duration::from_secs(5)), None).unwrap();
+        client.write(data.as_bytes()).unwrap();
+        let head = client.recv_until(b"\r\n\r\n").unwrap().unwrap();
+        let head = String::from_utf8(head).unwrap();
+        let len = head.lines()
+            .find_map(|l| l.strip_prefix("Content-Length: "))
+            .map(|l| l.parse().unwrap())
+            .unwrap_or(0);
+        let body = client.recv_exact(len).unwrap();
+        format!("{}|{}", head.lines().next().unwrap(), String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn test_http_server() {
+        let dir = std::env::temp_dir().join(format!("uwscr_http_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<h1>index</h1>").unwrap();
+
+        let mut router = Router::new();
+        router.add("POST", "/echo/:name", "echo");
+        let limits = HttpLimits { max_header: 256, max_body: 8 };
+        let server = HttpServer::start("127.0.0.1", 0, router, Some(dir.clone()), limits, || {
+            |target: &&str, req: HttpRequest| {
+                let body = format!("{target}:{}:{}:{}",
+                    req.params[0].1,
+                    req.query.first().map(|q| q.1.as_str()).unwrap_or_default(),
+                    String::from_utf8_lossy(&req.body)
+                );
+                HttpResponse::text(201, &body)
+            }
+        }).unwrap();
+        let port = server.local_addr().port();
+
+        let res = request(port, "POST /echo/a%20b?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello");
+        assert_eq!(res, "HTTP/1.1 201 Created|echo:a b:1:hello");
+        let res = request(port, "POST /echo/c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n");
+        assert_eq!(res, "HTTP/1.1 201 Created|echo:c::abcde");
+        let res = request(port, "GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(res, "HTTP/1.1 200 OK|<h1>index</h1>");
+        let res = request(port, "GET /../secret HTTP/1.1\r\n\r\n");
+        assert_eq!(res, "HTTP/1.1 404 Not Found|Not Found");
+        let res = request(port, "GET /echo/a HTTP/1.1\r\n\r\n");
+        assert_eq!(res, "HTTP/1.1 405 Method Not Allowed|Method Not Allowed");
+        // 上限を超えるリクエスト
+        let res = request(port, "POST /echo/a HTTP/1.1\r\nContent-Length: 9\r\n\r\n");
+        assert_eq!(res, "HTTP/1.1 413 Payload Too Large|Payload Too Large");
+        let res = request(port, "POST /echo/c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nabcde\r\n5\r\nfghij\r\n0\r\n\r\n");
+        assert_eq!(res, "HTTP/1.1 413 Payload Too Large|Payload Too Large");
+        let res = request(port, &format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(300)));
+        assert_eq!(res, "HTTP/1.1 431 Request Header Fields Too Large|Request Header Fields Too Large");
+
+        server.stop();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// 上限を超えたリクエストを送り、応答の先頭行とConnectionヘッダ、その後切断されたかどうかを返す
+    fn request_too_large(port: u16, data: &[u8]) -> (String, String, bool) {
+        let client = TcpClient::connect("127.0.0.1", port, Some(Duration::from_secs(5)), None).unwrap();
+        client.write(data).unwrap();
+        let head = client.recv_until(b"\r\n\r\n").unwrap().unwrap();
+        let head = String::from_utf8(head).unwrap();
+        let len = head.lines()
+            .find_map(|l| l.strip_prefix("Content-Length: "))
+            .map(|l| l.parse().unwrap())
+            .unwrap_or(0);
+        client.recv_exact(len).unwrap();
+        let connection = head.lines()
+            .find_map(|l| l.strip_prefix("Connection: "))
+            .unwrap_or_default()
+            .to_string();
+        // 後続のリクエストには応答せず切断される
+        let closed = match client.recv(1) {
+            Ok(data) => data.is_empty(),
+            Err(e) => e.message != UErrorMessage::SocketTimeout,
+        };
+        (head.lines().next().unwrap().to_string(), connection, closed)
+    }
+
+    #[test]
+    fn test_http_server_too_large() {
+        let mut router = Router::new();
+        router.add("*", "/*", ());
+        let limits = HttpLimits { max_header: 256, max_body: 16 };
+        let server = HttpServer::start("127.0.0.1", 0, router, None, limits, || {
+            |_: &(), _: HttpRequest| HttpResponse::text(200, "ok")
+        }).unwrap();
+        let port = server.local_addr().port();
+
+        // 区切りが来る前に上限を超えたヘッダ
+        let data = format!("GET / HTTP/1.1\r\nX-Long: {}", "a".repeat(1000));
+        let (status, connection, closed) = request_too_large(port, data.as_bytes());
+        assert_eq!(status, "HTTP/1.1 431 Request Header Fields Too Large");
+        assert_eq!(connection, "close");
+        assert!(closed);
+
+        // キープアライブのリクエストでもボディが上限を超えたら後続のリクエストを処理しない
+        let data = format!("POST / HTTP/1.1\r\nContent-Length: 32\r\n\r\n{}GET / HTTP/1.1\r\n\r\n", "b".repeat(32));
+        let (status, connection, closed) = request_too_large(port, data.as_bytes());
+        assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+        assert_eq!(connection, "close");
+        assert!(closed);
+
+        // 上限以内なら接続は維持される
+        let client = TcpClient::connect("127.0.0.1", port, Some(Duration::from_secs(5)), None).unwrap();
+        for _ in 0..2 {
+            client.write(b"POST / HTTP/1.1\r\nContent-Length: 16\r\n\r\n0123456789abcdef").unwrap();
+            let head = client.recv_until(b"\r\n\r\n").unwrap().unwrap();
+            let head = String::from_utf8(head).unwrap();
+            assert!(head.starts_with("HTTP/1.1 200 OK"));
+            assert!(head.contains("Connection: keep-alive"));
+            assert_eq!(client.recv_exact(2).unwrap(), b"ok");
+        }
+
+        server.stop();
+    }
+}
@@ -0,0 +1,271 @@
+//! メールの送信 (SMTP) と受信 (IMAP, POP3)
+
+mod mime;
+mod smtp;
+mod imap;
+mod pop3;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::time::Duration;
+
+use super::SocketResult;
+use super::tcp::{socket_error, Stream, TcpClient, TlsOptions};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+
+pub use mime::{Attachment, OutgoingMail, ReceivedMail};
+
+/// メールサーバーのプロトコル
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MailProtocol {
+    Smtp,
+    Imap,
+    Pop3,
+}
+impl std::fmt::Display for MailProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MailProtocol::Smtp => write!(f, "smtp"),
+            MailProtocol::Imap => write!(f, "imap"),
+            MailProtocol::Pop3 => write!(f, "pop3"),
+        }
+    }
+}
+
+/// 接続の暗号化方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MailSecurity {
+    /// 暗号化しない
+    None,
+    /// 平文で接続後にSTARTTLS (POP3はSTLS) で暗号化する
+    StartTls,
+    /// 接続時からTLSを使う
+    Tls,
+}
+
+/// メールサーバーへの接続設定、送受信のたびに接続する
+#[derive(Debug, Clone, PartialEq)]
+pub struct MailClient {
+    pub protocol: MailProtocol,
+    pub host: String,
+    pub port: u16,
+    pub security: MailSecurity,
+    /// 証明書とホスト名を検証しない
+    pub insecure: bool,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub timeout: Option<Duration>,
+}
+impl std::fmt::Display for MailClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MailClient[{}://{}:{}]", self.protocol, self.host, self.port)
+    }
+}
+impl MailClient {
+    /// portが0ならプロトコルと暗号化方式の既定ポートにする
+    pub fn new(protocol: MailProtocol, host: &str, port: u16, security: MailSecurity) -> Self {
+        let port = match port {
+            0 => Self::default_port(protocol, security),
+            port => port,
+        };
+        Self {
+            protocol,
+            host: host.to_string(),
+            port,
+            security,
+            insecure: false,
+            user: None,
+            password: None,
+            timeout: Some(Duration::from_secs(30)),
+        }
+    }
+    fn default_port(protocol: MailProtocol, security: MailSecurity) -> u16 {
+        match (protocol, security) {
+            (MailProtocol::Smtp, MailSecurity::Tls) => 465,
+            (MailProtocol::Smtp, MailSecurity::StartTls) => 587,
+            (MailProtocol::Smtp, MailSecurity::None) => 25,
+            (MailProtocol::Imap, MailSecurity::Tls) => 993,
+            (MailProtocol::Imap, _) => 143,
+            (MailProtocol::Pop3, MailSecurity::Tls) => 995,
+            (MailProtocol::Pop3, _) => 110,
+        }
+    }
+    /// メールを送信する、SMTPのみ
+    pub fn send(&self, mail: &OutgoingMail) -> SocketResult<()> {
+        // 改行によるヘッダやSMTPコマンドの挿入を防ぐ
+        if let Some(value) = mail.find_line_break() {
+            return Err(UError::new(UErrorKind::MailError, UErrorMessage::MailLineBreak(value.to_string())));
+        }
+        let conn = MailConnection::connect(self)?;
+        smtp::send(conn, self, mail)
+    }
+    /// 新しい順に最大count件 (0なら全件) のメールを受信し、IDと受信データの組を返す
+    /// mailboxとunseen_onlyはIMAPのみ
+    pub fn fetch(&self, count: usize, mailbox: &str, unseen_only: bool) -> SocketResult<Vec<(String, Vec<u8>)>> {
+        let conn = MailConnection::connect(self)?;
+        match self.protocol {
+            MailProtocol::Imap => imap::fetch(conn, self, count, mailbox, unseen_only),
+            MailProtocol::Pop3 => pop3::fetch(conn, self, count),
+            MailProtocol::Smtp => unreachable!(),
+        }
+    }
+    fn credentials(&self) -> Option<(&str, &str)> {
+        let user = self.user.as_deref()?;
+        Some((user, self.password.as_deref().unwrap_or_default()))
+    }
+}
+
+/// サーバーがエラーを返した
+fn server_error(response: impl Into<String>) -> UError {
+    UError::new(UErrorKind::MailError, UErrorMessage::MailServerError(response.into()))
+}
+
+/// 行単位でやり取りするメールサーバーとの接続
+struct MailConnection {
+    reader: BufReader<Stream>,
+    host: String,
+    insecure: bool,
+}
+impl MailConnection {
+    fn connect(client: &MailClient) -> SocketResult<Self> {
+        let tcp = TcpClient::connect_tcp(&client.host, client.port, client.timeout)?;
+        let conn = Self {
+            reader: BufReader::new(Stream::Plain(tcp)),
+            host: client.host.clone(),
+            insecure: client.insecure,
+        };
+        match client.security {
+            MailSecurity::Tls => conn.start_tls(),
+            _ => Ok(conn),
+        }
+    }
+    /// 接続をTLSにする
+    fn start_tls(self) -> SocketResult<Self> {
+        let Self { reader, host, insecure } = self;
+        let stream = match reader.into_inner() {
+            Stream::Plain(tcp) => {
+                let connector = TcpClient::tls_connector(TlsOptions { insecure, ca: None })?;
+                let stream = connector.connect(&host, tcp)
+                    .map_err(|e| UError::new(UErrorKind::SocketError, UErrorMessage::Any(e.to_string())))?;
+                Stream::Tls(Box::new(stream))
+            },
+            stream => stream,
+        };
+        Ok(Self { reader: BufReader::new(stream), host, insecure })
+    }
+    /// EHLOで名乗るための自身のアドレス
+    fn local_name(&self) -> String {
+        match self.reader.get_ref().tcp().local_addr() {
+            Ok(addr) if addr.is_ipv6() => format!("[IPv6:{}]", addr.ip()),
+            Ok(addr) => format!("[{}]", addr.ip()),
+            Err(_) => "localhost".into(),
+        }
+    }
+    /// 1行受信する、改行は含まない
+    fn read_line_bytes(&mut self) -> SocketResult<Vec<u8>> {
+        let mut line = vec![];
+        let size = self.reader.read_until(b'\n', &mut line).map_err(socket_error)?;
+        if size == 0 {
+            return Err(UError::new(UErrorKind::SocketError, UErrorMessage::SocketHasBeenClosed));
+        }
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        Ok(line)
+    }
+    fn read_line(&mut self) -> SocketResult<String> {
+        self.read_line_bytes()
+            .map(|line| String::from_utf8_lossy(&line).to_string())
+    }
+    fn read_exact(&mut self, size: usize) -> SocketResult<Vec<u8>> {
+        let mut buf = vec![0; size];
+        self.reader.read_exact(&mut buf).map_err(socket_error)?;
+        Ok(buf)
+    }
+    fn write_all(&mut self, data: &[u8]) -> SocketResult<()> {
+        let stream = self.reader.get_mut();
+        stream.write_all(data)
+            .and_then(|_| stream.flush())
+            .map_err(socket_error)
+    }
+    /// CRLFを付けて送信する
+    fn write_line(&mut self, line: &str) -> SocketResult<()> {
+        if line.contains(['\r', '\n']) {
+            return Err(UError::new(UErrorKind::MailError, UErrorMessage::MailLineBreak(line.to_string())));
+        }
+        self.write_all(format!("{line}\r\n").as_bytes())
+    }
+}
+
+/// 本文の改行をCRLFにそろえ、行頭のドットを重ねる (SMTPのDATA用)
+fn dot_stuff(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 16);
+    for line in data.split(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.starts_with(b".") {
+            out.push(b'.');
+        }
+        out.extend_from_slice(line);
+        out.extend_from_slice(b"\r\n");
+    }
+    // 末尾の改行によって増えた空行を除く
+    if data.ends_with(b"\n") {
+        out.truncate(out.len() - 2);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    /// 期待する受信行と返す応答の組で振る舞う試験用サーバー
+    /// 受信行が空なら応答のみを送る、*で終わる場合は前方一致、.ならドットの行までをまとめて受信する
+    pub(super) fn fake_server(script: Vec<(&'static str, &'static str)>) -> (u16, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut received = vec![];
+            for (expected, response) in script {
+                if expected == "." {
+                    let mut data = String::new();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line == ".\r\n" {
+                            break;
+                        }
+                        data.push_str(&line);
+                    }
+                    received.push(data);
+                } else if ! expected.is_empty() {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end_matches(['\r', '\n']).to_string();
+                    match expected.strip_suffix('*') {
+                        Some(prefix) => assert!(line.starts_with(prefix), "{line} != {expected}"),
+                        None => assert_eq!(line, expected),
+                    }
+                    received.push(line);
+                }
+                writer.write_all(response.as_bytes()).unwrap();
+            }
+            received
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn test_dot_stuff() {
+        assert_eq!(dot_stuff(b"a\n.b\r\n..c"), b"a\r\n..b\r\n...c\r\n");
+        assert_eq!(dot_stuff(b"a\r\n"), b"a\r\n");
+    }
+}
@@ -0,0 +1,175 @@
+//! IMAPによる受信
+
+use base64::{Engine, engine::general_purpose};
+
+use super::{MailClient, MailConnection, MailSecurity, server_error};
+use crate::builtins::socket::SocketResult;
+
+/// タグ付きの応答までに受信した行
+struct Untagged {
+    /// リテラルを除いた行
+    line: String,
+    /// 行に含まれていたリテラル ({n}で始まるデータ)
+    literals: Vec<Vec<u8>>,
+}
+
+struct Session {
+    conn: MailConnection,
+    tag: u32,
+}
+impl Session {
+    /// コマンドを送信し、OKが返るまでの応答を返す
+    fn command(&mut self, command: &str) -> SocketResult<Vec<Untagged>> {
+        self.tag += 1;
+        let tag = format!("A{:04}", self.tag);
+        self.conn.write_line(&format!("{tag} {command}"))?;
+        let mut responses = vec![];
+        loop {
+            let untagged = self.read_response()?;
+            if let Some(status) = untagged.line.strip_prefix(&tag) {
+                let status = status.trim_start();
+                if status.get(..2).is_some_and(|s| s.eq_ignore_ascii_case("OK")) {
+                    return Ok(responses);
+                }
+                return Err(server_error(status));
+            }
+            responses.push(untagged);
+        }
+    }
+    /// リテラルを含めて応答を1つ受信する
+    fn read_response(&mut self) -> SocketResult<Untagged> {
+        let mut line = String::new();
+        let mut literals = vec![];
+        loop {
+            let part = self.conn.read_line()?;
+            let size = part.strip_suffix('}')
+                .and_then(|s| s.rsplit_once('{'))
+                .and_then(|(_, n)| n.parse::<usize>().ok());
+            line.push_str(&part);
+            match size {
+                Some(size) => literals.push(self.conn.read_exact(size)?),
+                None => return Ok(Untagged { line, literals }),
+            }
+        }
+    }
+}
+
+/// IMAPの引用符付き文字列
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+/// メールボックス名を修正UTF-7 (RFC 3501) にする
+fn encode_mailbox(name: &str) -> String {
+    let mut out = String::new();
+    let mut pending: Vec<u16> = vec![];
+    let flush = |out: &mut String, pending: &mut Vec<u16>| {
+        if ! pending.is_empty() {
+            let bytes = pending.iter().flat_map(|u| u.to_be_bytes()).collect::<Vec<_>>();
+            let encoded = general_purpose::STANDARD_NO_PAD.encode(bytes).replace('/', ",");
+            out.push_str(&format!("&{encoded}-"));
+            pending.clear();
+        }
+    };
+    for c in name.chars() {
+        match c {
+            '&' => {
+                flush(&mut out, &mut pending);
+                out.push_str("&-");
+            },
+            ' '..='~' => {
+                flush(&mut out, &mut pending);
+                out.push(c);
+            },
+            _ => {
+                let mut buf = [0; 2];
+                pending.extend_from_slice(c.encode_utf16(&mut buf));
+            },
+        }
+    }
+    flush(&mut out, &mut pending);
+    out
+}
+
+pub(super) fn fetch(conn: MailConnection, client: &MailClient, count: usize, mailbox: &str, unseen_only: bool) -> SocketResult<Vec<(String, Vec<u8>)>> {
+    let mut session = Session { conn, tag: 0 };
+    let greeting = session.read_response()?;
+    if ! greeting.line.starts_with("* OK") && ! greeting.line.starts_with("* PREAUTH") {
+        return Err(server_error(greeting.line));
+    }
+    if client.security == MailSecurity::StartTls {
+        session.command("STARTTLS")?;
+        session.conn = session.conn.start_tls()?;
+    }
+    if let Some((user, password)) = client.credentials() {
+        session.command(&format!("LOGIN {} {}", quote(user), quote(password)))?;
+    }
+    session.command(&format!("SELECT {}", quote(&encode_mailbox(mailbox))))?;
+
+    let criteria = if unseen_only { "UNSEEN" } else { "ALL" };
+    let mut uids = session.command(&format!("UID SEARCH {criteria}"))?
+        .into_iter()
+        .filter_map(|res| res.line.strip_prefix("* SEARCH").map(|s| s.to_string()))
+        .flat_map(|s| s.split_whitespace().filter_map(|n| n.parse::<u32>().ok()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    uids.sort_unstable_by(|a, b| b.cmp(a));
+    if count > 0 {
+        uids.truncate(count);
+    }
+
+    let mut messages = vec![];
+    for uid in uids {
+        // PEEKなので既読にはならない
+        let body = session.command(&format!("UID FETCH {uid} BODY.PEEK[]"))?
+            .into_iter()
+            .find(|res| res.line.contains("FETCH") && ! res.literals.is_empty())
+            .and_then(|res| res.literals.into_iter().next());
+        if let Some(body) = body {
+            messages.push((uid.to_string(), body));
+        }
+    }
+    let _ = session.command("LOGOUT");
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::MailProtocol;
+    use super::super::tests::fake_server;
+
+    #[test]
+    fn test_encode_mailbox() {
+        assert_eq!(encode_mailbox("INBOX"), "INBOX");
+        assert_eq!(encode_mailbox("受信トレイ/仕事&私用"), "&U9dP4TDIMOwwpA-/&TtVOiw-&-&ecF1KA-");
+    }
+
+    #[test]
+    fn test_imap_fetch() {
+        let (port, server) = fake_server(vec![
+            ("", "* OK ready\r\n"),
+            ("A0001 LOGIN \"user\" \"pa\\\"ss\"", "A0001 OK logged in\r\n"),
+            ("A0002 SELECT \"INBOX\"", "* 3 EXISTS\r\nA0002 OK [READ-WRITE] selected\r\n"),
+            ("A0003 UID SEARCH UNSEEN", "* SEARCH 5 12\r\nA0003 OK done\r\n"),
+            ("A0004 UID FETCH 12 BODY.PEEK[]", "* 2 FETCH (UID 12 BODY[] {17}\r\nSubject: new\r\n\r\nb)\r\nA0004 OK done\r\n"),
+            ("A0005 UID FETCH 5 BODY.PEEK[]", "A0005 NO gone\r\n"),
+        ]);
+        let mut client = MailClient::new(MailProtocol::Imap, "127.0.0.1", port, MailSecurity::None);
+        client.user = Some("user".into());
+        client.password = Some("pa\"ss".into());
+        let err = client.fetch(0, "INBOX", true).unwrap_err();
+        assert_eq!(err.message, crate::error::UErrorMessage::MailServerError("NO gone".into()));
+        server.join().unwrap();
+
+        let (port, server) = fake_server(vec![
+            ("", "* OK ready\r\n"),
+            ("A0001 SELECT \"INBOX\"", "A0001 OK selected\r\n"),
+            ("A0002 UID SEARCH ALL", "* SEARCH 5 12\r\nA0002 OK done\r\n"),
+            ("A0003 UID FETCH 12 BODY.PEEK[]", "* 2 FETCH (UID 12 BODY[] {17}\r\nSubject: new\r\n\r\nb)\r\nA0003 OK done\r\n"),
+            ("A0004 LOGOUT", "* BYE\r\nA0004 OK\r\n"),
+        ]);
+        let client = MailClient::new(MailProtocol::Imap, "127.0.0.1", port, MailSecurity::None);
+        let messages = client.fetch(1, "INBOX", false).unwrap();
+        assert_eq!(messages, vec![("12".to_string(), b"Subject: new\r\n\r\nb".to_vec())]);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,542 @@
+//! メールメッセージ (RFC 5322 / MIME) の組み立てと解析
+
+use base64::{Engine, engine::general_purpose};
+use regex::Regex;
+use serde_json::{Map, Value as JsonValue};
+
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// base64の1行の長さ
+const BASE64_LINE: usize = 76;
+/// ヘッダのエンコード済み単語1つに含めるバイト数の目安
+const ENCODED_WORD_BYTES: usize = 45;
+
+static ENCODED_WORD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"=\?([^?\s]+)\?([bBqQ])\?([^?\s]*)\?=").unwrap()
+});
+static BETWEEN_WORDS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(\?=)\s+(=\?)").unwrap()
+});
+
+/// 添付ファイル
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    /// ディレクトリに保存する、同名のファイルがあれば名前に番号を付ける
+    pub fn save(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        // ファイル名に使えない文字とディレクトリの指定を除く
+        let name = self.filename
+            .replace(['\\', '/', ':', '*', '?', '"', '<', '>', '|'], "_")
+            .trim_matches(['.', ' '])
+            .to_string();
+        let name = if name.is_empty() { "attachment".to_string() } else { name };
+        let mut path = dir.join(&name);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+        let mut n = 2;
+        while path.exists() {
+            path = dir.join(format!("{stem} ({n}){ext}"));
+            n += 1;
+        }
+        std::fs::write(&path, &self.data)?;
+        Ok(path)
+    }
+}
+
+/// 送信するメール
+#[derive(Debug, Clone, Default)]
+pub struct OutgoingMail {
+    pub from: String,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    pub bcc: Vec<String>,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+    pub attachments: Vec<Attachment>,
+}
+
+impl OutgoingMail {
+    /// 送信するメッセージ本体、Bccは含まない
+    pub fn build(&self) -> Vec<u8> {
+        let mut out = String::new();
+        let mut header = |name: &str, value: String| {
+            out.push_str(&format!("{name}: {value}\r\n"));
+        };
+        header("From", encode_address(&self.from));
+        header("To", self.to.iter().map(|a| encode_address(a)).collect::<Vec<_>>().join(", "));
+        if ! self.cc.is_empty() {
+            header("Cc", self.cc.iter().map(|a| encode_address(a)).collect::<Vec<_>>().join(", "));
+        }
+        header("Subject", encode_word(&self.subject));
+        header("Date", chrono::Local::now().to_rfc2822());
+        let domain = envelope_address(&self.from)
+            .split_once('@')
+            .map(|(_, d)| d.to_string())
+            .unwrap_or("localhost".into());
+        header("Message-ID", format!("<{:016x}.{:08x}@{domain}>", rand::random::<u64>(), rand::random::<u32>()));
+        header("MIME-Version", "1.0".into());
+
+        let body = self.body_part();
+        let body = if self.attachments.is_empty() {
+            body
+        } else {
+            let mut parts = vec![body];
+            parts.extend(self.attachments.iter().map(attachment_part));
+            multipart("mixed", parts)
+        };
+        out.push_str(&body);
+        out.into_bytes()
+    }
+    /// ヘッダやエンベロープに使う値のうち改行を含むものを返す
+    pub fn find_line_break(&self) -> Option<&str> {
+        let attachments = self.attachments.iter()
+            .flat_map(|a| [a.filename.as_str(), a.content_type.as_str()]);
+        std::iter::once(self.from.as_str())
+            .chain(self.to.iter().chain(&self.cc).chain(&self.bcc).map(String::as_str))
+            .chain(std::iter::once(self.subject.as_str()))
+            .chain(attachments)
+            .find(|value| value.contains(['\r', '\n']))
+    }
+    /// テキスト部分、HTMLがあればmultipart/alternativeにする
+    fn body_part(&self) -> String {
+        let text = text_part("plain", &self.text);
+        match &self.html {
+            Some(html) => multipart("alternative", vec![text, text_part("html", html)]),
+            None => text,
+        }
+    }
+}
+
+fn wrap_base64(data: &[u8]) -> String {
+    let encoded = general_purpose::STANDARD.encode(data);
+    encoded.as_bytes()
+        .chunks(BASE64_LINE)
+        .map(|line| String::from_utf8_lossy(line).to_string() + "\r\n")
+        .collect()
+}
+fn text_part(subtype: &str, text: &str) -> String {
+    format!(
+        "Content-Type: text/{subtype}; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+        wrap_base64(text.as_bytes())
+    )
+}
+fn attachment_part(attachment: &Attachment) -> String {
+    let name = &attachment.filename;
+    let disposition = if name.is_ascii() {
+        format!("filename=\"{}\"", name.replace('"', ""))
+    } else {
+        format!("filename*=UTF-8''{}", urlencoding::encode(name))
+    };
+    format!(
+        "Content-Type: {}; name=\"{}\"\r\nContent-Disposition: attachment; {disposition}\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+        attachment.content_type,
+        encode_word(name).replace('"', ""),
+        wrap_base64(&attachment.data)
+    )
+}
+fn multipart(subtype: &str, parts: Vec<String>) -> String {
+    let boundary = format!("=_uwscr_{:016x}", rand::random::<u64>());
+    let mut out = format!("Content-Type: multipart/{subtype}; boundary=\"{boundary}\"\r\n\r\n");
+    for part in parts {
+        out.push_str(&format!("--{boundary}\r\n{part}"));
+    }
+    out.push_str(&format!("--{boundary}--\r\n"));
+    out
+}
+
+/// ASCII以外を含む場合はRFC 2047のエンコード済み単語にする
+pub fn encode_word(text: &str) -> String {
+    if text.is_ascii() {
+        return text.to_string();
+    }
+    let mut words = vec![];
+    let mut chunk = String::new();
+    for c in text.chars() {
+        if chunk.len() + c.len_utf8() > ENCODED_WORD_BYTES {
+            words.push(std::mem::take(&mut chunk));
+        }
+        chunk.push(c);
+    }
+    words.push(chunk);
+    words.iter()
+        .map(|w| format!("=?UTF-8?B?{}?=", general_purpose::STANDARD.encode(w)))
+        .collect::<Vec<_>>()
+        .join("\r\n ")
+}
+/// `名前 <アドレス>` の名前部分をエンコードする
+fn encode_address(address: &str) -> String {
+    match address.rsplit_once('<') {
+        Some((name, addr)) => {
+            let name = name.trim().trim_matches('"');
+            if name.is_empty() {
+                format!("<{addr}")
+            } else {
+                format!("{} <{addr}", encode_word(name))
+            }
+        },
+        None => address.trim().to_string(),
+    }
+}
+/// `名前 <アドレス>` からアドレス部分を取り出す
+pub fn envelope_address(address: &str) -> &str {
+    match address.rsplit_once('<') {
+        Some((_, addr)) => addr.trim_end().trim_end_matches('>').trim(),
+        None => address.trim(),
+    }
+}
+
+/// 受信したメール
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReceivedMail {
+    pub headers: Vec<(String, String)>,
+    pub text: Option<String>,
+    pub html: Option<String>,
+    pub attachments: Vec<Attachment>,
+}
+
+impl ReceivedMail {
+    pub fn parse(raw: &[u8]) -> Self {
+        let mut mail = Self::default();
+        let (headers, body) = split_header(raw);
+        mail.read_part(&headers, body);
+        mail.headers = headers.into_iter()
+            .map(|(name, value)| (name, decode_words(&value)))
+            .collect();
+        mail
+    }
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+    fn read_part(&mut self, headers: &[(String, String)], body: &[u8]) {
+        let get = |name: &str| headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str());
+        let (mime, params) = parse_content_type(get("content-type").unwrap_or("text/plain"));
+        if mime.starts_with("multipart/") {
+            if let Some(boundary) = param(&params, "boundary") {
+                for part in split_multipart(body, &boundary) {
+                    let (headers, body) = split_header(part);
+                    self.read_part(&headers, body);
+                }
+            }
+            return;
+        }
+        let data = decode_transfer(get("content-transfer-encoding").unwrap_or("7bit"), body);
+        let (disposition, disp_params) = parse_content_type(get("content-disposition").unwrap_or("inline"));
+        let filename = param(&disp_params, "filename").or(param(&params, "name"));
+        let is_text = mime == "text/plain" || mime == "text/html";
+        if disposition == "attachment" || filename.is_some() || ! is_text && ! mime.starts_with("message/") {
+            let filename = filename.unwrap_or_else(|| format!("part{}", self.attachments.len() + 1));
+            self.attachments.push(Attachment { filename, content_type: mime, data });
+            return;
+        }
+        let charset = param(&params, "charset").unwrap_or("utf-8".into());
+        let text = decode_charset(&charset, &data);
+        match mime.as_str() {
+            "text/html" if self.html.is_none() => self.html = Some(text),
+            "text/plain" if self.text.is_none() => self.text = Some(text),
+            _ => {},
+        }
+    }
+    /// UObjectにする値
+    pub fn to_json(&self, id: JsonValue) -> JsonValue {
+        let addresses = |name: &str| -> JsonValue {
+            self.header(name)
+                .map(|v| v.split(',').map(|a| a.trim()).filter(|a| ! a.is_empty()).map(JsonValue::from).collect())
+                .unwrap_or(JsonValue::Array(vec![]))
+        };
+        let headers = self.headers.iter()
+            .map(|(n, v)| (n.to_ascii_lowercase(), JsonValue::from(v.as_str())))
+            .collect::<Map<_, _>>();
+        let attachments = self.attachments.iter()
+            .map(|a| serde_json::json!({
+                "filename": a.filename,
+                "contentType": a.content_type,
+                "size": a.data.len(),
+                "data": general_purpose::STANDARD.encode(&a.data),
+            }))
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "id": id,
+            "from": self.header("from").unwrap_or_default(),
+            "to": addresses("to"),
+            "cc": addresses("cc"),
+            "subject": self.header("subject").unwrap_or_default(),
+            "date": self.header("date").unwrap_or_default(),
+            "messageId": self.header("message-id").unwrap_or_default(),
+            "headers": headers,
+            "text": self.text,
+            "html": self.html,
+            "attachments": attachments,
+        })
+    }
+}
+
+/// ヘッダと本文に分ける、ヘッダの折り返しは戻す
+fn split_header(raw: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let (head, body) = match find(raw, b"\r\n\r\n") {
+        Some(pos) => (&raw[..pos], &raw[pos + 4..]),
+        None => match find(raw, b"\n\n") {
+            Some(pos) => (&raw[..pos], &raw[pos + 2..]),
+            None => (raw, &raw[raw.len()..]),
+        },
+    };
+    let head = String::from_utf8_lossy(head);
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in head.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    (headers, body)
+}
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = vec![];
+    let mut current: Option<usize> = None;
+    let mut pos = 0;
+    while pos < body.len() {
+        let end = find(&body[pos..], b"\n").map(|i| pos + i + 1).unwrap_or(body.len());
+        let line = String::from_utf8_lossy(&body[pos..end]);
+        let line = line.trim_end();
+        if line.starts_with(&delimiter) {
+            if let Some(start) = current {
+                // 区切りの直前の改行は本文に含まない
+                let mut part_end = pos;
+                if body[..part_end].ends_with(b"\n") { part_end -= 1; }
+                if body[..part_end].ends_with(b"\r") { part_end -= 1; }
+                parts.push(&body[start..part_end.max(start)]);
+            }
+            if line[delimiter.len()..].starts_with("--") {
+                break;
+            }
+            current = Some(end);
+        }
+        pos = end;
+    }
+    parts
+}
+/// `type/subtype; name=value` を小文字の型と引数に分ける
+fn parse_content_type(value: &str) -> (String, Vec<(String, String)>) {
+    let mut items = split_params(value).into_iter();
+    let mime = items.next().unwrap_or_default().to_ascii_lowercase();
+    let params = items
+        .filter_map(|item| {
+            let (name, value) = item.split_once('=')?;
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+                .map(|v| v.replace("\\\"", "\"").replace("\\\\", "\\"))
+                .unwrap_or(value.to_string());
+            Some((name.trim().to_ascii_lowercase(), value))
+        })
+        .collect();
+    (mime, params)
+}
+/// 引用符の中を除いて ; で区切る
+fn split_params(value: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = ! quoted,
+            ';' if ! quoted => {
+                items.push(std::mem::take(&mut current).trim().to_string());
+                continue;
+            },
+            _ => {},
+        }
+        current.push(c);
+    }
+    items.push(current.trim().to_string());
+    items
+}
+/// 引数の値、`name*=charset''値` 形式 (RFC 2231) とエンコード済み単語も解釈する
+fn param(params: &[(String, String)], name: &str) -> Option<String> {
+    let extended = format!("{name}*");
+    if let Some((_, value)) = params.iter().find(|(n, _)| *n == extended) {
+        let mut split = value.splitn(3, '\'');
+        if let (Some(charset), Some(_), Some(encoded)) = (split.next(), split.next(), split.next()) {
+            let bytes = percent_decode(encoded);
+            return Some(decode_charset(charset, &bytes));
+        }
+    }
+    params.iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| decode_words(v))
+}
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        if bytes[i] == b'%' && let Some(b) = hex {
+            out.push(b);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    out
+}
+fn decode_transfer(encoding: &str, body: &[u8]) -> Vec<u8> {
+    match encoding.trim().to_ascii_lowercase().as_str() {
+        "base64" => {
+            let cleaned = body.iter()
+                .copied()
+                .filter(|b| ! b.is_ascii_whitespace())
+                .collect::<Vec<_>>();
+            general_purpose::STANDARD.decode(&cleaned)
+                .unwrap_or_else(|_| body.to_vec())
+        },
+        "quoted-printable" => decode_quoted_printable(body, false),
+        _ => body.to_vec(),
+    }
+}
+/// quoted-printableを戻す、headerならQエンコーディングとして _ を空白にする
+fn decode_quoted_printable(data: &[u8], header: bool) -> Vec<u8> {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut out = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'=' => {
+                let rest = &data[i + 1..];
+                if rest.starts_with(b"\r\n") {
+                    i += 3;
+                } else if rest.starts_with(b"\n") {
+                    i += 2;
+                } else if let (Some(h), Some(l)) = (rest.first().and_then(|b| hex(*b)), rest.get(1).and_then(|b| hex(*b))) {
+                    out.push(h << 4 | l);
+                    i += 3;
+                } else {
+                    out.push(b'=');
+                    i += 1;
+                }
+            },
+            b'_' if header => {
+                out.push(b' ');
+                i += 1;
+            },
+            b => {
+                out.push(b);
+                i += 1;
+            },
+        }
+    }
+    out
+}
+fn decode_charset(charset: &str, data: &[u8]) -> String {
+    match encoding_rs::Encoding::for_label(charset.trim().as_bytes()) {
+        Some(encoding) => encoding.decode(data).0.to_string(),
+        None => String::from_utf8_lossy(data).to_string(),
+    }
+}
+/// RFC 2047のエンコード済み単語を戻す
+pub fn decode_words(value: &str) -> String {
+    // 連続するエンコード済み単語の間の空白は無視する
+    let joined = BETWEEN_WORDS.replace_all(value, "$1$2");
+    ENCODED_WORD.replace_all(&joined, |caps: &regex::Captures| {
+        let data = match &caps[2] {
+            "b" | "B" => general_purpose::STANDARD.decode(&caps[3]).ok(),
+            _ => Some(decode_quoted_printable(caps[3].as_bytes(), true)),
+        };
+        match data {
+            Some(data) => decode_charset(&caps[1], &data),
+            None => caps[0].to_string(),
+        }
+    }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_and_parse() {
+        let mail = OutgoingMail {
+            from: "送信者 <from@example.com>".into(),
+            to: vec!["a@example.com".into(), "\"B\" <b@example.com>".into()],
+            subject: "日次レポート (2026/10/18) の送付".into(),
+            text: "本文です\r\n.先頭がドット".into(),
+            html: Some("<p>本文です</p>".into()),
+            attachments: vec![Attachment {
+                filename: "集計.csv".into(),
+                content_type: "text/csv".into(),
+                data: b"a,b\r\n1,2\r\n".to_vec(),
+            }],
+            ..Default::default()
+        };
+        let raw = mail.build();
+        let text = String::from_utf8(raw.clone()).unwrap();
+        assert!(text.is_ascii());
+        assert!(text.contains("To: a@example.com, B <b@example.com>\r\n"));
+        assert!(text.contains("filename*=UTF-8''%E9%9B%86%E8%A8%88.csv"));
+
+        let parsed = ReceivedMail::parse(&raw);
+        assert_eq!(parsed.header("from"), Some("送信者 <from@example.com>"));
+        assert_eq!(parsed.header("subject"), Some("日次レポート (2026/10/18) の送付"));
+        assert_eq!(parsed.text.as_deref(), Some("本文です\r\n.先頭がドット"));
+        assert_eq!(parsed.html.as_deref(), Some("<p>本文です</p>"));
+        assert_eq!(parsed.attachments, mail.attachments);
+        let json = parsed.to_json(1.into());
+        assert_eq!(json["to"][1], "B <b@example.com>");
+        assert_eq!(json["attachments"][0]["size"], 10);
+    }
+
+    #[test]
+    fn test_parse_encodings() {
+        let raw = b"Subject: =?ISO-2022-JP?B?GyRCJUYlOSVIGyhC?= =?UTF-8?Q?=E3=81=A7=E3=81=99_ok?=\r\n\
+Content-Type: text/plain; charset=\"Shift_JIS\"\r\n\
+Content-Transfer-Encoding: quoted-printable\r\n\
+\r\n\
+=82=A0=82=A2=\r\n\
+=82=A4 x=3Dy\r\n";
+        let mail = ReceivedMail::parse(raw);
+        assert_eq!(mail.header("subject"), Some("テストです ok"));
+        assert_eq!(mail.text.as_deref(), Some("あいう x=y\r\n"));
+        assert_eq!(envelope_address("Name <a@b.c>"), "a@b.c");
+        assert_eq!(envelope_address(" a@b.c "), "a@b.c");
+    }
+
+    #[test]
+    fn test_save_attachment() {
+        let dir = std::env::temp_dir().join(format!("uwscr_mail_{:08x}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let attachment = Attachment {
+            filename: "..\\report:1.txt".into(),
+            content_type: "text/plain".into(),
+            data: b"data".to_vec(),
+        };
+        let first = attachment.save(&dir).unwrap();
+        let second = attachment.save(&dir).unwrap();
+        assert_eq!(first, dir.join("_report_1.txt"));
+        assert_eq!(second, dir.join("_report_1 (2).txt"));
+        assert_eq!(std::fs::read(&second).unwrap(), b"data");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
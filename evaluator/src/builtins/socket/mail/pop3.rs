@@ -0,0 +1,101 @@
+//! POP3による受信
+
+use super::{MailClient, MailConnection, MailSecurity, server_error};
+use crate::builtins::socket::SocketResult;
+
+/// +OKでなければエラー、+OKに続く文字列を返す
+fn expect_ok(conn: &mut MailConnection) -> SocketResult<String> {
+    let line = conn.read_line()?;
+    match line.strip_prefix("+OK") {
+        Some(rest) => Ok(rest.trim().to_string()),
+        None => Err(server_error(line)),
+    }
+}
+fn command(conn: &mut MailConnection, line: &str) -> SocketResult<String> {
+    conn.write_line(line)?;
+    expect_ok(conn)
+}
+/// ドットのみの行までを受信し、行頭のドットを戻す
+fn read_multiline(conn: &mut MailConnection) -> SocketResult<Vec<u8>> {
+    let mut data = vec![];
+    loop {
+        let line = conn.read_line_bytes()?;
+        if line == b"." {
+            return Ok(data);
+        }
+        let line = line.strip_prefix(b".").unwrap_or(&line);
+        data.extend_from_slice(line);
+        data.extend_from_slice(b"\r\n");
+    }
+}
+
+pub(super) fn fetch(mut conn: MailConnection, client: &MailClient, count: usize) -> SocketResult<Vec<(String, Vec<u8>)>> {
+    expect_ok(&mut conn)?;
+    if client.security == MailSecurity::StartTls {
+        command(&mut conn, "STLS")?;
+        conn = conn.start_tls()?;
+    }
+    if let Some((user, password)) = client.credentials() {
+        command(&mut conn, &format!("USER {user}"))?;
+        command(&mut conn, &format!("PASS {password}"))?;
+    }
+    let stat = command(&mut conn, "STAT")?;
+    let total = stat.split_whitespace()
+        .next()
+        .and_then(|n| n.parse::<usize>().ok())
+        .ok_or_else(|| server_error(stat.clone()))?;
+    let last = match count {
+        0 => 1,
+        count => total.saturating_sub(count) + 1,
+    };
+
+    let mut messages = vec![];
+    for n in (last..=total).rev() {
+        command(&mut conn, &format!("RETR {n}"))?;
+        messages.push((n.to_string(), read_multiline(&mut conn)?));
+    }
+    // QUITしなければ削除などの変更は反映されないが、受信のみなので結果は問わない
+    let _ = command(&mut conn, "QUIT");
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::MailProtocol;
+    use super::super::tests::fake_server;
+
+    #[test]
+    fn test_pop3_fetch() {
+        let (port, server) = fake_server(vec![
+            ("", "+OK ready\r\n"),
+            ("USER user", "+OK\r\n"),
+            ("PASS pass", "+OK logged in\r\n"),
+            ("STAT", "+OK 3 300\r\n"),
+            ("RETR 3", "+OK\r\nSubject: third\r\n\r\n..dot\r\n.\r\n"),
+            ("RETR 2", "+OK\r\nSubject: second\r\n\r\n.\r\n"),
+            ("QUIT", "+OK bye\r\n"),
+        ]);
+        let mut client = MailClient::new(MailProtocol::Pop3, "127.0.0.1", port, MailSecurity::None);
+        client.user = Some("user".into());
+        client.password = Some("pass".into());
+        let messages = client.fetch(2, "INBOX", false).unwrap();
+        assert_eq!(messages, vec![
+            ("3".to_string(), b"Subject: third\r\n\r\n.dot\r\n".to_vec()),
+            ("2".to_string(), b"Subject: second\r\n\r\n".to_vec()),
+        ]);
+        server.join().unwrap();
+
+        let (port, server) = fake_server(vec![
+            ("", "+OK ready\r\n"),
+            ("USER user", "+OK\r\n"),
+            ("PASS bad", "-ERR invalid password\r\n"),
+        ]);
+        let mut client = MailClient::new(MailProtocol::Pop3, "127.0.0.1", port, MailSecurity::None);
+        client.user = Some("user".into());
+        client.password = Some("bad".into());
+        let err = client.fetch(0, "INBOX", false).unwrap_err();
+        assert_eq!(err.message, crate::error::UErrorMessage::MailServerError("-ERR invalid password".into()));
+        server.join().unwrap();
+    }
+}
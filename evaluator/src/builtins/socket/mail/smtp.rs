@@ -0,0 +1,169 @@
+//! SMTPによる送信
+
+use base64::{Engine, engine::general_purpose};
+
+use super::{MailClient, MailConnection, MailSecurity, OutgoingMail, dot_stuff, server_error};
+use super::mime::envelope_address;
+use crate::builtins::socket::SocketResult;
+
+/// 応答コードと応答行
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+/// 複数行の応答 (コードの直後が - の行) をまとめて受信する
+fn read_reply(conn: &mut MailConnection) -> SocketResult<Reply> {
+    let mut lines = vec![];
+    loop {
+        let line = conn.read_line()?;
+        let code = line.get(..3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| server_error(line.clone()))?;
+        let more = line.as_bytes().get(3) == Some(&b'-');
+        lines.push(line.get(4..).unwrap_or_default().to_string());
+        if ! more {
+            return Ok(Reply { code, lines });
+        }
+    }
+}
+/// 応答コードが期待するものでなければエラー
+fn expect(conn: &mut MailConnection, codes: &[u16]) -> SocketResult<Reply> {
+    let reply = read_reply(conn)?;
+    if codes.contains(&reply.code) {
+        Ok(reply)
+    } else {
+        Err(server_error(format!("{} {}", reply.code, reply.lines.join(" "))))
+    }
+}
+fn command(conn: &mut MailConnection, line: &str, codes: &[u16]) -> SocketResult<Reply> {
+    conn.write_line(line)?;
+    expect(conn, codes)
+}
+fn ehlo(conn: &mut MailConnection) -> SocketResult<Vec<String>> {
+    let name = conn.local_name();
+    let reply = command(conn, &format!("EHLO {name}"), &[250])?;
+    // 1行目は挨拶なので除く
+    Ok(reply.lines.into_iter().skip(1).collect())
+}
+
+pub(super) fn send(mut conn: MailConnection, client: &MailClient, mail: &OutgoingMail) -> SocketResult<()> {
+    expect(&mut conn, &[220])?;
+    let mut extensions = ehlo(&mut conn)?;
+    if client.security == MailSecurity::StartTls {
+        command(&mut conn, "STARTTLS", &[220])?;
+        conn = conn.start_tls()?;
+        extensions = ehlo(&mut conn)?;
+    }
+    if let Some((user, password)) = client.credentials() {
+        let mechanisms = extensions.iter()
+            .map(|ext| ext.to_ascii_uppercase())
+            .find_map(|ext| ext.strip_prefix("AUTH ").map(str::to_string))
+            .unwrap_or_default();
+        let has = |name: &str| mechanisms.split_whitespace().any(|m| m == name);
+        let encode = |s: &str| general_purpose::STANDARD.encode(s);
+        // LOGINのみに対応するサーバー以外はPLAINを使う
+        if has("PLAIN") || ! has("LOGIN") {
+            let token = encode(&format!("\0{user}\0{password}"));
+            command(&mut conn, &format!("AUTH PLAIN {token}"), &[235])?;
+        } else {
+            command(&mut conn, "AUTH LOGIN", &[334])?;
+            command(&mut conn, &encode(user), &[334])?;
+            command(&mut conn, &encode(password), &[235])?;
+        }
+    }
+
+    command(&mut conn, &format!("MAIL FROM:<{}>", envelope_address(&mail.from)), &[250])?;
+    for rcpt in mail.to.iter().chain(&mail.cc).chain(&mail.bcc) {
+        command(&mut conn, &format!("RCPT TO:<{}>", envelope_address(rcpt)), &[250, 251])?;
+    }
+    command(&mut conn, "DATA", &[354])?;
+    let mut data = dot_stuff(&mail.build());
+    data.extend_from_slice(b".\r\n");
+    conn.write_all(&data)?;
+    expect(&mut conn, &[250])?;
+    // 送信は完了しているのでQUITの失敗は無視する
+    let _ = command(&mut conn, "QUIT", &[221]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{MailProtocol, ReceivedMail};
+    use super::super::tests::fake_server;
+
+    #[test]
+    fn test_smtp_send() {
+        let (port, server) = fake_server(vec![
+            ("", "220 test ESMTP\r\n"),
+            ("EHLO [127.0.0.1]", "250-test\r\n250-AUTH LOGIN CRAM-MD5\r\n250 8BITMIME\r\n"),
+            ("AUTH LOGIN", "334 VXNlcm5hbWU6\r\n"),
+            ("dXNlcg==", "334 UGFzc3dvcmQ6\r\n"),
+            ("cGFzcw==", "235 ok\r\n"),
+            ("MAIL FROM:<from@example.com>", "250 ok\r\n"),
+            ("RCPT TO:<to@example.com>", "250 ok\r\n"),
+            ("RCPT TO:<hidden@example.com>", "250 ok\r\n"),
+            ("DATA", "354 go\r\n"),
+            (".", "250 queued\r\n"),
+            ("QUIT", "221 bye\r\n"),
+        ]);
+        let mut client = MailClient::new(MailProtocol::Smtp, "127.0.0.1", port, MailSecurity::None);
+        client.user = Some("user".into());
+        client.password = Some("pass".into());
+        let mail = OutgoingMail {
+            from: "From <from@example.com>".into(),
+            to: vec!["to@example.com".into()],
+            bcc: vec!["hidden@example.com".into()],
+            subject: "件名".into(),
+            text: ".dot\r\n".into(),
+            ..Default::default()
+        };
+        client.send(&mail).unwrap();
+        let received = server.join().unwrap();
+        let data = &received[8];
+        assert!(! data.contains("hidden@example.com"));
+        let parsed = ReceivedMail::parse(data.as_bytes());
+        assert_eq!(parsed.header("subject"), Some("件名"));
+        assert_eq!(parsed.text.as_deref(), Some(".dot\r\n"));
+    }
+
+    #[test]
+    fn test_smtp_rejected() {
+        let (port, server) = fake_server(vec![
+            ("", "220 test ESMTP\r\n"),
+            ("EHLO *", "250 test\r\n"),
+            ("MAIL FROM:<from@example.com>", "250 ok\r\n"),
+            ("RCPT TO:<nobody@example.com>", "550 5.1.1 no such user\r\n"),
+        ]);
+        let client = MailClient::new(MailProtocol::Smtp, "127.0.0.1", port, MailSecurity::None);
+        let mail = OutgoingMail {
+            from: "from@example.com".into(),
+            to: vec!["nobody@example.com".into()],
+            ..Default::default()
+        };
+        let err = client.send(&mail).unwrap_err();
+        assert_eq!(err.message, crate::error::UErrorMessage::MailServerError("550 5.1.1 no such user".into()));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_smtp_line_break() {
+        // 接続前に拒否されるのでサーバーは不要
+        let client = MailClient::new(MailProtocol::Smtp, "127.0.0.1", 1, MailSecurity::None);
+        let mail = OutgoingMail {
+            from: "from@example.com".into(),
+            to: vec!["to@example.com".into()],
+            subject: "hello\r\nBcc: victim@example.com".into(),
+            ..Default::default()
+        };
+        let err = client.send(&mail).unwrap_err();
+        assert_eq!(err.message, crate::error::UErrorMessage::MailLineBreak("hello\r\nBcc: victim@example.com".into()));
+        let mail = OutgoingMail {
+            from: "from@example.com>\r\nRCPT TO:<victim@example.com".into(),
+            to: vec!["to@example.com".into()],
+            ..Default::default()
+        };
+        assert!(client.send(&mail).is_err());
+    }
+}
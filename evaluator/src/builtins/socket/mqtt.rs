@@ -0,0 +1,601 @@
+#![allow(clippy::result_large_err)]
+//! MQTT 3.1.1 / 5 クライアント
+
+mod packet;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::sync::{Arc, Condvar, Mutex, Weak, mpsc};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+
+use super::{SocketResult, SOCKET_CLOSED_ERROR};
+use super::tcp::{socket_error, Stream, TcpClient, TlsOptions};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+
+use packet::{Packet, topic_matches};
+
+/// 受信を待つ間にロックを解放する間隔
+const RECV_SLICE: Duration = Duration::from_millis(100);
+/// 一度に受信するサイズ
+const READ_CHUNK_SIZE: usize = 8192;
+
+fn mqtt_error(message: impl Into<String>) -> UError {
+    UError::new(UErrorKind::MqttError, UErrorMessage::MqttBrokerError(message.into()))
+}
+/// CONNACKの理由コードを説明にする
+fn connack_reason(code: u8) -> String {
+    let reason = match code {
+        0x01 | 0x84 => "unsupported protocol version",
+        0x02 | 0x85 => "client identifier not valid",
+        0x03 | 0x88 => "server unavailable",
+        0x04 | 0x86 => "bad user name or password",
+        0x05 | 0x87 => "not authorized",
+        _ => "connection refused",
+    };
+    format!("{reason} (0x{code:02x})")
+}
+
+/// 受信したメッセージ
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+/// 接続の設定
+#[derive(Debug)]
+pub struct MqttOptions {
+    /// 空ならランダムに決める
+    pub client_id: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// MQTT_V311またはMQTT_V5
+    pub version: u8,
+    /// 秒、0なら無効
+    pub keep_alive: u16,
+    /// SomeならTLSで接続する
+    pub tls: Option<TlsOptions>,
+    /// 接続と応答待ちのタイムアウト
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Session {
+    stream: Stream,
+    /// 受信済みでまだパケットになっていないデータ
+    pending: Vec<u8>,
+    last_sent: Instant,
+}
+impl Session {
+    fn send(&mut self, packet: &Packet, version: u8) -> io::Result<()> {
+        self.stream.write_all(&packet.encode(version))?;
+        self.stream.flush()?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+    /// 受信してpendingに追加する、0なら接続が閉じられた
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut buf = [0; READ_CHUNK_SIZE];
+        let size = self.stream.read(&mut buf)?;
+        self.pending.extend_from_slice(&buf[..size]);
+        Ok(size)
+    }
+    /// パケットを1つ受信するまで待つ
+    fn read_packet(&mut self, version: u8) -> SocketResult<Packet> {
+        loop {
+            if let Some(packet) = Packet::decode(&mut self.pending, version).map_err(mqtt_error)? {
+                return Ok(packet);
+            }
+            if self.fill().map_err(socket_error)? == 0 {
+                return Err(SOCKET_CLOSED_ERROR);
+            }
+        }
+    }
+    fn close(&mut self, version: u8) {
+        let _ = self.send(&Packet::Disconnect, version);
+        match &mut self.stream {
+            Stream::Plain(stream) => {
+                let _ = stream.shutdown(Shutdown::Both);
+            },
+            Stream::Tls(stream) => {
+                let _ = stream.shutdown();
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    session: Mutex<Option<Session>>,
+    version: u8,
+    keep_alive: Duration,
+    timeout: Option<Duration>,
+    peer: String,
+    next_id: AtomicU16,
+    /// コールバックのない購読で受信したメッセージ
+    queue: Mutex<VecDeque<MqttMessage>>,
+    queue_ready: Condvar,
+    /// 応答を待っているパケットIDと受信した応答
+    acks: Mutex<HashMap<u16, Packet>>,
+    ack_ready: Condvar,
+    /// コールバックのある購読のトピックフィルタと送り先
+    routes: Mutex<Vec<(String, mpsc::Sender<MqttMessage>)>>,
+    /// QoS 2で受信しPUBRELを待っているパケットID
+    incoming: Mutex<HashSet<u16>>,
+}
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+impl Inner {
+    fn is_closed(&self) -> bool {
+        self.session.lock().unwrap().is_none()
+    }
+    fn send(&self, packet: &Packet) -> SocketResult<()> {
+        let mut guard = self.session.lock().unwrap();
+        let session = guard.as_mut().ok_or(SOCKET_CLOSED_ERROR)?;
+        session.send(packet, self.version).map_err(socket_error)
+    }
+    fn next_id(&self) -> u16 {
+        loop {
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            if id != 0 {
+                return id;
+            }
+        }
+    }
+    /// 受信済みのデータを処理する、接続が終了したらfalse
+    fn poll(&self) -> bool {
+        let packets = {
+            let mut guard = self.session.lock().unwrap();
+            let Some(session) = guard.as_mut() else {
+                return false;
+            };
+            if ! self.keep_alive.is_zero() && session.last_sent.elapsed() >= self.keep_alive
+                && session.send(&Packet::PingReq, self.version).is_err()
+            {
+                guard.take();
+                return false;
+            }
+            match session.fill() {
+                Ok(0) => {
+                    guard.take();
+                    return false;
+                },
+                Ok(_) => {},
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+                Err(_) => {
+                    guard.take();
+                    return false;
+                },
+            }
+            let mut packets = vec![];
+            loop {
+                match Packet::decode(&mut session.pending, self.version) {
+                    Ok(Some(packet)) => packets.push(packet),
+                    Ok(None) => break,
+                    Err(_) => {
+                        guard.take();
+                        return false;
+                    },
+                }
+            }
+            packets
+        };
+        for packet in packets {
+            self.handle(packet);
+        }
+        true
+    }
+    fn handle(&self, packet: Packet) {
+        match packet {
+            Packet::Publish { topic, payload, qos, retain, id, .. } => {
+                let duplicated = match (qos, id) {
+                    (1, Some(id)) => {
+                        let _ = self.send(&Packet::PubAck(id, 0));
+                        false
+                    },
+                    (2, Some(id)) => {
+                        let _ = self.send(&Packet::PubRec(id, 0));
+                        // PUBRELまでに再送されたものは配信しない
+                        ! self.incoming.lock().unwrap().insert(id)
+                    },
+                    _ => false,
+                };
+                if ! duplicated {
+                    self.deliver(MqttMessage { topic, payload, qos, retain });
+                }
+            },
+            Packet::PubRel(id, _) => {
+                self.incoming.lock().unwrap().remove(&id);
+                let _ = self.send(&Packet::PubComp(id, 0));
+            },
+            // 送信したQoS 2のメッセージはPUBCOMPまで進める
+            Packet::PubRec(id, code) if code < 0x80 => {
+                let _ = self.send(&Packet::PubRel(id, 0));
+            },
+            Packet::PubAck(id, _) |
+            Packet::PubRec(id, _) |
+            Packet::PubComp(id, _) |
+            Packet::SubAck { id, .. } |
+            Packet::UnsubAck { id, .. } => {
+                self.acks.lock().unwrap().insert(id, packet);
+                self.ack_ready.notify_all();
+            },
+            Packet::Disconnect => self.close(),
+            _ => {},
+        }
+    }
+    /// 一致するコールバックに送る、なければ受信キューに入れる
+    fn deliver(&self, message: MqttMessage) {
+        let mut routes = self.routes.lock().unwrap();
+        while let Some(index) = routes.iter().position(|(filter, _)| topic_matches(filter, &message.topic)) {
+            if routes[index].1.send(message.clone()).is_ok() {
+                return;
+            }
+            // コールバックが終了していれば取り除く
+            routes.remove(index);
+        }
+        drop(routes);
+        self.queue.lock().unwrap().push_back(message);
+        self.queue_ready.notify_all();
+    }
+    /// パケットIDに対する応答を待つ
+    fn wait_ack(&self, id: u16) -> SocketResult<Packet> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut acks = self.acks.lock().unwrap();
+        loop {
+            if let Some(packet) = acks.remove(&id) {
+                return Ok(packet);
+            }
+            if self.is_closed() {
+                return Err(SOCKET_CLOSED_ERROR);
+            }
+            let wait = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(wait) => wait.min(RECV_SLICE),
+                    None => return Err(UError::new(UErrorKind::SocketError, UErrorMessage::SocketTimeout)),
+                },
+                None => RECV_SLICE,
+            };
+            acks = self.ack_ready.wait_timeout(acks, wait).unwrap().0;
+        }
+    }
+    fn close(&self) {
+        if let Some(mut session) = self.session.lock().unwrap().take() {
+            session.close(self.version);
+        }
+        // コールバックと受信待ちを終了させる
+        self.routes.lock().unwrap().clear();
+        self.queue_ready.notify_all();
+        self.ack_ready.notify_all();
+    }
+}
+
+/// MQTTクライアント、受信はバックグラウンドのスレッドで行う
+#[derive(Debug, Clone)]
+pub struct MqttClient {
+    inner: Arc<Inner>,
+}
+impl std::fmt::Display for MqttClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.inner.session.try_lock().as_deref() {
+            Ok(None) => write!(f, "MqttClient (Closed)"),
+            _ => write!(f, "MqttClient[{}]", self.inner.peer),
+        }
+    }
+}
+impl PartialEq for MqttClient {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+impl MqttClient {
+    /// ブローカーに接続する
+    pub fn connect(host: &str, port: u16, options: MqttOptions) -> SocketResult<Self> {
+        let MqttOptions { client_id, user, password, version, keep_alive, tls, timeout } = options;
+        let tcp = TcpClient::connect_tcp(host, port, timeout)?;
+        let stream = match tls {
+            Some(options) => {
+                let connector = TcpClient::tls_connector(options)?;
+                let stream = connector.connect(host, tcp)
+                    .map_err(|e| UError::new(UErrorKind::SocketError, UErrorMessage::Any(e.to_string())))?;
+                Stream::Tls(Box::new(stream))
+            },
+            None => Stream::Plain(tcp),
+        };
+        let client_id = match client_id.is_empty() {
+            true => format!("uwscr-{:08x}", rand::random::<u32>()),
+            false => client_id,
+        };
+        let mut session = Session { stream, pending: vec![], last_sent: Instant::now() };
+        let connect = Packet::Connect { version, client_id, user, password, keep_alive, clean: true };
+        session.send(&connect, version).map_err(socket_error)?;
+        match session.read_packet(version)? {
+            Packet::ConnAck { code: 0, .. } => {},
+            Packet::ConnAck { code, .. } => return Err(mqtt_error(connack_reason(code))),
+            packet => return Err(mqtt_error(format!("unexpected packet: {packet:?}"))),
+        }
+        // 以降の受信はバックグラウンドで短い間隔で行う
+        session.stream.tcp().set_read_timeout(Some(RECV_SLICE)).map_err(socket_error)?;
+
+        let inner = Arc::new(Inner {
+            session: Mutex::new(Some(session)),
+            version,
+            keep_alive: Duration::from_secs(keep_alive as u64),
+            timeout,
+            peer: format!("{host}:{port}"),
+            next_id: AtomicU16::new(1),
+            queue: Mutex::new(VecDeque::new()),
+            queue_ready: Condvar::new(),
+            acks: Mutex::new(HashMap::new()),
+            ack_ready: Condvar::new(),
+            routes: Mutex::new(vec![]),
+            incoming: Mutex::new(HashSet::new()),
+        });
+        let weak = Arc::downgrade(&inner);
+        std::thread::spawn(move || Self::run(weak));
+        Ok(Self { inner })
+    }
+    /// クライアントが破棄されるか切断されるまで受信を続ける
+    fn run(weak: Weak<Inner>) {
+        while let Some(inner) = weak.upgrade() {
+            if ! inner.poll() {
+                // 受信待ちを終了させる
+                inner.close();
+                break;
+            }
+        }
+    }
+    /// メッセージを送信する、QoS 1以上ならブローカーの応答を待つ
+    pub fn publish(&self, topic: &str, payload: &[u8], qos: u8, retain: bool) -> SocketResult<()> {
+        let id = (qos > 0).then(|| self.inner.next_id());
+        let packet = Packet::Publish { topic: topic.into(), payload: payload.into(), qos, retain, dup: false, id };
+        self.inner.send(&packet)?;
+        let Some(id) = id else {
+            return Ok(());
+        };
+        match self.inner.wait_ack(id)? {
+            Packet::PubAck(_, code) |
+            Packet::PubRec(_, code) |
+            Packet::PubComp(_, code) if code >= 0x80 => Err(mqtt_error(format!("publish rejected (0x{code:02x})"))),
+            _ => Ok(()),
+        }
+    }
+    /// トピックフィルタを購読し、ブローカーが許可したQoSを返す
+    pub fn subscribe(&self, filters: &[String], qos: u8) -> SocketResult<Vec<u8>> {
+        let id = self.inner.next_id();
+        let filters = filters.iter().map(|f| (f.clone(), qos)).collect::<Vec<_>>();
+        self.inner.send(&Packet::Subscribe { id, filters: filters.clone() })?;
+        match self.inner.wait_ack(id)? {
+            Packet::SubAck { codes, .. } => {
+                match codes.iter().zip(&filters).find(|(code, _)| **code >= 0x80) {
+                    Some((code, (filter, _))) => Err(mqtt_error(format!("subscription to {filter} rejected (0x{code:02x})"))),
+                    None => Ok(codes),
+                }
+            },
+            packet => Err(mqtt_error(format!("unexpected packet: {packet:?}"))),
+        }
+    }
+    /// トピックフィルタの購読を解除する、コールバックも終了する
+    pub fn unsubscribe(&self, filters: &[String]) -> SocketResult<()> {
+        let id = self.inner.next_id();
+        self.inner.send(&Packet::Unsubscribe { id, filters: filters.to_vec() })?;
+        self.inner.wait_ack(id)?;
+        self.unroute(filters);
+        Ok(())
+    }
+    /// トピックフィルタに一致するメッセージを受信キューではなく戻り値に送る
+    ///
+    /// 購読前に登録し、戻り値を破棄すれば登録は解除される
+    pub fn route(&self, filters: &[String]) -> mpsc::Receiver<MqttMessage> {
+        let (sender, receiver) = mpsc::channel();
+        let mut routes = self.inner.routes.lock().unwrap();
+        for filter in filters {
+            routes.push((filter.clone(), sender.clone()));
+        }
+        receiver
+    }
+    /// routeの登録を解除する
+    pub fn unroute(&self, filters: &[String]) {
+        self.inner.routes.lock().unwrap()
+            .retain(|(filter, _)| ! filters.contains(filter));
+    }
+    /// 受信キューからメッセージを取り出す
+    /// - timeout: Noneなら受信するまで待つ、タイムアウトしたらOk(None)
+    pub fn receive(&self, timeout: Option<Duration>) -> SocketResult<Option<MqttMessage>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut queue = self.inner.queue.lock().unwrap();
+        loop {
+            if let Some(message) = queue.pop_front() {
+                return Ok(Some(message));
+            }
+            if self.inner.is_closed() {
+                return Err(SOCKET_CLOSED_ERROR);
+            }
+            let wait = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(wait) => wait.min(RECV_SLICE),
+                    None => return Ok(None),
+                },
+                None => RECV_SLICE,
+            };
+            queue = self.inner.queue_ready.wait_timeout(queue, wait).unwrap().0;
+        }
+    }
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+    /// DISCONNECTを送って切断する
+    pub fn close(&self) {
+        self.inner.close();
+    }
+    /// クライアントの破棄を妨げない参照を得る
+    pub fn downgrade(&self) -> WeakMqttClient {
+        WeakMqttClient(Arc::downgrade(&self.inner))
+    }
+}
+
+/// MqttClientの弱参照、購読のコールバックを実行するスレッドが保持する
+#[derive(Debug, Clone)]
+pub struct WeakMqttClient(Weak<Inner>);
+impl WeakMqttClient {
+    /// クライアントが破棄されていればNone
+    pub fn upgrade(&self) -> Option<MqttClient> {
+        self.0.upgrade().map(|inner| MqttClient { inner })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::packet::MQTT_V5;
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// 1接続だけ受け付ける簡易ブローカー、購読中のトピックへの発行はそのまま送り返す
+    const MQTT_V311: u8 = 4;
+
+    fn fake_broker(version: u8, password: &'static str) -> (u16, JoinHandle<Vec<Packet>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = vec![];
+            let mut received = vec![];
+            let mut subscriptions: Vec<(String, u8)> = vec![];
+            let mut next_id = 100;
+            let mut chunk = [0; 1024];
+            loop {
+                let packet = match Packet::decode(&mut buf, version).unwrap() {
+                    Some(packet) => packet,
+                    None => match stream.read(&mut chunk).unwrap() {
+                        0 => return received,
+                        size => {
+                            buf.extend_from_slice(&chunk[..size]);
+                            continue;
+                        },
+                    },
+                };
+                received.push(packet.clone());
+                let mut reply = |p: Packet| stream.write_all(&p.encode(version)).unwrap();
+                match packet {
+                    Packet::Connect { password: p, .. } => {
+                        let code = match (p.as_deref() == Some(password), version) {
+                            (true, _) => 0,
+                            (false, MQTT_V5) => 0x86,
+                            (false, _) => 4,
+                        };
+                        reply(Packet::ConnAck { session_present: false, code });
+                        if code != 0 {
+                            return received;
+                        }
+                    },
+                    Packet::Subscribe { id, filters } => {
+                        let codes = filters.iter().map(|(_, qos)| *qos).collect();
+                        subscriptions.extend(filters);
+                        reply(Packet::SubAck { id, codes });
+                    },
+                    Packet::Unsubscribe { id, filters } => {
+                        subscriptions.retain(|(f, _)| ! filters.contains(f));
+                        reply(Packet::UnsubAck { id, codes: vec![0; filters.len()] });
+                    },
+                    Packet::Publish { topic, payload, qos, retain, id, .. } => {
+                        match (qos, id) {
+                            (1, Some(id)) => reply(Packet::PubAck(id, 0)),
+                            (2, Some(id)) => reply(Packet::PubRec(id, 0)),
+                            _ => {},
+                        }
+                        if let Some((_, granted)) = subscriptions.iter().find(|(f, _)| topic_matches(f, &topic)) {
+                            let qos = qos.min(*granted);
+                            let id = (qos > 0).then(|| { next_id += 1; next_id });
+                            reply(Packet::Publish { topic, payload, qos, retain, dup: false, id });
+                        }
+                    },
+                    Packet::PubRel(id, _) => reply(Packet::PubComp(id, 0)),
+                    Packet::PingReq => reply(Packet::PingResp),
+                    Packet::Disconnect => return received,
+                    _ => {},
+                }
+            }
+        });
+        (port, handle)
+    }
+
+    fn options(version: u8, password: &str) -> MqttOptions {
+        MqttOptions {
+            client_id: String::new(),
+            user: Some("user".into()),
+            password: Some(password.into()),
+            version,
+            keep_alive: 60,
+            tls: None,
+            timeout: Some(Duration::from_secs(5)),
+        }
+    }
+
+    #[test]
+    fn test_mqtt_client() {
+        let (port, broker) = fake_broker(MQTT_V311, "pass");
+        let client = MqttClient::connect("127.0.0.1", port, options(MQTT_V311, "pass")).unwrap();
+
+        // コールバックに送る購読と受信キューに入る購読
+        let sensors = ["sensors/+/temp".to_string()];
+        let receiver = client.route(&sensors);
+        assert_eq!(client.subscribe(&sensors, 1).unwrap(), vec![1]);
+        assert_eq!(client.subscribe(&["alerts/#".to_string()], 2).unwrap(), vec![2]);
+
+        client.publish("sensors/line1/temp", b"21.5", 1, false).unwrap();
+        client.publish("alerts/fire", b"!", 2, true).unwrap();
+        client.publish("other", b"ignored", 0, false).unwrap();
+
+        let message = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(message, MqttMessage { topic: "sensors/line1/temp".into(), payload: b"21.5".to_vec(), qos: 1, retain: false });
+        let message = client.receive(Some(Duration::from_secs(5))).unwrap().unwrap();
+        assert_eq!(message, MqttMessage { topic: "alerts/fire".into(), payload: b"!".to_vec(), qos: 2, retain: true });
+        assert_eq!(client.receive(Some(Duration::from_millis(200))).unwrap(), None);
+
+        client.unsubscribe(&sensors).unwrap();
+        assert!(client.inner.routes.lock().unwrap().is_empty());
+        client.publish("sensors/line1/temp", b"22.0", 0, false).unwrap();
+
+        client.close();
+        assert!(client.is_closed());
+        assert_eq!(client.receive(None).unwrap_err().message, UErrorMessage::SocketHasBeenClosed);
+        let received = broker.join().unwrap();
+        assert!(matches!(&received[0], Packet::Connect { client_id, user: Some(user), .. } if client_id.starts_with("uwscr-") && user == "user"));
+        assert!(received.contains(&Packet::PubRel(4, 0)));
+        assert_eq!(received.last(), Some(&Packet::Disconnect));
+    }
+
+    #[test]
+    fn test_mqtt_drop_client() {
+        let (port, broker) = fake_broker(MQTT_V311, "pass");
+        let client = MqttClient::connect("127.0.0.1", port, options(MQTT_V311, "pass")).unwrap();
+        let topics = ["sensors/#".to_string()];
+        let receiver = client.route(&topics);
+        client.subscribe(&topics, 0).unwrap();
+        let weak = client.downgrade();
+        assert_eq!(weak.upgrade(), Some(client.clone()));
+
+        // 弱参照と送り先が残っていてもクライアントを破棄すれば切断される
+        drop(client);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Err(mpsc::RecvTimeoutError::Disconnected));
+        assert!(weak.upgrade().is_none());
+        let received = broker.join().unwrap();
+        assert_eq!(received.last(), Some(&Packet::Disconnect));
+    }
+
+    #[test]
+    fn test_mqtt_connect_refused() {
+        let (port, broker) = fake_broker(MQTT_V5, "pass");
+        let err = MqttClient::connect("127.0.0.1", port, options(MQTT_V5, "wrong")).unwrap_err();
+        assert_eq!(err.message, UErrorMessage::MqttBrokerError("bad user name or password (0x86)".into()));
+        broker.join().unwrap();
+    }
+}
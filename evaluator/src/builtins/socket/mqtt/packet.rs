@@ -0,0 +1,380 @@
+//! MQTTパケットの組み立てと解析 (3.1.1 と 5)
+
+/// MQTT 5のプロトコルレベル
+pub const MQTT_V5: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Connect {
+        version: u8,
+        client_id: String,
+        user: Option<String>,
+        password: Option<String>,
+        keep_alive: u16,
+        clean: bool,
+    },
+    ConnAck { session_present: bool, code: u8 },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        qos: u8,
+        retain: bool,
+        dup: bool,
+        /// QoS 1以上のみ
+        id: Option<u16>,
+    },
+    /// パケットIDと理由コード (3.1.1では常に0)
+    PubAck(u16, u8),
+    PubRec(u16, u8),
+    PubRel(u16, u8),
+    PubComp(u16, u8),
+    Subscribe { id: u16, filters: Vec<(String, u8)> },
+    SubAck { id: u16, codes: Vec<u8> },
+    Unsubscribe { id: u16, filters: Vec<String> },
+    /// 理由コードは5のみ
+    UnsubAck { id: u16, codes: Vec<u8> },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+fn put_u16(buf: &mut Vec<u8>, n: u16) {
+    buf.extend_from_slice(&n.to_be_bytes());
+}
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_u16(buf, bytes.len() as u16);
+    buf.extend_from_slice(bytes);
+}
+fn put_varint(buf: &mut Vec<u8>, mut n: usize) {
+    loop {
+        let mut byte = (n % 128) as u8;
+        n /= 128;
+        if n > 0 {
+            byte |= 0x80;
+        }
+        buf.push(byte);
+        if n == 0 {
+            break;
+        }
+    }
+}
+
+/// パケット本体を先頭から読む
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data.get(self.pos..self.pos + size)
+            .ok_or_else(|| "malformed packet".to_string())?;
+        self.pos += size;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let size = self.u16()? as usize;
+        self.take(size)
+    }
+    fn string(&mut self) -> Result<String, String> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| e.to_string())
+    }
+    fn varint(&mut self) -> Result<usize, String> {
+        let mut n = 0;
+        for i in 0..4 {
+            let byte = self.u8()?;
+            n |= ((byte & 0x7f) as usize) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err("malformed remaining length".into())
+    }
+    /// MQTT 5のプロパティは使わないので読み飛ばす
+    fn skip_properties(&mut self, version: u8) -> Result<(), String> {
+        if version >= MQTT_V5 {
+            let size = self.varint()?;
+            self.take(size)?;
+        }
+        Ok(())
+    }
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        self.pos = self.data.len();
+        rest
+    }
+}
+
+impl Packet {
+    pub fn encode(&self, version: u8) -> Vec<u8> {
+        let v5 = version >= MQTT_V5;
+        let mut body = vec![];
+        let header = match self {
+            Packet::Connect { version, client_id, user, password, keep_alive, clean } => {
+                put_bytes(&mut body, b"MQTT");
+                body.push(*version);
+                let mut flags = 0;
+                if user.is_some() { flags |= 0x80; }
+                if password.is_some() { flags |= 0x40; }
+                if *clean { flags |= 0x02; }
+                body.push(flags);
+                put_u16(&mut body, *keep_alive);
+                if *version >= MQTT_V5 { body.push(0); }
+                put_bytes(&mut body, client_id.as_bytes());
+                if let Some(user) = user { put_bytes(&mut body, user.as_bytes()); }
+                if let Some(password) = password { put_bytes(&mut body, password.as_bytes()); }
+                0x10
+            },
+            Packet::ConnAck { session_present, code } => {
+                body.push(*session_present as u8);
+                body.push(*code);
+                if v5 { body.push(0); }
+                0x20
+            },
+            Packet::Publish { topic, payload, qos, retain, dup, id } => {
+                put_bytes(&mut body, topic.as_bytes());
+                if let Some(id) = id { put_u16(&mut body, *id); }
+                if v5 { body.push(0); }
+                body.extend_from_slice(payload);
+                0x30 | (*dup as u8) << 3 | qos << 1 | *retain as u8
+            },
+            Packet::PubAck(id, code) |
+            Packet::PubRec(id, code) |
+            Packet::PubRel(id, code) |
+            Packet::PubComp(id, code) => {
+                put_u16(&mut body, *id);
+                // 成功の場合は理由コードを省略できる
+                if v5 && *code != 0 { body.push(*code); }
+                match self {
+                    Packet::PubAck(..) => 0x40,
+                    Packet::PubRec(..) => 0x50,
+                    Packet::PubRel(..) => 0x62,
+                    _ => 0x70,
+                }
+            },
+            Packet::Subscribe { id, filters } => {
+                put_u16(&mut body, *id);
+                if v5 { body.push(0); }
+                for (filter, qos) in filters {
+                    put_bytes(&mut body, filter.as_bytes());
+                    body.push(*qos);
+                }
+                0x82
+            },
+            Packet::SubAck { id, codes } => {
+                put_u16(&mut body, *id);
+                if v5 { body.push(0); }
+                body.extend_from_slice(codes);
+                0x90
+            },
+            Packet::Unsubscribe { id, filters } => {
+                put_u16(&mut body, *id);
+                if v5 { body.push(0); }
+                for filter in filters {
+                    put_bytes(&mut body, filter.as_bytes());
+                }
+                0xa2
+            },
+            Packet::UnsubAck { id, codes } => {
+                put_u16(&mut body, *id);
+                if v5 {
+                    body.push(0);
+                    body.extend_from_slice(codes);
+                }
+                0xb0
+            },
+            Packet::PingReq => 0xc0,
+            Packet::PingResp => 0xd0,
+            Packet::Disconnect => 0xe0,
+        };
+        let mut packet = vec![header];
+        put_varint(&mut packet, body.len());
+        packet.extend(body);
+        packet
+    }
+
+    /// 受信データの先頭からパケットを1つ取り出す、データが足りなければNone
+    pub fn decode(buf: &mut Vec<u8>, version: u8) -> Result<Option<Packet>, String> {
+        let Some(&header) = buf.first() else {
+            return Ok(None);
+        };
+        // 残りの長さは最大4バイト
+        let mut size = 0;
+        let mut offset = 1;
+        loop {
+            let Some(&byte) = buf.get(offset) else {
+                return Ok(None);
+            };
+            size |= ((byte & 0x7f) as usize) << (7 * (offset - 1));
+            offset += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if offset > 4 {
+                return Err("malformed remaining length".into());
+            }
+        }
+        if buf.len() < offset + size {
+            return Ok(None);
+        }
+        let data = buf.drain(..offset + size).skip(offset).collect::<Vec<_>>();
+        let mut r = Reader { data: &data, pos: 0 };
+        let v5 = version >= MQTT_V5;
+        let ack = |r: &mut Reader| -> Result<(u16, u8), String> {
+            let id = r.u16()?;
+            let code = if r.is_empty() { 0 } else { r.u8()? };
+            Ok((id, code))
+        };
+        let packet = match header >> 4 {
+            1 => {
+                if r.bytes()? != b"MQTT" {
+                    return Err("unsupported protocol".into());
+                }
+                let version = r.u8()?;
+                let flags = r.u8()?;
+                let keep_alive = r.u16()?;
+                r.skip_properties(version)?;
+                let client_id = r.string()?;
+                if flags & 0x04 != 0 {
+                    // 遺言メッセージは使わない
+                    r.skip_properties(version)?;
+                    r.bytes()?;
+                    r.bytes()?;
+                }
+                let user = if flags & 0x80 != 0 { Some(r.string()?) } else { None };
+                let password = if flags & 0x40 != 0 { Some(r.string()?) } else { None };
+                Packet::Connect { version, client_id, user, password, keep_alive, clean: flags & 0x02 != 0 }
+            },
+            2 => {
+                let flags = r.u8()?;
+                let code = r.u8()?;
+                Packet::ConnAck { session_present: flags & 0x01 != 0, code }
+            },
+            3 => {
+                let qos = (header >> 1) & 0x03;
+                let topic = r.string()?;
+                let id = if qos > 0 { Some(r.u16()?) } else { None };
+                r.skip_properties(version)?;
+                let payload = r.rest().to_vec();
+                Packet::Publish { topic, payload, qos, retain: header & 0x01 != 0, dup: header & 0x08 != 0, id }
+            },
+            4 => ack(&mut r).map(|(id, code)| Packet::PubAck(id, code))?,
+            5 => ack(&mut r).map(|(id, code)| Packet::PubRec(id, code))?,
+            6 => ack(&mut r).map(|(id, code)| Packet::PubRel(id, code))?,
+            7 => ack(&mut r).map(|(id, code)| Packet::PubComp(id, code))?,
+            8 => {
+                let id = r.u16()?;
+                r.skip_properties(version)?;
+                let mut filters = vec![];
+                while ! r.is_empty() {
+                    filters.push((r.string()?, r.u8()? & 0x03));
+                }
+                Packet::Subscribe { id, filters }
+            },
+            9 => {
+                let id = r.u16()?;
+                r.skip_properties(version)?;
+                Packet::SubAck { id, codes: r.rest().to_vec() }
+            },
+            10 => {
+                let id = r.u16()?;
+                r.skip_properties(version)?;
+                let mut filters = vec![];
+                while ! r.is_empty() {
+                    filters.push(r.string()?);
+                }
+                Packet::Unsubscribe { id, filters }
+            },
+            11 => {
+                let id = r.u16()?;
+                let codes = if v5 {
+                    r.skip_properties(version)?;
+                    r.rest().to_vec()
+                } else {
+                    vec![]
+                };
+                Packet::UnsubAck { id, codes }
+            },
+            12 => Packet::PingReq,
+            13 => Packet::PingResp,
+            14 => Packet::Disconnect,
+            t => return Err(format!("unknown packet type: {t}")),
+        };
+        Ok(Some(packet))
+    }
+}
+
+/// トピックがトピックフィルタに一致するかどうか
+/// - `+` は1階層、`#` は以下のすべての階層に一致する
+/// - `$` で始まるトピックはワイルドカードで始まるフィルタには一致しない
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut filter = filter.split('/');
+    let mut topic = topic.split('/');
+    loop {
+        match (filter.next(), topic.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {},
+            (Some(f), Some(t)) if f == t => {},
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let packets = [
+            Packet::Connect { version: 4, client_id: "id".into(), user: Some("u".into()), password: Some("p".into()), keep_alive: 60, clean: true },
+            Packet::ConnAck { session_present: false, code: 0 },
+            Packet::Publish { topic: "a/b".into(), payload: vec![0; 200], qos: 2, retain: true, dup: false, id: Some(7) },
+            Packet::PubRel(7, 0),
+            Packet::Subscribe { id: 1, filters: vec![("a/#".into(), 1), ("b/+".into(), 0)] },
+            Packet::SubAck { id: 1, codes: vec![1, 0x80] },
+            Packet::Unsubscribe { id: 2, filters: vec!["a/#".into()] },
+            Packet::PingReq,
+        ];
+        for version in [4, MQTT_V5] {
+            let mut buf = packets.iter().flat_map(|p| p.encode(version)).collect::<Vec<_>>();
+            let mut decoded = vec![];
+            while let Some(packet) = Packet::decode(&mut buf, version).unwrap() {
+                decoded.push(packet);
+            }
+            assert_eq!(decoded, packets);
+        }
+        assert_eq!(Packet::UnsubAck { id: 3, codes: vec![0] }.encode(4), [0xb0, 2, 0, 3]);
+        // 途中までしか受信していない
+        let mut partial = packets[2].encode(4)[..10].to_vec();
+        assert_eq!(Packet::decode(&mut partial, 4).unwrap(), None);
+        assert_eq!(partial.len(), 10);
+    }
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches("sensors/+/temp", "sensors/line1/temp"));
+        assert!(! topic_matches("sensors/+/temp", "sensors/line1/humidity"));
+        assert!(! topic_matches("sensors/+", "sensors/line1/temp"));
+        assert!(topic_matches("sensors/#", "sensors"));
+        assert!(topic_matches("sensors/#", "sensors/line1/temp"));
+        assert!(topic_matches("#", "a/b"));
+        assert!(! topic_matches("#", "$SYS/broker"));
+        assert!(topic_matches("$SYS/#", "$SYS/broker"));
+        assert!(! topic_matches("a/b", "a/b/c"));
+    }
+}
@@ -1,13 +1,172 @@
 #![allow(clippy::result_large_err)]
 
-use std::io::{BufRead, BufReader, Read, Write};
-use std::net::TcpStream;
-use std::time::Duration;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use super::SocketResult;
+use native_tls::{Certificate, TlsConnector, TlsStream};
 
-// #[derive(Debug, Clone)]
-pub struct TcpClient;
+use super::{SocketResult, SOCKET_CLOSED_ERROR};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+
+/// 一度に受信するサイズ
+const READ_CHUNK_SIZE: usize = 8192;
+/// 受信待ちでロックを保持する最長時間、この間隔で他スレッドからの送信を受け付ける
+const RECV_SLICE: Duration = Duration::from_millis(100);
+
+/// io::Errorをソケットエラーにする、タイムアウトは専用のエラーにする
+pub(super) fn socket_error(e: io::Error) -> UError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock |
+        io::ErrorKind::TimedOut => UError::new(UErrorKind::SocketError, UErrorMessage::SocketTimeout),
+        // Connection::recv_untilで上限を超えた
+        io::ErrorKind::FileTooLarge => UError::new(UErrorKind::SocketError, UErrorMessage::SocketDataTooLarge),
+        _ => UError::new(UErrorKind::SocketError, UErrorMessage::Any(e.to_string())),
+    }
+}
+
+/// TLS接続の設定
+#[derive(Debug, Default)]
+pub struct TlsOptions {
+    /// 証明書とホスト名を検証しない
+    pub insecure: bool,
+    /// 追加で信頼するCA証明書 (PEMまたはDER)
+    pub ca: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub(super) enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+impl Stream {
+    pub(super) fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: Stream,
+    /// 受信済みでまだ返していないデータ
+    pending: Vec<u8>,
+    peer: String,
+    /// 受信のタイムアウト、ソケットにはRECV_SLICEずつ設定する
+    read_timeout: Option<Duration>,
+}
+impl Connection {
+    /// 受信してpendingに追加する、0なら接続が閉じられた
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut buf = [0; READ_CHUNK_SIZE];
+        let size = self.stream.read(&mut buf)?;
+        self.pending.extend_from_slice(&buf[..size]);
+        Ok(size)
+    }
+    fn take(&mut self, size: usize) -> Vec<u8> {
+        self.pending.drain(..size).collect()
+    }
+    /// 受信済みのデータを最大sizeまで返す、なければ一度だけ受信を待つ
+    fn recv(&mut self, size: usize) -> io::Result<Vec<u8>> {
+        if self.pending.is_empty() {
+            self.fill()?;
+        }
+        let size = size.min(self.pending.len());
+        Ok(self.take(size))
+    }
+    /// sizeバイトになるまで受信する
+    fn recv_exact(&mut self, size: usize) -> io::Result<Vec<u8>> {
+        while self.pending.len() < size {
+            if self.fill()? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+        }
+        Ok(self.take(size))
+    }
+    /// 区切りまで受信し、区切りを除いたデータを返す
+    /// 区切りが来ないまま接続が閉じられた場合は残りのデータを返し、データもなければNone
+    /// 区切りまでのデータがlimitバイトを超えたらFileTooLargeエラー
+    fn recv_until(&mut self, delimiter: &[u8], limit: usize) -> io::Result<Option<Vec<u8>>> {
+        let mut searched: usize = 0;
+        loop {
+            let start = searched.saturating_sub(delimiter.len().saturating_sub(1));
+            let found = self.pending[start..].windows(delimiter.len())
+                .position(|w| w == delimiter);
+            if found.map_or(self.pending.len(), |pos| start + pos) > limit {
+                return Err(io::Error::from(io::ErrorKind::FileTooLarge));
+            }
+            if let Some(pos) = found {
+                let data = self.take(start + pos);
+                self.pending.drain(..delimiter.len());
+                return Ok(Some(data));
+            }
+            searched = self.pending.len();
+            if self.fill()? == 0 {
+                let rest = self.take(self.pending.len());
+                return Ok((! rest.is_empty()).then_some(rest));
+            }
+        }
+    }
+    fn close(&mut self) {
+        match &mut self.stream {
+            Stream::Plain(stream) => {
+                let _ = stream.shutdown(Shutdown::Both);
+            },
+            Stream::Tls(stream) => {
+                let _ = stream.shutdown();
+            },
+        }
+    }
+}
+
+/// 接続を維持するTCPクライアント
+#[derive(Debug, Clone)]
+pub struct TcpClient {
+    conn: Arc<Mutex<Option<Connection>>>,
+    /// 受信待ちのスレッドを止めずに切断するためのハンドル
+    raw: Arc<TcpStream>,
+}
+impl std::fmt::Display for TcpClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.conn.try_lock().as_deref() {
+            Ok(Some(conn)) => write!(f, "TcpClient[{}]", conn.peer),
+            Ok(None) => write!(f, "TcpClient (Closed)"),
+            Err(_) => write!(f, "TcpClient"),
+        }
+    }
+}
+impl PartialEq for TcpClient {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.conn, &other.conn)
+    }
+}
 impl TcpClient {
     /// サーバーにデータを送信し、レスポンスを得る
     pub fn send(address: &str, port: u16, buf: &[u8]) -> SocketResult<Vec<u8>> {
@@ -19,6 +178,309 @@ impl TcpClient {
         stream.read_to_end(&mut buf)?;
         Ok(buf)
     }
+    /// サーバーに接続する
+    /// - timeout: 接続のタイムアウト、送受信のタイムアウトの初期値にもなる
+    /// - tls: SomeならTLSで接続する
+    pub fn connect(host: &str, port: u16, timeout: Option<Duration>, tls: Option<TlsOptions>) -> SocketResult<Self> {
+        let tcp = Self::connect_tcp(host, port, timeout)?;
+        let raw = tcp.try_clone().map_err(socket_error)?;
+        let peer = format!("{host}:{port}");
+
+        let stream = match tls {
+            Some(options) => {
+                let connector = Self::tls_connector(options)?;
+                let stream = connector.connect(host, tcp)
+                    .map_err(|e| UError::new(UErrorKind::SocketError, UErrorMessage::Any(e.to_string())))?;
+                Stream::Tls(Box::new(stream))
+            },
+            None => Stream::Plain(tcp),
+        };
+        Ok(Self::new(stream, raw, peer, timeout))
+    }
+    /// 名前解決したアドレスに順に接続を試み、送受信のタイムアウトを設定する
+    pub(super) fn connect_tcp(host: &str, port: u16, timeout: Option<Duration>) -> SocketResult<TcpStream> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{host}:{port}"));
+        let mut connected = None;
+        for addr in (host, port).to_socket_addrs().map_err(socket_error)? {
+            let result = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match result {
+                Ok(stream) => {
+                    connected = Some(stream);
+                    break;
+                },
+                Err(e) => last_error = e,
+            }
+        }
+        let tcp = connected.ok_or_else(|| socket_error(last_error))?;
+        Self::set_options(&tcp, timeout).map_err(socket_error)?;
+        Ok(tcp)
+    }
+    /// 受け付けた接続からTcpClientを作る
+    fn from_stream(stream: TcpStream, peer: SocketAddr, timeout: Option<Duration>) -> io::Result<Self> {
+        stream.set_nonblocking(false)?;
+        Self::set_options(&stream, timeout)?;
+        let raw = stream.try_clone()?;
+        Ok(Self::new(Stream::Plain(stream), raw, peer.to_string(), timeout))
+    }
+    fn new(stream: Stream, raw: TcpStream, peer: String, read_timeout: Option<Duration>) -> Self {
+        let conn = Connection { stream, pending: vec![], peer, read_timeout };
+        Self { conn: Arc::new(Mutex::new(Some(conn))), raw: Arc::new(raw) }
+    }
+    fn set_options(stream: &TcpStream, timeout: Option<Duration>) -> io::Result<()> {
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        stream.set_nodelay(true)
+    }
+    pub(super) fn tls_connector(options: TlsOptions) -> SocketResult<TlsConnector> {
+        let tls_error = |e: native_tls::Error| UError::new(UErrorKind::SocketError, UErrorMessage::Any(e.to_string()));
+        let mut builder = TlsConnector::builder();
+        builder
+            .danger_accept_invalid_certs(options.insecure)
+            .danger_accept_invalid_hostnames(options.insecure);
+        if let Some(ca) = options.ca {
+            let cert = Certificate::from_pem(&ca)
+                .or_else(|_| Certificate::from_der(&ca))
+                .map_err(tls_error)?;
+            builder.add_root_certificate(cert);
+        }
+        builder.build().map_err(tls_error)
+    }
+    fn use_conn<T>(&self, f: impl FnOnce(&mut Connection) -> io::Result<T>) -> SocketResult<T> {
+        let mut guard = self.conn.lock().unwrap();
+        let conn = guard.as_mut()
+            .ok_or(SOCKET_CLOSED_ERROR)?;
+        f(conn).map_err(socket_error)
+    }
+    /// 受信を伴う処理を行う
+    ///
+    /// 受信待ちの間もRECV_SLICEごとにロックを解放して他スレッドからの送信を妨げない\
+    /// fはタイムアウトで中断されても受信済みのデータをpendingに残すため、そのままやり直せる
+    fn use_conn_recv<T>(&self, mut f: impl FnMut(&mut Connection) -> io::Result<T>) -> SocketResult<T> {
+        let mut deadline = None;
+        loop {
+            {
+                let mut guard = self.conn.lock().unwrap();
+                let conn = guard.as_mut()
+                    .ok_or(SOCKET_CLOSED_ERROR)?;
+                let deadline = *deadline.get_or_insert_with(|| conn.read_timeout.map(|timeout| Instant::now() + timeout));
+                let slice = match deadline {
+                    Some(deadline) => deadline.saturating_duration_since(Instant::now())
+                        .clamp(Duration::from_millis(1), RECV_SLICE),
+                    None => RECV_SLICE,
+                };
+                conn.stream.tcp().set_read_timeout(Some(slice)).map_err(socket_error)?;
+                let result = f(conn);
+                let timed_out = matches!(&result, Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut));
+                if ! timed_out || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    // Readとして使われる場合に備えて本来のタイムアウトに戻す
+                    conn.stream.tcp().set_read_timeout(conn.read_timeout).map_err(socket_error)?;
+                    return result.map_err(socket_error);
+                }
+            }
+            thread::yield_now();
+        }
+    }
+    /// データを送信し、送信したサイズを返す
+    pub fn write(&self, buf: &[u8]) -> SocketResult<usize> {
+        self.use_conn(|conn| {
+            conn.stream.write_all(buf)?;
+            conn.stream.flush()?;
+            Ok(buf.len())
+        })
+    }
+    /// 最大sizeバイトを受信する
+    pub fn recv(&self, size: usize) -> SocketResult<Vec<u8>> {
+        self.use_conn_recv(|conn| conn.recv(size))
+    }
+    /// ちょうどsizeバイトを受信する
+    pub fn recv_exact(&self, size: usize) -> SocketResult<Vec<u8>> {
+        self.use_conn_recv(|conn| conn.recv_exact(size))
+    }
+    /// 区切りまで受信する
+    pub fn recv_until(&self, delimiter: &[u8]) -> SocketResult<Option<Vec<u8>>> {
+        self.use_conn_recv(|conn| conn.recv_until(delimiter, usize::MAX))
+    }
+    /// 区切りまで受信する、区切りまでがlimitバイトを超えたらエラー
+    pub fn recv_until_limit(&self, delimiter: &[u8], limit: usize) -> SocketResult<Option<Vec<u8>>> {
+        self.use_conn_recv(|conn| conn.recv_until(delimiter, limit))
+    }
+    /// 1行受信する、改行 (LFまたはCRLF) は含まない
+    pub fn recv_line(&self) -> SocketResult<Option<String>> {
+        let line = self.recv_until(b"\n")?.map(|mut line| {
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+            String::from_utf8_lossy(&line).to_string()
+        });
+        Ok(line)
+    }
+    /// 送受信のタイムアウトを設定する、Noneなら無制限
+    pub fn set_timeout(&self, read: Option<Duration>, write: Option<Duration>) -> SocketResult<()> {
+        self.use_conn(|conn| {
+            conn.read_timeout = read;
+            conn.stream.tcp().set_read_timeout(read)?;
+            conn.stream.tcp().set_write_timeout(write)
+        })
+    }
+    pub fn close(&self) {
+        // 別スレッドが受信待ちでロックを保持していても先に切断して待ちを解除させる
+        let _ = self.raw.shutdown(Shutdown::Both);
+        let mut guard = self.conn.lock().unwrap();
+        if let Some(mut conn) = guard.take() {
+            conn.close();
+        }
+    }
+    fn is_closed(&self) -> bool {
+        matches!(self.conn.try_lock().as_deref(), Ok(None))
+    }
+    /// 受信タイムアウトのみを変更する、受信中の他スレッドを待たない
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.raw.set_read_timeout(timeout)
+    }
+    fn use_conn_io<T>(&self, f: impl FnOnce(&mut Connection) -> io::Result<T>) -> io::Result<T> {
+        let mut guard = self.conn.lock().unwrap();
+        let conn = guard.as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        f(conn)
+    }
+}
+/// 他のプロトコルの下位ストリームとして使う
+impl Read for TcpClient {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.use_conn_io(|conn| {
+            if conn.pending.is_empty() {
+                return conn.stream.read(buf);
+            }
+            let size = buf.len().min(conn.pending.len());
+            buf[..size].copy_from_slice(&conn.take(size));
+            Ok(size)
+        })
+    }
+}
+impl Write for TcpClient {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.use_conn_io(|conn| conn.stream.write(buf))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.use_conn_io(|conn| conn.stream.flush())
+    }
+}
+
+/// 接続待ちの確認間隔
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// 複数のクライアントをそれぞれ別スレッドで処理するTCPサーバー
+///
+/// 停止するかオブジェクトが破棄されるまでバックグラウンドで接続を待ち受ける
+#[derive(Debug, Clone)]
+pub struct TcpServer {
+    inner: Arc<ServerInner>,
+}
+#[derive(Debug)]
+struct ServerInner {
+    local: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    /// 処理中の接続
+    clients: Arc<Mutex<Vec<TcpClient>>>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+impl Drop for ServerInner {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+impl ServerInner {
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        let handle = self.handle.lock().unwrap().take();
+        if let Some(handle) = handle {
+            // 待ち受けスレッド自身から止められた場合は待たない
+            if handle.thread().id() != thread::current().id() {
+                let _ = handle.join();
+            }
+        }
+        let clients = std::mem::take(&mut *self.clients.lock().unwrap());
+        for client in clients {
+            client.close();
+        }
+    }
+}
+impl std::fmt::Display for TcpServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_stopped() {
+            write!(f, "TcpServer (Stopped)")
+        } else {
+            write!(f, "TcpServer[{}]", self.inner.local)
+        }
+    }
+}
+impl PartialEq for TcpServer {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+impl TcpServer {
+    /// 待ち受けを開始する
+    /// - timeout: 各接続の送受信タイムアウト
+    /// - factory: 接続ごとに呼ばれ、その接続を処理するハンドラを返す
+    ///
+    /// ハンドラは接続ごとのスレッドで実行され、ハンドラを抜けると接続は閉じられる
+    pub fn start<F, H>(address: &str, port: u16, timeout: Option<Duration>, mut factory: F) -> SocketResult<Self>
+    where
+        F: FnMut() -> H + Send + 'static,
+        H: FnOnce(TcpClient, SocketAddr) + Send + 'static,
+    {
+        let listener = std::net::TcpListener::bind((address, port)).map_err(socket_error)?;
+        listener.set_nonblocking(true).map_err(socket_error)?;
+        let local = listener.local_addr().map_err(socket_error)?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let clients = Arc::new(Mutex::new(Vec::<TcpClient>::new()));
+
+        let handle = {
+            let stopped = stopped.clone();
+            let clients = clients.clone();
+            thread::spawn(move || {
+                while ! stopped.load(Ordering::Relaxed) {
+                    let (stream, peer) = match listener.accept() {
+                        Ok(accepted) => accepted,
+                        Err(_) => {
+                            thread::sleep(ACCEPT_INTERVAL);
+                            continue;
+                        },
+                    };
+                    let Ok(client) = TcpClient::from_stream(stream, peer, timeout) else {
+                        continue;
+                    };
+                    {
+                        let mut clients = clients.lock().unwrap();
+                        clients.retain(|c| ! c.is_closed());
+                        clients.push(client.clone());
+                    }
+                    let handler = factory();
+                    thread::spawn(move || {
+                        handler(client.clone(), peer);
+                        client.close();
+                    });
+                }
+            })
+        };
+        let inner = ServerInner { local, stopped, clients, handle: Mutex::new(Some(handle)) };
+        Ok(Self { inner: Arc::new(inner) })
+    }
+    /// 待ち受けているアドレス
+    pub fn local_addr(&self) -> SocketAddr {
+        self.inner.local
+    }
+    pub fn is_stopped(&self) -> bool {
+        self.inner.stopped.load(Ordering::Relaxed)
+    }
+    /// 待ち受けを終了し、処理中の接続もすべて閉じる
+    pub fn stop(&self) {
+        self.inner.stop();
+    }
 }
 
 #[derive(Debug)]
@@ -67,3 +529,178 @@ pub enum EndOfData {
     Crlf,
     Byte(u8),
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tcp_client() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"hello\r\nworld\nab|cd|1234").unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&buf).unwrap();
+        });
+
+        let timeout = Some(Duration::from_secs(5));
+        let client = TcpClient::connect("127.0.0.1", port, timeout, None).unwrap();
+        assert_eq!(client.recv_line().unwrap().as_deref(), Some("hello"));
+        assert_eq!(client.recv_line().unwrap().as_deref(), Some("world"));
+        assert_eq!(client.recv_until(b"|").unwrap(), Some(b"ab".to_vec()));
+        assert_eq!(client.recv_until(b"|").unwrap(), Some(b"cd".to_vec()));
+        assert_eq!(client.recv_exact(4).unwrap(), b"1234");
+        assert_eq!(client.write(b"ping").unwrap(), 4);
+        assert_eq!(client.recv_exact(4).unwrap(), b"ping");
+        server.join().unwrap();
+        // 接続が閉じられた
+        assert_eq!(client.recv_until(b"|").unwrap(), None);
+        assert!(client.recv_exact(1).is_err());
+
+        client.close();
+        assert_eq!(client.recv(1).unwrap_err().message, UErrorMessage::SocketHasBeenClosed);
+    }
+
+    #[test]
+    fn test_tcp_server() {
+        let server = TcpServer::start("127.0.0.1", 0, None, || {
+            |client: TcpClient, peer: SocketAddr| {
+                // 複数のメッセージをやり取りする
+                while let Ok(Some(line)) = client.recv_line() {
+                    let _ = client.write(format!("{line}@{}\n", peer.ip()).as_bytes());
+                }
+            }
+        }).unwrap();
+        let port = server.local_addr().port();
+        let timeout = Some(Duration::from_secs(5));
+        // 複数のクライアントを同時に処理する
+        let a = TcpClient::connect("127.0.0.1", port, timeout, None).unwrap();
+        let b = TcpClient::connect("127.0.0.1", port, timeout, None).unwrap();
+        for n in 0..3 {
+            a.write(format!("a{n}\n").as_bytes()).unwrap();
+            b.write(format!("b{n}\n").as_bytes()).unwrap();
+            assert_eq!(b.recv_line().unwrap(), Some(format!("b{n}@127.0.0.1")));
+            assert_eq!(a.recv_line().unwrap(), Some(format!("a{n}@127.0.0.1")));
+        }
+        // 停止すると処理中の接続も閉じられる
+        server.stop();
+        assert!(server.is_stopped());
+        assert_eq!(a.recv_line().unwrap(), None);
+        assert!(TcpClient::connect("127.0.0.1", port, timeout, None).is_err());
+    }
+
+    #[test]
+    fn test_tcp_server_timeout() {
+        // 接続ごとのタイムアウトで無応答のクライアントを切断する
+        let server = TcpServer::start("127.0.0.1", 0, Some(Duration::from_millis(100)), || {
+            |client: TcpClient, _: SocketAddr| {
+                if let Err(e) = client.recv_line() {
+                    let _ = client.write(format!("{:?}\n", e.message).as_bytes());
+                }
+            }
+        }).unwrap();
+        let port = server.local_addr().port();
+        let client = TcpClient::connect("127.0.0.1", port, Some(Duration::from_secs(5)), None).unwrap();
+        let started = Instant::now();
+        assert_eq!(client.recv_line().unwrap().as_deref(), Some("SocketTimeout"));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!(client.recv_line().unwrap(), None);
+        server.stop();
+    }
+
+    #[test]
+    fn test_tcp_server_peer_close() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let server = TcpServer::start("127.0.0.1", 0, None, move || {
+            let tx = tx.clone();
+            move |client: TcpClient, _: SocketAddr| {
+                let mut count = 0;
+                while let Ok(Some(line)) = client.recv_line() {
+                    count += 1;
+                    let _ = client.write(format!("{line}\n").as_bytes());
+                }
+                // クライアントが切断したらハンドラを抜ける
+                tx.send(count).unwrap();
+            }
+        }).unwrap();
+        let port = server.local_addr().port();
+        let timeout = Some(Duration::from_secs(5));
+        for n in 1..=2 {
+            let client = TcpClient::connect("127.0.0.1", port, timeout, None).unwrap();
+            for i in 0..n {
+                client.write(format!("{i}\n").as_bytes()).unwrap();
+                assert_eq!(client.recv_line().unwrap(), Some(i.to_string()));
+            }
+            client.close();
+            // 切断後もサーバーは次の接続を受け付ける
+            assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), n);
+        }
+        assert!(! server.is_stopped());
+        server.stop();
+    }
+
+    #[test]
+    fn test_tcp_server_concurrent() {
+        const CLIENTS: usize = 8;
+        // すべての接続が揃うまで応答しない、接続を順番に処理していたら揃わずにタイムアウトする
+        let barrier = Arc::new(std::sync::Barrier::new(CLIENTS));
+        let server = TcpServer::start("127.0.0.1", 0, None, move || {
+            let barrier = barrier.clone();
+            move |client: TcpClient, _: SocketAddr| {
+                if let Ok(Some(line)) = client.recv_line() {
+                    barrier.wait();
+                    let _ = client.write(format!("{line}!\n").as_bytes());
+                }
+            }
+        }).unwrap();
+        let port = server.local_addr().port();
+        let handles = (0..CLIENTS).map(|n| {
+            thread::spawn(move || {
+                let client = TcpClient::connect("127.0.0.1", port, Some(Duration::from_secs(5)), None).unwrap();
+                client.write(format!("{n}\n").as_bytes()).unwrap();
+                client.recv_line()
+            })
+        }).collect::<Vec<_>>();
+        for (n, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap().unwrap(), Some(format!("{n}!")));
+        }
+        server.stop();
+    }
+
+    #[test]
+    fn test_timeout() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = TcpClient::connect("127.0.0.1", port, Some(Duration::from_secs(5)), None).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        client.set_timeout(Some(Duration::from_millis(50)), None).unwrap();
+        stream.write_all(b"part").unwrap();
+        let err = client.recv_until(b"\n").unwrap_err();
+        assert_eq!(err.message, UErrorMessage::SocketTimeout);
+        // タイムアウトしても受信済みのデータは失われない
+        stream.write_all(b"ial\n").unwrap();
+        assert_eq!(client.recv_line().unwrap().as_deref(), Some("partial"));
+    }
+
+    #[test]
+    fn test_send_while_receiving() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = TcpClient::connect("127.0.0.1", port, None, None).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        // 別スレッドが受信待ちの間も送信できる
+        let receiver = client.clone();
+        let receiving = thread::spawn(move || receiver.recv_line());
+        thread::sleep(Duration::from_millis(50));
+        client.write(b"ping\n").unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping\n");
+        stream.write_all(b"pong\n").unwrap();
+        assert_eq!(receiving.join().unwrap().unwrap().as_deref(), Some("pong"));
+    }
+}
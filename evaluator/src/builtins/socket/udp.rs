@@ -1,13 +1,27 @@
 #![allow(clippy::result_large_err)]
 
-use std::net::UdpSocket;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use super::{SocketResult, SOCKET_CLOSED_ERROR};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+
+/// 受信を待つ間にロックを解放する間隔
+const RECV_SLICE: Duration = Duration::from_millis(100);
+/// 受信に使うバッファの最小サイズ
+///
+/// バッファより大きいデータグラムはOSによって切り詰められるかエラーになるため、
+/// 常にデータグラムの最大サイズで受信してから指定サイズに切り詰める
+const MAX_DATAGRAM_SIZE: usize = 65536;
 
 #[derive(Debug, Clone)]
 pub struct UdpClient {
     socket: Arc<RwLock<Option<UdpSocket>>>,
+    /// 受信コールバックの登録ごとに増える、値が変われば以前のコールバックは終了する
+    listener: Arc<AtomicUsize>,
 }
 impl std::fmt::Display for UdpClient {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -35,7 +49,7 @@ impl PartialEq for UdpClient {
 impl UdpClient {
     pub fn new(address: &str, port: u16) -> SocketResult<Self> {
         let socket = UdpSocket::bind((address, port))?;
-        Ok(Self { socket: Arc::new(RwLock::new(Some(socket))) })
+        Ok(Self { socket: Arc::new(RwLock::new(Some(socket))), listener: Arc::new(AtomicUsize::new(0)) })
     }
     fn use_socket<T>(&self, f: impl FnOnce(&UdpSocket) -> SocketResult<T>) -> SocketResult<T> {
         let guard = self.socket.read().unwrap();
@@ -54,19 +68,214 @@ impl UdpClient {
         })
     }
     /// データを受信する
-    /// - size: 受信するデータのサイズ
-    pub fn receive(&self, size: usize) -> SocketResult<(Vec<u8>, String, u16)> {
+    /// - size: 受信するデータのサイズ、これより大きいデータグラムは切り詰められ残りは破棄される
+    /// - timeout: Noneなら受信するまで待つ、タイムアウトしたらOk(None)
+    ///
+    /// 受信を待つ間も一定間隔でロックを解放するため、他スレッドから閉じられる
+    pub fn receive(&self, size: usize, timeout: Option<Duration>) -> SocketResult<Option<(Vec<u8>, String, u16)>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut buf = vec![0; size.max(MAX_DATAGRAM_SIZE)];
+        loop {
+            let slice = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now())
+                    .clamp(Duration::from_millis(1), RECV_SLICE),
+                None => RECV_SLICE,
+            };
+            let received = self.use_socket(|s| {
+                s.set_read_timeout(Some(slice))?;
+                match s.recv_from(&mut buf) {
+                    Ok(received) => Ok(Some(received)),
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            })?;
+            if let Some((bytes_received, addr)) = received {
+                let data = buf[..bytes_received.min(size)].to_vec();
+                return Ok(Some((data, addr.ip().to_string(), addr.port())));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+        }
+    }
+    /// ブロードキャストアドレスへの送信を許可する
+    pub fn set_broadcast(&self, enable: bool) -> SocketResult<()> {
+        self.use_socket(|s| Ok(s.set_broadcast(enable)?))
+    }
+    /// マルチキャストグループに参加する
+    /// - interface: IPv4なら受信するインターフェースのIPアドレス、IPv6ならインターフェース番号、Noneなら既定
+    pub fn join_multicast(&self, group: &str, interface: Option<&str>) -> SocketResult<()> {
+        self.multicast(group, interface, true)
+    }
+    /// マルチキャストグループから脱退する
+    pub fn leave_multicast(&self, group: &str, interface: Option<&str>) -> SocketResult<()> {
+        self.multicast(group, interface, false)
+    }
+    fn multicast(&self, group: &str, interface: Option<&str>, join: bool) -> SocketResult<()> {
+        let invalid = |s: &str| UError::new(UErrorKind::SocketError, UErrorMessage::Any(format!("invalid multicast address: {s}")));
+        let group = group.parse::<IpAddr>().map_err(|_| invalid(group))?;
         self.use_socket(|s| {
-            let mut buf = vec![0; size];
-            let (bytes_received, addr) = s.recv_from(&mut buf)?;
-            let buf = buf[..bytes_received].to_vec();
-            let address = addr.ip().to_string();
-            let port = addr.port();
-            Ok((buf, address, port))
+            match group {
+                IpAddr::V4(group) => {
+                    let interface = match interface {
+                        Some(i) => i.parse::<Ipv4Addr>().map_err(|_| invalid(i))?,
+                        None => Ipv4Addr::UNSPECIFIED,
+                    };
+                    if join {
+                        s.join_multicast_v4(&group, &interface)?;
+                    } else {
+                        s.leave_multicast_v4(&group, &interface)?;
+                    }
+                },
+                IpAddr::V6(group) => {
+                    let interface = match interface {
+                        Some(i) => i.parse::<u32>().map_err(|_| invalid(i))?,
+                        None => 0,
+                    };
+                    if join {
+                        s.join_multicast_v6(&group, interface)?;
+                    } else {
+                        s.leave_multicast_v6(&group, interface)?;
+                    }
+                },
+            }
+            Ok(())
         })
     }
+    /// 受信コールバック用のIDを発行する、以前のIDは無効になる
+    pub fn new_listener(&self) -> usize {
+        self.listener.fetch_add(1, Ordering::SeqCst) + 1
+    }
+    pub fn is_listening(&self, id: usize) -> bool {
+        self.listener.load(Ordering::SeqCst) == id
+    }
+    /// 受信したデータごとにfを呼ぶ
+    ///
+    /// 閉じられるか別のコールバックが登録されるまで続く
+    pub fn listen(&self, id: usize, size: usize, mut f: impl FnMut(Vec<u8>, String, u16)) {
+        while self.is_listening(id) {
+            match self.receive(size, Some(RECV_SLICE)) {
+                Ok(Some((data, address, port))) => f(data, address, port),
+                Ok(None) => {},
+                Err(_) => break,
+            }
+        }
+    }
     pub fn close(&self) {
         let mut write = self.socket.write().unwrap();
         *write = None;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_udp_receive() {
+        let a = UdpClient::new("127.0.0.1", 0).unwrap();
+        let b = UdpClient::new("127.0.0.1", 0).unwrap();
+        let port = |c: &UdpClient| c.use_socket(|s| Ok(s.local_addr()?.port())).unwrap();
+        let (port_a, port_b) = (port(&a), port(&b));
+
+        // 受信しなければタイムアウトする
+        assert_eq!(b.receive(16, Some(Duration::from_millis(50))).unwrap(), None);
+        a.send("127.0.0.1", port_b, b"hello").unwrap();
+        let received = b.receive(16, Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(received, Some((b"hello".to_vec(), "127.0.0.1".to_string(), port_a)));
+
+        // 受信コールバック
+        let received = Arc::new(Mutex::new(vec![]));
+        let id = b.new_listener();
+        let listener = {
+            let b = b.clone();
+            let received = received.clone();
+            std::thread::spawn(move || b.listen(id, 16, |data, _, _| received.lock().unwrap().push(data)))
+        };
+        a.send("127.0.0.1", port_b, b"one").unwrap();
+        a.send("127.0.0.1", port_b, b"two").unwrap();
+        let start = Instant::now();
+        while received.lock().unwrap().len() < 2 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // 閉じればコールバックも終了する
+        b.close();
+        listener.join().unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![b"one".to_vec(), b"two".to_vec()]);
+        assert_eq!(b.receive(16, None).unwrap_err().message, UErrorMessage::SocketHasBeenClosed);
+
+        a.set_broadcast(true).unwrap();
+        assert!(a.use_socket(|s| Ok(s.broadcast()?)).unwrap());
+        assert!(a.join_multicast("not an address", None).is_err());
+    }
+
+    #[test]
+    fn test_udp_truncated() {
+        let a = UdpClient::new("127.0.0.1", 0).unwrap();
+        let b = UdpClient::new("127.0.0.1", 0).unwrap();
+        let port_b = b.use_socket(|s| Ok(s.local_addr()?.port())).unwrap();
+        let timeout = Some(Duration::from_secs(5));
+
+        // バッファより大きいデータグラムは切り詰められ、残りは次の受信に持ち越されない
+        a.send("127.0.0.1", port_b, b"0123456789").unwrap();
+        a.send("127.0.0.1", port_b, b"abc").unwrap();
+        assert_eq!(b.receive(4, timeout).unwrap().unwrap().0, b"0123");
+        assert_eq!(b.receive(4, timeout).unwrap().unwrap().0, b"abc");
+        let large = vec![7; 60000];
+        a.send("127.0.0.1", port_b, &large).unwrap();
+        assert_eq!(b.receive(16, timeout).unwrap().unwrap().0, vec![7; 16]);
+
+        // 受信コールバックも切り詰められたデータで続行する
+        let received = Arc::new(Mutex::new(vec![]));
+        let id = b.new_listener();
+        let listener = {
+            let b = b.clone();
+            let received = received.clone();
+            std::thread::spawn(move || b.listen(id, 2, |data, _, _| received.lock().unwrap().push(data)))
+        };
+        a.send("127.0.0.1", port_b, b"first").unwrap();
+        a.send("127.0.0.1", port_b, b"second").unwrap();
+        let start = Instant::now();
+        while received.lock().unwrap().len() < 2 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        b.new_listener();
+        listener.join().unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![b"fi".to_vec(), b"se".to_vec()]);
+    }
+
+    #[test]
+    fn test_udp_concurrent() {
+        const SENDERS: usize = 8;
+        let b = UdpClient::new("127.0.0.1", 0).unwrap();
+        let port_b = b.use_socket(|s| Ok(s.local_addr()?.port())).unwrap();
+        // 複数の送信元から同時に受信し、送信元ごとに区別できる
+        let handles = (0..SENDERS).map(|n| {
+            std::thread::spawn(move || {
+                let a = UdpClient::new("127.0.0.1", 0).unwrap();
+                a.send("127.0.0.1", port_b, n.to_string().as_bytes()).unwrap();
+                a.use_socket(|s| Ok(s.local_addr()?.port())).unwrap()
+            })
+        }).collect::<Vec<_>>();
+        let mut ports = handles.into_iter()
+            .enumerate()
+            .map(|(n, h)| (h.join().unwrap(), n.to_string().into_bytes()))
+            .collect::<Vec<_>>();
+        let mut received = (0..SENDERS)
+            .map(|_| {
+                let (data, _, port) = b.receive(16, Some(Duration::from_secs(5))).unwrap().unwrap();
+                (port, data)
+            })
+            .collect::<Vec<_>>();
+        ports.sort();
+        received.sort();
+        assert_eq!(received, ports);
+        // 受信待ちの間に別スレッドから閉じられる
+        let receiver = b.clone();
+        let receiving = std::thread::spawn(move || receiver.receive(16, None));
+        std::thread::sleep(Duration::from_millis(50));
+        b.close();
+        assert_eq!(receiving.join().unwrap().unwrap_err().message, UErrorMessage::SocketHasBeenClosed);
+    }
+}
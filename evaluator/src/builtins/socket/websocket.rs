@@ -1,20 +1,75 @@
 #![allow(clippy::result_large_err)]
 
 use tungstenite::stream::MaybeTlsStream;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::http::{HeaderName, HeaderValue};
 pub use tungstenite::Message;
-use std::net::TcpStream;
-use std::sync::{Arc, RwLock};
-use super::SocketResult;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use super::{SocketResult, SOCKET_CLOSED_ERROR, TcpClient, TcpServer};
 use crate::{UError, UErrorKind, UErrorMessage};
 
+/// 受信待ちでロックを保持する最長時間、この間隔で他スレッドからの送信を受け付ける
+const RECV_SLICE: Duration = Duration::from_millis(100);
+
+fn websocket_error(msg: impl std::fmt::Display) -> UError {
+    UError::new(UErrorKind::WebSocketError, UErrorMessage::Any(msg.to_string()))
+}
+
+/// クライアントとして接続したストリーム、またはサーバーが受け付けたストリーム
+#[derive(Debug)]
+enum WsStream {
+    Client(MaybeTlsStream<TcpStream>),
+    Server(TcpClient),
+}
+impl WsStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            WsStream::Client(MaybeTlsStream::Plain(stream)) => stream.set_read_timeout(timeout),
+            WsStream::Client(_) => Ok(()),
+            WsStream::Server(client) => client.set_read_timeout(timeout),
+        }
+    }
+}
+impl Read for WsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            WsStream::Client(stream) => stream.read(buf),
+            WsStream::Server(client) => client.read(buf),
+        }
+    }
+}
+impl Write for WsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            WsStream::Client(stream) => stream.write(buf),
+            WsStream::Server(client) => Write::write(client, buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            WsStream::Client(stream) => stream.flush(),
+            WsStream::Server(client) => client.flush(),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct WebSocketInner {
-    socket: tungstenite::WebSocket<MaybeTlsStream<TcpStream>>,
+    socket: tungstenite::WebSocket<WsStream>,
     closed: bool
 }
 impl WebSocketInner {
     fn close(&mut self) {
         let _ = self.socket.close(None);
+        let _ = self.socket.flush();
+        if let WsStream::Server(client) = self.socket.get_ref() {
+            client.close();
+        }
         self.closed = true;
     }
     fn is_closed(&self) -> bool {
@@ -30,6 +85,8 @@ impl Drop for WebSocketInner {
 #[derive(Debug, Clone)]
 pub struct WebSocket {
     inner: Arc<RwLock<WebSocketInner>>,
+    /// 受信コールバックの登録ごとに増える、値が変われば以前のコールバックは終了する
+    listener: Arc<AtomicUsize>,
 }
 impl std::fmt::Display for WebSocket {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -37,11 +94,13 @@ impl std::fmt::Display for WebSocket {
         if socket.is_closed() {
             write!(f, "WebSocket (Closed)")
         } else {
-            let stream = socket.socket.get_ref();
-            if let MaybeTlsStream::Plain(s) = stream && let Ok(addr) = s.peer_addr() {
-                write!(f, "WebSocket -> {}:{}", addr.ip(), addr.port())
-            } else {
-                write!(f, "WebSocket")
+            match socket.socket.get_ref() {
+                WsStream::Client(MaybeTlsStream::Plain(s)) => match s.peer_addr() {
+                    Ok(addr) => write!(f, "WebSocket -> {}:{}", addr.ip(), addr.port()),
+                    Err(_) => write!(f, "WebSocket"),
+                },
+                WsStream::Client(_) => write!(f, "WebSocket"),
+                WsStream::Server(client) => write!(f, "WebSocket <- {client}"),
             }
         }
     }
@@ -54,27 +113,380 @@ impl PartialEq for WebSocket {
 }
 impl WebSocket {
     pub fn new(uri: &str) -> SocketResult<Self> {
-        let (socket, response) = tungstenite::connect(uri)?;
+        Self::connect(uri, &[], &[])
+    }
+    /// 追加のヘッダやサブプロトコルを指定して接続する
+    pub fn connect(uri: &str, headers: &[(String, String)], protocols: &[String]) -> SocketResult<Self> {
+        let mut request = uri.into_client_request()?;
+        for (key, value) in headers {
+            let key = HeaderName::from_str(key).map_err(websocket_error)?;
+            let value = HeaderValue::from_str(value).map_err(websocket_error)?;
+            request.headers_mut().insert(key, value);
+        }
+        if ! protocols.is_empty() {
+            let value = HeaderValue::from_str(&protocols.join(", ")).map_err(websocket_error)?;
+            request.headers_mut().insert("Sec-WebSocket-Protocol", value);
+        }
+        let uri = request.uri();
+        if uri.scheme_str() != Some("ws") {
+            return Err(websocket_error(format!("unsupported scheme: {uri}")));
+        }
+        let host = uri.host().unwrap_or_default().trim_matches(['[', ']']).to_string();
+        let port = uri.port_u16().unwrap_or(80);
+        let stream = TcpStream::connect((host.as_str(), port)).map_err(websocket_error)?;
+        let stream = WsStream::Client(MaybeTlsStream::Plain(stream));
+        let (socket, response) = tungstenite::client(request, stream)
+            .map_err(websocket_error)?;
         let status = response.status();
         if status.as_u16() >= 400 {
             Err(UError::new(UErrorKind::SocketError, UErrorMessage::Any(status.to_string())))
         } else {
-            let inner = WebSocketInner { socket, closed: false };
-            Ok(Self { inner: Arc::new(RwLock::new(inner))})
+            Ok(Self::from_socket(socket))
         }
     }
+    /// サーバーとして受け付けた接続でハンドシェイクを行う
+    fn accept(client: TcpClient) -> SocketResult<Self> {
+        let socket = tungstenite::accept(WsStream::Server(client))
+            .map_err(websocket_error)?;
+        Ok(Self::from_socket(socket))
+    }
+    fn from_socket(socket: tungstenite::WebSocket<WsStream>) -> Self {
+        let inner = WebSocketInner { socket, closed: false };
+        Self { inner: Arc::new(RwLock::new(inner)), listener: Arc::new(AtomicUsize::new(0)) }
+    }
     pub fn close(&self) {
         let mut socket = self.inner.write().unwrap();
         socket.close();
     }
+    pub fn is_closed(&self) -> bool {
+        self.inner.read().unwrap().is_closed()
+    }
     pub fn send(&self, message: Message) -> SocketResult<()> {
         let mut write = self.inner.write().unwrap();
+        if write.is_closed() {
+            return Err(SOCKET_CLOSED_ERROR);
+        }
         write.socket.send(message)?;
         Ok(())
     }
-    pub fn receive(&self) -> SocketResult<Message> {
-        let mut write = self.inner.write().unwrap();
-        let msg = write.socket.read()?;
-        Ok(msg)
+    /// メッセージを受信する
+    /// - timeout: Noneなら受信するまで待つ、タイムアウトしたらOk(None)
+    ///
+    /// 受信を待つ間も一定間隔でロックを解放するため、他スレッドから送信できる\
+    /// pingを受けたら即座にpongを返す
+    pub fn receive(&self, timeout: Option<Duration>) -> SocketResult<Option<Message>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let slice = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now())
+                    .clamp(Duration::from_millis(1), RECV_SLICE),
+                None => RECV_SLICE,
+            };
+            {
+                let mut inner = self.inner.write().unwrap();
+                if inner.is_closed() {
+                    return Err(SOCKET_CLOSED_ERROR);
+                }
+                inner.socket.get_ref().set_read_timeout(Some(slice)).map_err(websocket_error)?;
+                match inner.socket.read() {
+                    Ok(message) => {
+                        if message.is_ping() {
+                            let _ = inner.socket.flush();
+                        }
+                        return Ok(Some(message));
+                    },
+                    Err(tungstenite::Error::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+            // ロックを待つ他スレッドに譲る
+            std::thread::yield_now();
+        }
+    }
+    /// 受信コールバック用のIDを発行する、以前のIDは無効になる
+    pub fn new_listener(&self) -> usize {
+        self.listener.fetch_add(1, Ordering::SeqCst) + 1
+    }
+    pub fn is_listening(&self, id: usize) -> bool {
+        self.listener.load(Ordering::SeqCst) == id
+    }
+    /// 受信したデータメッセージごとにfを呼ぶ
+    ///
+    /// 接続が閉じられるか別のコールバックが登録されるまで続く
+    pub fn listen(&self, id: usize, mut f: impl FnMut(Message)) {
+        while self.is_listening(id) {
+            match self.receive(Some(RECV_SLICE)) {
+                Ok(Some(message)) => match message {
+                    Message::Text(_) |
+                    Message::Binary(_) => f(message),
+                    Message::Close(_) => break,
+                    _ => {},
+                },
+                Ok(None) => {},
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// 複数のクライアントを受け付けるWebSocketサーバー
+#[derive(Debug, Clone)]
+pub struct WebSocketServer {
+    server: TcpServer,
+    clients: Arc<Mutex<Vec<WebSocket>>>,
+}
+impl std::fmt::Display for WebSocketServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.server.is_stopped() {
+            write!(f, "WebSocketServer (Stopped)")
+        } else {
+            write!(f, "WebSocketServer[{}]", self.server.local_addr())
+        }
     }
-}
\ No newline at end of file
+}
+impl PartialEq for WebSocketServer {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.clients, &other.clients)
+    }
+}
+impl WebSocketServer {
+    /// 待ち受けを開始する
+    /// - factory: 接続ごとに呼ばれ、その接続を処理するハンドラを返す
+    ///
+    /// ハンドラは接続ごとのスレッドで実行され、ハンドラを抜けると接続は閉じられる
+    pub fn start<F, H>(address: &str, port: u16, mut factory: F) -> SocketResult<Self>
+    where
+        F: FnMut() -> H + Send + 'static,
+        H: FnOnce(WebSocket, SocketAddr) + Send + 'static,
+    {
+        let clients = Arc::new(Mutex::new(Vec::<WebSocket>::new()));
+        let server = {
+            let clients = clients.clone();
+            TcpServer::start(address, port, None, move || {
+                let handler = factory();
+                let clients = clients.clone();
+                move |client: TcpClient, peer: SocketAddr| {
+                    let Ok(ws) = WebSocket::accept(client) else {
+                        return;
+                    };
+                    {
+                        let mut clients = clients.lock().unwrap();
+                        clients.retain(|ws| ! ws.is_closed());
+                        clients.push(ws.clone());
+                    }
+                    handler(ws.clone(), peer);
+                    ws.close();
+                    clients.lock().unwrap().retain(|ws| ! ws.is_closed());
+                }
+            })?
+        };
+        Ok(Self { server, clients })
+    }
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.local_addr()
+    }
+    /// 接続中のすべてのクライアントに送信し、送信できた数を返す
+    pub fn broadcast(&self, message: Message) -> usize {
+        let clients = self.clients.lock().unwrap().clone();
+        clients.iter()
+            .filter(|ws| ws.send(message.clone()).is_ok())
+            .count()
+    }
+    /// 接続中のクライアント数
+    pub fn count(&self) -> usize {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|ws| ! ws.is_closed());
+        clients.len()
+    }
+    pub fn stop(&self) {
+        self.server.stop();
+        let clients = std::mem::take(&mut *self.clients.lock().unwrap());
+        for ws in clients {
+            ws.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 受信したテキストを返すサーバー、ハンドラを抜けるとtxに通知する
+    fn echo_server(tx: std::sync::mpsc::Sender<SocketAddr>) -> WebSocketServer {
+        WebSocketServer::start("127.0.0.1", 0, move || {
+            let tx = tx.clone();
+            move |ws: WebSocket, peer: SocketAddr| {
+                while let Ok(Some(message)) = ws.receive(None) {
+                    match message {
+                        Message::Text(text) => {
+                            let _ = ws.send(Message::Text(format!("echo:{text}")));
+                        },
+                        Message::Close(_) => break,
+                        _ => {},
+                    }
+                }
+                let _ = tx.send(peer);
+            }
+        }).unwrap()
+    }
+
+    fn wait_for_count(server: &WebSocketServer, count: usize) {
+        let start = Instant::now();
+        while server.count() != count && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(server.count(), count);
+    }
+
+    #[test]
+    fn test_websocket_server() {
+        let server = WebSocketServer::start("127.0.0.1", 0, || {
+            |ws: WebSocket, _: SocketAddr| {
+                // 受信したテキストをそのまま返す
+                while let Ok(Some(message)) = ws.receive(None) {
+                    match message {
+                        Message::Text(text) => ws.send(Message::Text(format!("echo:{text}"))).unwrap(),
+                        Message::Close(_) => break,
+                        _ => {},
+                    }
+                }
+            }
+        }).unwrap();
+        let uri = format!("ws://{}/", server.local_addr());
+        let headers = vec![("X-Test".to_string(), "1".to_string())];
+        let a = WebSocket::connect(&uri, &headers, &["chat".to_string()]).unwrap();
+        let b = WebSocket::new(&uri).unwrap();
+
+        a.send(Message::Text("hello".into())).unwrap();
+        let received = a.receive(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(received, Some(Message::Text("echo:hello".into())));
+
+        // 受信待ちの間も送信できる
+        assert_eq!(b.receive(Some(Duration::from_millis(50))).unwrap(), None);
+
+        // 全クライアントへの送信
+        while server.count() < 2 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(server.broadcast(Message::Text("all".into())), 2);
+        assert_eq!(b.receive(Some(Duration::from_secs(5))).unwrap(), Some(Message::Text("all".into())));
+
+        // 受信コールバック
+        let received = Arc::new(Mutex::new(vec![]));
+        let id = a.new_listener();
+        let listener = {
+            let a = a.clone();
+            let received = received.clone();
+            std::thread::spawn(move || a.listen(id, |message| received.lock().unwrap().push(message)))
+        };
+        a.send(Message::Text("callback".into())).unwrap();
+        let start = Instant::now();
+        while received.lock().unwrap().len() < 2 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        a.new_listener();
+        listener.join().unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![
+            Message::Text("all".into()),
+            Message::Text("echo:callback".into()),
+        ]);
+
+        server.stop();
+        assert!(b.receive(Some(Duration::from_secs(5))).is_err() || b.is_closed());
+    }
+
+    #[test]
+    fn test_websocket_timeout() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let server = echo_server(tx);
+        let ws = WebSocket::new(&format!("ws://{}/", server.local_addr())).unwrap();
+        // タイムアウトしたらNoneを返し、接続はそのまま使える
+        let start = Instant::now();
+        assert_eq!(ws.receive(Some(Duration::from_millis(150))).unwrap(), None);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert!(! ws.is_closed());
+        ws.send(Message::Text("after".into())).unwrap();
+        assert_eq!(ws.receive(Some(Duration::from_secs(5))).unwrap(), Some(Message::Text("echo:after".into())));
+        server.stop();
+    }
+
+    #[test]
+    fn test_websocket_peer_close() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let server = echo_server(tx);
+        let uri = format!("ws://{}/", server.local_addr());
+
+        // クライアントが切断するとサーバー側のハンドラが終了し、接続数から除かれる
+        let ws = WebSocket::new(&uri).unwrap();
+        wait_for_count(&server, 1);
+        ws.close();
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        wait_for_count(&server, 0);
+        assert_eq!(ws.send(Message::Text("closed".into())).unwrap_err().message, UErrorMessage::SocketHasBeenClosed);
+
+        // サーバーが停止するとクライアント側はCloseを受信するか切断される
+        let ws = WebSocket::new(&uri).unwrap();
+        wait_for_count(&server, 1);
+        server.stop();
+        let received = ws.receive(Some(Duration::from_secs(5)));
+        assert!(matches!(received, Ok(Some(Message::Close(_))) | Err(_)));
+    }
+
+    #[test]
+    fn test_websocket_concurrent() {
+        const CLIENTS: usize = 8;
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let server = echo_server(tx);
+        let uri = format!("ws://{}/", server.local_addr());
+        let handles = (0..CLIENTS).map(|n| {
+            let uri = uri.clone();
+            std::thread::spawn(move || {
+                let ws = WebSocket::new(&uri).unwrap();
+                let mut received = vec![];
+                for i in 0..3 {
+                    ws.send(Message::Text(format!("{n}-{i}"))).unwrap();
+                    received.push(ws.receive(Some(Duration::from_secs(5))).unwrap());
+                }
+                ws.close();
+                received
+            })
+        }).collect::<Vec<_>>();
+        for (n, handle) in handles.into_iter().enumerate() {
+            let expected = (0..3)
+                .map(|i| Some(Message::Text(format!("echo:{n}-{i}"))))
+                .collect::<Vec<_>>();
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+        server.stop();
+    }
+
+    #[test]
+    fn test_broadcast_with_disconnected_client() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let server = echo_server(tx);
+        let uri = format!("ws://{}/", server.local_addr());
+        let clients = (0..3).map(|_| WebSocket::new(&uri).unwrap()).collect::<Vec<_>>();
+        wait_for_count(&server, 3);
+
+        // 1つが切断しても残りのクライアントには届く
+        clients[1].close();
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        wait_for_count(&server, 2);
+        assert_eq!(server.broadcast(Message::Text("all".into())), 2);
+        for ws in [&clients[0], &clients[2]] {
+            assert_eq!(ws.receive(Some(Duration::from_secs(5))).unwrap(), Some(Message::Text("all".into())));
+        }
+        assert!(clients[1].receive(Some(Duration::from_millis(50))).is_err());
+
+        // 切断が検知される前の接続への送信に失敗しても他への送信は続ける
+        drop(clients);
+        let sent = server.broadcast(Message::Text("none".into()));
+        assert!(sent <= 2);
+        wait_for_count(&server, 0);
+        assert_eq!(server.broadcast(Message::Text("none".into())), 0);
+        server.stop();
+    }
+}
@@ -93,6 +93,7 @@ pub fn builtin_func_sets() -> BuiltinFunctionSets {
     sets.add("poff", poff, get_desc!(poff));
     sets.add("sethotkey", sethotkey, get_desc!(sethotkey));
     sets.add("gettime", gettime, get_desc!(gettime));
+    sets.add("datetime", datetime, get_desc!(datetime));
     sets.add("speak", speak, get_desc!(speak));
     sets.add("recostate", recostate, get_desc!(recostate));
     sets.add("dictate", dictate, get_desc!(dictate));
@@ -1162,6 +1163,10 @@ pub enum GTimeOffset {
     G_OFFSET_SECONDS = 3,
     #[strum[props(desc="補正値をミリ秒とする")]]
     G_OFFSET_MILLIS  = 4,
+    #[strum[props(desc="補正値を月とする")]]
+    G_OFFSET_MONTHS  = 5,
+    #[strum[props(desc="補正値を年とする")]]
+    G_OFFSET_YEARS   = 6,
 }
 #[allow(non_camel_case_types)]
 #[derive(Debug, EnumString, EnumProperty, VariantNames, ToPrimitive, FromPrimitive)]
@@ -1193,7 +1198,9 @@ pub enum GTimeWeekDay {
 - G_OFFSET_HOURS: 時間
 - G_OFFSET_MINUTES: 分
 - G_OFFSET_SECONDS: 秒
-- G_OFFSET_MILLIS: ミリ秒"#},
+- G_OFFSET_MILLIS: ミリ秒
+- G_OFFSET_MONTHS: 月
+- G_OFFSET_YEARS: 年"#},
         {o,n="ミリ秒",t="真偽値",d=r#"TRUEなら戻り値をミリ秒にする"#},
     ],
 )]
@@ -1213,6 +1220,27 @@ pub fn gettime(evaluator: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncR
     }
 }
 
+#[builtin_func_desc(
+    desc="DateTimeオブジェクトを作成する",
+    rtype={desc="DateTimeオブジェクト",types="DateTime"}
+    args=[
+        {o,n="日時",t="文字列または数値",d=r#"以下のいずれかを指定、省略時は現在日時
+- 日時文字列: ISO 8601 (RFC 3339)、RFC 2822、またはgettimeが受ける形式
+- 数値: gettimeの戻り値 (2000/01/01からの経過秒数)
+- DateTimeオブジェクト"#},
+        {o,n="タイムゾーン",t="文字列",d=r#"local, UTC, +09:00 のようなUTCからのオフセット、または Asia/Tokyo のようなIANAタイムゾーン名
+省略時は日時文字列にオフセットが含まれればそのオフセット、なければlocal"#},
+        {o,n="書式",t="文字列",d="日時文字列を解釈する書式 (%Y/%m/%d %H:%M:%S 等)"},
+    ],
+)]
+pub fn datetime(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let value = args.get_as_object(0, Some(Object::Empty))?;
+    let zone = args.get_as_object_or_empty(1)?;
+    let fmt = args.get_as_string_or_empty(2)?;
+    let dt = UDateTime::from_object(&value, zone.as_ref(), fmt.as_deref())?;
+    Ok(Object::DateTime(dt))
+}
+
 #[builtin_func_desc(
     desc="音声を再生する",
     args=[
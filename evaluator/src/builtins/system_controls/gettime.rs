@@ -7,7 +7,7 @@ use std::sync::OnceLock;
 use std::fmt::Write;
 
 use chrono::{
-    DateTime, Datelike, Timelike, Weekday, NaiveDate, Duration, NaiveDateTime,
+    DateTime, Datelike, Timelike, Weekday, NaiveDate, Duration, NaiveDateTime, Months,
     offset::{Local, TimeZone},
     format,
     ParseError
@@ -26,23 +26,14 @@ impl GetTime {
         Self { dt }
     }
     fn from_str(dt: &str) -> GetTimeResult<Self> {
-        let naive = match dt.len() {
-            8 => {
-                let s = format!("{dt}000000");
-                NaiveDateTime::parse_from_str(&s, "%Y%m%d%H%M%S")
+        let naive = match parse_naive(dt) {
+            Ok(naive) => naive,
+            Err(e) => {
+                // DateTimeオブジェクトの文字列表現 (RFC 3339) も受ける
+                let dt = DateTime::parse_from_rfc3339(dt).map_err(|_| e)?;
+                return Ok(Self { dt: dt.with_timezone(&Local) });
             },
-            10 => {
-                let mut s = dt.replace("/", "-");
-                s.push_str("000000");
-                NaiveDateTime::parse_from_str(&s, "%F%H%M%S")
-            },
-            14 => NaiveDateTime::parse_from_str(dt, "%Y%m%d%H%M%S"),
-            19 => {
-                let s = dt.replace("/", "-");
-                NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %T")
-            },
-            _ => NaiveDateTime::from_str(dt),
-        }?;
+        };
         let dt = Local.from_local_datetime(&naive).single()
             .ok_or(GetTimeError::NaiveToLocalError)?;
         let gt = Self { dt };
@@ -82,36 +73,27 @@ impl GetTime {
     fn seconds(&self) -> i64 {
         self.dt.timestamp() - Self::get_20000101_sec()
     }
-    fn to_duration(offset: f64, opt: GTimeOffset) -> Duration {
+    fn to_duration(offset: f64, opt: &GTimeOffset) -> Duration {
         let milliseconds = match opt {
             GTimeOffset::G_OFFSET_DAYS => offset * (24 * 60 * 60 * 1000) as f64,
             GTimeOffset::G_OFFSET_HOURS => offset * (60 * 60 * 1000) as f64,
             GTimeOffset::G_OFFSET_MINUTES => offset * (60 * 1000) as f64,
             GTimeOffset::G_OFFSET_SECONDS => offset * 1000_f64,
             GTimeOffset::G_OFFSET_MILLIS => offset,
+            GTimeOffset::G_OFFSET_MONTHS |
+            GTimeOffset::G_OFFSET_YEARS => 0.0,
         } as i64;
         Duration::milliseconds(milliseconds)
     }
     fn set_duration(&mut self, duration: Duration) {
         self.dt += duration;
     }
+    fn set_months(&mut self, months: i32) -> GetTimeResult<()> {
+        self.dt = add_months(self.dt, months).ok_or(GetTimeError::OutOfRange)?;
+        Ok(())
+    }
     fn format(&self, fmt: &str, locale_str: Option<&str>) -> String {
-        let locale = match locale_str {
-            Some(s) => {
-                let value = Self::fix_locale_str(s);
-                match format::Locale::try_from(value.as_str()) {
-                    Ok(l) => l,
-                    Err(_e) => {
-                        dbg!(_e);
-                        format::Locale::ja_JP
-                    },
-                }
-            },
-            None => match *CURRENT_LOCALE {
-                Locale::Jp => format::Locale::ja_JP,
-                Locale::En => format::Locale::en_US,
-            },
-        };
+        let locale = get_locale(locale_str);
         let delayed = self.dt.format_localized(fmt, locale);
         let mut buf = String::new();
         match write!(&mut buf, "{}", delayed) {
@@ -119,6 +101,19 @@ impl GetTime {
             Err(_) => fmt.to_string()
         }
     }
+}
+
+/// ロケール文字列からchronoのロケールを得る、省略時や不明なロケールならUWSCRの言語設定に従う
+pub fn get_locale(locale_str: Option<&str>) -> format::Locale {
+    locale_str
+        .and_then(|s| format::Locale::try_from(GetTime::fix_locale_str(s).as_str()).ok())
+        .unwrap_or(match *CURRENT_LOCALE {
+            Locale::Jp => format::Locale::ja_JP,
+            Locale::En => format::Locale::en_US,
+        })
+}
+
+impl GetTime {
     fn fix_locale_str(locale: &str) -> String {
         let mut split = locale.split(['_', '-', '@']);
         match (split.next(), split.next(), split.next()) {
@@ -139,12 +134,55 @@ impl GetTime {
     }
 }
 
+/// gettimeが受ける形式の日時文字列を解釈する
+///
+/// - yyyymmdd
+/// - yyyy/mm/dd, yyyy-mm-dd
+/// - yyyymmddhhnnss
+/// - yyyy/mm/dd hh:nn:ss, yyyy-mm-dd hh:nn:ss, yyyy-mm-ddThh:nn:ss
+/// - ISO 8601 (オフセットなし)
+pub fn parse_naive(dt: &str) -> Result<NaiveDateTime, ParseError> {
+    match dt.len() {
+        8 => {
+            let s = format!("{dt}000000");
+            NaiveDateTime::parse_from_str(&s, "%Y%m%d%H%M%S")
+        },
+        10 => {
+            let mut s = dt.replace("/", "-");
+            s.push_str("000000");
+            NaiveDateTime::parse_from_str(&s, "%F%H%M%S")
+        },
+        14 => NaiveDateTime::parse_from_str(dt, "%Y%m%d%H%M%S"),
+        19 => {
+            let s = dt.replace("/", "-").replacen('T', " ", 1);
+            NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %T")
+        },
+        _ => NaiveDateTime::from_str(dt),
+    }
+}
+
+/// 2000/01/01 00:00:00 (ローカル時間) のUNIXタイムスタンプ (ミリ秒)
+pub fn base_timestamp_millis() -> i64 {
+    GetTime::get_20000101_milli()
+}
+
+/// 暦の上で月を加算する、該当日がなければ月末になる
+pub fn add_months<Tz: TimeZone>(dt: DateTime<Tz>, months: i32) -> Option<DateTime<Tz>> {
+    let abs = Months::new(months.unsigned_abs());
+    if months < 0 {
+        dt.checked_sub_months(abs)
+    } else {
+        dt.checked_add_months(abs)
+    }
+}
+
 pub type GetTimeResult<T> = Result<T, GetTimeError>;
 pub enum GetTimeError {
     ParseError(ParseError),
     InvalidSecond(i64),
     InvalidMilliSecond(i64),
     NaiveToLocalError,
+    OutOfRange,
 }
 impl From<ParseError> for GetTimeError {
     fn from(e: ParseError) -> Self {
@@ -168,6 +206,10 @@ impl std::fmt::Display for GetTimeError {
                 "ローカル時間への変換に失敗しました",
                 "Failed to convert NaiveDateTime to Local",
             ),
+            GetTimeError::OutOfRange => write_locale!(f,
+                "日時が範囲外です",
+                "Date and time out of range",
+            ),
         }
     }
 }
@@ -217,8 +259,14 @@ pub fn get(dt: Option<String>, offset: f64, opt: GTimeOffset) -> GetTimeResult<G
         None => GetTime::now(),
     };
     if offset != 0.0 {
-        let duration = GetTime::to_duration(offset, opt);
-        gt.set_duration(duration);
+        match opt {
+            GTimeOffset::G_OFFSET_MONTHS => gt.set_months(offset as i32)?,
+            GTimeOffset::G_OFFSET_YEARS => gt.set_months((offset as i32).saturating_mul(12))?,
+            _ => {
+                let duration = GetTime::to_duration(offset, &opt);
+                gt.set_duration(duration);
+            },
+        }
     }
     Ok(gt.into())
 }
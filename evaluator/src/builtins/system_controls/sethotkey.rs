@@ -4,7 +4,7 @@ use crate::{
     object::function::Function
 };
 use util::error::UWSCRErrorTitle;
-use util::winapi::show_message;
+use util::winapi::{show_message, error_exit_code};
 use util::logging::{out_log, LogType};
 use parser::ast::{FuncParam, ParamKind, Expression};
 
@@ -145,7 +145,7 @@ impl UWindow<()> for SetHotKeyWindow {
                             let msg = err.errror_text_with_line();
                             out_log(&msg, LogType::Error);
                             show_message(&msg, &UWSCRErrorTitle::SetHotKey.to_string(), true);
-                            std::process::exit(0);
+                            std::process::exit(error_exit_code());
                         }
                     }
                     DefWindowProcW(hwnd, msg, wparam, lparam)
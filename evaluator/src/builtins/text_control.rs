@@ -8,6 +8,8 @@ use util::winapi::{
 };
 
 use regex::Regex;
+use serde_json::Value as JsonValue;
+use std::sync::{Arc, Mutex};
 use strum_macros::{EnumString, VariantNames};
 use num_derive::{ToPrimitive, FromPrimitive};
 use num_traits::FromPrimitive;
@@ -40,8 +42,14 @@ pub fn builtin_func_sets() -> BuiltinFunctionSets {
     sets.add("chgmoj", replace, get_desc!(replace));
     sets.add("tojson", tojson, get_desc!(tojson));
     sets.add("fromjson", fromjson, get_desc!(fromjson));
+    sets.add("tohashtbl", tohashtbl, get_desc!(tohashtbl));
     sets.add("toyaml", toyaml, get_desc!(toyaml));
     sets.add("fromyaml", fromyaml, get_desc!(fromyaml));
+    sets.add("validatejson", validatejson, get_desc!(validatejson));
+    sets.add("totoml", totoml, get_desc!(totoml));
+    sets.add("fromtoml", fromtoml, get_desc!(fromtoml));
+    sets.add("parsexml", parsexml, get_desc!(parsexml));
+    sets.add("toxml", toxml, get_desc!(toxml));
     sets.add("copy", copy, get_desc!(copy));
     sets.add("pos", pos, get_desc!(pos));
     sets.add("betweenstr", betweenstr, get_desc!(betweenstr));
@@ -329,16 +337,19 @@ pub fn replace(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
 }
 
 #[builtin_func_desc(
-    desc="UObjectをjson文字列にする",
+    desc="UObjectまたは連想配列をjson文字列にする",
     rtype={desc="json文字列",types="文字列"}
     args=[
-        {n="UObject",t="UObject",d="jsonに変換するUObject"},
+        {n="UObject",t="UObjectまたは連想配列",d="jsonに変換するUObject、連想配列のキーは文字列になる"},
         {o,n="整形",t="真偽値",d="TRUEならjsonに改行等を含めて見やすくする"},
     ],
 )]
 pub fn tojson(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     let prettify = args.get_as_bool(1, Some(false))?;
-    let uo = args.get_as_uobject(0)?;
+    let uo = match args.get_as_object(0, None)? {
+        Object::HashTbl(hash) => UObject::from(JsonValue::try_from(Object::HashTbl(hash))?),
+        _ => args.get_as_uobject(0)?,
+    };
     let result = if prettify {
         uo.to_json_string_pretty()
     } else {
@@ -363,6 +374,29 @@ pub fn fromjson(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     Ok(obj)
 }
 
+#[builtin_func_desc(
+    desc="UObjectまたはjson文字列を連想配列にする",
+    rtype={desc="連想配列、ネストしたオブジェクトも連想配列になる",types="連想配列"}
+    args=[
+        {n="値",t="UObjectまたは文字列",d="変換するUObjectまたはjson文字列、最上位はオブジェクトであること"},
+        {o,n="オプション",t="定数",d="HASH_CASECARE, HASH_SORT (OR連結可)"},
+    ],
+)]
+pub fn tohashtbl(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let json = match args.get_as_object(0, None)? {
+        Object::UObject(uo) => JsonValue::try_from(Object::UObject(uo))?,
+        o => serde_json::from_str(&o.to_string())
+            .map_err(|e| builtin_func_error(UErrorMessage::Any(e.to_string())))?,
+    };
+    let opt = args.get_as_int(1, Some(0u32))?;
+    let sort = (opt & HashTblEnum::HASH_SORT as u32) > 0;
+    let casecare = (opt & HashTblEnum::HASH_CASECARE as u32) > 0;
+    let Some(hash) = HashTbl::from_json(&json, sort, casecare) else {
+        return Err(builtin_func_error(UErrorMessage::InvalidArgument(args.get_as_object(0, None)?)));
+    };
+    Ok(Object::HashTbl(Arc::new(Mutex::new(hash))))
+}
+
 #[builtin_func_desc(
     desc="UObjectをyaml文字列にする",
     rtype={desc="yaml文字列",types="文字列"}
@@ -391,6 +425,90 @@ pub fn fromyaml(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
     Ok(obj)
 }
 
+#[builtin_func_desc(
+    desc="UObjectをJSON Schemaで検証する",
+    rtype={desc="違反内容(path, keyword, message)のUObjectの配列、違反がなければ空配列",types="配列"}
+    args=[
+        {n="UObject",t="UObject",d="検証するUObject"},
+        {n="スキーマ",t="UObjectまたは文字列",d="JSON SchemaのUObject、またはスキーマファイル(json/yaml)のパス"},
+    ],
+)]
+pub fn validatejson(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let uo = args.get_as_uobject(0)?;
+    let schema = match args.get_as_object(1, None)? {
+        Object::UObject(schema) => schema,
+        path => {
+            let text = std::fs::read_to_string(path.to_string()).map_err(UError::from)?;
+            UObject::from_json_str(&text)
+                .or_else(|_| UObject::from_yaml_str(&text))
+                .map_err(|e| builtin_func_error(UErrorMessage::InvalidJsonSchema(e.to_string())))?
+        },
+    };
+    let violations = uo.validate(&schema)?;
+    Ok(Object::Array(violations))
+}
+
+#[builtin_func_desc(
+    desc="UObjectをtoml文字列にする",
+    rtype={desc="toml文字列",types="文字列"}
+    args=[
+        {n="UObject",t="UObject",d="tomlに変換するUObject、最上位は連想配列であること"},
+    ],
+)]
+pub fn totoml(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let uo = args.get_as_uobject(0)?;
+    uo.to_toml_string()
+        .map(|toml| toml.into())
+        .map_err(|e| builtin_func_error(UErrorMessage::Any(e.to_string())))
+}
+#[builtin_func_desc(
+    desc="toml文字列をUObjectにする",
+    rtype={desc="成功時UObject、失敗時EMPTY",types="UObject"}
+    args=[
+        {n="toml文字列",t="文字列",d="UObjectに変換するtoml文字列"},
+    ],
+)]
+pub fn fromtoml(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let toml = args.get_as_string(0, None)?;
+    let obj = UObject::from_toml_str(&toml)
+        .map(Object::UObject)
+        .unwrap_or_default();
+    Ok(obj)
+}
+
+#[builtin_func_desc(
+    desc="xml文字列をパースしXmlNodeにする",
+    rtype={desc="文書全体を表すXmlNode",types="XmlNode"}
+    args=[
+        {n="xml文字列",t="文字列",d="パースするxml文字列"},
+    ],
+)]
+pub fn parsexml(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let xml = args.get_as_string(0, None)?;
+    let node = XmlNode::parse(&xml)?;
+    Ok(Object::XmlNode(node))
+}
+#[builtin_func_desc(
+    desc="XmlNodeまたはUObjectをxml文字列にする",
+    rtype={desc="xml文字列",types="文字列"}
+    args=[
+        {n="値",t="XmlNodeまたはUObject",d="xmlに変換する値、UObjectはルート要素名をキーとする要素をひとつだけ持つこと"},
+        {o,n="整形",t="真偽値",d="TRUEなら改行とインデントを含めて見やすくする"},
+    ],
+)]
+pub fn toxml(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+    let pretty = args.get_as_bool(1, Some(false))?;
+    let node = match args.get_as_object(0, None)? {
+        Object::XmlNode(node) => node,
+        obj @ Object::UObject(_) => {
+            let value = JsonValue::try_from(obj)?;
+            XmlNode::from_json(&value)?
+        },
+        obj => return Err(builtin_func_error(UErrorMessage::BuiltinArgInvalid(obj))),
+    };
+    Ok(node.to_xml(pretty).into())
+}
+
 #[builtin_func_desc(
     desc="文字列をコピー",
     rtype={desc="コピーした文字列",types="文字列"}
@@ -0,0 +1,467 @@
+//! Rustアプリケーションにスクリプトエンジンとして組み込むためのAPI
+//!
+//! ```ignore
+//! use evaluator::embed::{Interpreter, HostCallbacks};
+//! use evaluator::limits::ExecLimits;
+//! use evaluator::builtins::*;
+//!
+//! #[builtin_func_desc(
+//!     desc="値を2倍にする"
+//!     args=[{n="値",t="数値",d="対象の値"}],
+//!     rtype={desc="2倍の値",types="数値"}
+//! )]
+//! fn double(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+//!     let n = args.get_as_f64(0, None)?;
+//!     Ok(Object::Num(n * 2.0))
+//! }
+//!
+//! let mut uwscr = Interpreter::new();
+//! uwscr.set_callbacks(HostCallbacks::new().on_print(|msg| println!("{msg}")));
+//! uwscr.set_limits(ExecLimits::new().max_steps(10_000));
+//! uwscr.register_function("double", double, get_desc!(double));
+//! uwscr.set_global("config", &serde_json::json!({"value": 21}))?;
+//! uwscr.eval_str("answer = double(config.value)")?;
+//! let answer: i32 = uwscr.get_global("answer")?;
+//! ```
+//!
+//! - `gui` featureの有無に関わらず動作し、printウィンドウは表示されない
+//! - print文やダイアログ関数はウィンドウの代わりに [`HostCallbacks`] を呼び出す
+//! - exitexitはプロセスを終了せず `UErrorKind::ExitExit` を返す
+//! - COMを利用する関数を呼ぶ場合はホスト側でCOMを初期化しておくこと
+
+use crate::Evaluator;
+use crate::environment::{Environment, NamedObject, ContainerType};
+use crate::object::Object;
+use crate::builtins::{BuiltinFunction, BuiltinFunctionSets, FuncDesc, get_builtin_string_names};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+use crate::limits::ExecLimits;
+use crate::sandbox::SandboxPolicy;
+use crate::EvalResult;
+
+use parser::Parser;
+use parser::lexer::Lexer;
+use crate::gui::console_dialog;
+use util::logging::{out_log, LogType};
+use util::winapi::is_headless;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+
+type PrintCallback = Arc<dyn Fn(&str) + Send + Sync>;
+type LogCallback = Arc<dyn Fn(&LogType, &str) + Send + Sync>;
+type DialogCallback = Arc<dyn Fn(&HostDialog) -> Option<Object> + Send + Sync>;
+
+/// ウィンドウ表示の代わりにホストへ渡される出力やダイアログを受けるコールバック
+#[derive(Clone, Default)]
+pub struct HostCallbacks {
+    print: Option<PrintCallback>,
+    log: Option<LogCallback>,
+    dialog: Option<DialogCallback>,
+}
+
+impl HostCallbacks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// print文の出力を受ける
+    pub fn on_print<F>(mut self, f: F) -> Self
+    where F: Fn(&str) + Send + Sync + 'static
+    {
+        self.print = Some(Arc::new(f));
+        self
+    }
+    /// ログファイルへ出力される内容を受ける、指定時はログファイルに書き込まない
+    pub fn on_log<F>(mut self, f: F) -> Self
+    where F: Fn(&LogType, &str) + Send + Sync + 'static
+    {
+        self.log = Some(Arc::new(f));
+        self
+    }
+    /// msgbox, input, slctboxの表示要求を受ける
+    ///
+    /// 戻り値がそのまま関数の戻り値になる、Noneを返した場合はキャンセル扱い
+    pub fn on_dialog<F>(mut self, f: F) -> Self
+    where F: Fn(&HostDialog) -> Option<Object> + Send + Sync + 'static
+    {
+        self.dialog = Some(Arc::new(f));
+        self
+    }
+}
+
+impl fmt::Debug for HostCallbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostCallbacks")
+            .field("print", &self.print.is_some())
+            .field("log", &self.log.is_some())
+            .field("dialog", &self.dialog.is_some())
+            .finish()
+    }
+}
+
+/// ホストに表示を依頼するダイアログ
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostDialog {
+    /// msgbox関数
+    MsgBox {
+        message: String,
+        /// BTN定数のOR
+        buttons: i32,
+        focus: Option<i32>,
+    },
+    /// input関数、labels, defaults, masksは入力欄ごとの値
+    Input {
+        message: String,
+        labels: Vec<Option<String>>,
+        defaults: Vec<Option<String>>,
+        masks: Vec<bool>,
+    },
+    /// slctbox関数
+    Slctbox {
+        message: String,
+        /// SLCT定数のOR
+        kind: i32,
+        items: Vec<String>,
+        /// タイムアウト秒
+        timeout: Option<f64>,
+    },
+}
+
+impl HostDialog {
+    /// ダイアログがキャンセルされた場合の戻り値
+    pub fn cancel_value(&self) -> Object {
+        match self {
+            HostDialog::MsgBox { buttons, focus, .. } => {
+                // フォーカスボタン、なければ最も小さい値のボタンが押されたものとする
+                let pressed = focus.unwrap_or(buttons & buttons.wrapping_neg());
+                Object::Num(pressed as f64)
+            },
+            HostDialog::Input { labels, .. } => if labels.len() > 1 {
+                Object::Array(vec![])
+            } else {
+                Object::Empty
+            },
+            HostDialog::Slctbox { .. } => Object::Num(-1.0),
+        }
+    }
+}
+
+impl Evaluator {
+    /// ホストアプリケーションに組み込まれている場合のコールバックを設定する
+    pub fn set_host_callbacks(&mut self, callbacks: HostCallbacks) {
+        self.host = Some(Arc::new(callbacks));
+        self.gui_print = Some(false);
+    }
+    /// ホストアプリケーションに組み込まれているかどうか
+    pub fn is_embedded(&self) -> bool {
+        self.host.is_some()
+    }
+    /// print文の出力をホストに渡す、組み込みでなければfalse
+    pub(crate) fn host_print(&self, msg: &str) -> bool {
+        match &self.host {
+            Some(host) => {
+                if let Some(print) = &host.print {
+                    print(msg);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+    /// ログ出力、ホストがコールバックを持つ場合はそちらに渡す
+    pub(crate) fn out_log(&self, msg: &String, log_type: LogType) {
+        match self.host.as_ref().and_then(|host| host.log.as_ref()) {
+            Some(log) => log(&log_type, msg),
+            None => out_log(msg, log_type),
+        }
+    }
+    /// ダイアログ表示をホストに依頼する
+    ///
+    /// 組み込みでなければヘッドレスモードの場合のみコンソールで応答を得る、いずれでもなければNone
+    pub(crate) fn host_dialog(&self, dialog: HostDialog) -> Option<Object> {
+        let Some(host) = self.host.as_ref() else {
+            return is_headless().then(|| console_dialog(&dialog));
+        };
+        let result = host.dialog.as_ref()
+            .and_then(|f| f(&dialog))
+            .unwrap_or_else(|| dialog.cancel_value());
+        Some(result)
+    }
+}
+
+/// ホストアプリケーションから利用するスクリプト実行環境
+///
+/// 評価した結果の変数や関数定義は次回以降の評価に引き継がれる
+#[derive(Debug)]
+pub struct Interpreter {
+    evaluator: Evaluator,
+    names: Vec<String>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_params(vec![])
+    }
+    /// PARAM_STRの値を指定して作成
+    pub fn with_params(params: Vec<String>) -> Self {
+        let mut evaluator = Evaluator::new(Environment::new(params));
+        evaluator.set_host_callbacks(HostCallbacks::default());
+        Self {
+            evaluator,
+            names: get_builtin_string_names(),
+        }
+    }
+    pub fn set_callbacks(&mut self, callbacks: HostCallbacks) {
+        self.evaluator.set_host_callbacks(callbacks);
+    }
+    pub fn set_limits(&mut self, limits: ExecLimits) {
+        self.evaluator.set_limits(limits);
+    }
+    /// サンドボックスを設定する、既定では設定ファイルのサンドボックス設定に従う
+    pub fn set_sandbox(&mut self, policy: Option<SandboxPolicy>) {
+        self.evaluator.set_sandbox(policy);
+    }
+    pub fn evaluator(&mut self) -> &mut Evaluator {
+        &mut self.evaluator
+    }
+    /// ビルトイン関数として登録する、同名のビルトイン関数は上書きされる
+    pub fn register_function(&mut self, name: &str, func: BuiltinFunction, desc: FuncDesc) {
+        let mut sets = BuiltinFunctionSets::new();
+        sets.add(name, func, desc);
+        self.register_functions(sets);
+    }
+    /// 関数セットをまとめてビルトイン関数として登録する
+    pub fn register_functions(&mut self, sets: BuiltinFunctionSets) {
+        let mut vec = vec![];
+        sets.set(&mut vec);
+        self.register(vec, ContainerType::BuiltinFunc);
+    }
+    /// ビルトイン定数として登録する、同名のビルトイン定数は上書きされる
+    pub fn register_const(&mut self, name: &str, value: Object) {
+        let obj = NamedObject::new_builtin_const(name.to_ascii_uppercase(), value);
+        self.register(vec![obj], ContainerType::BuiltinConst);
+    }
+    /// Rustの値を変換してビルトイン定数として登録する
+    pub fn register_const_value<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) -> EvalResult<()> {
+        let value = to_object(value)?;
+        self.register_const(name, value);
+        Ok(())
+    }
+    fn register(&mut self, mut objects: Vec<NamedObject>, container_type: ContainerType) {
+        for obj in &objects {
+            self.add_name(&obj.name);
+        }
+        let mut global = self.evaluator.env.global.lock().unwrap();
+        global.retain(|o| {
+            o.container_type != container_type ||
+            ! objects.iter().any(|new| new.name.eq_ignore_ascii_case(&o.name))
+        });
+        global.append(&mut objects);
+    }
+    fn add_name(&mut self, name: &str) {
+        if ! self.names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            self.names.push(name.to_string());
+        }
+    }
+    /// グローバル変数に値を代入する、未定義ならpublic変数として定義する
+    pub fn set_global_object(&mut self, name: &str, value: Object) -> EvalResult<()> {
+        let env = &mut self.evaluator.env;
+        if env.assign_public(name, value.clone()).is_err() {
+            env.define_public(name, value)?;
+        }
+        self.add_name(name);
+        Ok(())
+    }
+    /// Rustの値を変換してグローバル変数に代入する
+    pub fn set_global<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) -> EvalResult<()> {
+        let value = to_object(value)?;
+        self.set_global_object(name, value)
+    }
+    /// 変数の値を得る
+    pub fn get_global_object(&self, name: &str) -> Option<Object> {
+        self.evaluator.get_variable(name)
+    }
+    /// 変数の値をRustの値に変換して得る
+    pub fn get_global<T: DeserializeOwned>(&self, name: &str) -> EvalResult<T> {
+        let value = self.get_global_object(name)
+            .ok_or(UError::new(
+                UErrorKind::EvaluatorError,
+                UErrorMessage::VariableNotFound(name.to_string())
+            ))?;
+        from_object(value)
+    }
+    /// スクリプト文字列を評価する
+    ///
+    /// 最後に評価された式の値を返す
+    pub fn eval_str(&mut self, script: &str) -> EvalResult<Option<Object>> {
+        self.eval_script(script, None)
+    }
+    /// スクリプトファイルを評価する、callのパスはファイルのあるディレクトリが基準となる
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> EvalResult<Option<Object>> {
+        let path = path.as_ref();
+        let script = std::fs::read_to_string(path)?;
+        let dir = path.parent().map(|p| p.to_path_buf());
+        self.eval_script(&script, dir)
+    }
+    fn eval_script(&mut self, script: &str, dir: Option<PathBuf>) -> EvalResult<Option<Object>> {
+        let parser = Parser::new(Lexer::new(script), dir, Some(self.names.clone()));
+        let program = parser.parse()
+            .map_err(|errors| {
+                let count = errors.len();
+                let errors = errors.into_iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                UError::new(
+                    UErrorKind::EvalParseErrors(count),
+                    UErrorMessage::ParserErrors(errors),
+                )
+            })?;
+        if let Some(limit) = &self.evaluator.limit {
+            limit.reset();
+        }
+        self.evaluator.eval(program, false)
+    }
+}
+
+/// Rustの値をObjectに変換する
+///
+/// 配列は配列に、マップはUObjectになる
+pub fn to_object<T: Serialize + ?Sized>(value: &T) -> EvalResult<Object> {
+    let value = serde_json::to_value(value)
+        .map_err(|e| UError::new(UErrorKind::ConversionError, UErrorMessage::Any(e.to_string())))?;
+    Ok(json_to_object(value))
+}
+
+/// ObjectをRustの値に変換する
+///
+/// 配列、連想配列、UObjectはそれぞれシーケンスとマップとして扱われる
+pub fn from_object<T: DeserializeOwned>(object: Object) -> EvalResult<T> {
+    let value = object_to_json(object)?;
+    serde_json::from_value(value)
+        .map_err(|e| UError::new(UErrorKind::ConversionError, UErrorMessage::Any(e.to_string())))
+}
+
+fn json_to_object(value: JsonValue) -> Object {
+    match value {
+        JsonValue::Array(vec) => {
+            let arr = vec.into_iter().map(json_to_object).collect();
+            Object::Array(arr)
+        },
+        value => value.into(),
+    }
+}
+
+fn object_to_json(object: Object) -> EvalResult<JsonValue> {
+    match object {
+        Object::Empty |
+        Object::EmptyParam => Ok(JsonValue::Null),
+        Object::Array(arr) => {
+            let vec = arr.into_iter()
+                .map(object_to_json)
+                .collect::<EvalResult<_>>()?;
+            Ok(JsonValue::Array(vec))
+        },
+        Object::HashTbl(arc) => {
+            let map = arc.lock().unwrap().map();
+            hashtbl_to_json(map)
+        },
+        o => o.try_into(),
+    }
+}
+
+fn hashtbl_to_json(map: indexmap::IndexMap<String, Object>) -> EvalResult<JsonValue> {
+    let map = map.into_iter()
+        .map(|(key, value)| Ok((key, object_to_json(value)?)))
+        .collect::<EvalResult<serde_json::Map<_, _>>>()?;
+    Ok(JsonValue::Object(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::*;
+    use std::sync::Mutex;
+
+    #[builtin_func_desc(
+        desc="値を2倍にする"
+        args=[{n="値",t="数値",d="対象の値"}],
+        rtype={desc="2倍の値",types="数値"}
+    )]
+    fn double(_: &mut Evaluator, args: BuiltinFuncArgs) -> BuiltinFuncResult {
+        let n = args.get_as_f64(0, None)?;
+        Ok(Object::Num(n * 2.0))
+    }
+
+    #[test]
+    fn test_register_and_globals() {
+        let mut uwscr = Interpreter::new();
+        uwscr.register_function("double", double, get_desc!(double));
+        uwscr.register_const("HOST_NAME", "test".into());
+        uwscr.set_global("config", &serde_json::json!({"value": 21, "list": [1, 2, 3]})).unwrap();
+        uwscr.set_global("nums", &vec![1, 2, 3]).unwrap();
+        uwscr.eval_str("public answer = double(config.value)\r\npublic total = nums[0] + nums[1] + nums[2]\r\npublic name = HOST_NAME").unwrap();
+        assert_eq!(uwscr.get_global::<i32>("answer").unwrap(), 42);
+        assert_eq!(uwscr.get_global::<i32>("total").unwrap(), 6);
+        assert_eq!(uwscr.get_global::<String>("name").unwrap(), "test");
+        let result = uwscr.eval_str("answer + 1").unwrap();
+        assert_eq!(result, Some(Object::Num(43.0)));
+    }
+
+    #[test]
+    fn test_sandbox_host_function() {
+        let mut uwscr = Interpreter::new();
+        uwscr.register_function("double", double, get_desc!(double));
+        uwscr.set_sandbox(Some(SandboxPolicy::new()));
+        let err = uwscr.eval_str("double(1)").unwrap_err();
+        assert_eq!(err.message, UErrorMessage::FunctionDeniedBySandbox("DOUBLE".into()));
+        uwscr.set_sandbox(Some(SandboxPolicy::new().allow_function("double")));
+        assert_eq!(uwscr.eval_str("double(2)").unwrap(), Some(Object::Num(4.0)));
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let printed = Arc::new(Mutex::new(Vec::<String>::new()));
+        let cloned = printed.clone();
+        let callbacks = HostCallbacks::new()
+            .on_print(move |msg| cloned.lock().unwrap().push(msg.to_string()))
+            .on_log(|_, _| {})
+            .on_dialog(|dialog| match dialog {
+                HostDialog::Input { .. } => Some("foo".into()),
+                _ => None,
+            });
+        let mut uwscr = Interpreter::new();
+        uwscr.set_callbacks(callbacks);
+        uwscr.eval_str("print input(\"name?\")\r\nprint msgbox(\"ok?\", BTN_YES or BTN_NO) = BTN_YES").unwrap();
+        assert_eq!(*printed.lock().unwrap(), vec!["foo".to_string(), "True".to_string()]);
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut uwscr = Interpreter::new();
+        uwscr.set_limits(ExecLimits::new().max_steps(100));
+        let err = uwscr.eval_str("while true\r\nwend").unwrap_err();
+        assert_eq!(err.kind, UErrorKind::LimitError);
+        // 評価ごとにカウントはリセットされる
+        assert!(uwscr.eval_str("a = 1").is_ok());
+
+        let mut uwscr = Interpreter::new();
+        uwscr.set_limits(ExecLimits::new().timeout(std::time::Duration::from_millis(50)));
+        let err = uwscr.eval_str("while true\r\nwend").unwrap_err();
+        assert_eq!(err.message, UErrorMessage::TimeLimitExceeded(50));
+
+        let mut uwscr = Interpreter::new();
+        let err = uwscr.eval_str("exitexit 3").unwrap_err();
+        assert_eq!(err.kind, UErrorKind::ExitExit(3));
+    }
+}
@@ -435,6 +435,12 @@ impl Environment {
         self.global.lock().unwrap().iter().any(|obj| obj.name.eq_ignore_ascii_case(name) && container_types.contains(&obj.container_type))
     }
 
+    /// 定数 (ローカル定数、グローバル定数、ビルトイン定数) であればtrue
+    pub fn is_const(&mut self, name: &str) -> bool {
+        self.contains_in_local(name, &[ContainerType::Const]) ||
+        self.contains_in_global(name, &[ContainerType::Const, ContainerType::BuiltinConst])
+    }
+
     fn define(&mut self, name: &str, object: Object, container_type: ContainerType, to_global: bool) -> Result<(), UError> {
         if self.is_reserved(name) {
             return Err(UError::new(
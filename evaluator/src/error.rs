@@ -9,6 +9,7 @@ use crate::def_dll::DllType;
 pub use util::write_locale;
 pub use util::error::{CURRENT_LOCALE, Locale};
 use util::clipboard::ClipboardError;
+use util::settings::SandboxCapability;
 use util::winapi::Win32Error;
 use parser::ast::{Expression, Infix, Identifier};
 
@@ -202,6 +203,14 @@ pub enum UErrorKind {
     FormError,
     CaptureError,
     SocketError,
+    MailError,
+    MqttError,
+    LimitError,
+    SandboxError,
+    DateTimeError,
+    CsvError,
+    XmlError,
+    RngError,
 }
 
 impl fmt::Display for UErrorKind {
@@ -427,6 +436,38 @@ impl fmt::Display for UErrorKind {
                 "ソケットエラー",
                 "Socket Error",
             ),
+            Self::MailError => write_locale!(f,
+                "メールエラー",
+                "Mail Error",
+            ),
+            Self::MqttError => write_locale!(f,
+                "MQTTエラー",
+                "MQTT Error",
+            ),
+            Self::LimitError => write_locale!(f,
+                "実行制限エラー",
+                "Execution Limit Error",
+            ),
+            Self::SandboxError => write_locale!(f,
+                "サンドボックスエラー",
+                "Sandbox Error",
+            ),
+            Self::DateTimeError => write_locale!(f,
+                "DateTimeエラー",
+                "DateTime Error",
+            ),
+            Self::CsvError => write_locale!(f,
+                "CSVエラー",
+                "CSV Error",
+            ),
+            Self::XmlError => write_locale!(f,
+                "XMLエラー",
+                "XML Error",
+            ),
+            Self::RngError => write_locale!(f,
+                "乱数エラー",
+                "Random Number Error",
+            ),
         }
     }
 }
@@ -620,6 +661,62 @@ pub enum UErrorMessage {
     ShouldBeAsciiCharacter(String),
     ExplorerMayBeSuspended,
     SocketHasBeenClosed,
+    SocketTimeout,
+    /// メールサーバーのエラー応答
+    SocketDataTooLarge,
+    HttpHeaderTooLarge(usize),
+    HttpBodyTooLarge(usize),
+    MailServerError(String),
+    MailLineBreak(String),
+    MqttBrokerError(String),
+    StepLimitExceeded(u64),
+    /// 制限時間 (ミリ秒)
+    TimeLimitExceeded(u64),
+    /// 関数呼び出しの深さの上限
+    DepthLimitExceeded(u32),
+    /// 配列・文字列サイズの上限 (バイト)
+    AllocLimitExceeded(usize),
+    /// 禁止された機能, 関数名
+    DeniedBySandbox(SandboxCapability, String),
+    PathDeniedBySandbox(String),
+    FunctionDeniedBySandbox(String),
+    /// 解釈できない日時文字列, 理由
+    InvalidDateTime(String, String),
+    InvalidTimeZone(String),
+    InvalidDateTimeFormat(String),
+    /// 年・月に加算できない値
+    InvalidDateTimeAmount(String),
+    DateTimeOutOfRange,
+    /// 行番号, 列, 値
+    CsvConversionFailed(usize, String, String),
+    CsvStreamAlreadyStarted,
+    /// パス, 理由
+    InvalidJsonPath(String, String),
+    InvalidJsonPointer(String),
+    /// 操作の位置, 理由
+    JsonPatchFailed(usize, String),
+    InvalidJsonSchema(String),
+    XmlParseError(String),
+    /// パス, 理由
+    InvalidXPath(String, String),
+    /// セレクタ, 理由
+    InvalidXmlSelector(String, String),
+    InvalidIniSection(String),
+    /// 数値として解釈できない文字列, 型名
+    InvalidNumberString(String, String),
+    NumericOverflow,
+    /// 最小, 最大
+    InvalidRandomRange(String, String),
+    InvalidWeights(String),
+    /// 要求数, 要素数
+    SampleSizeExceeded(usize, usize),
+    ZipEntryNotFound(String),
+    ZipSavePathRequired,
+    InvalidZipEntryName(String),
+    ZipPasswordRequired(String),
+    /// メソッド, URL
+    NoRecordedResponse(String, String),
+    InvalidCassette(String),
 }
 
 impl fmt::Display for UErrorMessage {
@@ -1386,6 +1483,166 @@ impl fmt::Display for UErrorMessage {
                 "ソケットが閉じられています",
                 "Socket has been closed",
             ),
+            Self::SocketTimeout => write_locale!(f,
+                "ソケットの送受信がタイムアウトしました",
+                "Socket operation timed out",
+            ),
+            Self::SocketDataTooLarge => write_locale!(f,
+                "受信データが上限を超えました",
+                "Received data exceeded the limit",
+            ),
+            Self::HttpHeaderTooLarge(max) => write_locale!(f,
+                "リクエストヘッダが上限 ({max}バイト) を超えました",
+                "Request header exceeded the limit of {max} bytes",
+            ),
+            Self::HttpBodyTooLarge(max) => write_locale!(f,
+                "リクエストボディが上限 ({max}バイト) を超えました",
+                "Request body exceeded the limit of {max} bytes",
+            ),
+            Self::MailServerError(s) => write_locale!(f,
+                "メールサーバーがエラーを返しました: {s}",
+                "Mail server returned an error: {s}",
+            ),
+            Self::MailLineBreak(s) => write_locale!(f,
+                "メールのヘッダやアドレスに改行は使えません: {s:?}",
+                "Line breaks are not allowed in mail headers or addresses: {s:?}",
+            ),
+            Self::MqttBrokerError(s) => write_locale!(f,
+                "MQTTブローカーがエラーを返しました: {s}",
+                "MQTT broker returned an error: {s}",
+            ),
+            Self::StepLimitExceeded(n) => write_locale!(f,
+                "評価できる文の上限 ({n}) を超えました",
+                "Exceeded the maximum number of statements ({n})",
+            ),
+            Self::TimeLimitExceeded(ms) => write_locale!(f,
+                "実行時間の上限 ({ms}ミリ秒) を超えました",
+                "Exceeded the time limit ({ms}ms)",
+            ),
+            Self::DepthLimitExceeded(n) => write_locale!(f,
+                "関数呼び出しの深さの上限 ({n}) を超えました",
+                "Exceeded the maximum call depth ({n})",
+            ),
+            Self::AllocLimitExceeded(n) => write_locale!(f,
+                "配列または文字列のサイズが上限 ({n}バイト) を超えました",
+                "Array or string exceeded the size limit ({n} bytes)",
+            ),
+            Self::DeniedBySandbox(cap, name) => write_locale!(f,
+                "サンドボックスモードでは{cap}が許可されていません ({name})",
+                "{cap} is not allowed in sandbox mode: {name}",
+            ),
+            Self::PathDeniedBySandbox(path) => write_locale!(f,
+                "サンドボックスで許可されたフォルダ外のパスです ({path})",
+                "Path is outside of the folders allowed by sandbox: {path}",
+            ),
+            Self::FunctionDeniedBySandbox(name) => write_locale!(f,
+                "サンドボックスで許可されていない関数です ({name})",
+                "Function is not allowed in sandbox mode: {name}",
+            ),
+            Self::InvalidDateTime(value, reason) => write_locale!(f,
+                "日時として解釈できません: {value} ({reason})",
+                "Unable to parse as date and time: {value} ({reason})",
+            ),
+            Self::InvalidTimeZone(zone) => write_locale!(f,
+                "不正なタイムゾーンです: {zone} (local, UTC, +09:00 のようなオフセットまたは Asia/Tokyo のようなタイムゾーン名を指定してください)",
+                "Invalid time zone: {zone} (use local, UTC, an offset such as +09:00 or a zone name such as Asia/Tokyo)",
+            ),
+            Self::InvalidDateTimeFormat(fmt) => write_locale!(f,
+                "不正な日時書式です: {fmt}",
+                "Invalid date time format: {fmt}",
+            ),
+            Self::InvalidDateTimeAmount(value) => write_locale!(f,
+                "年・月の加算には整数を指定してください: {value}",
+                "Years and months must be added as integers: {value}",
+            ),
+            Self::DateTimeOutOfRange => write_locale!(f,
+                "日時が範囲外です",
+                "Date and time out of range",
+            ),
+            Self::CsvConversionFailed(row, column, value) => write_locale!(f,
+                "{row}行目の列[{column}]の値を変換できません: {value}",
+                "Unable to convert value of column [{column}] at row {row}: {value}",
+            ),
+            Self::CsvStreamAlreadyStarted => write_locale!(f,
+                "読み書きを開始した後は設定を変更できません",
+                "Options can not be changed after reading or writing has started",
+            ),
+            Self::InvalidJsonPath(path, reason) => write_locale!(f,
+                "不正なJSONPathです ({reason}): {path}",
+                "Invalid JSONPath ({reason}): {path}",
+            ),
+            Self::InvalidJsonPointer(reason) => write_locale!(f,
+                "JSON Pointerが不正です: {reason}",
+                "Invalid JSON Pointer: {reason}",
+            ),
+            Self::JsonPatchFailed(index, reason) => write_locale!(f,
+                "パッチ操作[{index}]に失敗しました: {reason}",
+                "Patch operation [{index}] failed: {reason}",
+            ),
+            Self::InvalidJsonSchema(reason) => write_locale!(f,
+                "JSON Schemaが不正です: {reason}",
+                "Invalid JSON Schema: {reason}",
+            ),
+            Self::XmlParseError(reason) => write_locale!(f,
+                "XMLが不正です: {reason}",
+                "Invalid XML: {reason}",
+            ),
+            Self::InvalidXPath(path, reason) => write_locale!(f,
+                "XPathが不正です ({path}): {reason}",
+                "Invalid XPath ({path}): {reason}",
+            ),
+            Self::InvalidXmlSelector(selector, reason) => write_locale!(f,
+                "セレクタが不正です ({selector}): {reason}",
+                "Invalid selector ({selector}): {reason}",
+            ),
+            Self::InvalidIniSection(section) => write_locale!(f,
+                "セクション{section}の値はUObjectまたは連想配列である必要があります",
+                "Value of section {section} must be a UObject or a hashtable",
+            ),
+            Self::InvalidNumberString(value, kind) => write_locale!(f,
+                "{value} は{kind}として解釈できません",
+                "{value} can not be parsed as {kind}",
+            ),
+            Self::NumericOverflow => write_locale!(f,
+                "計算結果が扱える範囲を超えました",
+                "Calculation result is out of range",
+            ),
+            Self::InvalidRandomRange(min, max) => write_locale!(f,
+                "乱数の範囲が不正です (最小: {min}, 最大: {max})",
+                "Invalid range for random number (min: {min}, max: {max})",
+            ),
+            Self::InvalidWeights(reason) => write_locale!(f,
+                "重みが不正です: {reason}",
+                "Invalid weights: {reason}",
+            ),
+            Self::SampleSizeExceeded(n, len) => write_locale!(f,
+                "{len}個の要素から{n}個は取り出せません",
+                "Can not take {n} items from {len} items",
+            ),
+            Self::ZipEntryNotFound(name) => write_locale!(f,
+                "エントリが見つかりません: {name}",
+                "Entry not found: {name}",
+            ),
+            Self::ZipSavePathRequired => write_locale!(f,
+                "保存先のパスを指定してください",
+                "Path to save is required",
+            ),
+            Self::InvalidZipEntryName(name) => write_locale!(f,
+                "展開先フォルダの外を指すエントリ名です: {name}",
+                "Entry name points outside of the destination folder: {name}",
+            ),
+            Self::ZipPasswordRequired(name) => write_locale!(f,
+                "暗号化されたエントリを保存するにはパスワードが必要です: {name}",
+                "Password is required to save encrypted entry: {name}",
+            ),
+            Self::NoRecordedResponse(method, url) => write_locale!(f,
+                "記録されたレスポンスがありません: {method} {url}",
+                "No recorded response for {method} {url}",
+            ),
+            Self::InvalidCassette(e) => write_locale!(f,
+                "記録ファイルを読み書きできません: {e}",
+                "Failed to read or write cassette file: {e}",
+            ),
         }
     }
 }
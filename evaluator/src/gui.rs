@@ -5,6 +5,7 @@ pub mod popupmenu;
 pub mod print;
 pub mod balloon;
 pub mod form;
+pub mod console;
 
 pub use msgbox::*;
 pub use slctbox::*;
@@ -13,6 +14,7 @@ pub use popupmenu::*;
 pub use print::*;
 pub use balloon::*;
 pub use form::*;
+pub use console::*;
 
 use util::write_locale;
 use util::error::{CURRENT_LOCALE, Locale};
@@ -0,0 +1,271 @@
+//! ヘッドレスモードでのダイアログの代替
+//!
+//! ウィンドウを表示する代わりにメッセージを標準エラー出力に書き、
+//! 応答ファイルまたは標準入力から1行ずつ応答を読む
+//! 応答がない (EOF) 場合はキャンセル扱い
+
+use super::msgbox::{BTN_YES, BTN_NO, BTN_OK, BTN_CANCEL, BTN_ABORT, BTN_RETRY, BTN_IGNORE};
+use crate::object::Object;
+use crate::embed::HostDialog;
+use crate::builtins::dialog::SlctConst;
+use util::settings::USETTINGS;
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::sync::{LazyLock, Mutex};
+
+/// 応答ファイルの未使用の行、Noneなら標準入力から読む
+static ANSWERS: LazyLock<Mutex<Option<VecDeque<String>>>> = LazyLock::new(|| {
+    let path = USETTINGS.lock().unwrap().headless.answer_file.clone();
+    let answers = path.map(|path| {
+        match std::fs::read_to_string(&path) {
+            Ok(s) => s.trim_start_matches('\u{feff}')
+                .lines()
+                .map(|line| line.to_string())
+                .collect(),
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                VecDeque::new()
+            },
+        }
+    });
+    Mutex::new(answers)
+});
+
+const BUTTONS: [(i32, &str); 7] = [
+    (BTN_YES.0, "yes"),
+    (BTN_NO.0, "no"),
+    (BTN_OK.0, "ok"),
+    (BTN_CANCEL.0, "cancel"),
+    (BTN_ABORT.0, "abort"),
+    (BTN_RETRY.0, "retry"),
+    (BTN_IGNORE.0, "ignore"),
+];
+
+/// ダイアログの代わりにコンソールで応答を得る
+pub fn console_dialog(dialog: &HostDialog) -> Object {
+    let result = match dialog {
+        HostDialog::MsgBox { message, buttons, focus } => msgbox(message, *buttons, *focus),
+        HostDialog::Input { message, labels, defaults, .. } => input(message, labels, defaults),
+        HostDialog::Slctbox { message, kind, items, .. } => slctbox(message, *kind, items),
+    };
+    result.unwrap_or_else(|| dialog.cancel_value())
+}
+
+fn prompt(prompt: &str) {
+    let mut stderr = io::stderr();
+    let _ = write!(stderr, "{prompt}");
+    let _ = stderr.flush();
+}
+
+fn read_answer() -> Option<String> {
+    let mut answers = ANSWERS.lock().unwrap();
+    match answers.as_mut() {
+        Some(queue) => {
+            let answer = queue.pop_front()?;
+            // 応答ファイルの内容も入力されたかのように出力しておく
+            eprintln!("{answer}");
+            Some(answer)
+        },
+        None => {
+            let mut line = String::new();
+            match io::stdin().lock().read_line(&mut line) {
+                Ok(0) | Err(_) => None,
+                Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+            }
+        },
+    }
+}
+
+/// ボタン名またはBTN定数の値を受ける、空行ならフォーカスされたボタン
+fn msgbox(message: &str, buttons: i32, focus: Option<i32>) -> Option<Object> {
+    let names = available_buttons(buttons).into_iter()
+        .map(|(_, name)| name)
+        .collect::<Vec<_>>();
+    eprintln!("{message}");
+    prompt(&format!("[{}] > ", names.join("/")));
+    let answer = read_answer()?;
+    let pressed = parse_msgbox_answer(&answer, buttons, focus)?;
+    Some(Object::Num(pressed as f64))
+}
+
+fn available_buttons(buttons: i32) -> Vec<(i32, &'static str)> {
+    BUTTONS.into_iter()
+        .filter(|(btn, _)| buttons & btn == *btn)
+        .collect()
+}
+
+/// msgboxの応答から押されたボタンを得る、該当するボタンがなければNone
+fn parse_msgbox_answer(answer: &str, buttons: i32, focus: Option<i32>) -> Option<i32> {
+    let available = available_buttons(buttons);
+    let answer = answer.trim();
+    if answer.is_empty() {
+        focus.filter(|f| available.iter().any(|(btn, _)| btn == f))
+            .or(available.first().map(|(btn, _)| *btn))
+    } else {
+        available.iter()
+            .find(|(btn, name)| {
+                name.eq_ignore_ascii_case(answer) || answer.parse::<i32>().is_ok_and(|n| n == *btn)
+            })
+            .map(|(btn, _)| *btn)
+    }
+}
+
+/// 入力欄ごとに1行ずつ受ける、空行ならデフォルト値
+fn input(message: &str, labels: &[Option<String>], defaults: &[Option<String>]) -> Option<Object> {
+    eprintln!("{message}");
+    let mut values = vec![];
+    for (label, default) in labels.iter().zip(defaults) {
+        let label = label.as_deref().unwrap_or_default();
+        match default {
+            Some(default) => prompt(&format!("{label} [{default}] > ")),
+            None => prompt(&format!("{label} > ")),
+        }
+        let answer = read_answer()?;
+        values.push(input_value(answer, default));
+    }
+    if values.len() == 1 {
+        values.pop().map(Object::String)
+    } else {
+        Some(Object::Array(values.into_iter().map(Object::String).collect()))
+    }
+}
+
+fn input_value(answer: String, default: &Option<String>) -> String {
+    if answer.is_empty() {
+        default.clone().unwrap_or_default()
+    } else {
+        answer
+    }
+}
+
+/// 項目番号 (1から) または項目名を受ける、複数選択可能ならカンマ区切り
+fn slctbox(message: &str, kind: i32, items: &[String]) -> Option<Object> {
+    if ! message.is_empty() {
+        eprintln!("{message}");
+    }
+    for (i, item) in items.iter().enumerate() {
+        eprintln!("{}: {item}", i + 1);
+    }
+    if is_multi_select(kind) {
+        prompt(&format!("[1-{},...] > ", items.len()));
+    } else {
+        prompt(&format!("[1-{}] > ", items.len()));
+    }
+    let answer = read_answer()?;
+    parse_slct_answer(&answer, kind, items)
+}
+
+fn includes(kind: i32, c: SlctConst) -> bool {
+    let c = c as i32;
+    kind & c == c
+}
+
+fn is_multi_select(kind: i32) -> bool {
+    if includes(kind, SlctConst::SLCT_BTN) {
+        false
+    } else if includes(kind, SlctConst::SLCT_CHK) {
+        true
+    } else if includes(kind, SlctConst::SLCT_RDO) || includes(kind, SlctConst::SLCT_CMB) {
+        false
+    } else {
+        includes(kind, SlctConst::SLCT_LST)
+    }
+}
+
+/// slctboxの応答から戻り値を得る、空行や該当する項目がなければNone
+fn parse_slct_answer(answer: &str, kind: i32, items: &[String]) -> Option<Object> {
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return None;
+    }
+    let multi = is_multi_select(kind);
+    let tokens = if multi {
+        answer.split(',').map(|t| t.trim()).collect()
+    } else {
+        vec![answer]
+    };
+    let indexes = tokens.into_iter()
+        .map(|token| {
+            match token.parse::<usize>() {
+                Ok(n) => (1..=items.len()).contains(&n).then(|| n - 1),
+                Err(_) => items.iter().position(|item| item == token),
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let values = indexes.into_iter()
+        .map(|index| {
+            if includes(kind, SlctConst::SLCT_STR) {
+                Some(Object::String(items[index].clone()))
+            } else if includes(kind, SlctConst::SLCT_NUM) {
+                Some(Object::Num(index as f64))
+            } else {
+                2i32.checked_pow(index as u32).map(|c| Object::Num(c as f64))
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    if ! includes(kind, SlctConst::SLCT_STR) && ! includes(kind, SlctConst::SLCT_NUM) {
+        // 定数で返す場合は複数選択でもORした値を返す
+        let c = values.iter()
+            .filter_map(|o| if let Object::Num(n) = o {Some(*n as i32)} else {None})
+            .fold(0, |a, b| a | b);
+        Some(Object::Num(c as f64))
+    } else if multi {
+        Some(Object::Array(values))
+    } else {
+        values.into_iter().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const YES_NO: i32 = BTN_YES.0 | BTN_NO.0;
+
+    #[rstest]
+    #[case("yes", None, Some(BTN_YES.0))]
+    #[case(" NO ", None, Some(BTN_NO.0))]
+    #[case("8", None, Some(BTN_NO.0))]
+    #[case("", None, Some(BTN_YES.0))]
+    #[case("", Some(BTN_NO.0), Some(BTN_NO.0))]
+    // 表示されていないボタンはフォーカスにも応答にもならない
+    #[case("", Some(BTN_OK.0), Some(BTN_YES.0))]
+    #[case("ok", None, None)]
+    #[case("2", None, None)]
+    fn test_msgbox_answer(#[case] answer: &str, #[case] focus: Option<i32>, #[case] expected: Option<i32>) {
+        assert_eq!(parse_msgbox_answer(answer, YES_NO, focus), expected);
+    }
+
+    #[test]
+    fn test_input_value() {
+        let default = Some("foo".to_string());
+        assert_eq!(input_value("bar".into(), &default), "bar");
+        assert_eq!(input_value("".into(), &default), "foo");
+        assert_eq!(input_value("".into(), &None), "");
+    }
+
+    fn items() -> Vec<String> {
+        vec!["a".into(), "b".into(), "c".into()]
+    }
+
+    #[rstest]
+    #[case("2", 0, Some(Object::Num(2.0)))]
+    #[case("c", 0, Some(Object::Num(4.0)))]
+    #[case("2", SlctConst::SLCT_STR as i32, Some(Object::String("b".into())))]
+    #[case("2", SlctConst::SLCT_NUM as i32, Some(Object::Num(1.0)))]
+    #[case("1, 3", SlctConst::SLCT_CHK as i32, Some(Object::Num(5.0)))]
+    #[case("1,c", SlctConst::SLCT_CHK as i32 | SlctConst::SLCT_STR as i32, Some(Object::Array(vec![Object::String("a".into()), Object::String("c".into())])))]
+    // 複数選択できなければカンマ区切りは項目名として扱う
+    #[case("1,3", 0, None)]
+    #[case("4", 0, None)]
+    #[case("0", 0, None)]
+    #[case("d", 0, None)]
+    #[case("", 0, None)]
+    fn test_slct_answer(#[case] answer: &str, #[case] kind: i32, #[case] expected: Option<Object>) {
+        assert_eq!(parse_slct_answer(answer, kind, &items()), expected);
+    }
+}
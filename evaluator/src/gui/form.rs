@@ -1,4 +1,4 @@
-use util::winapi::show_message;
+use util::winapi::{show_message, error_exit_code};
 use util::write_locale;
 use util::error::{
     Locale, CURRENT_LOCALE,
@@ -705,7 +705,7 @@ impl WebView {
                                 out_log(&msg, LogType::Error);
                                 let title = UWSCRErrorTitle::RuntimeError.to_string();
                                 show_message(&msg, &title, true);
-                                std::process::exit(0);
+                                std::process::exit(error_exit_code());
                             }
                         }
                     }
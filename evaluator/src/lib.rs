@@ -4,6 +4,9 @@ pub mod builtins;
 pub mod def_dll;
 pub mod error;
 pub mod gui;
+pub mod embed;
+pub mod limits;
+pub mod sandbox;
 
 use environment::*;
 use object::*;
@@ -12,9 +15,12 @@ use def_dll::*;
 use error::{UError, UErrorKind, UErrorMessage};
 use builtins::system_controls::{POFF, poff::{sign_out, power_off, shutdown, reboot}};
 use gui::{UWindow, LogPrintWin, FontFamily};
+use embed::HostCallbacks;
+use limits::{ExecLimits, LimitCounter};
+use sandbox::SandboxPolicy;
 
 use util::com::Com;
-use util::winapi::{show_message,FORCE_WINDOW_MODE};
+use util::winapi::{show_message, is_headless, error_exit_code, FORCE_WINDOW_MODE};
 use util::logging::{self, out_log, LogType};
 use util::settings::*;
 use util::error::UWSCRErrorTitle;
@@ -90,6 +96,10 @@ pub struct Evaluator {
     pub gui_print: Option<bool>,
     special_char: bool,
     short_circuit: bool,
+    host: Option<Arc<HostCallbacks>>,
+    limit: Arc<LimitCounter>,
+    call_depth: u32,
+    sandbox: Option<Arc<SandboxPolicy>>,
 }
 impl Clone for Evaluator {
     fn clone(&self) -> Self {
@@ -102,6 +112,10 @@ impl Clone for Evaluator {
             gui_print: self.gui_print,
             special_char: self.special_char,
             short_circuit: self.short_circuit,
+            host: self.host.clone(),
+            limit: self.limit.clone(),
+            call_depth: self.call_depth,
+            sandbox: self.sandbox.clone(),
         }
     }
 }
@@ -117,6 +131,14 @@ impl Evaluator {
     }
 
     pub fn new(env: Environment) -> Self {
+        let (sandbox, limits) = {
+            let usettings = USETTINGS.lock().unwrap();
+            (
+                SandboxPolicy::from_settings(&usettings.sandbox).map(Arc::new),
+                ExecLimits::from_options(&usettings.options),
+            )
+        };
+        let limit = Arc::new(LimitCounter::new(limits));
         Evaluator {
             env,
             ignore_com_err: false,
@@ -126,6 +148,10 @@ impl Evaluator {
             gui_print: None,
             special_char: false,
             short_circuit: true,
+            host: None,
+            limit,
+            call_depth: 0,
+            sandbox,
         }
     }
     fn new_thread(&mut self) -> Self {
@@ -145,6 +171,10 @@ impl Evaluator {
             gui_print: self.gui_print,
             special_char: self.special_char,
             short_circuit: self.short_circuit,
+            host: self.host.clone(),
+            limit: self.limit.clone(),
+            call_depth: 0,
+            sandbox: self.sandbox.clone(),
         }
     }
 
@@ -221,7 +251,10 @@ impl Evaluator {
         });
 
 
-        if cfg!(feature="gui") {
+        if self.is_embedded() || is_headless() {
+            // ホストに組み込まれている場合やヘッドレスモードではprintウィンドウを使わない
+            self.gui_print = Some(false);
+        } else if cfg!(feature="gui") {
             Self::start_logprint_win(true);
             self.gui_print = Some(true);
         } else if self.gui_print.is_none() {
@@ -259,6 +292,10 @@ impl Evaluator {
                 },
                 Err(e) => {
                     match e.kind {
+                        UErrorKind::ExitExit(_) if self.is_embedded() => {
+                            // プロセスの終了はホストに委ねる
+                            return Err(e);
+                        },
                         UErrorKind::ExitExit(n) => {
                             self.clear();
                             std::process::exit(n);
@@ -386,15 +423,16 @@ impl Evaluator {
         let casecare = (opt & HashTblEnum::HASH_CASECARE as u32) > 0;
         let mut hashtbl = HashTbl::new(sort, casecare);
         for (name_expr, val_expr) in hash.members {
-            let name = if let Expression::Literal(Literal::ExpandableString(s)) = name_expr {
-                self.expand_string(s, true, None).to_string()
-            } else if let Expression::Literal(Literal::String(s)) = name_expr {
-                s
-            } else {
-                name_expr.to_string()
+            let key = match name_expr {
+                Expression::Literal(Literal::ExpandableString(s)) => Object::String(self.expand_string(s, true, None).to_string()),
+                Expression::Literal(Literal::String(s)) => Object::String(s),
+                // 数値と真偽値のキーは型を保持する
+                Expression::Literal(Literal::Num(n)) => Object::Num(n),
+                Expression::Literal(Literal::Bool(b)) => Object::Bool(b),
+                name_expr => Object::String(name_expr.to_string()),
             };
             let value = self.eval_expression(val_expr)?;
-            hashtbl.insert(name, value);
+            hashtbl.insert_key(key, value);
         }
         let object = Object::HashTbl(Arc::new(Mutex::new(hashtbl)));
         match module {
@@ -425,8 +463,11 @@ impl Evaluator {
             obj => obj.to_string()
         };
 
-        out_log(&msg, LogType::Print);
+        self.out_log(&msg, LogType::Print);
 
+        if self.host_print(&msg) {
+            return Ok(None);
+        }
         if self.gui_print.unwrap_or(false) {
             match LOGPRINTWIN.get() {
                 Some(lp) => {
@@ -439,7 +480,8 @@ impl Evaluator {
                     println!("{msg}");
                 },
             }
-        } else {
+        } else if ! is_headless() {
+            // ヘッドレスモードではout_logが標準出力へ書き出している
             println!("{msg}");
         }
         Ok(None)
@@ -500,13 +542,33 @@ impl Evaluator {
             OptionSetting::CondUwsc(b) => {
                 usettings.options.cond_uwsc = b;
             }
+            OptionSetting::TimeLimit(n) => {
+                usettings.options.time_limit = n;
+                self.update_limits(|limits| limits.timeout = (n > 0).then(|| std::time::Duration::from_secs(n)));
+            },
+            OptionSetting::StepLimit(n) => {
+                usettings.options.step_limit = n;
+                self.update_limits(|limits| limits.max_steps = (n > 0).then_some(n));
+            },
+            OptionSetting::DepthLimit(n) => {
+                usettings.options.depth_limit = n;
+                self.update_limits(|limits| limits.max_depth = (n > 0).then_some(n));
+            },
+            OptionSetting::MemLimit(n) => {
+                usettings.options.mem_limit = n;
+                self.update_limits(|limits| limits.max_alloc = (n > 0).then(|| (n as usize).saturating_mul(1024 * 1024)));
+            },
+            OptionSetting::RandSeed(n) => object::rng::set_default_seed(Some(n)),
             OptionSetting::AllowIEObj(b) => usettings.options.allow_ie_object = b,
         }
     }
 
     fn eval_statement(&mut self, statement: StatementWithRow) -> EvalResult<Option<Object>> {
         let StatementWithRow { statement, row, line, script_name } = statement;
-        let result = self.eval_statement_inner(statement);
+        let result = match self.check_limit() {
+            Ok(_) => self.eval_statement_inner(statement),
+            Err(e) => Err(e),
+        };
         if self.ignore_com_err {
             match result {
                 Ok(r) => Ok(r),
@@ -605,6 +667,7 @@ impl Evaluator {
                 Ok(None)
             },
             Statement::DefDll{name, alias, params, ret_type, path} => {
+                self.check_sandbox(SandboxCapability::Ffi, &name)?;
                 let params = DefDll::convert_params(params, self)?;
                 let defdll = DefDll::new(name, alias, path, params, ret_type)?;
                 self.env.define_dll_function(defdll)?;
@@ -884,15 +947,41 @@ impl Evaluator {
         Ok(None)
     }
 
-    fn eval_select_statement(&mut self, expression: Expression, cases: Vec<(Vec<Expression>, BlockStatement)>, default: Option<BlockStatement>) -> EvalResult<Option<Object>> {
+    fn eval_select_statement(&mut self, expression: Expression, cases: Vec<SelectCase>, default: Option<BlockStatement>) -> EvalResult<Option<Object>> {
         let select_obj = self.eval_expression(expression)?;
-        for (case_exp, block) in cases {
-            for e in case_exp {
-                let case_obj = self.eval_expression(e)?;
-                if case_obj.is_equal(&select_obj) {
-                    return self.eval_block_statement(block);
+        for SelectCase { patterns, guard, body } in cases {
+            let mut bindings = vec![];
+            let mut matched = false;
+            for pattern in patterns {
+                bindings.clear();
+                if self.match_case_pattern(&select_obj, pattern, &mut bindings)? {
+                    matched = true;
+                    break;
+                }
+            }
+            if ! matched {
+                continue;
+            }
+            // ガード節が偽の場合に束縛を取り消せるよう元の値を控えておく
+            let previous = bindings.iter()
+                .map(|(name, _)| (name.clone(), self.env.get_variable(name)))
+                .collect::<Vec<_>>();
+            for (name, value) in bindings {
+                self.env.assign(&name, value)?;
+            }
+            // ガード節が偽なら次のcaseへ
+            if let Some(condition) = guard {
+                if ! self.eval_conditional_expression(condition)? {
+                    for (name, value) in previous.into_iter().rev() {
+                        match value {
+                            Some(value) => { self.env.assign(&name, value)?; },
+                            None => self.env.remove_variable(name),
+                        }
+                    }
+                    continue;
                 }
             }
+            return self.eval_block_statement(body);
         }
         match default {
             Some(b) => self.eval_block_statement(b),
@@ -900,7 +989,98 @@ impl Evaluator {
         }
     }
 
+    /// caseパターンに一致するかどうか
+    ///
+    /// 一致した場合に束縛すべき変数はbindingsに積まれる
+    fn match_case_pattern(&mut self, obj: &Object, pattern: CasePattern, bindings: &mut Vec<(String, Object)>) -> EvalResult<bool> {
+        let matched = match pattern {
+            CasePattern::Value(expression) => {
+                match self.eval_expression(expression)? {
+                    Object::RegEx(pattern) => match obj {
+                        Object::String(s) => {
+                            let re = Regex::new(&pattern)
+                                .map_err(|_| UError::new(UErrorKind::SyntaxError, UErrorMessage::InvalidRegexPattern(pattern)))?;
+                            re.is_match(s)
+                        },
+                        Object::RegEx(other) => &pattern == other,
+                        _ => false,
+                    },
+                    value => value.is_equal(obj),
+                }
+            },
+            CasePattern::Range(from, to) => {
+                let from = self.eval_expression(from)?;
+                let to = self.eval_expression(to)?;
+                obj.greater_than_equal(&from)?.is_truthy() && obj.less_than_equal(&to)?.is_truthy()
+            },
+            CasePattern::Compare(infix, expression) => {
+                let right = self.eval_expression(expression)?;
+                self.eval_infix_expression(infix, obj.clone(), right)?.is_truthy()
+            },
+            CasePattern::Type(expression) => {
+                let expected = self.eval_expression(expression)?.to_string();
+                obj.get_type().to_string().eq_ignore_ascii_case(&expected)
+            },
+            CasePattern::Array(patterns) => {
+                let items = match obj {
+                    Object::Array(arr) => arr.clone(),
+                    Object::ByteArray(arr) => arr.iter().map(|b| Object::Num(*b as f64)).collect(),
+                    Object::UObject(uo) => match uo.to_object_vec() {
+                        Ok(vec) => vec,
+                        Err(_) => return Ok(false),
+                    },
+                    _ => return Ok(false),
+                };
+                if items.len() != patterns.len() {
+                    return Ok(false);
+                }
+                for (item, pattern) in items.iter().zip(patterns) {
+                    if ! self.match_case_pattern(item, pattern, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            },
+            CasePattern::Object(members) => {
+                for (key, pattern) in members {
+                    let value = match obj {
+                        Object::UObject(uo) => match uo.get(&Object::String(key)) {
+                            Ok(value) => value,
+                            Err(_) => return Ok(false),
+                        },
+                        Object::HashTbl(hash) => {
+                            let hash = hash.lock().unwrap();
+                            if ! hash.check(key.clone()).is_truthy() {
+                                return Ok(false);
+                            }
+                            hash.get(&key)
+                        },
+                        _ => return Ok(false),
+                    };
+                    if ! self.match_case_pattern(&value, pattern, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            },
+            CasePattern::Bind(Identifier(name)) => {
+                // 定数は束縛せず値を比較する
+                if self.env.is_const(&name) {
+                    let value = self.env.get_variable(&name).unwrap_or_default();
+                    value.is_equal(obj)
+                } else {
+                    bindings.push((name, obj.clone()));
+                    true
+                }
+            },
+            CasePattern::Wildcard => true,
+        };
+        Ok(matched)
+    }
+
     fn eval_loopblock_statement(&mut self, block: BlockStatement) -> EvalResult<Option<Object>> {
+        // 空のループでも制限を超えられないよう繰り返しごとに確認する
+        self.check_limit()?;
         for statement in block {
             match self.eval_statement(statement) {
                 Ok(opt) => if let Some(o) = opt {
@@ -1075,6 +1255,18 @@ impl Evaluator {
             Object::ParamStr(v) => {
                 self.eval_for_in_statement_inner(v, var, index_var, islast_var, block, alt)
             }
+            Object::CsvStream(csv) => {
+                let rows = CsvRows(csv);
+                self.eval_for_in_statement_iter(rows, var, index_var, islast_var, block, alt)
+            },
+            Object::XmlNode(node) => {
+                let vec = node.to_vec();
+                self.eval_for_in_statement_inner(vec, var, index_var, islast_var, block, alt)
+            },
+            Object::ZipArchive(zip) => {
+                let names = zip.lock().unwrap().names();
+                self.eval_for_in_statement_inner(names, var, index_var, islast_var, block, alt)
+            },
             _ => Err(UError::new(
                 UErrorKind::SyntaxError,
                 UErrorMessage::ForInError
@@ -1089,16 +1281,32 @@ impl Evaluator {
         islast_var: Option<Identifier>,
         block: BlockStatement,
         alt: Option<BlockStatement>,
+    ) -> EvalResult<Option<Object>> {
+        let iter = col_obj.into_iter().map(|o| Ok(o.into()));
+        self.eval_for_in_statement_iter(iter, var, index_var, islast_var, block, alt)
+    }
+    /// 要素を順に取り出しながらループする
+    fn eval_for_in_statement_iter<I: Iterator<Item = EvalResult<Object>>>(
+        &mut self,
+        iter: I,
+        var: &str,
+        index_var: Option<Identifier>,
+        islast_var: Option<Identifier>,
+        block: BlockStatement,
+        alt: Option<BlockStatement>,
     ) -> EvalResult<Option<Object>> {
         let mut broke = false;
-        let len = col_obj.len();
-        for (i, o) in col_obj.into_iter().enumerate() {
-            self.env.assign(var, o.into())?;
+        let mut iter = iter.peekable();
+        let mut i: usize = 0;
+        while let Some(o) = iter.next() {
+            self.env.assign(var, o?)?;
             if let Some(Identifier(name)) = &index_var {
                 self.env.assign(name, i.into())?;
             }
+            i += 1;
             if let Some(Identifier(name)) = &islast_var {
-                let is_last = i + 1 == len;
+                // 最終要素かどうかを知るため次の要素を先読みする
+                let is_last = iter.peek().is_none();
                 self.env.assign(name, is_last.into())?;
             }
             match self.eval_loopblock_statement(block.clone())? {
@@ -1317,6 +1525,7 @@ impl Evaluator {
                     );
                 },
                 Statement::DefDll { name, alias, params, ret_type, path } => {
+                    self.check_sandbox(SandboxCapability::Ffi, &name)?;
                     let params = DefDll::convert_params(params, self)?;
                     let defdll = DefDll::new(name, alias, path, params, ret_type)?;
                     match &defdll.alias {
@@ -1402,6 +1611,7 @@ impl Evaluator {
                 let evaluator2 = evaluator.clone();
                 panic::set_hook(Box::new(move |panic_info|{
                     let maybe_uerror = uerror2.lock().unwrap();
+                    let mut exit_code = 0;
                     // attach_console();
                     match maybe_uerror.as_ref() {
                         Some(e) => match &e.kind {
@@ -1415,23 +1625,28 @@ impl Evaluator {
                                     out_log(&err, LogType::Error);
                                     let title = UWSCRErrorTitle::ThreadError.to_string();
                                     show_message(&err, &title, true);
+                                    exit_code = error_exit_code();
                                 }
                             }
                             _ => {
                                 let err = e.errror_text_with_line();
-                                out_log(&err, LogType::Error);
-                                let title = UWSCRErrorTitle::ThreadError.to_string();
-                                show_message(&err, &title, true);
+                                evaluator2.out_log(&err, LogType::Error);
+                                if ! evaluator2.is_embedded() {
+                                    let title = UWSCRErrorTitle::ThreadError.to_string();
+                                    show_message(&err, &title, true);
+                                }
+                                exit_code = error_exit_code();
                             }
                         },
                         None => {
                             let err = panic_info.to_string();
                             out_log(&err, LogType::Panic);
                             show_message(&err, "Panic on thread", true);
+                            exit_code = error_exit_code();
                         },
                     }
                     // free_console();
-                    std::process::exit(0);
+                    std::process::exit(exit_code);
                 }));
                 let result = evaluator.eval_function_call_expression(*func, args, false);
                 evaluator.clear_local();
@@ -1473,6 +1688,7 @@ impl Evaluator {
                                 UErrorMessage::InvalidIndex(o),
                             )),
                         };
+                        self.check_array_len(size)?;
                         let mut array = vec![];
                         for e in v {
                             array.push(self.eval_expression(e)?);
@@ -1527,6 +1743,7 @@ impl Evaluator {
                                 UErrorMessage::InvalidArraySize,
                             ));
                         }
+                        self.check_array_len(actual_size)?;
                         array.resize(actual_size, Object::Empty);
                         for size in sizes {
                             // 低い方から処理
@@ -1889,6 +2106,9 @@ impl Evaluator {
                 }
             },
             Object::MemberCaller(ref method, ref name) => {
+                if let Some(sandbox) = &self.sandbox {
+                    sandbox.check_member(method, name)?;
+                }
                 match method {
                     MemberCaller::RemoteObject(remote) => {
                         let index = index.to_string();
@@ -1910,7 +2130,15 @@ impl Evaluator {
                     MemberCaller::TabWindow(_) |
                     MemberCaller::WebRequest(_) |
                     MemberCaller::WebResponse(_) |
-                    MemberCaller::HtmlNode(_) => {
+                    MemberCaller::HtmlNode(_) |
+                    MemberCaller::DateTime(_) |
+                    MemberCaller::CsvStream(_) |
+                    MemberCaller::XmlNode(_) |
+                    MemberCaller::HashTbl(_) |
+                    MemberCaller::Rng(_) |
+                    MemberCaller::ZipArchive(_) |
+                    MemberCaller::HttpRequest(_) |
+                    MemberCaller::HttpResponse(_) => {
                         return Err(UError::new(
                             UErrorKind::DotOperatorError,
                             UErrorMessage::NotAnArray(left)
@@ -1998,6 +2226,7 @@ impl Evaluator {
         if let Object::Global = value {
             return Err(UError::new(UErrorKind::AssignError, UErrorMessage::GlobalCanNotBeAssigned))
         }
+        self.check_alloc(&value)?;
         let assigned_value = value.clone();
         match left {
             Expression::Identifier(Identifier(ref name)) => {
@@ -2248,11 +2477,8 @@ impl Evaluator {
                 Ok((Some(Object::Array(arr)), true))
             },
             Object::HashTbl(mutex) => {
-                let name = match index {
-                    Object::Num(n) => n.to_string(),
-                    Object::Bool(b) => b.to_string(),
-                    Object::String(s) => s,
-                    _ => return Err(UError::new(
+                let Some(name) = HashTbl::key_to_string(&index) else {
+                    return Err(UError::new(
                         UErrorKind::AssignError,
                         UErrorMessage::InvalidIndex(index)
                     ))
@@ -2262,9 +2488,9 @@ impl Evaluator {
                 let (maybe_new, update) = Self::update_array_object(obj.clone(), dimension, new)?;
                 if update {
                     if let Some(new_array) = maybe_new {
-                        hash.insert(name, new_array);
+                        hash.insert_key(index, new_array);
                     } else {
-                        hash.insert(name, new.to_owned());
+                        hash.insert_key(index, new.to_owned());
                     }
                 }
                 Ok((None, false))
@@ -2440,7 +2666,12 @@ impl Evaluator {
 
     fn eval_infix_expression(&mut self, infix: Infix, left: Object, right: Object) -> EvalResult<Object> {
         match infix {
-            Infix::Plus => left.add(right),
+            Infix::Plus => {
+                // 文字列・配列の連結ごとに大きさを確認する
+                let result = left.add(right)?;
+                self.check_alloc(&result)?;
+                Ok(result)
+            },
             Infix::Minus => left.sub(right),
             Infix::Multiply => left.mul(right),
             Infix::Divide => left.div(right),
@@ -2649,6 +2880,9 @@ impl Evaluator {
 
     fn eval_function_call_expression(&mut self, func: Expression, args: Vec<Expression>, is_await: bool) -> EvalResult<Object> {
         let func_object = self.eval_expression_for_func_call(func)?;
+        if let (Some(sandbox), Object::MemberCaller(method, member)) = (&self.sandbox, &func_object) {
+            sandbox.check_member(method, member)?;
+        }
         if let Object::MemberCaller(MemberCaller::ComObject(com), member) = func_object {
             // COMのメソッド呼び出し
             let mut comargs = ComObject::to_comarg(self, args)?;
@@ -2676,6 +2910,9 @@ impl Evaluator {
                 },
                 Object::AnonFunc(f) => f.invoke(self, arguments, None),
                 Object::BuiltinFunction(name, expected_len, builtin) => {
+                    if let Some(sandbox) = &self.sandbox {
+                        sandbox.check_builtin(&name, &arguments)?;
+                    }
                     if expected_len >= arguments.len() as i32 {
                         builtin(self, BuiltinFuncArgs::new(arguments, is_await))
                             .map_err(|err| err.to_uerror(name))
@@ -2813,30 +3050,86 @@ impl Evaluator {
                             remote.invoke_method(&member, args, is_await)
                         },
                         MemberCaller::WebRequest(mutex) => {
+                            let args = arguments.into_iter()
+                                .map(|(_, arg)| arg)
+                                .collect();
+                            WebRequest::invoke_method(&mutex, self, &member, args)
+                        },
+                        MemberCaller::WebResponse(res) => {
+                            let args = arguments.into_iter()
+                                .map(|(_, arg)| arg)
+                                .collect();
+                            res.invoke_method(&member, args)
+                        },
+                        MemberCaller::HtmlNode(node) => {
+                            let args = arguments.into_iter()
+                                .map(|(_, arg)| arg)
+                                .collect();
+                            node.invoke_method(&member, args)
+                        },
+                        MemberCaller::DateTime(dt) => {
+                            let args = arguments.into_iter()
+                                .map(|(_, arg)| arg)
+                                .collect();
+                            dt.invoke_method(&member, args)
+                        },
+                        MemberCaller::CsvStream(mutex) => {
                             let args = arguments.into_iter()
                                 .map(|(_, arg)| arg)
                                 .collect();
                             let maybe_obj = {
-                                let mut req = mutex.lock().unwrap();
-                                req.invoke_method(&member, args)?
+                                let mut csv = mutex.lock().unwrap();
+                                csv.invoke_method(&member, args)?
                             };
                             let obj = match maybe_obj {
                                 Some(obj) => obj,
-                                None => Object::WebRequest(mutex),
+                                None => Object::CsvStream(mutex),
                             };
                             Ok(obj)
                         },
-                        MemberCaller::WebResponse(res) => {
+                        MemberCaller::XmlNode(node) => {
                             let args = arguments.into_iter()
                                 .map(|(_, arg)| arg)
                                 .collect();
-                            res.invoke_method(&member, args)
+                            node.invoke_method(&member, args)
                         },
-                        MemberCaller::HtmlNode(node) => {
+                        MemberCaller::HashTbl(mutex) => {
                             let args = arguments.into_iter()
                                 .map(|(_, arg)| arg)
                                 .collect();
-                            node.invoke_method(&member, args)
+                            HashTbl::invoke_method(&mutex, self, &member, args)
+                        },
+                        MemberCaller::Rng(rng) => {
+                            let args = arguments.into_iter()
+                                .map(|(_, arg)| arg)
+                                .collect();
+                            rng.invoke_method(&member, args)
+                        },
+                        MemberCaller::ZipArchive(mutex) => {
+                            let args = arguments.into_iter()
+                                .map(|(_, arg)| arg)
+                                .collect();
+                            let maybe_obj = {
+                                let mut zip = mutex.lock().unwrap();
+                                zip.invoke_method(&member, args)?
+                            };
+                            let obj = match maybe_obj {
+                                Some(obj) => obj,
+                                None => Object::ZipArchive(mutex),
+                            };
+                            Ok(obj)
+                        },
+                        MemberCaller::HttpRequest(req) => {
+                            let args = arguments.into_iter()
+                                .map(|(_, arg)| arg)
+                                .collect();
+                            req.invoke_method(&member, args)
+                        },
+                        MemberCaller::HttpResponse(res) => {
+                            let args = arguments.into_iter()
+                                .map(|(_, arg)| arg)
+                                .collect();
+                            res.invoke_method(&member, args)
                         },
                         MemberCaller::ComObject(_) => {
                             unreachable!()
@@ -2861,7 +3154,12 @@ impl Evaluator {
                             let obj = remote.invoke_method(&member, args, is_await)?;
                             Ok(obj)
                         },
-                        MemberCaller::UObject(uobj) => uobj.invoke_method(&member),
+                        MemberCaller::UObject(uobj) => {
+                            let args = arguments.into_iter()
+                                .map(|(_, arg)| arg)
+                                .collect();
+                            uobj.invoke_method(&member, args)
+                        },
                         MemberCaller::SafeArray(sa) => {
                             match member {
                                 member if member.eq_ignore_ascii_case("get") => {
@@ -3043,6 +3341,60 @@ impl Evaluator {
                     node.get_property(&member)
                 }
             },
+            Object::DateTime(dt) => {
+                if is_func {
+                    Ok(Object::MemberCaller(MemberCaller::DateTime(dt), member))
+                } else {
+                    dt.get_property(&member)
+                }
+            },
+            Object::CsvStream(csv) => {
+                if is_func {
+                    Ok(Object::MemberCaller(MemberCaller::CsvStream(csv), member))
+                } else {
+                    let csv = csv.lock().unwrap();
+                    csv.get_property(&member)
+                }
+            },
+            Object::XmlNode(node) => {
+                if is_func {
+                    Ok(Object::MemberCaller(MemberCaller::XmlNode(node), member))
+                } else {
+                    node.get_property(&member)
+                }
+            },
+            Object::HashTbl(hash) if is_func => {
+                Ok(Object::MemberCaller(MemberCaller::HashTbl(hash), member))
+            },
+            Object::Rng(rng) => {
+                if is_func {
+                    Ok(Object::MemberCaller(MemberCaller::Rng(rng), member))
+                } else {
+                    rng.get_property(&member)
+                }
+            },
+            Object::ZipArchive(zip) => {
+                if is_func {
+                    Ok(Object::MemberCaller(MemberCaller::ZipArchive(zip), member))
+                } else {
+                    let zip = zip.lock().unwrap();
+                    zip.get_property(&member)
+                }
+            },
+            Object::HttpRequest(req) => {
+                if is_func {
+                    Ok(Object::MemberCaller(MemberCaller::HttpRequest(req), member))
+                } else {
+                    req.get_property(&member)
+                }
+            },
+            Object::HttpResponse(res) => {
+                if is_func {
+                    Ok(Object::MemberCaller(MemberCaller::HttpResponse(res), member))
+                } else {
+                    res.get_property(&member)
+                }
+            },
             Object::WebViewForm(form) => {
                 if is_func {
                     Ok(Object::MemberCaller(MemberCaller::WebViewForm(form), member))
@@ -3489,6 +3841,35 @@ hoge['foo'] = 1 and hoge['bar'] = 2
         "#,
         Object::Bool(true)
     )]
+    #[case(
+        r#"
+hashtbl hoge
+hoge[1] = "a"
+hoge[TRUE] = "b"
+hoge.keys()
+        "#,
+        Object::Array(vec![Object::Num(1.0), Object::Bool(true)])
+    )]
+    #[case(
+        r#"
+hashtbl hoge
+hoge["a"] = 1
+hoge.get("b", 5) + hoge.setdefault("a", 10) + hoge.setdefault("c", 100) + hoge["c"]
+        "#,
+        Object::Num(206.0)
+    )]
+    #[case(
+        r#"
+hashtbl hoge
+hoge["a"] = 3
+hoge["b"] = 1
+hoge["c"] = 2
+hoge.sort_by(| k, v => v |)
+filtered = hoge.filter(| k, v => v > 1 |)
+join(hoge.keys(), "") + join(filtered.keys(), "")
+        "#,
+        Object::String("BCACA".to_string())
+    )]
     fn test_assign_hashtbl(#[case] input: &str, #[case] expected: Object) {
         expect_object_test(None, input, expected);
     }
@@ -3517,6 +3898,75 @@ hoge[0]
         expect_object_test(None, input, expected);
     }
 
+    #[rstest]
+    #[case(r#"
+join(map([1, 2, 3], | n => n * 2 |), ",")
+        "#,
+        "2,4,6".into()
+    )]
+    #[case(r#"
+map([[1, 2], [3]], | n => n + 1 |, TRUE)
+        "#,
+        Object::Array(vec![
+            Object::Array(vec![2.into(), 3.into()]),
+            Object::Array(vec![4.into()]),
+        ])
+    )]
+    #[case(r#"
+function even(n, i)
+    result = (n mod 2) == 0
+fend
+join(filter([1, 2, 3, 4], even), ",") + "/" + findindex([1, 3, 4], even) + "/" + find([1, 3], even)
+        "#,
+        "2,4/2/".into()
+    )]
+    #[case(r#"
+reduce([1, 2, 3], | acc, n => acc + n |, 10) + reduce([5], | acc, n => acc + n |)
+        "#,
+        21.into()
+    )]
+    #[case(r#"
+any([1, 2], | n => n > 1 |) and all([2, 3], | n => n > 1 |) and all([], | n => FALSE |)
+        "#,
+        true.into()
+    )]
+    #[case(r#"
+g = groupby(["a", "bb", "cc", "d"], | s => length(s) |)
+join(g[2], ",") + "/" + length(g.keys())
+        "#,
+        "bb,cc/2".into()
+    )]
+    #[case(r#"
+join(uniq([1, 2, 1, 3, 2]), ",") + "/" + join(uniq(["a", "B", "b"], | s => chgmoj(s, "b", "B") |), ",")
+        "#,
+        "1,2,3/a,B".into()
+    )]
+    #[case(r#"
+length(flatten([1, [2, [3, [4]]]])) + length(flatten([1, [2, [3, [4]]]], 1)) * 10
+        "#,
+        34.into()
+    )]
+    #[case(r#"
+z = ziparray([1, 2, 3], ["a", "b"])
+join(z[1], "") + length(z)
+        "#,
+        "2b2".into()
+    )]
+    #[case(r#"
+join(sort([3, 1, 2]), ",") + "/" + join(sort(["bb", "a", "ccc"], | a, b => length(b) - length(a) |), ",") + "/" + join(sort([1, 3, 2], EMPTY, TRUE), ",")
+        "#,
+        "1,2,3/ccc,bb,a/3,2,1".into()
+    )]
+    #[case(r#"
+c = chunk([1, 2, 3, 4, 5], 2)
+length(c) + length(c[2])
+        "#,
+        4.into()
+    )]
+    fn test_array_functions(#[case] input: &str, #[case] expected: Object) {
+        expect_object_test(None, input, expected);
+    }
+
     #[rstest]
     #[case(
         r#"
@@ -4291,6 +4741,163 @@ a
         expect_object_test(None, input, expected);
     }
 
+    #[rstest]
+    #[case(
+        r#"
+select 5
+case 1 to 3
+a = "should not get this message"
+case 4 to 6
+a = "range succeed!"
+selend
+a
+        "#,
+        Object::String("range succeed!".to_string())
+    )]
+    #[case(
+        r#"
+select 10
+case is < 5
+a = "should not get this message"
+case is >= 10
+a = "compare succeed!"
+selend
+a
+        "#,
+        Object::String("compare succeed!".to_string())
+    )]
+    #[case(
+        r#"
+select "hoge"
+case is TYPE_NUMBER
+a = "should not get this message"
+case is TYPE_STRING
+a = "type succeed!"
+selend
+a
+        "#,
+        Object::String("type succeed!".to_string())
+    )]
+    #[case(
+        r#"
+select "abc123"
+case newre("^\d+$")
+a = "should not get this message"
+case newre("^[a-z]+\d+$")
+a = "regex succeed!"
+selend
+a
+        "#,
+        Object::String("regex succeed!".to_string())
+    )]
+    #[case(
+        r#"
+select [1, 2, 3]
+case [x, y]
+a = "should not get this message"
+case [1, x, _]
+a = x
+selend
+a
+        "#,
+        Object::Num(2.0)
+    )]
+    #[case(
+        r#"
+select @{"name": "foo", "items": [1, 2]}@
+case {"name": "bar"}
+a = "should not get this message"
+case {"name": n, "items": [_, i]}
+a = n + i
+selend
+a
+        "#,
+        Object::String("foo2".to_string())
+    )]
+    #[case(
+        r#"
+select [3, 4]
+case [x, y] if x > y
+a = "should not get this message"
+case [x, y] if x < y
+a = x * y
+selend
+a
+        "#,
+        Object::Num(12.0)
+    )]
+    #[case(
+        r#"
+x = "outer"
+select [1, 2]
+case [x, _] if x > 1
+a = "should not get this message"
+default
+a = x
+selend
+a
+        "#,
+        Object::String("outer".to_string())
+    )]
+    #[case(
+        r#"
+select [1, 2]
+case [y, _] if y > 1
+a = "should not get this message"
+default
+try
+    a = y
+except
+    a = "unbound"
+endtry
+selend
+a
+        "#,
+        Object::String("unbound".to_string())
+    )]
+    #[case(
+        r#"
+const C = 1
+select [2, 3]
+case [C, _]
+a = "should not get this message"
+case [_, C]
+a = "should not get this message"
+default
+a = C
+selend
+a
+        "#,
+        Object::Num(1.0)
+    )]
+    #[case(
+        r#"
+const C = 2
+select [1, 2]
+case [x, C]
+a = x + C
+selend
+a
+        "#,
+        Object::Num(3.0)
+    )]
+    #[case(
+        r#"
+select 2
+case [_, _]
+a = "should not get this message"
+default
+a = [1, _ // 行継続
+2]
+selend
+length(a)
+        "#,
+        Object::Num(2.0)
+    )]
+    fn test_select_pattern(#[case] input: &str, #[case] expected: Object) {
+        expect_object_test(None, input, expected);
+    }
+
     #[rstest]
     #[case(
         r#"
@@ -0,0 +1,323 @@
+//! スクリプト実行時の制限
+//!
+//! OPTION設定、設定ファイル、または組み込み時の[`ExecLimits`]で指定する
+//!
+//! - 実行時間: 文を評価する度に経過時間を確認する
+//! - 文の数: ループの繰り返しも1回ごとに数える
+//! - 関数呼び出しの深さ: ユーザー定義関数の呼び出しごとに数える
+//! - 配列・文字列のサイズ: 配列作成時、`+` による連結時、変数への代入時に値自身のおおよそのサイズを確認する
+//!   (要素を辿らないため値の大きさに関わらず一定時間で確認できる)
+//!
+//! 上限を超えた場合は通常のエラーとして扱われるためtry-exceptで捕捉できる
+
+use crate::Evaluator;
+use crate::object::Object;
+use crate::error::{UError, UErrorKind, UErrorMessage};
+use crate::EvalResult;
+
+use util::settings::UOption;
+
+use std::sync::{Mutex, RwLock, RwLockReadGuard};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// 文の数または実行時間の上限を超えた後にexcept節やfinally節を実行するための猶予 (文の数)
+const GRACE_STEPS: u64 = 1000;
+/// 文の数または実行時間の上限を超えた後にexcept節やfinally節を実行するための猶予 (時間)
+const GRACE_TIME: Duration = Duration::from_secs(1);
+
+/// 実行制限の設定
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecLimits {
+    /// 評価できる文の最大数、ループの繰り返しも1回ごとに数える
+    pub max_steps: Option<u64>,
+    /// 実行時間の上限
+    pub timeout: Option<Duration>,
+    /// 関数呼び出しの深さの上限
+    pub max_depth: Option<u32>,
+    /// 配列・文字列のおおよそのサイズ上限 (バイト)
+    pub max_alloc: Option<usize>,
+}
+
+impl ExecLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// OPTION設定から作成する、0の項目は無制限
+    pub fn from_options(options: &UOption) -> Self {
+        Self {
+            max_steps: (options.step_limit > 0).then_some(options.step_limit),
+            timeout: (options.time_limit > 0).then(|| Duration::from_secs(options.time_limit)),
+            max_depth: (options.depth_limit > 0).then_some(options.depth_limit),
+            max_alloc: (options.mem_limit > 0).then(|| (options.mem_limit as usize).saturating_mul(1024 * 1024)),
+        }
+    }
+    pub fn max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    pub fn max_depth(mut self, depth: u32) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+    pub fn max_alloc(mut self, bytes: usize) -> Self {
+        self.max_alloc = Some(bytes);
+        self
+    }
+    pub fn is_unlimited(&self) -> bool {
+        self.max_steps.is_none() &&
+        self.timeout.is_none() &&
+        self.max_depth.is_none() &&
+        self.max_alloc.is_none()
+    }
+}
+
+/// 実行制限の状態、スレッド間で共有される
+///
+/// 制限がない場合も作成され、OPTIONによる変更はその場で行われるため実行中のスレッドにも反映される
+#[derive(Debug)]
+pub struct LimitCounter {
+    /// OPTIONによりカウンタを維持したまま変更される
+    limits: RwLock<ExecLimits>,
+    /// 制限が一つもなければtrue、文の評価ごとにロックを取らないためのもの
+    unlimited: AtomicBool,
+    steps: AtomicU64,
+    started: Mutex<Instant>,
+    /// 文の数または実行時間の上限を一度超えたかどうか
+    exceeded: AtomicBool,
+}
+
+impl LimitCounter {
+    pub fn new(limits: ExecLimits) -> Self {
+        Self {
+            unlimited: AtomicBool::new(limits.is_unlimited()),
+            limits: RwLock::new(limits),
+            steps: AtomicU64::new(0),
+            started: Mutex::new(Instant::now()),
+            exceeded: AtomicBool::new(false),
+        }
+    }
+    pub fn limits(&self) -> RwLockReadGuard<'_, ExecLimits> {
+        self.limits.read().unwrap()
+    }
+    /// 文の評価数と経過時間を維持したまま上限を変更する
+    pub fn update<F: FnOnce(&mut ExecLimits)>(&self, f: F) {
+        let mut limits = self.limits.write().unwrap();
+        f(&mut limits);
+        let unlimited = limits.is_unlimited();
+        // 無制限の間は数えていないため、制限が加わった時点から数え始める
+        if self.unlimited.swap(unlimited, Ordering::Relaxed) && ! unlimited {
+            self.reset();
+        }
+    }
+    pub fn is_unlimited(&self) -> bool {
+        self.unlimited.load(Ordering::Relaxed)
+    }
+    /// 文の評価数と経過時間をリセットする
+    pub fn reset(&self) {
+        self.steps.store(0, Ordering::Relaxed);
+        self.exceeded.store(false, Ordering::Relaxed);
+        *self.started.lock().unwrap() = Instant::now();
+    }
+    /// 文を一つ評価するごとに呼ばれ、制限を超えていればエラーを返す
+    ///
+    /// 初めて上限を超えた後は例外処理のために少しだけ猶予を与え、それも超えたら以降は常にエラー
+    pub fn check(&self) -> Result<(), UError> {
+        if self.is_unlimited() {
+            return Ok(());
+        }
+        let exceeded = self.exceeded.load(Ordering::Relaxed);
+        let limits = self.limits();
+        if let Some(max) = limits.max_steps {
+            let steps = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+            let limit = if exceeded {max.saturating_add(GRACE_STEPS)} else {max};
+            if steps > limit {
+                self.exceeded.store(true, Ordering::Relaxed);
+                return Err(UError::new(
+                    UErrorKind::LimitError,
+                    UErrorMessage::StepLimitExceeded(max)
+                ));
+            }
+        }
+        if let Some(timeout) = limits.timeout {
+            let elapsed = self.started.lock().unwrap().elapsed();
+            let limit = if exceeded {timeout + GRACE_TIME} else {timeout};
+            if elapsed > limit {
+                self.exceeded.store(true, Ordering::Relaxed);
+                return Err(UError::new(
+                    UErrorKind::LimitError,
+                    UErrorMessage::TimeLimitExceeded(timeout.as_millis() as u64)
+                ));
+            }
+        }
+        Ok(())
+    }
+    /// 関数呼び出しの深さを確認する
+    pub fn check_depth(&self, depth: u32) -> Result<(), UError> {
+        match self.limits().max_depth {
+            Some(max) if depth > max => Err(UError::new(
+                UErrorKind::LimitError,
+                UErrorMessage::DepthLimitExceeded(max)
+            )),
+            _ => Ok(())
+        }
+    }
+    /// 配列・文字列のおおよそのサイズを確認する
+    pub fn check_alloc(&self, bytes: usize) -> Result<(), UError> {
+        match self.limits().max_alloc {
+            Some(max) if bytes > max => Err(UError::new(
+                UErrorKind::LimitError,
+                UErrorMessage::AllocLimitExceeded(max)
+            )),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Evaluator {
+    /// 実行制限を設定する、文の評価数と経過時間はリセットされる
+    pub fn set_limits(&mut self, limits: ExecLimits) {
+        self.limit.update(|current| *current = limits);
+        self.limit.reset();
+    }
+    /// 現在の実行制限の一部を変更する
+    ///
+    /// 文の評価数と経過時間は引き継ぎ、同じ制限を共有するスレッドにも反映される
+    pub(crate) fn update_limits<F: FnOnce(&mut ExecLimits)>(&mut self, f: F) {
+        self.limit.update(f);
+    }
+    pub(crate) fn check_limit(&self) -> EvalResult<()> {
+        self.limit.check()
+    }
+    /// 関数呼び出しの深さの上限が設定されているかどうか
+    pub(crate) fn has_depth_limit(&self) -> bool {
+        ! self.limit.is_unlimited() && self.limit.limits().max_depth.is_some()
+    }
+    /// 関数呼び出しの深さを一つ増やす、上限を超えていればエラー
+    pub(crate) fn enter_function(&mut self) -> EvalResult<()> {
+        let depth = self.call_depth + 1;
+        if ! self.limit.is_unlimited() {
+            self.limit.check_depth(depth)?;
+        }
+        self.call_depth = depth;
+        Ok(())
+    }
+    /// 関数呼び出しの深さを一つ減らす
+    pub(crate) fn leave_function(&mut self) {
+        self.call_depth = self.call_depth.saturating_sub(1);
+    }
+    /// 指定要素数の配列を作成できるかどうか
+    pub(crate) fn check_array_len(&self, len: usize) -> EvalResult<()> {
+        if self.limit.is_unlimited() {
+            return Ok(());
+        }
+        self.limit.check_alloc(len.saturating_mul(size_of::<Object>()))
+    }
+    /// 値のおおよそのサイズを確認する
+    pub(crate) fn check_alloc(&self, obj: &Object) -> EvalResult<()> {
+        if self.limit.is_unlimited() {
+            return Ok(());
+        }
+        self.limit.check_alloc(approx_size(obj))
+    }
+}
+
+/// 文字列と配列のおおよそのサイズ
+///
+/// 代入や連結のたびに呼ばれるため要素は辿らず値自身の大きさのみを返す
+fn approx_size(obj: &Object) -> usize {
+    let size = match obj {
+        Object::String(s) => s.len(),
+        Object::Array(arr) => arr.len().saturating_mul(size_of::<Object>()),
+        Object::ByteArray(arr) => arr.len(),
+        _ => 0,
+    };
+    size + size_of::<Object>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::Interpreter;
+    use crate::environment::Environment;
+
+    #[test]
+    fn test_depth_limit() {
+        let mut uwscr = Interpreter::new();
+        uwscr.set_limits(ExecLimits::new().max_depth(50));
+        let script = "function f(n)\r\n    result = f(n + 1)\r\nfend\r\nf(1)";
+        let err = uwscr.eval_str(script).unwrap_err();
+        assert_eq!(err.message, UErrorMessage::DepthLimitExceeded(50));
+        assert!(err.line.has_row());
+        // 上限に達していなければ再帰できる
+        let script = "function g(n)\r\n    result = 0\r\n    if n > 0 then\r\n        result = g(n - 1)\r\n    endif\r\nfend\r\na = g(40)";
+        assert!(uwscr.eval_str(script).is_ok());
+    }
+
+    #[test]
+    fn test_alloc_limit() {
+        let mut uwscr = Interpreter::new();
+        uwscr.set_limits(ExecLimits::new().max_alloc(1024 * 1024));
+        let err = uwscr.eval_str("dim arr[10000000]").unwrap_err();
+        assert_eq!(err.message, UErrorMessage::AllocLimitExceeded(1024 * 1024));
+        let err = uwscr.eval_str("s = \"a\"\r\nwhile true\r\n    s = s + s\r\nwend").unwrap_err();
+        assert_eq!(err.kind, UErrorKind::LimitError);
+        assert!(uwscr.eval_str("dim arr[100]").is_ok());
+    }
+
+    #[test]
+    fn test_update_limits() {
+        let mut uwscr = Interpreter::new();
+        uwscr.set_limits(ExecLimits::new().max_steps(100));
+        // OPTIONで別の制限を加えても評価済みの文の数は維持される
+        let script = "for i = 1 to 60\r\nnext\r\nOPTION DEPTHLIMIT=10\r\nfor i = 1 to 60\r\nnext";
+        let err = uwscr.eval_str(script).unwrap_err();
+        assert_eq!(err.message, UErrorMessage::StepLimitExceeded(100));
+    }
+
+    #[test]
+    fn test_shared_limits() {
+        let mut evaluator = Evaluator::new(Environment::new(vec![]));
+        evaluator.set_limits(ExecLimits::new());
+        let thread = evaluator.new_thread();
+        assert!(thread.check_limit().is_ok());
+        // 後から加えた制限が実行中のスレッドにも反映される
+        evaluator.update_limits(|limits| limits.max_steps = Some(1));
+        assert!(thread.check_limit().is_ok());
+        let err = thread.check_limit().unwrap_err();
+        assert_eq!(err.message, UErrorMessage::StepLimitExceeded(1));
+        // 制限の解除も反映される
+        evaluator.update_limits(|limits| limits.max_steps = None);
+        assert!(thread.check_limit().is_ok());
+    }
+
+    #[test]
+    fn test_alloc_nested() {
+        let mut uwscr = Interpreter::new();
+        uwscr.set_limits(ExecLimits::new().max_alloc(1024 * 1024));
+        // ループ内の連結と代入が上限内であれば成功する
+        let script = "s = \"a\"\r\nfor i = 1 to 10\r\n    s = s + s\r\nnext\r\narr = [s]\r\nfor i = 1 to 99\r\n    arr = arr + s\r\nnext\r\nn = length(arr)";
+        assert!(uwscr.eval_str(script).is_ok());
+        assert_eq!(uwscr.get_global::<f64>("n").unwrap(), 100.0);
+        // 連結だけでも上限を超えればエラー
+        let err = uwscr.eval_str("s = \"a\"\r\nwhile true\r\n    n = length(s + s + s)\r\n    s = s + s\r\nwend").unwrap_err();
+        assert_eq!(err.message, UErrorMessage::AllocLimitExceeded(1024 * 1024));
+    }
+
+    #[test]
+    fn test_catch_limit() {
+        let mut uwscr = Interpreter::new();
+        uwscr.set_limits(ExecLimits::new().max_steps(100));
+        let script = "try\r\n    while true\r\n    wend\r\nexcept\r\n    caught = TRY_ERRMSG\r\nendtry";
+        assert!(uwscr.eval_str(script).is_ok());
+        let caught = uwscr.get_global::<String>("caught").unwrap();
+        assert!(caught.contains("100"));
+        // 猶予を超えたら捕捉してもループを続けられない
+        let script = "while true\r\n    try\r\n        while true\r\n        wend\r\n    except\r\n    endtry\r\nwend";
+        assert!(uwscr.eval_str(script).is_err());
+    }
+}
@@ -11,6 +11,11 @@ pub mod browser;
 mod web;
 pub mod comobject;
 mod variant;
+pub mod datetime;
+pub mod csvstream;
+pub mod xml;
+pub mod rng;
+pub mod ziparchive;
 
 pub use self::hashtbl::{HashTbl, HashTblEnum};
 pub use self::version::Version;
@@ -22,10 +27,15 @@ pub use self::uobject::UObject;
 pub use self::fopen::*;
 pub use self::class::ClassInstance;
 pub use variant::Variant;
+pub use datetime::{UDateTime, DateTimeZone};
+pub use csvstream::{CsvStream, CsvRows};
+pub use xml::XmlNode;
+pub use rng::URng;
+pub use ziparchive::ZipArchive;
 use browser::{BrowserBuilder, Browser, TabWindow, RemoteObject};
 pub use web::{WebRequest, WebResponse, HtmlNode};
 pub use comobject::{ComObject, ComError, ComArg, Unknown, Excel, ExcelOpenFlag, ObjectTitle, VariantExt, SAVec};
-use crate::builtins::socket::USocket;
+use crate::builtins::socket::{USocket, HttpRequest, HttpResponse};
 
 use util::settings::USETTINGS;
 use crate::environment::Layer;
@@ -119,6 +129,20 @@ pub enum Object {
     ColorFound(ColorFound),
     /// ネットワーク系オブジェクト
     Socket(USocket),
+    /// DateTimeオブジェクト
+    DateTime(UDateTime),
+    /// CsvReader, CsvWriterオブジェクト
+    CsvStream(Arc<Mutex<CsvStream>>),
+    /// XMLノード
+    XmlNode(XmlNode),
+    /// 乱数生成器
+    Rng(URng),
+    /// zipファイル
+    ZipArchive(Arc<Mutex<ZipArchive>>),
+    /// HTTPサーバーが受けたリクエスト
+    HttpRequest(HttpRequest),
+    /// HTTPサーバーが返すレスポンス
+    HttpResponse(HttpResponse),
 }
 impl std::fmt::Debug for Object {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -178,6 +202,13 @@ impl std::fmt::Debug for Object {
             #[cfg(feature="chkimg")]
             Object::ColorFound(arg0) => f.debug_tuple("ColorFound").field(arg0).finish(),
             Object::Socket(arg0) => write!(f, "{arg0:?}"),
+            Object::DateTime(arg0) => f.debug_tuple("DateTime").field(arg0).finish(),
+            Object::CsvStream(arg0) => f.debug_tuple("CsvStream").field(arg0).finish(),
+            Object::XmlNode(arg0) => f.debug_tuple("XmlNode").field(arg0).finish(),
+            Object::Rng(arg0) => f.debug_tuple("Rng").field(arg0).finish(),
+            Object::ZipArchive(arg0) => f.debug_tuple("ZipArchive").field(arg0).finish(),
+            Object::HttpRequest(arg0) => f.debug_tuple("HttpRequest").field(arg0).finish(),
+            Object::HttpResponse(arg0) => f.debug_tuple("HttpResponse").field(arg0).finish(),
         }
     }
 }
@@ -308,6 +339,14 @@ impl fmt::Display for Object {
                             MemberCaller::WebViewRemoteObject(_) => write!(f, "WebViewRemoteObject.{member}"),
                             MemberCaller::UObject(_) => write!(f, "UObject.{member}"),
                             MemberCaller::SafeArray(_) => write!(f, "SafeArray.{member}"),
+                            MemberCaller::DateTime(_) => write!(f, "DateTime.{member}"),
+                            MemberCaller::CsvStream(_) => write!(f, "CsvStream.{member}"),
+                            MemberCaller::XmlNode(_) => write!(f, "XmlNode.{member}"),
+                            MemberCaller::HashTbl(_) => write!(f, "HashTbl.{member}"),
+                            MemberCaller::Rng(_) => write!(f, "Rng.{member}"),
+                            MemberCaller::ZipArchive(_) => write!(f, "ZipArchive.{member}"),
+                            MemberCaller::HttpRequest(_) => write!(f, "HttpRequest.{member}"),
+                            MemberCaller::HttpResponse(_) => write!(f, "HttpResponse.{member}"),
                         }
                     },
             Object::ComObject(com) => write!(f, "{com}"),
@@ -322,6 +361,23 @@ impl fmt::Display for Object {
             #[cfg(feature="chkimg")]
             Object::ColorFound(found) => write!(f, "{found}"),
             Object::Socket(nw) => write!(f, "{nw}"),
+            Object::DateTime(dt) => write!(f, "{dt}"),
+            Object::CsvStream(csv) => {
+                        match csv.try_lock() {
+                            Ok(csv) => write!(f, "{csv}"),
+                            Err(_) => write!(f, "CsvStream"),
+                        }
+                    },
+            Object::XmlNode(node) => write!(f, "{node}"),
+            Object::Rng(rng) => write!(f, "{rng}"),
+            Object::ZipArchive(zip) => {
+                        match zip.try_lock() {
+                            Ok(zip) => write!(f, "{zip}"),
+                            Err(_) => write!(f, "ZipArchive"),
+                        }
+                    },
+            Object::HttpRequest(req) => write!(f, "{req}"),
+            Object::HttpResponse(res) => write!(f, "{res}"),
         }
     }
 }
@@ -494,7 +550,28 @@ impl PartialEq for Object {
             },
             Object::Socket(nw1) => {
                 if let Object::Socket(nw2) = other { nw1 == nw2 } else {false}
-            }
+            },
+            Object::DateTime(dt1) => {
+                if let Object::DateTime(dt2) = other { dt1 == dt2 } else {false}
+            },
+            Object::CsvStream(csv1) => if let Object::CsvStream(csv2) = other {
+                compare_mutex(csv1, csv2)
+            } else {false},
+            Object::XmlNode(node) => {
+                if let Object::XmlNode(node2) = other {node == node2} else {false}
+            },
+            Object::Rng(rng) => {
+                if let Object::Rng(rng2) = other {rng == rng2} else {false}
+            },
+            Object::ZipArchive(zip1) => if let Object::ZipArchive(zip2) = other {
+                compare_mutex(zip1, zip2)
+            } else {false},
+            Object::HttpRequest(req) => {
+                if let Object::HttpRequest(req2) = other {req == req2} else {false}
+            },
+            Object::HttpResponse(res) => {
+                if let Object::HttpResponse(res2) = other {res == res2} else {false}
+            },
         }
     }
 }
@@ -560,8 +637,21 @@ impl Object {
             Object::ParamStr(_) => ObjectType::TYPE_PARAM_STR,
             Object::Socket(socket) => match socket {
                 USocket::Udp(_) => ObjectType::TYPE_SOCKET_UDP,
+                USocket::Tcp(_) => ObjectType::TYPE_SOCKET_TCP_CLIENT,
+                USocket::TcpServer(_) => ObjectType::TYPE_SOCKET_TCP_SERVER,
                 USocket::WebSocket(_) => ObjectType::TYPE_SOCKET_WEBSOCKET,
-            }
+                USocket::WebSocketServer(_) => ObjectType::TYPE_SOCKET_WEBSOCKET_SERVER,
+                USocket::HttpServer(_) => ObjectType::TYPE_SOCKET_HTTP_SERVER,
+                USocket::Mail(_) => ObjectType::TYPE_SOCKET_MAIL,
+                USocket::Mqtt(_) => ObjectType::TYPE_SOCKET_MQTT,
+            },
+            Object::DateTime(_) => ObjectType::TYPE_DATETIME,
+            Object::CsvStream(_) => ObjectType::TYPE_CSV_STREAM,
+            Object::XmlNode(_) => ObjectType::TYPE_XML_NODE,
+            Object::Rng(_) => ObjectType::TYPE_RNG,
+            Object::ZipArchive(_) => ObjectType::TYPE_ZIP_ARCHIVE,
+            Object::HttpRequest(_) => ObjectType::TYPE_HTTP_REQUEST,
+            Object::HttpResponse(_) => ObjectType::TYPE_HTTP_RESPONSE,
 
             Object::EmptyParam |
             Object::DynamicVar(_) |
@@ -594,6 +684,13 @@ impl Object {
             Object::HtmlNode(node) => return node.len(),
 
             Object::Socket(_) |
+            Object::DateTime(_) |
+            Object::CsvStream(_) |
+            Object::XmlNode(_) |
+            Object::Rng(_) |
+            Object::ZipArchive(_) |
+            Object::HttpRequest(_) |
+            Object::HttpResponse(_) |
             Object::AnonFunc(_) |
             Object::Function(_) |
             Object::AsyncFunction(_) |
@@ -872,6 +969,10 @@ impl From<Object> for i32 {
 /* 演算 */
 impl PartialOrd for Object {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if let (Object::DateTime(dt1), Object::DateTime(dt2)) = (self, other) {
+            // DateTime同士は時刻で比較
+            return dt1.partial_cmp(dt2);
+        }
         match (self.as_f64(true), other.as_f64(true)) {
             // ともに数値にできるなら数値として比較
             (Some(n1), Some(n2)) => n1.partial_cmp(&n2),
@@ -1495,6 +1596,14 @@ pub enum MemberCaller {
     WebViewRemoteObject(WebViewRemoteObject),
     UObject(UObject),
     SafeArray(SAVec),
+    DateTime(UDateTime),
+    CsvStream(Arc<Mutex<CsvStream>>),
+    XmlNode(XmlNode),
+    HashTbl(Arc<Mutex<HashTbl>>),
+    Rng(URng),
+    ZipArchive(Arc<Mutex<ZipArchive>>),
+    HttpRequest(HttpRequest),
+    HttpResponse(HttpResponse),
 }
 
 impl PartialEq for MemberCaller {
@@ -1523,6 +1632,14 @@ impl PartialEq for MemberCaller {
             (Self::WebViewRemoteObject(l0), Self::WebViewRemoteObject(r0)) => l0 == r0,
             (Self::UObject(l0), Self::UObject(r0)) => l0 == r0,
             (Self::SafeArray(l0), Self::SafeArray(r0)) => l0 == r0,
+            (Self::DateTime(l0), Self::DateTime(r0)) => l0 == r0,
+            (Self::CsvStream(l0), Self::CsvStream(r0)) => compare_mutex(l0, r0),
+            (Self::XmlNode(l0), Self::XmlNode(r0)) => l0 == r0,
+            (Self::HashTbl(l0), Self::HashTbl(r0)) => compare_mutex(l0, r0),
+            (Self::Rng(l0), Self::Rng(r0)) => l0 == r0,
+            (Self::ZipArchive(l0), Self::ZipArchive(r0)) => compare_mutex(l0, r0),
+            (Self::HttpRequest(l0), Self::HttpRequest(r0)) => l0 == r0,
+            (Self::HttpResponse(l0), Self::HttpResponse(r0)) => l0 == r0,
             _ => false,
         }
     }
@@ -1579,9 +1696,20 @@ pub enum ObjectType {
     TYPE_CHKCLR_ITEM,
     TYPE_PARAM_STR,
     TYPE_SOCKET_UDP,
-    // TYPE_SOCKET_TCP_CLIENT,
-    // TYPE_SOCKET_TCP_LISTENER,
+    TYPE_SOCKET_TCP_CLIENT,
+    TYPE_SOCKET_TCP_SERVER,
     TYPE_SOCKET_WEBSOCKET,
+    TYPE_SOCKET_WEBSOCKET_SERVER,
+    TYPE_SOCKET_HTTP_SERVER,
+    TYPE_SOCKET_MAIL,
+    TYPE_SOCKET_MQTT,
+    TYPE_DATETIME,
+    TYPE_CSV_STREAM,
+    TYPE_XML_NODE,
+    TYPE_RNG,
+    TYPE_ZIP_ARCHIVE,
+    TYPE_HTTP_REQUEST,
+    TYPE_HTTP_RESPONSE,
 
     TYPE_MEMBER_CALLER,
     TYPE_NOT_VALUE_TYPE,
@@ -0,0 +1,988 @@
+//! CsvReader, CsvWriterオブジェクト
+//!
+//! csvopenと異なりファイル全体を読み込まず、1行ずつ読み書きする
+
+use super::{Object, HashTbl, UDateTime, FopenEncoding, FopenError};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+use crate::builtins::file_control::CsvTypeConst;
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use csv::{ReaderBuilder, WriterBuilder, StringRecord, Trim};
+use encoding_rs::{Decoder, Encoding, UTF_8, UTF_16LE, UTF_16BE, SHIFT_JIS};
+use num_traits::FromPrimitive;
+use serde_json::{Map, Value as JsonValue};
+
+pub type CsvResult<T> = Result<T, UError>;
+
+/// エンコーディングの判定に使う先頭部分のサイズ
+const SNIFF_SIZE: usize = 64 * 1024;
+/// デコード時に一度に読み込むサイズ
+const DECODE_BUF_SIZE: usize = 8 * 1024;
+
+fn stream_error<E: Into<FopenError>>(e: E) -> UError {
+    UError::new(UErrorKind::CsvError, UErrorMessage::FopenError(e.into()))
+}
+
+/// 型変換する列の指定
+#[derive(Debug, Clone, PartialEq)]
+enum CsvColumn {
+    /// 列番号 (1から)
+    Index(usize),
+    /// ヘッダ名
+    Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ColumnType {
+    column: CsvColumn,
+    kind: CsvTypeConst,
+    /// 日時の書式
+    format: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct CsvOptions {
+    /// 1行目をヘッダとして扱う
+    header: bool,
+    delimiter: u8,
+    quote: u8,
+    /// Noneならクォート文字を2つ重ねてエスケープする
+    escape: Option<u8>,
+    /// この文字で始まる行を読み飛ばす
+    comment: Option<u8>,
+    encoding: FopenEncoding,
+    /// 各値の前後の空白を除去する
+    trim: bool,
+    types: Vec<ColumnType>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            header: false,
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            comment: None,
+            encoding: FopenEncoding::Auto,
+            trim: false,
+            types: vec![],
+        }
+    }
+}
+
+impl CsvOptions {
+    fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            // ヘッダ行は自前で読む
+            .has_headers(false)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .comment(self.comment)
+            .flexible(true)
+            .trim(if self.trim {Trim::All} else {Trim::None});
+        if let Some(escape) = self.escape {
+            builder.escape(Some(escape)).double_quote(false);
+        }
+        builder
+    }
+    fn writer_builder(&self) -> WriterBuilder {
+        let mut builder = WriterBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .flexible(true);
+        if let Some(escape) = self.escape {
+            builder.escape(escape).double_quote(false);
+        }
+        builder
+    }
+    /// 列の型指定を探す、同じ列に複数指定されていたら後のものを優先
+    fn column_type(&self, index: usize, name: Option<&str>) -> Option<&ColumnType> {
+        self.types.iter().rev().find(|t| match &t.column {
+            CsvColumn::Index(i) => *i == index + 1,
+            CsvColumn::Name(n) => name.is_some_and(|name| n.eq_ignore_ascii_case(name)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CsvMode {
+    Read,
+    Write,
+    Append,
+}
+
+enum CsvSource {
+    File(PathBuf),
+    /// fromcsvに渡された文字列、読み始めたらNone
+    Text(Option<String>),
+}
+
+enum CsvState {
+    /// まだ読み書きしていない
+    Ready,
+    Reading(Box<csv::Reader<Box<dyn Read + Send>>>),
+    Writing(Box<csv::Writer<Box<dyn Write + Send>>>),
+    Closed,
+}
+
+pub struct CsvStream {
+    mode: CsvMode,
+    source: CsvSource,
+    opt: CsvOptions,
+    state: CsvState,
+    /// ヘッダ名
+    headers: Option<Vec<String>>,
+    /// ヘッダ行を書き込み済みかどうか
+    header_written: bool,
+    /// 読み書きした行数 (ヘッダ行を除く)
+    count: usize,
+}
+
+impl fmt::Debug for CsvStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CsvStream")
+            .field("mode", &self.mode)
+            .field("path", &self.path())
+            .field("opt", &self.opt)
+            .field("headers", &self.headers)
+            .field("count", &self.count)
+            .finish()
+    }
+}
+
+impl fmt::Display for CsvStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            CsvMode::Read => write!(f, "CsvReader({})", self.path()),
+            CsvMode::Write |
+            CsvMode::Append => write!(f, "CsvWriter({})", self.path()),
+        }
+    }
+}
+
+impl CsvStream {
+    fn new(mode: CsvMode, source: CsvSource) -> Self {
+        Self {
+            mode,
+            source,
+            opt: CsvOptions::default(),
+            state: CsvState::Ready,
+            headers: None,
+            header_written: false,
+            count: 0,
+        }
+    }
+    pub fn reader(path: PathBuf) -> Self {
+        Self::new(CsvMode::Read, CsvSource::File(path))
+    }
+    pub fn writer(path: PathBuf, append: bool) -> Self {
+        let mode = if append {CsvMode::Append} else {CsvMode::Write};
+        Self::new(mode, CsvSource::File(path))
+    }
+    /// CSV文字列を読む
+    pub fn from_text(text: String) -> Self {
+        Self::new(CsvMode::Read, CsvSource::Text(Some(text)))
+    }
+    fn path(&self) -> String {
+        match &self.source {
+            CsvSource::File(path) => path.to_string_lossy().to_string(),
+            CsvSource::Text(_) => String::new(),
+        }
+    }
+    fn is_reader(&self) -> bool {
+        self.mode == CsvMode::Read
+    }
+    fn closed_error(&self) -> UError {
+        UError::new(
+            UErrorKind::CsvError,
+            UErrorMessage::FopenError(FopenError::NoOpenFileFound(self.path()))
+        )
+    }
+    /// 読み書きを始めていたら設定を変更できない
+    fn options(&mut self) -> CsvResult<&mut CsvOptions> {
+        match self.state {
+            CsvState::Ready => Ok(&mut self.opt),
+            _ => Err(UError::new(UErrorKind::CsvError, UErrorMessage::CsvStreamAlreadyStarted)),
+        }
+    }
+    pub fn set_header(&mut self, header: bool) -> CsvResult<()> {
+        self.options()?.header = header;
+        Ok(())
+    }
+    /// ヘッダ名を明示する
+    ///
+    /// 読み込み時はファイルにヘッダ行がないものとして扱い、書き込み時はヘッダ行として書き込む
+    pub fn set_header_names(&mut self, names: Vec<String>) -> CsvResult<()> {
+        self.options()?.header = true;
+        self.headers = Some(names);
+        Ok(())
+    }
+    pub fn set_delimiter(&mut self, delimiter: u8) -> CsvResult<()> {
+        self.options()?.delimiter = delimiter;
+        Ok(())
+    }
+
+    fn start_reading(&mut self) -> CsvResult<()> {
+        let source: Box<dyn Read + Send> = match &mut self.source {
+            CsvSource::File(path) => {
+                let file = File::open(path).map_err(stream_error)?;
+                decode_reader(BufReader::new(file), &self.opt.encoding).map_err(stream_error)?
+            },
+            CsvSource::Text(text) => {
+                let text = text.take().unwrap_or_default();
+                Box::new(Cursor::new(text.into_bytes()))
+            },
+        };
+        let mut reader = self.opt.reader_builder().from_reader(source);
+        if self.opt.header && self.headers.is_none() {
+            let mut record = StringRecord::new();
+            if reader.read_record(&mut record).map_err(stream_error)? {
+                self.headers = Some(record.iter().map(String::from).collect());
+            }
+        }
+        self.state = CsvState::Reading(Box::new(reader));
+        Ok(())
+    }
+    fn start_writing(&mut self) -> CsvResult<()> {
+        let CsvSource::File(path) = &self.source else {
+            return Err(self.closed_error());
+        };
+        let append = self.mode == CsvMode::Append;
+        // 追記の場合は既存ファイルのエンコーディングとヘッダを引き継ぐ
+        let existing = append && path.metadata().is_ok_and(|m| m.len() > 0);
+        let mut encoding = self.opt.encoding.clone();
+        if existing {
+            let mut file = BufReader::new(File::open(path).map_err(stream_error)?);
+            if encoding == FopenEncoding::Auto {
+                let mut head = Vec::with_capacity(SNIFF_SIZE);
+                (&mut file).take(SNIFF_SIZE as u64).read_to_end(&mut head).map_err(stream_error)?;
+                encoding = sniff_encoding(&head).into();
+                file.rewind().map_err(stream_error)?;
+            }
+            if self.opt.header && self.headers.is_none() {
+                let source = decode_reader(file, &encoding).map_err(stream_error)?;
+                let mut reader = self.opt.reader_builder().from_reader(source);
+                let mut record = StringRecord::new();
+                if reader.read_record(&mut record).map_err(stream_error)? {
+                    self.headers = Some(record.iter().map(String::from).collect());
+                }
+            }
+            self.header_written = true;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .write(! append)
+            .append(append)
+            .truncate(! append)
+            .open(path)
+            .map_err(stream_error)?;
+        let mut out = EncodeWriter::new(BufWriter::new(file), encoding);
+        if ! existing {
+            out.write_bom().map_err(stream_error)?;
+        }
+        let out: Box<dyn Write + Send> = Box::new(out);
+        self.state = CsvState::Writing(Box::new(self.opt.writer_builder().from_writer(out)));
+        Ok(())
+    }
+
+    /// 1行読んで型変換した値を返す、終端ならNone
+    fn read_values(&mut self) -> CsvResult<Option<Vec<Object>>> {
+        if let CsvState::Ready = self.state {
+            self.start_reading()?;
+        }
+        let mut record = StringRecord::new();
+        match &mut self.state {
+            CsvState::Reading(reader) => {
+                if ! reader.read_record(&mut record).map_err(stream_error)? {
+                    return Ok(None);
+                }
+            },
+            _ => return Err(self.closed_error()),
+        }
+        self.count += 1;
+        record.iter()
+            .enumerate()
+            .map(|(i, value)| self.convert(value, i))
+            .collect::<CsvResult<Vec<_>>>()
+            .map(Some)
+    }
+    fn header_name(&self, index: usize) -> Option<&str> {
+        self.headers.as_ref()
+            .and_then(|headers| headers.get(index))
+            .map(|name| name.as_str())
+    }
+    fn convert(&self, value: &str, index: usize) -> CsvResult<Object> {
+        let name = self.header_name(index);
+        let Some(ty) = self.opt.column_type(index, name) else {
+            return Ok(Object::String(value.to_string()));
+        };
+        // 空欄はEMPTYにする
+        if ty.kind != CsvTypeConst::CSV_STR && value.trim().is_empty() {
+            return Ok(Object::Empty);
+        }
+        let converted = match ty.kind {
+            CsvTypeConst::CSV_STR => Some(Object::String(value.to_string())),
+            CsvTypeConst::CSV_NUM => value.trim().parse::<f64>().ok().map(Object::Num),
+            CsvTypeConst::CSV_BOOL => parse_bool(value).map(Object::Bool),
+            CsvTypeConst::CSV_DATE => UDateTime::parse(value, None, ty.format.as_deref())
+                .ok()
+                .map(Object::DateTime),
+        };
+        converted.ok_or_else(|| {
+            let column = name.map(|s| s.to_string()).unwrap_or((index + 1).to_string());
+            UError::new(
+                UErrorKind::CsvError,
+                UErrorMessage::CsvConversionFailed(self.count, column, value.to_string())
+            )
+        })
+    }
+    /// ヘッダがあれば連想配列、なければ配列にする
+    fn to_row(&self, values: Vec<Object>) -> Object {
+        match &self.headers {
+            Some(_) => {
+                let mut tbl = HashTbl::new(false, false);
+                for (i, value) in values.into_iter().enumerate() {
+                    let key = self.header_name(i)
+                        .map(|s| s.to_string())
+                        .unwrap_or((i + 1).to_string());
+                    tbl.insert(key, value);
+                }
+                Object::HashTbl(Arc::new(Mutex::new(tbl)))
+            },
+            None => Object::Array(values),
+        }
+    }
+    fn to_json(&self, values: Vec<Object>) -> CsvResult<JsonValue> {
+        let values = values.into_iter()
+            .map(value_to_json)
+            .collect::<CsvResult<Vec<_>>>()?;
+        let json = match &self.headers {
+            Some(_) => {
+                let map = values.into_iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        let key = self.header_name(i)
+                            .map(|s| s.to_string())
+                            .unwrap_or((i + 1).to_string());
+                        (key, value)
+                    })
+                    .collect::<Map<_, _>>();
+                JsonValue::Object(map)
+            },
+            None => JsonValue::Array(values),
+        };
+        Ok(json)
+    }
+    /// 1行読む、終端ならNone
+    pub fn read(&mut self) -> CsvResult<Option<Object>> {
+        let row = self.read_values()?
+            .map(|values| self.to_row(values));
+        Ok(row)
+    }
+    /// 残りの行をすべて読む
+    pub fn read_all(&mut self, as_uobject: bool) -> CsvResult<Object> {
+        if as_uobject {
+            let mut rows = vec![];
+            while let Some(values) = self.read_values()? {
+                rows.push(self.to_json(values)?);
+            }
+            Ok(Object::UObject(JsonValue::Array(rows).into()))
+        } else {
+            let mut rows = vec![];
+            while let Some(row) = self.read()? {
+                rows.push(row);
+            }
+            Ok(Object::Array(rows))
+        }
+    }
+
+    /// 書き込む値を文字列にする
+    fn field(&self, index: usize, name: Option<&str>, value: &Object) -> CsvResult<String> {
+        let field = match value {
+            Object::Empty |
+            Object::EmptyParam |
+            Object::Null => String::new(),
+            Object::DateTime(dt) => {
+                let format = self.opt.column_type(index, name)
+                    .and_then(|t| t.format.as_deref());
+                match format {
+                    Some(fmt) => dt.format(fmt, None)?,
+                    None => dt.to_string(),
+                }
+            },
+            o => o.to_string(),
+        };
+        Ok(field)
+    }
+    fn fields_by_index(&self, values: Vec<Object>) -> CsvResult<Vec<String>> {
+        values.iter()
+            .enumerate()
+            .map(|(i, value)| self.field(i, self.header_name(i), value))
+            .collect()
+    }
+    fn fields_by_name(&mut self, pairs: Vec<(String, Object)>, records: &mut Vec<Vec<String>>) -> CsvResult<Vec<String>> {
+        if self.opt.header && self.headers.is_none() {
+            self.headers = Some(pairs.iter().map(|(key, _)| key.clone()).collect());
+        }
+        self.push_header(records);
+        match &self.headers {
+            // ヘッダにない名前は無視する
+            Some(headers) => headers.iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    match pairs.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)) {
+                        Some((_, value)) => self.field(i, Some(name), value),
+                        None => Ok(String::new()),
+                    }
+                })
+                .collect(),
+            None => {
+                let values = pairs.into_iter().map(|(_, value)| value).collect();
+                self.fields_by_index(values)
+            },
+        }
+    }
+    /// 最初の行の前にヘッダ行を加える
+    fn push_header(&mut self, records: &mut Vec<Vec<String>>) {
+        if ! self.header_written {
+            if let (true, Some(headers)) = (self.opt.header, &self.headers) {
+                records.push(headers.clone());
+            }
+            self.header_written = true;
+        }
+    }
+    /// 1行分の値から書き込むレコードを作る
+    fn records(&mut self, row: Object) -> CsvResult<Vec<Vec<String>>> {
+        let mut records = vec![];
+        let fields = match row {
+            Object::HashTbl(tbl) => {
+                let pairs = tbl.lock().unwrap().map().into_iter().collect();
+                self.fields_by_name(pairs, &mut records)?
+            },
+            Object::UObject(uo) => match JsonValue::try_from(Object::UObject(uo))? {
+                JsonValue::Object(map) => {
+                    let pairs = map.into_iter()
+                        .map(|(key, value)| (key, Object::from(value)))
+                        .collect();
+                    self.fields_by_name(pairs, &mut records)?
+                },
+                JsonValue::Array(vec) => {
+                    self.push_header(&mut records);
+                    self.fields_by_index(vec.into_iter().map(Object::from).collect())?
+                },
+                value => {
+                    self.push_header(&mut records);
+                    self.fields_by_index(vec![value.into()])?
+                },
+            },
+            Object::Array(arr) => {
+                self.push_header(&mut records);
+                self.fields_by_index(arr)?
+            },
+            o => {
+                self.push_header(&mut records);
+                self.fields_by_index(vec![o])?
+            },
+        };
+        records.push(fields);
+        Ok(records)
+    }
+    /// 1行書き込む
+    pub fn write(&mut self, row: Object) -> CsvResult<()> {
+        if let CsvState::Ready = self.state {
+            self.start_writing()?;
+        }
+        let records = self.records(row)?;
+        let CsvState::Writing(writer) = &mut self.state else {
+            return Err(self.closed_error());
+        };
+        for record in records {
+            writer.write_record(&record).map_err(stream_error)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+    pub fn flush(&mut self) -> CsvResult<()> {
+        if let CsvState::Writing(writer) = &mut self.state {
+            writer.flush().map_err(stream_error)?;
+        }
+        Ok(())
+    }
+    pub fn close(&mut self) -> CsvResult<()> {
+        self.flush()?;
+        self.state = CsvState::Closed;
+        Ok(())
+    }
+
+    pub fn get_property(&self, name: &str) -> CsvResult<Object> {
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "headers" => match &self.headers {
+                Some(headers) => Object::Array(headers.iter().map(|s| s.to_string().into()).collect()),
+                None => Object::Empty,
+            },
+            "count" => self.count.into(),
+            "path" => self.path().into(),
+            "closed" => matches!(self.state, CsvState::Closed).into(),
+            _ => return Err(UError::new(
+                UErrorKind::CsvError,
+                UErrorMessage::InvalidMember(name.to_string())
+            )),
+        };
+        Ok(obj)
+    }
+    /// 設定用メソッドは自身を返すためNoneを返す
+    pub fn invoke_method(&mut self, name: &str, args: Vec<Object>) -> CsvResult<Option<Object>> {
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "header" => {
+                match args.as_object(0)? {
+                    Object::Array(names) => {
+                        let names = names.iter().map(|o| o.to_string()).collect();
+                        self.set_header_names(names)?;
+                    },
+                    o => self.set_header(o.is_truthy())?,
+                }
+                None
+            },
+            "delimiter" => {
+                let delimiter = args.as_ascii(0)?;
+                self.set_delimiter(delimiter)?;
+                None
+            },
+            "quote" => {
+                let quote = args.as_ascii(0)?;
+                self.options()?.quote = quote;
+                None
+            },
+            "escape" => {
+                let escape = args.as_ascii_or_empty(0)?;
+                self.options()?.escape = escape;
+                None
+            },
+            "comment" => {
+                let comment = args.as_ascii_or_empty(0)?;
+                self.options()?.comment = comment;
+                None
+            },
+            "encoding" => {
+                let name = args.as_object(0)?.to_string();
+                let encoding = parse_encoding(&name)?;
+                self.options()?.encoding = encoding;
+                None
+            },
+            "trim" => {
+                let trim = args.first().is_none_or(|o| o.is_truthy());
+                self.options()?.trim = trim;
+                None
+            },
+            "coltype" => {
+                let column = match args.as_object(0)? {
+                    Object::Num(n) if n >= 1.0 => CsvColumn::Index(n as usize),
+                    o => CsvColumn::Name(o.to_string()),
+                };
+                let kind = args.as_object(1)?;
+                let kind = kind.as_f64(false)
+                    .and_then(CsvTypeConst::from_f64)
+                    .ok_or(UError::new(
+                        UErrorKind::CsvError,
+                        UErrorMessage::ArgumentIsNotNumber(2, kind.to_string())
+                    ))?;
+                let format = args.get(2)
+                    .filter(|o| ! matches!(o, Object::Empty | Object::EmptyParam))
+                    .map(|o| o.to_string());
+                self.options()?.types.push(ColumnType { column, kind, format });
+                None
+            },
+            "read" if self.is_reader() => {
+                let row = self.read()?.unwrap_or_default();
+                Some(row)
+            },
+            "readall" if self.is_reader() => {
+                let as_uobject = args.first().is_some_and(|o| o.is_truthy());
+                Some(self.read_all(as_uobject)?)
+            },
+            "write" if ! self.is_reader() => {
+                self.write(args.as_object(0)?)?;
+                Some(Object::Empty)
+            },
+            "writeall" if ! self.is_reader() => {
+                for row in rows_of(args.as_object(0)?)? {
+                    self.write(row)?;
+                }
+                Some(Object::Empty)
+            },
+            "flush" if ! self.is_reader() => {
+                self.flush()?;
+                Some(Object::Empty)
+            },
+            "close" => {
+                self.close()?;
+                Some(Object::Empty)
+            },
+            _ => return Err(UError::new(
+                UErrorKind::CsvError,
+                UErrorMessage::InvalidMember(name.to_string())
+            )),
+        };
+        Ok(obj)
+    }
+}
+
+/// for-inでCsvReaderから1行ずつ取り出す
+pub struct CsvRows(pub Arc<Mutex<CsvStream>>);
+
+impl Iterator for CsvRows {
+    type Item = CsvResult<Object>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut stream = self.0.lock().unwrap();
+        if ! stream.is_reader() {
+            return Some(Err(UError::new(UErrorKind::SyntaxError, UErrorMessage::ForInError)));
+        }
+        stream.read().transpose()
+    }
+}
+
+/// 複数行を配列の各要素として扱う
+fn rows_of(rows: Object) -> CsvResult<Vec<Object>> {
+    match rows {
+        Object::Array(arr) => Ok(arr),
+        Object::UObject(uo) => match JsonValue::try_from(Object::UObject(uo.clone()))? {
+            JsonValue::Array(vec) => Ok(vec.into_iter().map(Object::from).collect()),
+            _ => Ok(vec![Object::UObject(uo)]),
+        },
+        o => Ok(vec![o]),
+    }
+}
+
+/// 行の配列をCSV文字列にする
+pub fn rows_to_csv(rows: Object, header: bool, delimiter: u8) -> CsvResult<String> {
+    let mut stream = CsvStream::new(CsvMode::Write, CsvSource::Text(None));
+    stream.opt.header = header;
+    stream.opt.delimiter = delimiter;
+    let mut writer = stream.opt.writer_builder().from_writer(vec![]);
+    for row in rows_of(rows)? {
+        for record in stream.records(row)? {
+            writer.write_record(&record).map_err(stream_error)?;
+        }
+    }
+    let bytes = writer.into_inner().map_err(|e| stream_error(e.into_error()))?;
+    String::from_utf8(bytes).map_err(stream_error)
+}
+
+fn value_to_json(value: Object) -> CsvResult<JsonValue> {
+    match value {
+        Object::Empty => Ok(JsonValue::Null),
+        Object::DateTime(dt) => Ok(JsonValue::String(dt.to_string())),
+        o => JsonValue::try_from(o),
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_encoding(name: &str) -> CsvResult<FopenEncoding> {
+    let encoding = match name.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+        "" | "auto" => FopenEncoding::Auto,
+        "utf8" => FopenEncoding::Utf8,
+        "utf8b" | "utf8bom" => FopenEncoding::Utf8B,
+        "utf16" | "utf16le" | "unicode" => FopenEncoding::Utf16LE,
+        "utf16be" => FopenEncoding::Utf16BE,
+        "sjis" | "shiftjis" | "cp932" => FopenEncoding::Sjis,
+        _ => return Err(stream_error(FopenError::UnknownEncoding(name.to_string()))),
+    };
+    Ok(encoding)
+}
+
+/// BOMまたはUTF-8として正しいかどうかでエンコーディングを判定する
+fn sniff_encoding(head: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(head) {
+        return encoding;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => UTF_8,
+        // 末尾で文字が途切れているだけならUTF-8
+        Err(e) if e.error_len().is_none() => UTF_8,
+        Err(_) => SHIFT_JIS,
+    }
+}
+
+/// 指定エンコーディングからUTF-8に変換しながら読む
+fn decode_reader<R: Read + Send + 'static>(mut source: R, encoding: &FopenEncoding) -> io::Result<Box<dyn Read + Send>> {
+    let encoding = match encoding {
+        FopenEncoding::Utf8 |
+        FopenEncoding::Utf8B => UTF_8,
+        FopenEncoding::Utf16LE => UTF_16LE,
+        FopenEncoding::Utf16BE => UTF_16BE,
+        FopenEncoding::Sjis => SHIFT_JIS,
+        FopenEncoding::Auto => {
+            let mut head = Vec::with_capacity(SNIFF_SIZE);
+            (&mut source).take(SNIFF_SIZE as u64).read_to_end(&mut head)?;
+            let encoding = sniff_encoding(&head);
+            let source = Cursor::new(head).chain(source);
+            return Ok(Box::new(DecodeReader::new(source, encoding)));
+        },
+    };
+    Ok(Box::new(DecodeReader::new(source, encoding)))
+}
+
+struct DecodeReader<R: Read> {
+    inner: R,
+    decoder: Decoder,
+    /// デコード済みのUTF-8
+    output: Vec<u8>,
+    /// outputの未読位置
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> DecodeReader<R> {
+    fn new(inner: R, encoding: &'static Encoding) -> Self {
+        Self {
+            inner,
+            // BOMがあれば除去し、BOMの示すエンコーディングを優先する
+            decoder: encoding.new_decoder(),
+            output: vec![],
+            pos: 0,
+            eof: false,
+        }
+    }
+    fn fill(&mut self) -> io::Result<()> {
+        let mut input = [0; DECODE_BUF_SIZE];
+        let read = self.inner.read(&mut input)?;
+        let last = read == 0;
+        let max = self.decoder.max_utf8_buffer_length(read).unwrap_or(DECODE_BUF_SIZE * 3);
+        self.output.clear();
+        self.output.resize(max, 0);
+        let (_, _, written, _) = self.decoder.decode_to_utf8(&input[..read], &mut self.output, last);
+        self.output.truncate(written);
+        self.pos = 0;
+        self.eof = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.output.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let size = buf.len().min(self.output.len() - self.pos);
+        buf[..size].copy_from_slice(&self.output[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
+
+/// UTF-8を指定エンコーディングに変換しながら書く
+struct EncodeWriter<W: Write> {
+    inner: W,
+    encoding: FopenEncoding,
+    /// 途中で途切れたUTF-8のバイト列
+    pending: Vec<u8>,
+}
+
+impl<W: Write> EncodeWriter<W> {
+    fn new(inner: W, encoding: FopenEncoding) -> Self {
+        Self { inner, encoding, pending: vec![] }
+    }
+    fn write_bom(&mut self) -> io::Result<()> {
+        match self.encoding {
+            FopenEncoding::Utf8B => self.inner.write_all(&[0xEF, 0xBB, 0xBF]),
+            FopenEncoding::Utf16LE => self.inner.write_all(&[0xFF, 0xFE]),
+            FopenEncoding::Utf16BE => self.inner.write_all(&[0xFE, 0xFF]),
+            _ => Ok(()),
+        }
+    }
+    fn encode(&mut self, s: &str) -> io::Result<()> {
+        match self.encoding {
+            FopenEncoding::Utf16LE => {
+                let bytes = s.encode_utf16().flat_map(|u| u.to_le_bytes()).collect::<Vec<_>>();
+                self.inner.write_all(&bytes)
+            },
+            FopenEncoding::Utf16BE => {
+                let bytes = s.encode_utf16().flat_map(|u| u.to_be_bytes()).collect::<Vec<_>>();
+                self.inner.write_all(&bytes)
+            },
+            FopenEncoding::Sjis => {
+                let (bytes, _, _) = SHIFT_JIS.encode(s);
+                self.inner.write_all(&bytes)
+            },
+            _ => self.inner.write_all(s.as_bytes()),
+        }
+    }
+}
+
+impl<W: Write> Write for EncodeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let pending = std::mem::take(&mut self.pending);
+        let valid = match std::str::from_utf8(&pending) {
+            Ok(s) => s.len(),
+            Err(e) => e.valid_up_to(),
+        };
+        let (head, rest) = pending.split_at(valid);
+        if let Ok(s) = std::str::from_utf8(head) {
+            self.encode(s)?;
+        }
+        self.pending = rest.to_vec();
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+trait CsvArg {
+    fn as_object(&self, index: usize) -> CsvResult<Object>;
+    fn as_ascii(&self, index: usize) -> CsvResult<u8>;
+    fn as_ascii_or_empty(&self, index: usize) -> CsvResult<Option<u8>>;
+}
+impl CsvArg for Vec<Object> {
+    fn as_object(&self, index: usize) -> CsvResult<Object> {
+        self.get(index)
+            .cloned()
+            .ok_or(UError::new(UErrorKind::CsvError, UErrorMessage::BuiltinArgRequiredAt(index+1)))
+    }
+    fn as_ascii(&self, index: usize) -> CsvResult<u8> {
+        self.as_ascii_or_empty(index)?
+            .ok_or(UError::new(UErrorKind::CsvError, UErrorMessage::ShouldBeAsciiCharacter(String::new())))
+    }
+    fn as_ascii_or_empty(&self, index: usize) -> CsvResult<Option<u8>> {
+        let s = match self.as_object(index)? {
+            Object::Empty |
+            Object::EmptyParam => return Ok(None),
+            o => o.to_string(),
+        };
+        match s.as_bytes() {
+            [] => Ok(None),
+            [b] if b.is_ascii() => Ok(Some(*b)),
+            _ => Err(UError::new(UErrorKind::CsvError, UErrorMessage::ShouldBeAsciiCharacter(s))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("uwscr_csvstream_{}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_decode_reader() {
+        let sjis = SHIFT_JIS.encode("名前,値\r\nあ,1\r\n").0.into_owned();
+        let mut reader = decode_reader(Cursor::new(sjis), &FopenEncoding::Auto).unwrap();
+        let mut s = String::new();
+        reader.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "名前,値\r\nあ,1\r\n");
+
+        let mut utf16 = vec![0xFF, 0xFE];
+        utf16.extend("a,b\n".encode_utf16().flat_map(|u| u.to_le_bytes()));
+        let mut reader = decode_reader(Cursor::new(utf16), &FopenEncoding::Auto).unwrap();
+        let mut s = String::new();
+        reader.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "a,b\n");
+    }
+
+    #[test]
+    fn test_read_typed_columns() {
+        let text = "# comment\nid;name;active;date\n1;'a;b';yes;2024/01/02\n2;c;0;\n";
+        let mut stream = CsvStream::from_text(text.into());
+        stream.invoke_method("header", vec![true.into()]).unwrap();
+        stream.invoke_method("delimiter", vec![";".into()]).unwrap();
+        stream.invoke_method("quote", vec!["'".into()]).unwrap();
+        stream.invoke_method("comment", vec!["#".into()]).unwrap();
+        stream.invoke_method("coltype", vec![1.into(), (CsvTypeConst::CSV_NUM as i32).into()]).unwrap();
+        stream.invoke_method("coltype", vec!["active".into(), (CsvTypeConst::CSV_BOOL as i32).into()]).unwrap();
+        stream.invoke_method("coltype", vec!["DATE".into(), (CsvTypeConst::CSV_DATE as i32).into()]).unwrap();
+
+        let Some(values) = stream.read_values().unwrap() else { panic!() };
+        assert_eq!(values[0], Object::Num(1.0));
+        assert_eq!(values[1], Object::String("a;b".into()));
+        assert_eq!(values[2], Object::Bool(true));
+        assert!(matches!(values[3], Object::DateTime(_)));
+        let Some(values) = stream.read_values().unwrap() else { panic!() };
+        assert_eq!(values[2], Object::Bool(false));
+        assert_eq!(values[3], Object::Empty);
+        assert!(stream.read_values().unwrap().is_none());
+        assert_eq!(stream.headers, Some(vec!["id".into(), "name".into(), "active".into(), "date".into()]));
+        // 読み始めたら設定を変えられない
+        assert!(stream.invoke_method("delimiter", vec![",".into()]).is_err());
+    }
+
+    #[test]
+    fn test_conversion_error() {
+        let mut stream = CsvStream::from_text("1\nx\n".into());
+        stream.invoke_method("coltype", vec![1.into(), (CsvTypeConst::CSV_NUM as i32).into()]).unwrap();
+        assert_eq!(stream.read().unwrap(), Some(Object::Array(vec![Object::Num(1.0)])));
+        let err = stream.read().unwrap_err();
+        assert_eq!(err.message, UErrorMessage::CsvConversionFailed(2, "1".into(), "x".into()));
+    }
+
+    #[test]
+    fn test_write_and_append() {
+        let path = temp_path("append.csv");
+        let _ = std::fs::remove_file(&path);
+
+        let mut writer = CsvStream::writer(path.clone(), false);
+        writer.invoke_method("header", vec![Object::Array(vec!["id".into(), "name".into()])]).unwrap();
+        writer.invoke_method("encoding", vec!["sjis".into()]).unwrap();
+        writer.write(Object::Array(vec![1.into(), "あ,い".into()])).unwrap();
+        writer.close().unwrap();
+
+        // 追記時は既存のヘッダとエンコーディングを引き継ぐ
+        let mut writer = CsvStream::writer(path.clone(), true);
+        writer.set_header(true).unwrap();
+        let mut tbl = HashTbl::new(false, false);
+        tbl.insert("name".into(), "う".into());
+        tbl.insert("id".into(), 2.into());
+        writer.write(Object::HashTbl(Arc::new(Mutex::new(tbl)))).unwrap();
+        writer.close().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let (text, _, _) = SHIFT_JIS.decode(&bytes);
+        assert_eq!(text, "id,name\n1,\"あ,い\"\n2,う\n");
+
+        let mut reader = CsvStream::reader(path.clone());
+        reader.set_header(true).unwrap();
+        let Object::Array(rows) = reader.read_all(false).unwrap() else { panic!() };
+        assert_eq!(rows.len(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_rows_to_csv() {
+        let mut tbl = HashTbl::new(false, true);
+        tbl.insert("a".into(), 1.into());
+        tbl.insert("b".into(), "x\"y".into());
+        let rows = Object::Array(vec![
+            Object::HashTbl(Arc::new(Mutex::new(tbl))),
+            Object::Array(vec![2.into(), Object::Empty]),
+        ]);
+        let csv = rows_to_csv(rows, true, b',').unwrap();
+        assert_eq!(csv, "a,b\n1,\"x\"\"y\"\n2,\n");
+
+        let mut stream = CsvStream::from_text(csv);
+        stream.set_header(true).unwrap();
+        let Object::UObject(uo) = stream.read_all(true).unwrap() else { panic!() };
+        let json = JsonValue::try_from(Object::UObject(uo)).unwrap();
+        assert_eq!(json, serde_json::json!([{"a": "1", "b": "x\"y"}, {"a": "2", "b": ""}]));
+    }
+}
@@ -0,0 +1,556 @@
+//! DateTimeオブジェクト
+//!
+//! 日時はUTCで保持し、タイムゾーンは表示や暦の上での計算 (日・月・年の加算、切り捨て) にのみ使う
+
+use super::Object;
+use crate::error::{UError, UErrorKind, UErrorMessage};
+use crate::builtins::system_controls::GTimeOffset;
+use crate::builtins::system_controls::gettime;
+
+use chrono::{
+    DateTime, Datelike, Timelike, Weekday, NaiveDate, NaiveDateTime, Duration, Days,
+    FixedOffset, Local, Utc, Offset, TimeZone, SecondsFormat,
+};
+use chrono_tz::Tz;
+use num_traits::FromPrimitive;
+
+use std::cmp::Ordering;
+use std::fmt::{self, Write};
+
+pub type DateTimeResult<T> = Result<T, UError>;
+
+/// DateTimeオブジェクトのタイムゾーン
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateTimeZone {
+    /// システムのタイムゾーン
+    Local,
+    Utc,
+    /// UTCからの固定オフセット
+    Fixed(FixedOffset),
+    /// IANAタイムゾーン、夏時間の規則を持つ
+    Named(Tz),
+}
+
+impl DateTimeZone {
+    /// タイムゾーン文字列を解釈する
+    ///
+    /// - local または空文字: システムのタイムゾーン
+    /// - UTC, GMT, Z
+    /// - +09:00, -0500, +9 などのオフセット、UTC+9 のように前置してもよい
+    /// - Asia/Tokyo, America/New_York などのIANAタイムゾーン名 (大文字小文字を区別する)
+    pub fn parse(zone: &str) -> Option<Self> {
+        let zone = zone.trim();
+        let lower = zone.to_ascii_lowercase();
+        match lower.as_str() {
+            "" | "local" => Some(Self::Local),
+            "utc" | "gmt" | "z" => Some(Self::Utc),
+            _ => Self::parse_offset(&lower)
+                .map(Self::Fixed)
+                .or_else(|| zone.parse::<Tz>().ok().map(Self::Named)),
+        }
+    }
+    fn parse_offset(lower: &str) -> Option<FixedOffset> {
+        let rest = lower.strip_prefix("utc")
+            .or_else(|| lower.strip_prefix("gmt"))
+            .unwrap_or(lower);
+        let (sign, rest) = match rest.as_bytes().first()? {
+            b'+' => (1, &rest[1..]),
+            b'-' => (-1, &rest[1..]),
+            _ => return None,
+        };
+        let (hour, minute) = match rest.split_once(':') {
+            Some(hm) => hm,
+            None if rest.len() == 4 => rest.split_at(2),
+            None => (rest, "0"),
+        };
+        let hour = hour.parse::<i32>().ok().filter(|h| (0..24).contains(h))?;
+        let minute = minute.parse::<i32>().ok().filter(|m| (0..60).contains(m))?;
+        FixedOffset::east_opt(sign * (hour * 3600 + minute * 60))
+    }
+    fn from_object(obj: &Object) -> DateTimeResult<Self> {
+        let zone = obj.to_string();
+        Self::parse(&zone).ok_or(UError::new(
+            UErrorKind::DateTimeError,
+            UErrorMessage::InvalidTimeZone(zone)
+        ))
+    }
+    /// このタイムゾーンでの日時を得る
+    fn in_zone(&self, utc: &DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Self::Local => {
+                let local = utc.with_timezone(&Local);
+                local.with_timezone(&local.offset().fix())
+            },
+            Self::Utc => utc.with_timezone(&Utc.fix()),
+            Self::Fixed(offset) => utc.with_timezone(offset),
+            Self::Named(tz) => {
+                let named = utc.with_timezone(tz);
+                named.with_timezone(&named.offset().fix())
+            },
+        }
+    }
+    /// このタイムゾーンの日時をUTCにする
+    fn resolve_local(&self, naive: &NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            // 夏時間の切り替えで重複する場合は早い方
+            Self::Local => Local.from_local_datetime(naive).earliest().map(|dt| dt.with_timezone(&Utc)),
+            Self::Utc => Some(Utc.from_utc_datetime(naive)),
+            Self::Fixed(offset) => offset.from_local_datetime(naive).single().map(|dt| dt.with_timezone(&Utc)),
+            Self::Named(tz) => tz.from_local_datetime(naive).earliest().map(|dt| dt.with_timezone(&Utc)),
+        }
+    }
+}
+
+impl fmt::Display for DateTimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DateTimeZone::Local => write!(f, "Local"),
+            DateTimeZone::Utc => write!(f, "UTC"),
+            DateTimeZone::Fixed(offset) => write!(f, "{offset}"),
+            DateTimeZone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UDateTime {
+    utc: DateTime<Utc>,
+    zone: DateTimeZone,
+}
+
+impl PartialEq for UDateTime {
+    /// タイムゾーンが異なっても同じ時刻なら等しい
+    fn eq(&self, other: &Self) -> bool {
+        self.utc == other.utc
+    }
+}
+impl PartialOrd for UDateTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.utc.partial_cmp(&other.utc)
+    }
+}
+
+impl fmt::Display for UDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dt = self.local();
+        let use_z = self.zone == DateTimeZone::Utc;
+        write!(f, "{}", dt.to_rfc3339_opts(SecondsFormat::AutoSi, use_z))
+    }
+}
+
+impl UDateTime {
+    /// 現在日時
+    pub fn now(zone: DateTimeZone) -> Self {
+        Self { utc: Utc::now(), zone }
+    }
+    /// gettimeの戻り値 (2000/01/01からの経過秒数) から作る
+    pub fn from_gettime(secs: f64, zone: DateTimeZone) -> DateTimeResult<Self> {
+        let millis = gettime::base_timestamp_millis() + (secs * 1000.0) as i64;
+        let utc = Utc.timestamp_millis_opt(millis).single()
+            .ok_or(Self::out_of_range())?;
+        Ok(Self { utc, zone })
+    }
+    /// 日時文字列を解釈する
+    ///
+    /// 書式省略時は RFC 3339 (ISO 8601)、RFC 2822、gettimeが受ける形式の順に試す
+    ///
+    /// タイムゾーンを省略した場合、オフセットを含む文字列ならそのオフセット、含まなければローカル時間になる
+    /// タイムゾーンを指定した場合、オフセットを含む文字列ならそのタイムゾーンに変換し、含まなければそのタイムゾーンの日時とみなす
+    pub fn parse(value: &str, zone: Option<DateTimeZone>, fmt: Option<&str>) -> DateTimeResult<Self> {
+        let value = value.trim();
+        let aware = match fmt {
+            Some(fmt) => DateTime::parse_from_str(value, fmt).ok(),
+            None => DateTime::parse_from_rfc3339(value).ok()
+                .or_else(|| DateTime::parse_from_rfc2822(value).ok())
+                .or_else(|| value.parse::<DateTime<FixedOffset>>().ok()),
+        };
+        if let Some(dt) = aware {
+            let zone = zone.unwrap_or(DateTimeZone::Fixed(*dt.offset()));
+            return Ok(Self { utc: dt.with_timezone(&Utc), zone });
+        }
+        let naive = match fmt {
+            Some(fmt) => NaiveDateTime::parse_from_str(value, fmt)
+                .or_else(|e| {
+                    // 日付のみの書式
+                    NaiveDate::parse_from_str(value, fmt)
+                        .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+                        .map_err(|_| e)
+                }),
+            None => gettime::parse_naive(value),
+        }.map_err(|e| UError::new(
+            UErrorKind::DateTimeError,
+            UErrorMessage::InvalidDateTime(value.to_string(), e.to_string())
+        ))?;
+        let zone = zone.unwrap_or(DateTimeZone::Local);
+        let utc = zone.resolve_local(&naive).ok_or(Self::out_of_range())?;
+        Ok(Self { utc, zone })
+    }
+    /// datetime関数の引数から作る
+    pub fn from_object(value: &Object, zone: Option<&Object>, fmt: Option<&str>) -> DateTimeResult<Self> {
+        let zone = zone.map(DateTimeZone::from_object).transpose()?;
+        match value {
+            Object::Empty |
+            Object::EmptyParam => Ok(Self::now(zone.unwrap_or(DateTimeZone::Local))),
+            Object::Num(n) => Self::from_gettime(*n, zone.unwrap_or(DateTimeZone::Local)),
+            Object::DateTime(dt) => match zone {
+                Some(zone) => Ok(dt.with_zone(zone)),
+                None => Ok(dt.clone()),
+            },
+            o => Self::parse(&o.to_string(), zone, fmt),
+        }
+    }
+    fn out_of_range() -> UError {
+        UError::new(UErrorKind::DateTimeError, UErrorMessage::DateTimeOutOfRange)
+    }
+    fn invalid_amount(value: f64) -> UError {
+        UError::new(UErrorKind::DateTimeError, UErrorMessage::InvalidDateTimeAmount(value.to_string()))
+    }
+    /// タイムゾーンでの日時
+    fn local(&self) -> DateTime<FixedOffset> {
+        self.zone.in_zone(&self.utc)
+    }
+    /// タイムゾーンでの日時を変更したものを返す
+    fn map_local<F>(&self, f: F) -> DateTimeResult<Self>
+        where F: FnOnce(NaiveDateTime) -> Option<NaiveDateTime>
+    {
+        let naive = self.local().naive_local();
+        let utc = f(naive)
+            .and_then(|naive| self.zone.resolve_local(&naive))
+            .ok_or(Self::out_of_range())?;
+        Ok(Self { utc, zone: self.zone })
+    }
+    pub fn with_zone(&self, zone: DateTimeZone) -> Self {
+        Self { utc: self.utc, zone }
+    }
+    /// gettime互換の2000/01/01からの経過ミリ秒
+    pub fn gettime_millis(&self) -> i64 {
+        self.utc.timestamp_millis() - gettime::base_timestamp_millis()
+    }
+    fn unit_millis(unit: &GTimeOffset) -> Option<f64> {
+        let millis = match unit {
+            GTimeOffset::G_OFFSET_DAYS => 24.0 * 60.0 * 60.0 * 1000.0,
+            GTimeOffset::G_OFFSET_HOURS => 60.0 * 60.0 * 1000.0,
+            GTimeOffset::G_OFFSET_MINUTES => 60.0 * 1000.0,
+            GTimeOffset::G_OFFSET_SECONDS => 1000.0,
+            GTimeOffset::G_OFFSET_MILLIS => 1.0,
+            GTimeOffset::G_OFFSET_MONTHS |
+            GTimeOffset::G_OFFSET_YEARS => None?,
+        };
+        Some(millis)
+    }
+    /// 加算する、年・月・日は暦の上で加算するため夏時間をまたいでも時刻は変わらない
+    ///
+    /// 年・月は整数のみ受ける
+    pub fn add(&self, value: f64, unit: GTimeOffset) -> DateTimeResult<Self> {
+        if ! value.is_finite() {
+            return Err(Self::invalid_amount(value));
+        }
+        match unit {
+            GTimeOffset::G_OFFSET_YEARS |
+            GTimeOffset::G_OFFSET_MONTHS => {
+                if value.fract() != 0.0 {
+                    return Err(Self::invalid_amount(value));
+                }
+                let months = if let GTimeOffset::G_OFFSET_YEARS = unit {
+                    value * 12.0
+                } else {
+                    value
+                };
+                if months.abs() > i32::MAX as f64 {
+                    return Err(Self::out_of_range());
+                }
+                let dt = gettime::add_months(self.local(), months as i32).ok_or(Self::out_of_range())?;
+                self.map_local(|_| Some(dt.naive_local()))
+            },
+            GTimeOffset::G_OFFSET_DAYS if value.fract() == 0.0 => {
+                let days = Days::new(value.abs() as u64);
+                self.map_local(|naive| if value < 0.0 {
+                    naive.checked_sub_days(days)
+                } else {
+                    naive.checked_add_days(days)
+                })
+            },
+            unit => {
+                let millis = value * Self::unit_millis(&unit).unwrap_or_default();
+                if millis.abs() >= i64::MAX as f64 {
+                    return Err(Self::out_of_range());
+                }
+                let utc = self.utc.checked_add_signed(Duration::milliseconds(millis as i64))
+                    .ok_or(Self::out_of_range())?;
+                Ok(Self { utc, zone: self.zone })
+            },
+        }
+    }
+    /// 自身からotherを引いた差、年・月は端数を切り捨てた暦の上の差
+    pub fn diff(&self, other: &Self, unit: GTimeOffset) -> f64 {
+        match Self::unit_millis(&unit) {
+            Some(unit_millis) => {
+                let millis = (self.utc - other.utc).num_milliseconds();
+                millis as f64 / unit_millis
+            },
+            None => {
+                let months = self.months_since(other);
+                if let GTimeOffset::G_OFFSET_YEARS = unit {
+                    (months / 12) as f64
+                } else {
+                    months as f64
+                }
+            }
+        }
+    }
+    fn months_since(&self, other: &Self) -> i32 {
+        let to = self.local();
+        let from = other.with_zone(self.zone).local();
+        let mut months = (to.year() - from.year()) * 12 + to.month() as i32 - from.month() as i32;
+        // 1か月に満たない分を切り捨てる
+        if let Some(shifted) = gettime::add_months(from, months) {
+            if months > 0 && shifted > to {
+                months -= 1;
+            } else if months < 0 && shifted < to {
+                months += 1;
+            }
+        }
+        months
+    }
+    /// 指定単位未満を切り捨てる
+    pub fn truncate(&self, unit: GTimeOffset) -> DateTimeResult<Self> {
+        self.map_local(|naive| {
+            let date = naive.date();
+            let (h, m, s) = (naive.hour(), naive.minute(), naive.second());
+            match unit {
+                GTimeOffset::G_OFFSET_YEARS => NaiveDate::from_ymd_opt(date.year(), 1, 1)?.and_hms_opt(0, 0, 0),
+                GTimeOffset::G_OFFSET_MONTHS => date.with_day(1)?.and_hms_opt(0, 0, 0),
+                GTimeOffset::G_OFFSET_DAYS => date.and_hms_opt(0, 0, 0),
+                GTimeOffset::G_OFFSET_HOURS => date.and_hms_opt(h, 0, 0),
+                GTimeOffset::G_OFFSET_MINUTES => date.and_hms_opt(h, m, 0),
+                GTimeOffset::G_OFFSET_SECONDS => date.and_hms_opt(h, m, s),
+                GTimeOffset::G_OFFSET_MILLIS => {
+                    let milli = (naive.nanosecond() / 1_000_000).min(999);
+                    date.and_hms_milli_opt(h, m, s, milli)
+                },
+            }
+        })
+    }
+    /// strftime形式の書式で文字列にする
+    pub fn format(&self, fmt: &str, locale_str: Option<&str>) -> DateTimeResult<String> {
+        let locale = gettime::get_locale(locale_str);
+        let delayed = self.local().format_localized(fmt, locale);
+        let mut buf = String::new();
+        write!(&mut buf, "{delayed}").map_err(|_| UError::new(
+            UErrorKind::DateTimeError,
+            UErrorMessage::InvalidDateTimeFormat(fmt.to_string())
+        ))?;
+        Ok(buf)
+    }
+    fn weekday(&self) -> i32 {
+        match self.local().weekday() {
+            Weekday::Sun => 0,
+            Weekday::Mon => 1,
+            Weekday::Tue => 2,
+            Weekday::Wed => 3,
+            Weekday::Thu => 4,
+            Weekday::Fri => 5,
+            Weekday::Sat => 6,
+        }
+    }
+
+    pub fn get_property(&self, name: &str) -> DateTimeResult<Object> {
+        let dt = self.local();
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "year" => dt.year().into(),
+            "month" => dt.month().into(),
+            "day" => dt.day().into(),
+            "hour" => dt.hour().into(),
+            "minute" => dt.minute().into(),
+            "second" => dt.second().into(),
+            "millisec" => (dt.timestamp_subsec_millis().min(999)).into(),
+            "weekday" => self.weekday().into(),
+            "yearday" => dt.ordinal().into(),
+            "timezone" => self.zone.to_string().into(),
+            // UTCからのオフセット (分)
+            "offset" => (dt.offset().local_minus_utc() / 60).into(),
+            "gettime" => (self.gettime_millis().div_euclid(1000) as f64).into(),
+            "gettimems" => (self.gettime_millis() as f64).into(),
+            "timestamp" => (self.utc.timestamp() as f64).into(),
+            _ => Err(UError::new(
+                UErrorKind::DateTimeError,
+                UErrorMessage::InvalidMember(name.to_string())
+            ))?
+        };
+        Ok(obj)
+    }
+    pub fn invoke_method(&self, name: &str, args: Vec<Object>) -> DateTimeResult<Object> {
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "add" => {
+                let value = args.as_f64(0)?;
+                let unit = args.as_unit(1, GTimeOffset::G_OFFSET_DAYS)?;
+                Object::DateTime(self.add(value, unit)?)
+            },
+            "sub" => {
+                let value = args.as_f64(0)?;
+                let unit = args.as_unit(1, GTimeOffset::G_OFFSET_DAYS)?;
+                Object::DateTime(self.add(-value, unit)?)
+            },
+            "diff" => {
+                let other = args.as_datetime(0)?;
+                let unit = args.as_unit(1, GTimeOffset::G_OFFSET_SECONDS)?;
+                self.diff(&other, unit).into()
+            },
+            "truncate" => {
+                let unit = args.as_unit(0, GTimeOffset::G_OFFSET_DAYS)?;
+                Object::DateTime(self.truncate(unit)?)
+            },
+            "format" => {
+                let fmt = args.as_string(0)?.unwrap_or("%Y/%m/%d %H:%M:%S".into());
+                let locale = args.as_string(1)?;
+                self.format(&fmt, locale.as_deref())?.into()
+            },
+            "tozone" => {
+                let zone = args.first().unwrap_or(&Object::Empty);
+                let zone = DateTimeZone::from_object(zone)?;
+                Object::DateTime(self.with_zone(zone))
+            },
+            "toutc" => Object::DateTime(self.with_zone(DateTimeZone::Utc)),
+            "tolocal" => Object::DateTime(self.with_zone(DateTimeZone::Local)),
+            _ => Err(UError::new(
+                UErrorKind::DateTimeError,
+                UErrorMessage::InvalidMember(name.to_string())
+            ))?
+        };
+        Ok(obj)
+    }
+}
+
+trait DateTimeArg {
+    fn as_f64(&self, index: usize) -> DateTimeResult<f64>;
+    fn as_string(&self, index: usize) -> DateTimeResult<Option<String>>;
+    fn as_unit(&self, index: usize, default: GTimeOffset) -> DateTimeResult<GTimeOffset>;
+    fn as_datetime(&self, index: usize) -> DateTimeResult<UDateTime>;
+}
+impl DateTimeArg for Vec<Object> {
+    fn as_f64(&self, index: usize) -> DateTimeResult<f64> {
+        let obj = self.get(index).ok_or(UError::new(UErrorKind::DateTimeError, UErrorMessage::BuiltinArgRequiredAt(index+1)))?;
+        obj.as_f64(false)
+            .ok_or(UError::new(UErrorKind::DateTimeError, UErrorMessage::ArgumentIsNotNumber(index+1, obj.to_string())))
+    }
+    fn as_string(&self, index: usize) -> DateTimeResult<Option<String>> {
+        match self.get(index) {
+            None |
+            Some(Object::Empty) |
+            Some(Object::EmptyParam) => Ok(None),
+            Some(obj) => Ok(Some(obj.to_string())),
+        }
+    }
+    fn as_unit(&self, index: usize, default: GTimeOffset) -> DateTimeResult<GTimeOffset> {
+        match self.get(index) {
+            None |
+            Some(Object::Empty) |
+            Some(Object::EmptyParam) => Ok(default),
+            Some(obj) => obj.as_f64(false)
+                .and_then(GTimeOffset::from_f64)
+                .ok_or(UError::new(UErrorKind::DateTimeError, UErrorMessage::InvalidArgument(obj.clone()))),
+        }
+    }
+    fn as_datetime(&self, index: usize) -> DateTimeResult<UDateTime> {
+        let obj = self.get(index).ok_or(UError::new(UErrorKind::DateTimeError, UErrorMessage::BuiltinArgRequiredAt(index+1)))?;
+        UDateTime::from_object(obj, None, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> UDateTime {
+        UDateTime::parse(s, Some(DateTimeZone::Utc), None).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let dt = UDateTime::parse("2024-03-10T09:30:00+09:00", None, None).unwrap();
+        assert_eq!(dt.zone, DateTimeZone::Fixed(FixedOffset::east_opt(9 * 3600).unwrap()));
+        assert_eq!(dt.to_string(), "2024-03-10T09:30:00+09:00");
+        assert_eq!(dt.with_zone(DateTimeZone::Utc).to_string(), "2024-03-10T00:30:00Z");
+        let dt = UDateTime::parse("Sun, 10 Mar 2024 00:30:00 +0000", None, None).unwrap();
+        assert_eq!(dt, utc("2024/03/10 00:30:00"));
+        let dt = UDateTime::parse("10.03.2024", Some(DateTimeZone::Utc), Some("%d.%m.%Y")).unwrap();
+        assert_eq!(dt.to_string(), "2024-03-10T00:00:00Z");
+        assert!(UDateTime::parse("foo", None, None).is_err());
+    }
+
+    #[test]
+    fn test_zone() {
+        let zone = |s| DateTimeZone::parse(s);
+        assert_eq!(zone("utc"), Some(DateTimeZone::Utc));
+        assert_eq!(zone(""), Some(DateTimeZone::Local));
+        let jst = Some(DateTimeZone::Fixed(FixedOffset::east_opt(9 * 3600).unwrap()));
+        assert_eq!(zone("+09:00"), jst);
+        assert_eq!(zone("+0900"), jst);
+        assert_eq!(zone("UTC+9"), jst);
+        assert_eq!(zone("-05:30"), Some(DateTimeZone::Fixed(FixedOffset::west_opt(5 * 3600 + 30 * 60).unwrap())));
+        assert_eq!(zone("Asia/Tokyo"), Some(DateTimeZone::Named(Tz::Asia__Tokyo)));
+        assert_eq!(zone("Asia/Nowhere"), None);
+        assert_eq!(zone("+25:00"), None);
+    }
+
+    #[test]
+    fn test_named_zone() {
+        let ny = DateTimeZone::parse("America/New_York");
+        // 夏時間の開始をまたいでも日の加算では時刻が変わらない
+        let dt = UDateTime::parse("2024-03-09 12:00:00", ny, None).unwrap();
+        assert_eq!(dt.to_string(), "2024-03-09T12:00:00-05:00");
+        let next = dt.add(1.0, GTimeOffset::G_OFFSET_DAYS).unwrap();
+        assert_eq!(next.to_string(), "2024-03-10T12:00:00-04:00");
+        assert_eq!(next.diff(&dt, GTimeOffset::G_OFFSET_HOURS), 23.0);
+        assert_eq!(next.get_property("timezone").unwrap(), Object::String("America/New_York".into()));
+        // 存在しない時刻
+        assert!(UDateTime::parse("2024-03-10 02:30:00", ny, None).is_err());
+    }
+
+    #[test]
+    fn test_invalid_amount() {
+        let dt = utc("2024-01-31 12:34:56");
+        let invalid = |value: f64| UErrorMessage::InvalidDateTimeAmount(value.to_string());
+        assert_eq!(dt.add(1.5, GTimeOffset::G_OFFSET_MONTHS).unwrap_err().message, invalid(1.5));
+        assert_eq!(dt.add(f64::NAN, GTimeOffset::G_OFFSET_DAYS).unwrap_err().message, invalid(f64::NAN));
+        assert_eq!(dt.add(1e10, GTimeOffset::G_OFFSET_YEARS).unwrap_err().message, UErrorMessage::DateTimeOutOfRange);
+        assert_eq!(dt.add(1e30, GTimeOffset::G_OFFSET_SECONDS).unwrap_err().message, UErrorMessage::DateTimeOutOfRange);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let dt = utc("2024-01-31 12:34:56");
+        let next = dt.add(1.0, GTimeOffset::G_OFFSET_MONTHS).unwrap();
+        assert_eq!(next, utc("2024-02-29 12:34:56"));
+        assert_eq!(dt.add(-1.0, GTimeOffset::G_OFFSET_YEARS).unwrap(), utc("2023-01-31 12:34:56"));
+        assert_eq!(dt.add(1.5, GTimeOffset::G_OFFSET_DAYS).unwrap(), utc("2024-02-02 00:34:56"));
+        assert_eq!(dt.add(-90.0, GTimeOffset::G_OFFSET_MINUTES).unwrap(), utc("2024-01-31 11:04:56"));
+        assert_eq!(next.diff(&dt, GTimeOffset::G_OFFSET_DAYS), 29.0);
+        assert_eq!(next.diff(&dt, GTimeOffset::G_OFFSET_MONTHS), 1.0);
+        assert_eq!(dt.diff(&utc("2023-02-01 00:00:00"), GTimeOffset::G_OFFSET_YEARS), 0.0);
+        assert_eq!(dt.diff(&next, GTimeOffset::G_OFFSET_HOURS), -29.0 * 24.0);
+        assert_eq!(dt.truncate(GTimeOffset::G_OFFSET_DAYS).unwrap(), utc("2024-01-31 00:00:00"));
+        assert_eq!(dt.truncate(GTimeOffset::G_OFFSET_MONTHS).unwrap(), utc("2024-01-01 00:00:00"));
+    }
+
+    #[test]
+    fn test_gettime() {
+        let dt = UDateTime::from_gettime(0.0, DateTimeZone::Local).unwrap();
+        assert_eq!(dt.format("%Y/%m/%d %H:%M:%S", None).unwrap(), "2000/01/01 00:00:00");
+        let dt = UDateTime::parse("2024/03/10 09:30:00", None, None).unwrap();
+        let secs = dt.gettime_millis() as f64 / 1000.0;
+        assert_eq!(UDateTime::from_gettime(secs, DateTimeZone::Utc).unwrap(), dt);
+        let Ok(gt) = gettime::get(Some(dt.to_string()), 0.0, GTimeOffset::G_OFFSET_DAYS) else {
+            panic!("gettime could not parse {dt}");
+        };
+        assert_eq!(gt.timestamp_millis, dt.gettime_millis());
+    }
+
+    #[test]
+    fn test_format() {
+        let dt = utc("2024-03-10 09:30:00");
+        assert_eq!(dt.format("%Y年%m月%d日(%a)", Some("ja_JP")).unwrap(), "2024年03月10日(日)");
+        assert_eq!(dt.format("%A %B", Some("en-us")).unwrap(), "Sunday March");
+        assert_eq!(dt.format("%A", Some("fr_FR")).unwrap(), "dimanche");
+    }
+}
@@ -713,6 +713,45 @@ impl Fopen {
         fopen.close()?;
         Ok(())
     }
+    pub fn ini_to_sections(&self) -> IniSections {
+        match &self.buf {
+            Some(buf) => {
+                let ini = Ini::parse(buf);
+                ini.to_sections()
+            },
+            None => vec![],
+        }
+    }
+    pub fn ini_to_sections_from_path(path: &str) -> FopenResult<IniSections> {
+        let f_read = 2;
+        let mut fopen = Self::new(path, f_read);
+        if let Err(e) = fopen.open() {
+            match e {
+                // IOエラーは無視して空を返す
+                FopenError::IOError(_) => return Ok(vec![]),
+                e => return Err(e)
+            }
+        }
+        let sections = fopen.ini_to_sections();
+        fopen.close()?;
+        Ok(sections)
+    }
+    pub fn ini_update(&mut self, sections: &IniSections) {
+        let mut ini = match &self.buf {
+            Some(buf) => Ini::parse(buf),
+            None => Ini::new(),
+        };
+        ini.update(sections);
+        self.buf.replace(FopenBuf::new(ini.to_string()));
+    }
+    pub fn ini_update_from_path(path: &str, sections: &IniSections) -> FopenResult<()> {
+        let f_read_or_f_write = 6;
+        let mut fopen = Self::new(path, f_read_or_f_write);
+        fopen.open()?;
+        fopen.ini_update(sections);
+        fopen.close()?;
+        Ok(())
+    }
     pub fn delete(path: &str) -> bool {
         let mut result = true;
         let buf = PathBuf::from(path);
@@ -1198,6 +1237,9 @@ impl std::fmt::Display for UCsv {
 }
 
 
+/// セクション名とキー・値の組
+pub type IniSections = Vec<(String, Vec<(String, String)>)>;
+
 #[derive(Debug)]
 struct Ini {
     lines: Vec<IniLine>,
@@ -1391,6 +1433,55 @@ impl Ini {
             .collect()
     }
 
+    /// セクションごとのキーと値、同名のセクションはまとめる
+    fn to_sections(&self) -> IniSections {
+        let mut sections: IniSections = vec![];
+        for line in &self.lines {
+            let name = match line {
+                IniLine::Section(name) => name,
+                IniLine::Key(inikey) => &inikey.section,
+                IniLine::Other(_) => continue,
+            };
+            let index = match sections.iter().position(|(s, _)| s.eq_ignore_ascii_case(name)) {
+                Some(index) => index,
+                None => {
+                    sections.push((name.to_string(), vec![]));
+                    sections.len() - 1
+                },
+            };
+            if let IniLine::Key(inikey) = line {
+                let keys = &mut sections[index].1;
+                match keys.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(&inikey.key)) {
+                    Some((_, value)) => *value = inikey.value.clone(),
+                    None => keys.push((inikey.key.clone(), inikey.value.clone())),
+                }
+            }
+        }
+        sections
+    }
+    /// 内容をsectionsに合わせる\
+    /// 既存の値は置き換え、sectionsにないキーやセクションは削除する\
+    /// コメント等はそのまま残る
+    fn update(&mut self, sections: &IniSections) {
+        let find_section = |name: &str| sections.iter()
+            .find(|(s, _)| s.eq_ignore_ascii_case(name))
+            .map(|(_, keys)| keys);
+        self.lines.retain(|line| match line {
+            IniLine::Section(name) => find_section(name).is_some(),
+            IniLine::Key(inikey) => find_section(&inikey.section)
+                .is_some_and(|keys| keys.iter().any(|(k, _)| k.eq_ignore_ascii_case(&inikey.key))),
+            IniLine::Other(_) => true,
+        });
+        for (section, keys) in sections {
+            if ! self.lines.iter().any(|line| line.is_in_section(section)) {
+                self.lines.push(IniLine::Section(section.to_string()));
+            }
+            for (key, value) in keys {
+                self.set(section, key, value);
+            }
+        }
+    }
+
     fn to_lines(&self) -> Vec<String> {
         let lines = self.lines
             .iter()
@@ -1804,4 +1895,23 @@ mod tests {
             .to_string();
         assert_eq!(expect, value);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ini_sections_test() {
+        use super::Ini;
+        let lines = ["; comment", "[A]", "x=1", "y = 2", "", "[B]", "z=3", "[a]", "w=4"]
+            .map(String::from).to_vec();
+        let mut ini = Ini::parse(lines);
+        let mut sections = ini.to_sections();
+        assert_eq!(sections, vec![
+            ("A".to_string(), vec![("x".to_string(), "1".to_string()), ("y".to_string(), "2".to_string()), ("w".to_string(), "4".to_string())]),
+            ("B".to_string(), vec![("z".to_string(), "3".to_string())]),
+        ]);
+        sections[0].1.remove(1);
+        sections[0].1[0].1 = "10".into();
+        sections.remove(1);
+        sections.push(("C".into(), vec![("v".into(), "5".into())]));
+        ini.update(&sections);
+        assert_eq!(ini.to_lines(), vec!["; comment", "[A]", "x=10", "", "[a]", "w=4", "[C]", "v=5"]);
+    }
+}
@@ -65,7 +65,27 @@ impl Function {
             outer: None,
         }
     }
-    pub fn invoke(&self, evaluator: &mut Evaluator, mut arguments: Vec<(Option<Expression>, Object)>, this: Option<This>) -> EvalResult<Object> {
+    pub fn invoke(&self, evaluator: &mut Evaluator, arguments: Vec<(Option<Expression>, Object)>, this: Option<This>) -> EvalResult<Object> {
+        evaluator.enter_function()?;
+        let result = if evaluator.has_depth_limit() {
+            // 呼び出しの深さが上限に達する前にスタックが溢れないようにする
+            stacker::maybe_grow(2 * 1024 * 1024, 20*1024*1024, || {
+                self.call(evaluator, arguments, this)
+            })
+        } else {
+            self.call(evaluator, arguments, this)
+        };
+        evaluator.leave_function();
+        result
+    }
+    /// 値のみを引数として関数を呼び出す (コールバック用)
+    pub fn invoke_with_values(&self, evaluator: &mut Evaluator, values: Vec<Object>) -> EvalResult<Object> {
+        let arguments = values.into_iter()
+            .map(|o| (Some(Expression::EmptyArgument), o))
+            .collect();
+        self.invoke(evaluator, arguments, None)
+    }
+    fn call(&self, evaluator: &mut Evaluator, mut arguments: Vec<(Option<Expression>, Object)>, this: Option<This>) -> EvalResult<Object> {
         let param_len = self.params.len();
         let mut params = self.params.clone();
         // if param_len > arguments.len() {
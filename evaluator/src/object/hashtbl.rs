@@ -1,6 +1,12 @@
-use super::Object;
+use super::{Object, UObject};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+use crate::{Evaluator, EvalResult};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 use indexmap::IndexMap;
+use serde_json::{Map, Value as JsonValue};
 use strum_macros::{EnumString, VariantNames, EnumProperty};
 use num_derive::{ToPrimitive, FromPrimitive};
 
@@ -29,6 +35,8 @@ pub struct HashTbl {
     map: IndexMap<String, Object>,
     sort: bool,
     casecare: bool,
+    /// 文字列以外のキーの元の値
+    key_objects: HashMap<String, Object>,
 }
 
 impl HashTbl {
@@ -36,7 +44,8 @@ impl HashTbl {
         HashTbl {
             map: IndexMap::new(),
             sort,
-            casecare
+            casecare,
+            key_objects: HashMap::new(),
         }
     }
 
@@ -44,12 +53,34 @@ impl HashTbl {
         self.map.clone()
     }
 
+    /// キーとして使える値を文字列化する
+    pub fn key_to_string(key: &Object) -> Option<String> {
+        match key {
+            Object::Num(n) => Some(n.to_string()),
+            Object::Bool(b) => Some(b.to_string()),
+            Object::String(s) => Some(s.clone()),
+            _ => None
+        }
+    }
+    fn to_key(&self, name: &str) -> String {
+        if ! self.casecare { name.to_ascii_uppercase() } else { name.to_string() }
+    }
+    fn key_object(&self, key: &str) -> Object {
+        self.key_objects.get(key).cloned().unwrap_or(Object::String(key.to_string()))
+    }
+
     pub fn keys(&self) -> Vec<Object> {
-        self.map.keys().map(|key| Object::String(key.clone())).collect()
+        self.map.keys().map(|key| self.key_object(key)).collect()
     }
     pub fn values(&self) -> Vec<Object> {
         self.map.values().cloned().collect()
     }
+    /// [キー, 値] の配列
+    pub fn items(&self) -> Vec<Object> {
+        self.map.iter()
+            .map(|(key, value)| Object::Array(vec![self.key_object(key), value.clone()]))
+            .collect()
+    }
 
     pub fn len(&self) -> usize {
         self.map.len()
@@ -59,23 +90,36 @@ impl HashTbl {
     }
 
     pub fn insert(&mut self, name: String, value: Object) {
-        let key = if ! self.casecare { name.to_ascii_uppercase() } else { name };
+        let key = self.to_key(&name);
         let new = self.map.contains_key(&key);
         self.map.insert(key, value);
         if self.sort && ! new { // sort がtrueでかつ追加した場合はソートする
             self.map.sort_keys();
         }
     }
+    /// 数値や真偽値のキーは型を保持して追加する
+    /// キーとして使えない値の場合はfalseを返す
+    pub fn insert_key(&mut self, key: Object, value: Object) -> bool {
+        let Some(name) = Self::key_to_string(&key) else {
+            return false;
+        };
+        let normalized = self.to_key(&name);
+        if ! matches!(key, Object::String(_)) && ! self.map.contains_key(&normalized) {
+            self.key_objects.insert(normalized, key);
+        }
+        self.insert(name, value);
+        true
+    }
 
     pub fn get(&self, name: &String) -> Object {
-        let key = if ! self.casecare { name.to_ascii_uppercase() } else { name.to_string() };
+        let key = self.to_key(name);
         self.map.get(&key).unwrap_or(&Object::Empty).clone()
     }
     // hash[i, hash_key]
     pub fn get_key(&self, index: usize) -> Object {
         self.map.get_index(index).map_or(
             Object::Empty,
-            |(s, _)| Object::String(s.clone())
+            |(s, _)| self.key_object(s)
         )
     }
     // hash[i, hash_val]
@@ -87,18 +131,266 @@ impl HashTbl {
     }
     // hash[key, hash_exists]
     pub fn check(&self, name: String) -> Object {
-        let key = if ! self.casecare { name.to_ascii_uppercase() } else { name };
+        let key = self.to_key(&name);
         Object::Bool(self.map.contains_key(&key))
 
     }
     // hash[key, hash_remove]
     pub fn remove(&mut self, name: String) -> Object {
-        let key = if ! self.casecare { name.to_ascii_uppercase() } else { name };
+        let key = self.to_key(&name);
+        self.key_objects.remove(&key);
         let removed = self.map.shift_remove(&key).is_some();
         Object::Bool(removed)
     }
     // hash = hash_removeall
     pub fn clear(&mut self) {
         self.map.clear();
+        self.key_objects.clear();
+    }
+
+    /// JSONオブジェクトから連想配列を作る
+    /// ネストしたオブジェクトも連想配列になる
+    pub fn from_json(value: &JsonValue, sort: bool, casecare: bool) -> Option<Self> {
+        let JsonValue::Object(map) = value else {
+            return None;
+        };
+        let mut hash = Self::new(sort, casecare);
+        for (key, value) in map {
+            hash.insert(key.clone(), Self::json_to_object(value, sort, casecare));
+        }
+        Some(hash)
+    }
+    fn json_to_object(value: &JsonValue, sort: bool, casecare: bool) -> Object {
+        match value {
+            JsonValue::Object(_) => match Self::from_json(value, sort, casecare) {
+                Some(hash) => Object::HashTbl(Arc::new(Mutex::new(hash))),
+                None => Object::Empty,
+            },
+            JsonValue::Array(arr) => {
+                let arr = arr.iter()
+                    .map(|v| Self::json_to_object(v, sort, casecare))
+                    .collect();
+                Object::Array(arr)
+            },
+            value => value.clone().into(),
+        }
+    }
+    /// JSONオブジェクトに変換する
+    /// キーは文字列になる
+    pub fn to_json(&self) -> EvalResult<JsonValue> {
+        let map = self.map.iter()
+            .map(|(key, value)| Ok((key.clone(), value.clone().try_into()?)))
+            .collect::<EvalResult<Map<String, JsonValue>>>()?;
+        Ok(JsonValue::Object(map))
+    }
+
+    /// otherの内容を取り込む、双方が連想配列の値は再帰的にマージする
+    fn merge_from(&mut self, other: &HashTbl, overwrite: bool) {
+        for (key, value) in other.map.iter() {
+            let key = other.key_object(key);
+            let name = Self::key_to_string(&key).unwrap_or_default();
+            let current = self.map.get(&self.to_key(&name)).cloned();
+            match (current, value) {
+                (Some(Object::HashTbl(dst)), Object::HashTbl(src)) => {
+                    if ! Arc::ptr_eq(&dst, src) && let (Ok(mut dst), Ok(src)) = (dst.try_lock(), src.try_lock()) {
+                        dst.merge_from(&src, overwrite);
+                    }
+                },
+                (Some(_), _) if ! overwrite => {},
+                _ => {
+                    self.insert_key(key, value.clone());
+                },
+            }
+        }
+    }
+    /// 現在の順序で並べ替え済みのエントリに置き換える
+    fn reorder(&mut self, order: Vec<String>) {
+        let mut map = IndexMap::with_capacity(order.len());
+        for key in order {
+            if let Some(value) = self.map.shift_remove(&key) {
+                map.insert(key, value);
+            }
+        }
+        self.map = map;
+    }
+
+    pub fn invoke_method(mutex: &Arc<Mutex<HashTbl>>, evaluator: &mut Evaluator, name: &str, args: Vec<Object>) -> EvalResult<Object> {
+        let arg = |i: usize| args.get(i).cloned()
+            .ok_or(UError::new(UErrorKind::HashtblError, UErrorMessage::BuiltinArgRequiredAt(i + 1)));
+        let key_arg = |i: usize| {
+            let key = arg(i)?;
+            match Self::key_to_string(&key) {
+                Some(name) => Ok((key, name)),
+                None => Err(UError::new(UErrorKind::HashtblError, UErrorMessage::InvalidIndex(key))),
+            }
+        };
+        let func_arg = |i: usize| match arg(i)? {
+            Object::Function(f) |
+            Object::AnonFunc(f) => Ok(f),
+            o => Err(UError::new(UErrorKind::HashtblError, UErrorMessage::NotAFunction(o))),
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "keys" => Ok(Object::Array(mutex.lock().unwrap().keys())),
+            "values" => Ok(Object::Array(mutex.lock().unwrap().values())),
+            "items" => Ok(Object::Array(mutex.lock().unwrap().items())),
+            "get" => {
+                let (_, name) = key_arg(0)?;
+                let default = args.get(1).cloned().unwrap_or_default();
+                let hash = mutex.lock().unwrap();
+                let value = hash.map.get(&hash.to_key(&name)).cloned().unwrap_or(default);
+                Ok(value)
+            },
+            "setdefault" => {
+                let (key, name) = key_arg(0)?;
+                let default = args.get(1).cloned().unwrap_or_default();
+                let mut hash = mutex.lock().unwrap();
+                match hash.map.get(&hash.to_key(&name)) {
+                    Some(value) => Ok(value.clone()),
+                    None => {
+                        hash.insert_key(key, default.clone());
+                        Ok(default)
+                    },
+                }
+            },
+            "exists" => {
+                let (_, name) = key_arg(0)?;
+                Ok(mutex.lock().unwrap().check(name))
+            },
+            "remove" => {
+                let (_, name) = key_arg(0)?;
+                Ok(mutex.lock().unwrap().remove(name))
+            },
+            "merge" => {
+                let overwrite = args.get(1).is_none_or(|o| o.is_truthy());
+                match arg(0)? {
+                    Object::HashTbl(other) => if ! Arc::ptr_eq(mutex, &other) {
+                        let other = other.lock().unwrap().clone();
+                        mutex.lock().unwrap().merge_from(&other, overwrite);
+                    },
+                    Object::UObject(uo) => {
+                        let json = JsonValue::try_from(Object::UObject(uo.clone()))?;
+                        let mut hash = mutex.lock().unwrap();
+                        let Some(other) = Self::from_json(&json, hash.sort, hash.casecare) else {
+                            return Err(UError::new(UErrorKind::HashtblError, UErrorMessage::InvalidArgument(Object::UObject(uo))));
+                        };
+                        hash.merge_from(&other, overwrite);
+                    },
+                    o => return Err(UError::new(UErrorKind::HashtblError, UErrorMessage::InvalidArgument(o))),
+                }
+                Ok(Object::HashTbl(mutex.clone()))
+            },
+            "filter" => {
+                let func = func_arg(0)?;
+                // コールバック中に自身を操作できるようロックを解放しておく
+                let (items, sort, casecare) = {
+                    let hash = mutex.lock().unwrap();
+                    let items = hash.map.iter()
+                        .map(|(key, value)| (hash.key_object(key), value.clone()))
+                        .collect::<Vec<_>>();
+                    (items, hash.sort, hash.casecare)
+                };
+                let mut filtered = Self::new(sort, casecare);
+                for (key, value) in items {
+                    let result = func.invoke_with_values(evaluator, vec![key.clone(), value.clone()])?;
+                    if result.is_truthy() {
+                        filtered.insert_key(key, value);
+                    }
+                }
+                Ok(Object::HashTbl(Arc::new(Mutex::new(filtered))))
+            },
+            "sort_by" | "sortby" => {
+                let func = func_arg(0)?;
+                let desc = args.get(1).is_some_and(|o| o.is_truthy());
+                let items = {
+                    let hash = mutex.lock().unwrap();
+                    hash.map.iter()
+                        .map(|(key, value)| (key.clone(), hash.key_object(key), value.clone()))
+                        .collect::<Vec<_>>()
+                };
+                let mut sorted = Vec::with_capacity(items.len());
+                for (normalized, key, value) in items {
+                    let sort_key = func.invoke_with_values(evaluator, vec![key, value])?;
+                    sorted.push((normalized, sort_key));
+                }
+                sorted.sort_by(|(_, a), (_, b)| {
+                    let ord = a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal);
+                    if desc { ord.reverse() } else { ord }
+                });
+                let order = sorted.into_iter().map(|(key, _)| key).collect();
+                mutex.lock().unwrap().reorder(order);
+                Ok(Object::HashTbl(mutex.clone()))
+            },
+            "toobject" => {
+                let json = mutex.lock().unwrap().to_json()?;
+                Ok(Object::UObject(UObject::from(json)))
+            },
+            "tojson" => {
+                let pretty = args.first().is_some_and(|o| o.is_truthy());
+                let json = mutex.lock().unwrap().to_json()?;
+                let s = if pretty {
+                    serde_json::to_string_pretty(&json)
+                } else {
+                    serde_json::to_string(&json)
+                }.map_err(|e| UError::new(UErrorKind::HashtblError, UErrorMessage::Any(e.to_string())))?;
+                Ok(Object::String(s))
+            },
+            _ => Err(UError::new(UErrorKind::HashtblError, UErrorMessage::CanNotCallMethod(name.into()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_keys() {
+        let mut hash = HashTbl::new(false, false);
+        assert!(hash.insert_key(Object::Num(1.0), Object::String("one".into())));
+        assert!(hash.insert_key(Object::Bool(true), Object::Num(2.0)));
+        assert!(hash.insert_key(Object::String("a".into()), Object::Num(3.0)));
+        assert!(! hash.insert_key(Object::Empty, Object::Num(4.0)));
+        assert_eq!(hash.keys(), vec![Object::Num(1.0), Object::Bool(true), Object::String("A".into())]);
+        assert_eq!(hash.get(&"1".into()), Object::String("one".into()));
+        // 既存キーへの文字列での代入は元のキーの型を保つ
+        hash.insert("TRUE".into(), Object::Num(5.0));
+        assert_eq!(hash.get_key(1), Object::Bool(true));
+        hash.remove("1".into());
+        assert_eq!(hash.get_key(0), Object::Bool(true));
+    }
+
+    #[test]
+    fn test_json() {
+        let json: JsonValue = serde_json::from_str(r#"{"a": 1, "b": {"c": [true, {"d": null}]}}"#).unwrap();
+        let hash = HashTbl::from_json(&json, false, true).unwrap();
+        let Object::HashTbl(b) = hash.get(&"b".into()) else {
+            panic!("nested object should be a hashtbl");
+        };
+        let Object::Array(c) = b.lock().unwrap().get(&"c".into()) else {
+            panic!("array expected");
+        };
+        assert!(matches!(c[1], Object::HashTbl(_)));
+        assert_eq!(hash.to_json().unwrap(), json);
+        assert!(HashTbl::from_json(&JsonValue::Array(vec![]), false, false).is_none());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = HashTbl::new(false, false);
+        let mut nested = HashTbl::new(false, false);
+        nested.insert("x".into(), Object::Num(1.0));
+        a.insert("n".into(), Object::HashTbl(Arc::new(Mutex::new(nested))));
+        a.insert("k".into(), Object::Num(1.0));
+        let mut b = HashTbl::new(false, false);
+        let mut nested = HashTbl::new(false, false);
+        nested.insert("y".into(), Object::Num(2.0));
+        b.insert("n".into(), Object::HashTbl(Arc::new(Mutex::new(nested))));
+        b.insert("k".into(), Object::Num(2.0));
+        a.merge_from(&b, false);
+        assert_eq!(a.get(&"k".into()), Object::Num(1.0));
+        let Object::HashTbl(n) = a.get(&"n".into()) else {
+            panic!("hashtbl expected");
+        };
+        assert_eq!(n.lock().unwrap().keys(), vec![Object::String("X".into()), Object::String("Y".into())]);
     }
 }
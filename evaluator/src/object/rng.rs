@@ -0,0 +1,233 @@
+//! シード指定可能な乱数生成器
+//!
+//! 同じシードから作られたRngオブジェクトは常に同じ乱数列を返す\
+//! random関数が使う既定の乱数生成器もシードを指定して固定できる
+
+use super::Object;
+use crate::error::{UError, UErrorKind, UErrorMessage};
+
+use std::sync::{Arc, Mutex};
+use rand::{Rng, RngCore, SeedableRng};
+use rand::distributions::WeightedIndex;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+
+pub type RngResult<T> = Result<T, UError>;
+
+fn rng_error(message: UErrorMessage) -> UError {
+    UError::new(UErrorKind::RngError, message)
+}
+
+/// random関数が使う乱数生成器、Noneならスレッドローカルな乱数生成器を使う
+static DEFAULT_RNG: Mutex<Option<ChaCha8Rng>> = Mutex::new(None);
+
+/// 既定の乱数生成器のシードを設定する、Noneならシードなしに戻す
+pub fn set_default_seed(seed: Option<u64>) {
+    let mut rng = DEFAULT_RNG.lock().unwrap();
+    *rng = seed.map(ChaCha8Rng::seed_from_u64);
+}
+
+/// 既定の乱数生成器で処理を行う
+pub fn with_default_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    let mut guard = DEFAULT_RNG.lock().unwrap();
+    match guard.as_mut() {
+        Some(rng) => f(rng),
+        None => f(&mut rand::thread_rng()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct URng {
+    rng: Arc<Mutex<ChaCha8Rng>>,
+    seed: u64,
+}
+
+impl PartialEq for URng {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.rng, &other.rng)
+    }
+}
+
+impl std::fmt::Display for URng {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Rng({})", self.seed)
+    }
+}
+
+impl URng {
+    /// シードの最大値、数値型で正確に表せる範囲に収める
+    pub const MAX_SEED: u64 = (1 << 53) - 1;
+
+    /// シード未指定の場合はランダムなシードを用いる
+    pub fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| rand::thread_rng().gen_range(0..=Self::MAX_SEED));
+        let rng = ChaCha8Rng::seed_from_u64(seed);
+        Self { rng: Arc::new(Mutex::new(rng)), seed }
+    }
+
+    /// min以上max以下の整数
+    pub fn int(&self, min: i64, max: i64) -> RngResult<i64> {
+        if min > max {
+            return Err(rng_error(UErrorMessage::InvalidRandomRange(min.to_string(), max.to_string())));
+        }
+        Ok(self.rng.lock().unwrap().gen_range(min..=max))
+    }
+    /// min以上max未満の実数
+    pub fn float(&self, min: f64, max: f64) -> RngResult<f64> {
+        if min >= max || ! min.is_finite() || ! max.is_finite() {
+            return Err(rng_error(UErrorMessage::InvalidRandomRange(min.to_string(), max.to_string())));
+        }
+        Ok(self.rng.lock().unwrap().gen_range(min..max))
+    }
+    /// 正規分布に従う乱数 (ボックス=ミュラー法)
+    pub fn normal(&self, mean: f64, sd: f64) -> f64 {
+        let mut rng = self.rng.lock().unwrap();
+        // 0を避けるため (0, 1] の範囲にする
+        let u1 = 1.0 - rng.r#gen::<f64>();
+        let u2 = rng.r#gen::<f64>();
+        let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+        mean + sd * z
+    }
+    /// 要素をシャッフルした新しい配列を返す
+    pub fn shuffle<T: Clone>(&self, items: &[T]) -> Vec<T> {
+        let mut items = items.to_vec();
+        items.shuffle(&mut *self.rng.lock().unwrap());
+        items
+    }
+    /// 要素を一つ選ぶ、weightsがあれば重みに応じて選ぶ
+    pub fn choice<T: Clone>(&self, items: &[T], weights: Option<&[f64]>) -> RngResult<Option<T>> {
+        if items.is_empty() {
+            return Ok(None);
+        }
+        let mut rng = self.rng.lock().unwrap();
+        let index = match weights {
+            Some(weights) => {
+                if weights.len() != items.len() {
+                    return Err(rng_error(UErrorMessage::InvalidWeights(
+                        format!("{} != {}", weights.len(), items.len())
+                    )));
+                }
+                let dist = WeightedIndex::new(weights)
+                    .map_err(|e| rng_error(UErrorMessage::InvalidWeights(e.to_string())))?;
+                rng.sample(dist)
+            },
+            None => rng.gen_range(0..items.len()),
+        };
+        Ok(items.get(index).cloned())
+    }
+    /// 重複なしでn個取り出す
+    pub fn sample<T: Clone>(&self, items: &[T], n: usize) -> RngResult<Vec<T>> {
+        if n > items.len() {
+            return Err(rng_error(UErrorMessage::SampleSizeExceeded(n, items.len())));
+        }
+        let mut rng = self.rng.lock().unwrap();
+        let sampled = rand::seq::index::sample(&mut *rng, items.len(), n)
+            .into_iter()
+            .map(|i| items[i].clone())
+            .collect();
+        Ok(sampled)
+    }
+
+    pub fn get_property(&self, name: &str) -> RngResult<Object> {
+        match name.to_ascii_lowercase().as_str() {
+            "seed" => Ok((self.seed as f64).into()),
+            _ => Err(rng_error(UErrorMessage::InvalidMember(name.to_string()))),
+        }
+    }
+    pub fn invoke_method(&self, name: &str, args: Vec<Object>) -> RngResult<Object> {
+        let num_arg = |i: usize, default: Option<f64>| match args.get(i) {
+            None |
+            Some(Object::Empty) |
+            Some(Object::EmptyParam) => default.ok_or(rng_error(UErrorMessage::BuiltinArgRequiredAt(i + 1))),
+            Some(o) => o.as_f64(false).ok_or(rng_error(UErrorMessage::InvalidArgument(o.clone()))),
+        };
+        let array_arg = |i: usize| match args.get(i) {
+            Some(Object::Array(arr)) => Ok(arr.as_slice()),
+            Some(o) => Err(rng_error(UErrorMessage::InvalidArgument(o.clone()))),
+            None => Err(rng_error(UErrorMessage::BuiltinArgRequiredAt(i + 1))),
+        };
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "int" => {
+                let min = num_arg(0, None)? as i64;
+                let max = num_arg(1, None)? as i64;
+                (self.int(min, max)? as f64).into()
+            },
+            "float" => {
+                let min = num_arg(0, Some(0.0))?;
+                let max = num_arg(1, Some(1.0))?;
+                self.float(min, max)?.into()
+            },
+            "normal" => {
+                let mean = num_arg(0, Some(0.0))?;
+                let sd = num_arg(1, Some(1.0))?;
+                self.normal(mean, sd).into()
+            },
+            "shuffle" => Object::Array(self.shuffle(array_arg(0)?)),
+            "choice" => {
+                let items = array_arg(0)?;
+                let weights = match args.get(1) {
+                    None |
+                    Some(Object::Empty) |
+                    Some(Object::EmptyParam) => None,
+                    Some(_) => {
+                        let weights = array_arg(1)?.iter()
+                            .map(|o| o.as_f64(false).ok_or(rng_error(UErrorMessage::InvalidArgument(o.clone()))))
+                            .collect::<RngResult<Vec<_>>>()?;
+                        Some(weights)
+                    },
+                };
+                self.choice(items, weights.as_deref())?.unwrap_or_default()
+            },
+            "sample" => {
+                let items = array_arg(0)?;
+                let n = num_arg(1, None)?;
+                if n < 0.0 {
+                    return Err(rng_error(UErrorMessage::InvalidArgument(n.into())));
+                }
+                Object::Array(self.sample(items, n as usize)?)
+            },
+            _ => return Err(rng_error(UErrorMessage::InvalidMember(name.to_string()))),
+        };
+        Ok(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed() {
+        let a = URng::new(Some(42));
+        let b = URng::new(Some(42));
+        let seq_a = (0..10).map(|_| a.int(1, 100).unwrap()).collect::<Vec<_>>();
+        let seq_b = (0..10).map(|_| b.int(1, 100).unwrap()).collect::<Vec<_>>();
+        assert_eq!(seq_a, seq_b);
+        assert!(seq_a.iter().all(|n| (1..=100).contains(n)));
+        assert_eq!(a.shuffle(&[1, 2, 3, 4, 5]), b.shuffle(&[1, 2, 3, 4, 5]));
+        assert!(a.int(2, 1).is_err());
+        assert_eq!(a.int(3, 3).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_collections() {
+        let rng = URng::new(Some(1));
+        let items = [1, 2, 3, 4, 5];
+        let mut shuffled = rng.shuffle(&items);
+        shuffled.sort();
+        assert_eq!(shuffled, items);
+
+        let mut sampled = rng.sample(&items, 3).unwrap();
+        assert_eq!(sampled.len(), 3);
+        sampled.sort();
+        sampled.dedup();
+        assert_eq!(sampled.len(), 3);
+        assert!(rng.sample(&items, 6).is_err());
+
+        let weights = [0.0, 0.0, 1.0, 0.0, 0.0];
+        assert_eq!(rng.choice(&items, Some(&weights)).unwrap(), Some(3));
+        assert!(rng.choice(&items, Some(&[1.0])).is_err());
+        assert!(rng.choice(&items, Some(&[0.0; 5])).is_err());
+        assert_eq!(rng.choice::<i32>(&[], None).unwrap(), None);
+    }
+}
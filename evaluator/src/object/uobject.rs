@@ -1,6 +1,11 @@
+mod jsonpath;
+mod patch;
+mod schema;
+
 use super::Object;
 use crate::error::{UError,UErrorKind,UErrorMessage};
 use crate::EvalResult;
+use jsonpath::JsonPath;
 
 use std::ops::Deref;
 use std::sync::{Arc, RwLock};
@@ -115,6 +120,19 @@ impl UObject {
         };
         Ok(s)
     }
+    pub fn from_toml_str(toml: &str) -> UObjectResult<Self> {
+        let table = toml.parse::<toml::Table>()?;
+        let value = toml_to_json(toml::Value::Table(table));
+        Ok(Self::new(JYValue::Json(value)))
+    }
+    /// TOMLの最上位はテーブルである必要がある\
+    /// テーブル内のnullは出力せず、配列内のnullはエラーとする
+    pub fn to_toml_string(&self) -> Result<String, UObjectError> {
+        match json_to_toml(self.to_json_value())? {
+            Some(toml::Value::Table(table)) => Ok(toml::to_string_pretty(&table)?),
+            _ => Err(UObjectError("TOML root must be a table".into())),
+        }
+    }
     /// 任意のポインタを持った自身のクローンを作る
     pub fn clone_with_pointer(&self, pointer: Option<String>) -> Self {
         Self {
@@ -363,7 +381,169 @@ impl UObject {
         };
         Ok(values)
     }
-    pub fn invoke_method(&self, method: &str) -> EvalResult<Object> {
+    /// 自身の値をJsonValueとして複製する
+    fn to_json_value(&self) -> JsonValue {
+        let read = self.value.read().unwrap();
+        JsonValue::from(read.value_from_pointer(self.pointer.as_deref()))
+    }
+    /// 自身の値をJsonValueとして更新する\
+    /// YAMLの場合は更新後にYAMLに戻す
+    fn update_json<T, F>(&self, f: F) -> EvalResult<T>
+    where
+        F: FnOnce(&mut JsonValue) -> EvalResult<T>,
+    {
+        let not_found = || UError::new(
+            UErrorKind::UObjectError,
+            UErrorMessage::InvalidMemberOrIndex(self.pointer.clone().unwrap_or_default())
+        );
+        let mut write = self.value.write().unwrap();
+        match &mut *write {
+            JYValue::Json(root) => {
+                let value = match &self.pointer {
+                    Some(p) => root.pointer_mut(p).ok_or_else(not_found)?,
+                    None => root,
+                };
+                f(value)
+            },
+            JYValue::Yaml(root) => {
+                let value = match &self.pointer {
+                    Some(p) => root.pointer_mut(p).ok_or_else(not_found)?,
+                    None => root,
+                };
+                let mut json = JsonValue::from(JYValue::Yaml(value.clone()));
+                let result = f(&mut json)?;
+                *value = YamlValue::from(JYValue::Json(json));
+                Ok(result)
+            },
+        }
+    }
+    /// 自身からの相対ポインタが示す値をObjectにする
+    fn node_to_object(&self, pointer: String, value: &JsonValue) -> Object {
+        match value {
+            JsonValue::Array(_) |
+            JsonValue::Object(_) => {
+                let pointer = format!("{}{pointer}", self.pointer.as_deref().unwrap_or_default());
+                let pointer = (! pointer.is_empty()).then_some(pointer);
+                Object::UObject(self.clone_with_pointer(pointer))
+            },
+            value => value.clone().into(),
+        }
+    }
+    /// JSONPathまたはJSON Pointer (/から始まる場合) に該当する値を返す
+    pub fn query(&self, path: &str) -> EvalResult<Vec<Object>> {
+        let value = self.to_json_value();
+        let nodes = if path.is_empty() || path.starts_with('/') {
+            value.pointer(path)
+                .map(|v| vec![(path.to_string(), v)])
+                .unwrap_or_default()
+        } else {
+            JsonPath::parse(path)
+                .map_err(|e| UError::new(
+                    UErrorKind::UObjectError,
+                    UErrorMessage::InvalidJsonPath(path.to_string(), e)
+                ))?
+                .query(&value)
+        };
+        let objects = nodes.into_iter()
+            .map(|(pointer, v)| self.node_to_object(pointer, v))
+            .collect();
+        Ok(objects)
+    }
+    /// メンバー名、配列のインデックス、またはJSON Pointerで指定した値を削除する\
+    /// 削除できればtrue
+    pub fn delete(&self, target: &Object) -> EvalResult<bool> {
+        self.update_json(|value| {
+            let removed = match (target, value) {
+                (Object::String(pointer), value) if pointer.starts_with('/') => {
+                    let tokens = patch::parse_pointer(pointer)
+                        .map_err(|e| UError::new(UErrorKind::UObjectError, UErrorMessage::InvalidJsonPointer(e)))?;
+                    patch::remove(value, &tokens).is_ok()
+                },
+                (Object::String(key), JsonValue::Object(map)) => {
+                    patch::find_key(map, key)
+                        .and_then(|key| map.shift_remove(&key))
+                        .is_some()
+                },
+                (Object::Num(n), JsonValue::Array(arr)) => {
+                    if *n >= 0.0 && (*n as usize) < arr.len() {
+                        arr.remove(*n as usize);
+                        true
+                    } else {
+                        false
+                    }
+                },
+                _ => false,
+            };
+            Ok(removed)
+        })
+    }
+    /// 配列の指定位置に値を挿入する\
+    /// 成功時true
+    pub fn insert(&self, index: f64, new_value: Object) -> EvalResult<bool> {
+        let new_value = JsonValue::try_from(new_value)?;
+        self.update_json(|value| {
+            match value {
+                JsonValue::Array(arr) if index >= 0.0 && index as usize <= arr.len() => {
+                    arr.insert(index as usize, new_value);
+                    Ok(true)
+                },
+                _ => Ok(false),
+            }
+        })
+    }
+    /// 値を複製した新たなUObjectを作る
+    pub fn deep_clone(&self) -> Self {
+        let read = self.value.read().unwrap();
+        let value = match read.value_from_pointer(self.pointer.as_deref()) {
+            JYValueRef::Json(value) => JYValue::Json(value.clone()),
+            JYValueRef::Yaml(value) => JYValue::Yaml(value.clone()),
+        };
+        Self::new(value)
+    }
+    /// otherを再帰的にマージする
+    pub fn merge(&self, other: Object, concat: bool) -> EvalResult<()> {
+        let source = JsonValue::try_from(other)?;
+        self.update_json(|value| {
+            patch::merge(value, source, concat);
+            Ok(())
+        })
+    }
+    /// otherとの差分をJSON Patchとして返す
+    pub fn diff(&self, other: Object) -> EvalResult<Self> {
+        let to = JsonValue::try_from(other)?;
+        let ops = patch::diff(&self.to_json_value(), &to);
+        Ok(JsonValue::Array(ops).into())
+    }
+    /// JSON Patchを適用する、失敗した場合は何も変更しない
+    pub fn patch(&self, ops: Object) -> EvalResult<()> {
+        let ops = match JsonValue::try_from(ops)? {
+            JsonValue::Array(ops) => ops,
+            op => vec![op],
+        };
+        self.update_json(|value| {
+            patch::apply(value, &ops)
+                .map_err(|(i, e)| UError::new(UErrorKind::UObjectError, UErrorMessage::JsonPatchFailed(i, e)))
+        })
+    }
+    /// JSON Schemaで検証し、違反をUObjectの配列で返す
+    pub fn validate(&self, schema: &UObject) -> EvalResult<Vec<Object>> {
+        let violations = schema::validate(&self.to_json_value(), &schema.to_json_value())
+            .map_err(|e| UError::new(UErrorKind::UObjectError, UErrorMessage::InvalidJsonSchema(e)))?;
+        let violations = violations.into_iter()
+            .map(|v| {
+                let value = serde_json::json!({
+                    "path": v.pointer,
+                    "keyword": v.keyword,
+                    "message": v.message.to_string(),
+                });
+                Object::UObject(value.into())
+            })
+            .collect();
+        Ok(violations)
+    }
+    pub fn invoke_method(&self, method: &str, args: Vec<Object>) -> EvalResult<Object> {
+        let arg = |i: usize| args.get(i).cloned()
+            .ok_or(UError::new(UErrorKind::UObjectError, UErrorMessage::BuiltinArgRequiredAt(i + 1)));
         match method.to_ascii_lowercase().as_str() {
             "keys" => {
                 let keys = self.keys()?;
@@ -373,6 +553,41 @@ impl UObject {
                 let values = self.values()?;
                 Ok(Object::Array(values))
             },
+            "query" => {
+                let path = arg(0)?.to_string();
+                let found = self.query(&path)?;
+                Ok(Object::Array(found))
+            },
+            "queryfirst" => {
+                let path = arg(0)?.to_string();
+                let found = self.query(&path)?;
+                Ok(found.into_iter().next().unwrap_or(Object::Empty))
+            },
+            "delete" => {
+                let removed = self.delete(&arg(0)?)?;
+                Ok(removed.into())
+            },
+            "insert" => {
+                let index = arg(0)?;
+                let index = index.as_f64(false)
+                    .ok_or(UError::new(UErrorKind::UObjectError, UErrorMessage::InvalidMemberOrIndex(index.to_string())))?;
+                let inserted = self.insert(index, arg(1)?)?;
+                Ok(inserted.into())
+            },
+            "clone" => Ok(Object::UObject(self.deep_clone())),
+            "merge" => {
+                let concat = args.get(1).is_some_and(|o| o.is_truthy());
+                self.merge(arg(0)?, concat)?;
+                Ok(Object::UObject(self.clone()))
+            },
+            "diff" => {
+                let ops = self.diff(arg(0)?)?;
+                Ok(Object::UObject(ops))
+            },
+            "patch" => {
+                self.patch(arg(0)?)?;
+                Ok(Object::UObject(self.clone()))
+            },
             _ => Err(UError::new(UErrorKind::UObjectError, UErrorMessage::CanNotCallMethod(method.into()))),
         }
     }
@@ -397,6 +612,48 @@ impl PartialEq for UObject {
     }
 }
 
+fn toml_to_json(value: toml::Value) -> JsonValue {
+    match value {
+        toml::Value::String(s) => JsonValue::String(s),
+        toml::Value::Integer(i) => JsonValue::from(i),
+        // NaN, infはnullになる
+        toml::Value::Float(f) => JsonValue::from(f),
+        toml::Value::Boolean(b) => JsonValue::Bool(b),
+        toml::Value::Datetime(dt) => JsonValue::String(dt.to_string()),
+        toml::Value::Array(arr) => JsonValue::Array(arr.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => JsonValue::Object(
+            table.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect()
+        ),
+    }
+}
+fn json_to_toml(value: JsonValue) -> Result<Option<toml::Value>, UObjectError> {
+    let value = match value {
+        JsonValue::Null => return Ok(None),
+        JsonValue::Bool(b) => toml::Value::Boolean(b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => toml::Value::Integer(i),
+            None => toml::Value::Float(n.as_f64().unwrap_or_default()),
+        },
+        JsonValue::String(s) => toml::Value::String(s),
+        JsonValue::Array(arr) => {
+            let arr = arr.into_iter()
+                .map(|v| json_to_toml(v)?.ok_or(UObjectError("null in array can not be converted to TOML".into())))
+                .collect::<Result<_, _>>()?;
+            toml::Value::Array(arr)
+        },
+        JsonValue::Object(map) => {
+            let mut table = toml::Table::new();
+            for (k, v) in map {
+                if let Some(v) = json_to_toml(v)? {
+                    table.insert(k, v);
+                }
+            }
+            toml::Value::Table(table)
+        },
+    };
+    Ok(Some(value))
+}
+
 type UObjectResult<T> = Result<T, UObjectError>;
 pub struct UObjectError(String);
 impl<E: std::error::Error> From<E> for UObjectError {
@@ -705,7 +962,11 @@ impl TryFrom<Object> for JYValue {
                     .map(|o| o.try_into())
                     .collect::<Result<_, Self::Error>>()?;
                 Ok(JYValue::Json(JsonValue::Array(arr)))
-            }
+            },
+            Object::HashTbl(hash) => {
+                let json = Object::HashTbl(hash).try_into()?;
+                Ok(JYValue::Json(json))
+            },
             o => Err(UError::new(
                 UErrorKind::UObjectError,
                 UErrorMessage::CanNotConvertToUObject(o)
@@ -797,7 +1058,17 @@ impl TryFrom<Object> for JsonValue {
                     .map(|o| o.try_into())
                     .collect::<Result<_, Self::Error>>()?;
                 Ok(JsonValue::Array(vec))
-            }
+            },
+            Object::HashTbl(mutex) => {
+                // 自身を含む連想配列はロック済みのため変換できない
+                let Ok(hash) = mutex.try_lock() else {
+                    return Err(UError::new(
+                        UErrorKind::UObjectError,
+                        UErrorMessage::CanNotConvertToUObject(Object::HashTbl(mutex.clone()))
+                    ));
+                };
+                hash.to_json()
+            },
             o => Err(UError::new(
                 UErrorKind::UObjectError,
                 UErrorMessage::CanNotConvertToUObject(o)
@@ -0,0 +1,604 @@
+//! JSONPath
+//!
+//! 対応する構文
+//! - `$`: ルート、`@`: フィルタで評価中の要素
+//! - `.name`, `['name']`: メンバー
+//! - `[0]`, `[-1]`: 配列の要素、負数は末尾から数える
+//! - `.*`, `[*]`: すべての子要素
+//! - `..name`, `..*`: 子孫要素
+//! - `[0,2]`, `['a','b']`: 複数指定
+//! - `[1:3]`, `[::2]`: 配列のスライス
+//! - `[?(@.price < 10 && @.tags)]`: フィルタ、比較演算子は `== != < <= > >=`、論理演算子は `&& || !`
+//!
+//! メンバー名はまず大文字小文字を区別して探し、なければ区別せずに探す
+
+use serde_json::Value as JsonValue;
+
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Child(Vec<Selector>),
+    Descendant(Vec<Selector>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Name(String),
+    Index(i64),
+    Wildcard,
+    Slice(Option<i64>, Option<i64>, Option<i64>),
+    Filter(Filter),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Or(Box<Filter>, Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Compare(Operand, Comparison, Operand),
+    /// パスに該当する要素があるかどうか
+    Exists(Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    /// @ から始まるパス
+    Current(Vec<Segment>),
+    /// $ から始まるパス
+    Root(Vec<Segment>),
+    Literal(JsonValue),
+}
+
+/// 該当した要素とそのJSON Pointer
+pub type Node<'a> = (String, &'a JsonValue);
+
+impl JsonPath {
+    /// JSONPath文字列を解釈する、$ は省略可能
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let mut parser = Parser::new(path);
+        parser.skip_ws();
+        parser.eat('$');
+        let segments = parser.segments()?;
+        parser.skip_ws();
+        match parser.peek() {
+            None => Ok(Self { segments }),
+            Some(c) => Err(parser.unexpected(c)),
+        }
+    }
+    /// 該当する要素をすべて返す
+    pub fn query<'a>(&self, root: &'a JsonValue) -> Vec<Node<'a>> {
+        apply_segments(&self.segments, vec![(String::new(), root)], root)
+    }
+}
+
+/// JSON Pointerのトークンをエスケープする
+pub fn escape_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+fn apply_segments<'a>(segments: &[Segment], mut nodes: Vec<Node<'a>>, root: &'a JsonValue) -> Vec<Node<'a>> {
+    for segment in segments {
+        let mut next = vec![];
+        match segment {
+            Segment::Child(selectors) => {
+                for (pointer, value) in &nodes {
+                    for selector in selectors {
+                        select(selector, pointer, value, root, &mut next);
+                    }
+                }
+            },
+            Segment::Descendant(selectors) => {
+                let mut descendants = vec![];
+                for (pointer, value) in &nodes {
+                    collect_descendants(pointer.clone(), value, &mut descendants);
+                }
+                for (pointer, value) in &descendants {
+                    for selector in selectors {
+                        select(selector, pointer, value, root, &mut next);
+                    }
+                }
+            },
+        }
+        nodes = next;
+    }
+    nodes
+}
+
+/// 自身を含む子孫要素を深さ優先で集める
+fn collect_descendants<'a>(pointer: String, value: &'a JsonValue, out: &mut Vec<Node<'a>>) {
+    out.push((pointer.clone(), value));
+    for (child_pointer, child) in children(&pointer, value) {
+        collect_descendants(child_pointer, child, out);
+    }
+}
+
+fn children<'a>(pointer: &str, value: &'a JsonValue) -> Vec<Node<'a>> {
+    match value {
+        JsonValue::Array(arr) => arr.iter()
+            .enumerate()
+            .map(|(i, v)| (format!("{pointer}/{i}"), v))
+            .collect(),
+        JsonValue::Object(map) => map.iter()
+            .map(|(k, v)| (format!("{pointer}/{}", escape_token(k)), v))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn select<'a>(selector: &Selector, pointer: &str, value: &'a JsonValue, root: &'a JsonValue, out: &mut Vec<Node<'a>>) {
+    match selector {
+        Selector::Name(name) => {
+            if let JsonValue::Object(map) = value {
+                let found = map.get_key_value(name)
+                    .or_else(|| map.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)));
+                if let Some((key, v)) = found {
+                    out.push((format!("{pointer}/{}", escape_token(key)), v));
+                }
+            }
+        },
+        Selector::Index(index) => {
+            if let JsonValue::Array(arr) = value {
+                let i = if *index < 0 { arr.len() as i64 + index } else { *index };
+                if let Some(v) = usize::try_from(i).ok().and_then(|i| arr.get(i)) {
+                    out.push((format!("{pointer}/{i}"), v));
+                }
+            }
+        },
+        Selector::Wildcard => out.extend(children(pointer, value)),
+        Selector::Slice(start, end, step) => {
+            if let JsonValue::Array(arr) = value {
+                for i in slice_indexes(arr.len() as i64, *start, *end, step.unwrap_or(1)) {
+                    out.push((format!("{pointer}/{i}"), &arr[i]));
+                }
+            }
+        },
+        Selector::Filter(filter) => {
+            for (child_pointer, child) in children(pointer, value) {
+                if filter.test(child, root) {
+                    out.push((child_pointer, child));
+                }
+            }
+        },
+    }
+}
+
+fn slice_indexes(len: i64, start: Option<i64>, end: Option<i64>, step: i64) -> Vec<usize> {
+    let normalize = |i: i64| if i < 0 { len + i } else { i };
+    let mut indexes = vec![];
+    match step.cmp(&0) {
+        Ordering::Greater => {
+            let lower = normalize(start.unwrap_or(0)).clamp(0, len);
+            let upper = normalize(end.unwrap_or(len)).clamp(0, len);
+            let mut i = lower;
+            while i < upper {
+                indexes.push(i as usize);
+                i += step;
+            }
+        },
+        Ordering::Less => {
+            let upper = normalize(start.unwrap_or(len - 1)).clamp(-1, len - 1);
+            let lower = match end {
+                Some(end) => normalize(end).clamp(-1, len - 1),
+                None => -1,
+            };
+            let mut i = upper;
+            while i > lower {
+                indexes.push(i as usize);
+                i += step;
+            }
+        },
+        Ordering::Equal => {},
+    }
+    indexes
+}
+
+impl Filter {
+    fn test(&self, current: &JsonValue, root: &JsonValue) -> bool {
+        match self {
+            Filter::Or(left, right) => left.test(current, root) || right.test(current, root),
+            Filter::And(left, right) => left.test(current, root) && right.test(current, root),
+            Filter::Not(filter) => ! filter.test(current, root),
+            Filter::Exists(operand) => ! operand.values(current, root).is_empty(),
+            Filter::Compare(left, cmp, right) => {
+                // 比較対象は単一の値でなければならない
+                let left = left.single(current, root);
+                let right = right.single(current, root);
+                compare(left, cmp, right)
+            },
+        }
+    }
+}
+
+impl Operand {
+    fn values<'a>(&'a self, current: &'a JsonValue, root: &'a JsonValue) -> Vec<&'a JsonValue> {
+        match self {
+            Operand::Current(segments) => apply_segments(segments, vec![(String::new(), current)], root)
+                .into_iter().map(|(_, v)| v).collect(),
+            Operand::Root(segments) => apply_segments(segments, vec![(String::new(), root)], root)
+                .into_iter().map(|(_, v)| v).collect(),
+            Operand::Literal(value) => vec![value],
+        }
+    }
+    fn single<'a>(&'a self, current: &'a JsonValue, root: &'a JsonValue) -> Option<&'a JsonValue> {
+        let values = self.values(current, root);
+        match values.len() {
+            1 => values.into_iter().next(),
+            _ => None,
+        }
+    }
+}
+
+fn compare(left: Option<&JsonValue>, cmp: &Comparison, right: Option<&JsonValue>) -> bool {
+    let ordering = match (left, right) {
+        (None, None) => Some(Ordering::Equal),
+        (Some(JsonValue::Number(l)), Some(JsonValue::Number(r))) => {
+            l.as_f64().zip(r.as_f64()).and_then(|(l, r)| l.partial_cmp(&r))
+        },
+        (Some(JsonValue::String(l)), Some(JsonValue::String(r))) => Some(l.cmp(r)),
+        (Some(l), Some(r)) if l == r => Some(Ordering::Equal),
+        _ => None,
+    };
+    match cmp {
+        Comparison::Eq => ordering == Some(Ordering::Equal),
+        Comparison::Ne => ordering != Some(Ordering::Equal),
+        Comparison::Lt => ordering == Some(Ordering::Less),
+        Comparison::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        Comparison::Gt => ordering == Some(Ordering::Greater),
+        Comparison::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(path: &str) -> Self {
+        Self { chars: path.chars().collect(), pos: 0 }
+    }
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn eat_str(&mut self, s: &str) -> bool {
+        let len = s.chars().count();
+        let matched = self.chars.get(self.pos..self.pos + len)
+            .is_some_and(|chars| chars.iter().copied().eq(s.chars()));
+        if matched {
+            self.pos += len;
+        }
+        matched
+    }
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_ws();
+        if self.eat(c) {
+            Ok(())
+        } else {
+            match self.peek() {
+                Some(found) => Err(self.unexpected(found)),
+                None => Err(format!("'{c}' expected")),
+            }
+        }
+    }
+    fn skip_ws(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+    fn unexpected(&self, c: char) -> String {
+        format!("unexpected '{c}' at {}", self.pos + 1)
+    }
+    fn is_name_char(c: char) -> bool {
+        c.is_alphanumeric() || c == '_' || ! c.is_ascii()
+    }
+
+    fn segments(&mut self) -> Result<Vec<Segment>, String> {
+        let mut segments = vec![];
+        // $ を省略した場合は先頭のメンバー名を許す
+        if self.pos == 0 && self.peek().is_some_and(Self::is_name_char) {
+            segments.push(Segment::Child(vec![Selector::Name(self.name())]));
+        }
+        loop {
+            if self.eat_str("..") {
+                let selectors = match self.peek() {
+                    Some('[') => self.bracket()?,
+                    Some('*') => {
+                        self.pos += 1;
+                        vec![Selector::Wildcard]
+                    },
+                    _ => vec![Selector::Name(self.dot_name()?)],
+                };
+                segments.push(Segment::Descendant(selectors));
+            } else if self.eat('.') {
+                let selector = if self.eat('*') {
+                    Selector::Wildcard
+                } else {
+                    Selector::Name(self.dot_name()?)
+                };
+                segments.push(Segment::Child(vec![selector]));
+            } else if self.peek() == Some('[') {
+                let selectors = self.bracket()?;
+                segments.push(Segment::Child(selectors));
+            } else {
+                break;
+            }
+        }
+        Ok(segments)
+    }
+    fn name(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(Self::is_name_char) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+    fn dot_name(&mut self) -> Result<String, String> {
+        let name = self.name();
+        if name.is_empty() {
+            match self.peek() {
+                Some(c) => Err(self.unexpected(c)),
+                None => Err("member name expected".into()),
+            }
+        } else {
+            Ok(name)
+        }
+    }
+    fn bracket(&mut self) -> Result<Vec<Selector>, String> {
+        self.expect('[')?;
+        let mut selectors = vec![];
+        loop {
+            self.skip_ws();
+            selectors.push(self.selector()?);
+            self.skip_ws();
+            if self.eat(']') {
+                break;
+            }
+            self.expect(',')?;
+        }
+        Ok(selectors)
+    }
+    fn selector(&mut self) -> Result<Selector, String> {
+        match self.peek() {
+            Some('\'' | '"') => Ok(Selector::Name(self.string()?)),
+            Some('*') => {
+                self.pos += 1;
+                Ok(Selector::Wildcard)
+            },
+            Some('?') => {
+                self.pos += 1;
+                Ok(Selector::Filter(self.filter_or()?))
+            },
+            Some(_) => {
+                let start = self.integer()?;
+                self.skip_ws();
+                if self.eat(':') {
+                    self.skip_ws();
+                    let end = self.integer()?;
+                    self.skip_ws();
+                    let step = if self.eat(':') {
+                        self.skip_ws();
+                        self.integer()?
+                    } else {
+                        None
+                    };
+                    Ok(Selector::Slice(start, end, step))
+                } else {
+                    start.map(Selector::Index).ok_or("index expected".into())
+                }
+            },
+            None => Err("']' expected".into()),
+        }
+    }
+    /// 整数、なければNone
+    fn integer(&mut self) -> Result<Option<i64>, String> {
+        let start = self.pos;
+        self.eat('-');
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let s = self.chars[start..self.pos].iter().collect::<String>();
+        if s.is_empty() {
+            Ok(None)
+        } else {
+            s.parse().map(Some).map_err(|_| format!("invalid number: {s}"))
+        }
+    }
+    fn string(&mut self) -> Result<String, String> {
+        let Some(quote) = self.peek() else {
+            return Err("string expected".into());
+        };
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('r') => s.push('\r'),
+                        Some(c) => s.push(c),
+                        None => return Err("unterminated string".into()),
+                    }
+                    self.pos += 1;
+                },
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    break;
+                },
+                Some(c) => {
+                    s.push(c);
+                    self.pos += 1;
+                },
+                None => return Err("unterminated string".into()),
+            }
+        }
+        Ok(s)
+    }
+
+    fn filter_or(&mut self) -> Result<Filter, String> {
+        let mut left = self.filter_and()?;
+        loop {
+            self.skip_ws();
+            if self.eat_str("||") {
+                let right = self.filter_and()?;
+                left = Filter::Or(Box::new(left), Box::new(right));
+            } else {
+                return Ok(left);
+            }
+        }
+    }
+    fn filter_and(&mut self) -> Result<Filter, String> {
+        let mut left = self.filter_unary()?;
+        loop {
+            self.skip_ws();
+            if self.eat_str("&&") {
+                let right = self.filter_unary()?;
+                left = Filter::And(Box::new(left), Box::new(right));
+            } else {
+                return Ok(left);
+            }
+        }
+    }
+    fn filter_unary(&mut self) -> Result<Filter, String> {
+        self.skip_ws();
+        if self.peek() == Some('!') && self.peek_at(1) != Some('=') {
+            self.pos += 1;
+            let filter = self.filter_unary()?;
+            return Ok(Filter::Not(Box::new(filter)));
+        }
+        if self.eat('(') {
+            let filter = self.filter_or()?;
+            self.expect(')')?;
+            return Ok(filter);
+        }
+        let left = self.operand()?;
+        self.skip_ws();
+        let cmp = if self.eat_str("==") {
+            Comparison::Eq
+        } else if self.eat_str("!=") {
+            Comparison::Ne
+        } else if self.eat_str("<=") {
+            Comparison::Le
+        } else if self.eat_str(">=") {
+            Comparison::Ge
+        } else if self.eat('<') {
+            Comparison::Lt
+        } else if self.eat('>') {
+            Comparison::Gt
+        } else {
+            return match left {
+                Operand::Literal(_) => Err("comparison expected".into()),
+                path => Ok(Filter::Exists(path)),
+            };
+        };
+        self.skip_ws();
+        let right = self.operand()?;
+        Ok(Filter::Compare(left, cmp, right))
+    }
+    fn operand(&mut self) -> Result<Operand, String> {
+        self.skip_ws();
+        match self.peek() {
+            Some('@') => {
+                self.pos += 1;
+                Ok(Operand::Current(self.segments()?))
+            },
+            Some('$') => {
+                self.pos += 1;
+                Ok(Operand::Root(self.segments()?))
+            },
+            Some('\'' | '"') => Ok(Operand::Literal(JsonValue::String(self.string()?))),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                self.pos += 1;
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-')) {
+                    self.pos += 1;
+                }
+                let s = self.chars[start..self.pos].iter().collect::<String>();
+                let number = serde_json::from_str::<JsonValue>(&s)
+                    .map_err(|_| format!("invalid number: {s}"))?;
+                Ok(Operand::Literal(number))
+            },
+            Some(_) => {
+                if self.eat_str("true") {
+                    Ok(Operand::Literal(JsonValue::Bool(true)))
+                } else if self.eat_str("false") {
+                    Ok(Operand::Literal(JsonValue::Bool(false)))
+                } else if self.eat_str("null") {
+                    Ok(Operand::Literal(JsonValue::Null))
+                } else {
+                    Err(self.unexpected(self.peek().unwrap_or_default()))
+                }
+            },
+            None => Err("operand expected".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pointers(path: &str, value: &JsonValue) -> Vec<String> {
+        JsonPath::parse(path).unwrap()
+            .query(value)
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect()
+    }
+
+    #[test]
+    fn test_jsonpath() {
+        let value = json!({
+            "store": {
+                "book": [
+                    {"title": "a", "price": 8, "isbn": "1"},
+                    {"title": "b", "price": 12},
+                    {"title": "c/d", "price": 20, "isbn": "2"}
+                ],
+                "bicycle": {"price": 100}
+            }
+        });
+        assert_eq!(pointers("$.store.book[0].title", &value), vec!["/store/book/0/title"]);
+        assert_eq!(pointers("store.Book[-1]", &value), vec!["/store/book/2"]);
+        assert_eq!(pointers("$..price", &value).len(), 4);
+        assert_eq!(pointers("$.store.book[?(@.price < 10 || @.price >= 20)].title", &value),
+            vec!["/store/book/0/title", "/store/book/2/title"]);
+        assert_eq!(pointers("$.store.book[?(@.isbn && !(@.price > 10))]", &value), vec!["/store/book/0"]);
+        assert_eq!(pointers("$.store.book[::-2]", &value), vec!["/store/book/2", "/store/book/0"]);
+        assert_eq!(pointers("$.store.book[0,'x',1:2]", &value), vec!["/store/book/0", "/store/book/1"]);
+        assert_eq!(pointers("$.store.*", &value), vec!["/store/book", "/store/bicycle"]);
+        assert_eq!(pointers("$.store.book[?(@.title == 'c/d')]", &value), vec!["/store/book/2"]);
+        assert_eq!(value.pointer("/store/book/2/title"), Some(&json!("c/d")));
+        assert!(JsonPath::parse("$.store[").is_err());
+        assert!(JsonPath::parse("$.store.book[?(@.price <)]").is_err());
+    }
+}
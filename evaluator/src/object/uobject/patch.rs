@@ -0,0 +1,268 @@
+//! JSON Pointer (RFC 6901) による要素操作と JSON Patch (RFC 6902)、およびマージ
+
+use super::jsonpath::escape_token;
+
+use serde_json::{json, Map, Value as JsonValue};
+
+pub type PatchResult<T> = Result<T, String>;
+
+/// JSON Pointerをトークンに分解する
+pub fn parse_pointer(pointer: &str) -> PatchResult<Vec<String>> {
+    if pointer.is_empty() {
+        return Ok(vec![]);
+    }
+    match pointer.strip_prefix('/') {
+        Some(rest) => {
+            let tokens = rest.split('/')
+                .map(|token| token.replace("~1", "/").replace("~0", "~"))
+                .collect();
+            Ok(tokens)
+        },
+        None => Err(format!("invalid pointer: {pointer}")),
+    }
+}
+
+fn array_index(token: &str, len: usize, allow_end: bool) -> PatchResult<usize> {
+    let index = match token {
+        "-" if allow_end => len,
+        _ => token.parse::<usize>().map_err(|_| format!("invalid array index: {token}"))?,
+    };
+    let max = if allow_end { len } else { len.saturating_sub(1) };
+    if (allow_end || len > 0) && index <= max {
+        Ok(index)
+    } else {
+        Err(format!("index out of range: {token}"))
+    }
+}
+
+fn get<'a>(value: &'a JsonValue, tokens: &[String]) -> PatchResult<&'a JsonValue> {
+    tokens.iter().try_fold(value, |current, token| {
+        let next = match current {
+            JsonValue::Object(map) => map.get(token),
+            JsonValue::Array(arr) => token.parse::<usize>().ok().and_then(|i| arr.get(i)),
+            _ => None,
+        };
+        next.ok_or(format!("path not found: /{}", tokens.join("/")))
+    })
+}
+
+fn get_mut<'a>(value: &'a mut JsonValue, tokens: &[String]) -> PatchResult<&'a mut JsonValue> {
+    let path = tokens.join("/");
+    tokens.iter().try_fold(value, |current, token| {
+        let next = match current {
+            JsonValue::Object(map) => map.get_mut(token),
+            JsonValue::Array(arr) => token.parse::<usize>().ok().and_then(|i| arr.get_mut(i)),
+            _ => None,
+        };
+        next.ok_or(format!("path not found: /{path}"))
+    })
+}
+
+/// 指定位置に値を追加する、配列であれば挿入となる
+pub fn add(target: &mut JsonValue, tokens: &[String], value: JsonValue) -> PatchResult<()> {
+    let Some((last, parent)) = tokens.split_last() else {
+        *target = value;
+        return Ok(());
+    };
+    match get_mut(target, parent)? {
+        JsonValue::Object(map) => {
+            map.insert(last.clone(), value);
+            Ok(())
+        },
+        JsonValue::Array(arr) => {
+            let index = array_index(last, arr.len(), true)?;
+            arr.insert(index, value);
+            Ok(())
+        },
+        _ => Err(format!("parent is not a container: /{}", parent.join("/"))),
+    }
+}
+
+/// 指定位置の値を取り除いて返す
+pub fn remove(target: &mut JsonValue, tokens: &[String]) -> PatchResult<JsonValue> {
+    let Some((last, parent)) = tokens.split_last() else {
+        return Err("root can not be removed".into());
+    };
+    match get_mut(target, parent)? {
+        JsonValue::Object(map) => map.shift_remove(last)
+            .ok_or(format!("path not found: /{}", tokens.join("/"))),
+        JsonValue::Array(arr) => {
+            let index = array_index(last, arr.len(), false)?;
+            Ok(arr.remove(index))
+        },
+        _ => Err(format!("path not found: /{}", tokens.join("/"))),
+    }
+}
+
+fn str_member<'a>(op: &'a Map<String, JsonValue>, name: &str) -> PatchResult<&'a str> {
+    op.get(name)
+        .and_then(|v| v.as_str())
+        .ok_or(format!("'{name}' is required"))
+}
+
+fn apply_operation(target: &mut JsonValue, op: &JsonValue) -> PatchResult<()> {
+    let op = op.as_object().ok_or("operation must be an object".to_string())?;
+    let path = parse_pointer(str_member(op, "path")?)?;
+    let value = || op.get("value").cloned().ok_or("'value' is required".to_string());
+    match str_member(op, "op")? {
+        "add" => add(target, &path, value()?),
+        "remove" => remove(target, &path).map(|_| ()),
+        "replace" => {
+            let old = get_mut(target, &path)?;
+            *old = value()?;
+            Ok(())
+        },
+        "move" => {
+            let from = parse_pointer(str_member(op, "from")?)?;
+            if path.len() > from.len() && path.starts_with(&from) {
+                return Err("can not move a value into itself".into());
+            }
+            let moved = remove(target, &from)?;
+            add(target, &path, moved)
+        },
+        "copy" => {
+            let from = parse_pointer(str_member(op, "from")?)?;
+            let copied = get(target, &from)?.clone();
+            add(target, &path, copied)
+        },
+        "test" => {
+            if get(target, &path)? == &value()? {
+                Ok(())
+            } else {
+                Err(format!("test failed: /{}", path.join("/")))
+            }
+        },
+        other => Err(format!("unknown operation: {other}")),
+    }
+}
+
+/// JSON Patchを適用する\
+/// いずれかの操作が失敗した場合は何も変更せず、失敗した操作の位置と理由を返す
+pub fn apply(target: &mut JsonValue, ops: &[JsonValue]) -> Result<(), (usize, String)> {
+    let mut work = target.clone();
+    for (i, op) in ops.iter().enumerate() {
+        apply_operation(&mut work, op).map_err(|e| (i, e))?;
+    }
+    *target = work;
+    Ok(())
+}
+
+/// fromをtoにするためのJSON Patchを作る
+pub fn diff(from: &JsonValue, to: &JsonValue) -> Vec<JsonValue> {
+    let mut ops = vec![];
+    diff_into(from, to, String::new(), &mut ops);
+    ops
+}
+
+fn diff_into(from: &JsonValue, to: &JsonValue, pointer: String, ops: &mut Vec<JsonValue>) {
+    if from == to {
+        return;
+    }
+    match (from, to) {
+        (JsonValue::Object(f), JsonValue::Object(t)) => {
+            for key in f.keys().filter(|key| ! t.contains_key(*key)) {
+                let path = format!("{pointer}/{}", escape_token(key));
+                ops.push(json!({"op": "remove", "path": path}));
+            }
+            for (key, value) in t {
+                let path = format!("{pointer}/{}", escape_token(key));
+                match f.get(key) {
+                    Some(old) => diff_into(old, value, path, ops),
+                    None => ops.push(json!({"op": "add", "path": path, "value": value})),
+                }
+            }
+        },
+        (JsonValue::Array(f), JsonValue::Array(t)) => {
+            let common = f.len().min(t.len());
+            for i in 0..common {
+                diff_into(&f[i], &t[i], format!("{pointer}/{i}"), ops);
+            }
+            for (i, value) in t.iter().enumerate().skip(common) {
+                ops.push(json!({"op": "add", "path": format!("{pointer}/{i}"), "value": value}));
+            }
+            // 後ろから消さないと添字がずれる
+            for i in (common..f.len()).rev() {
+                ops.push(json!({"op": "remove", "path": format!("{pointer}/{i}")}));
+            }
+        },
+        _ => ops.push(json!({"op": "replace", "path": pointer, "value": to})),
+    }
+}
+
+/// キーを探す、大文字小文字が一致するものがなければ区別せずに探す
+pub fn find_key(map: &Map<String, JsonValue>, key: &str) -> Option<String> {
+    if map.contains_key(key) {
+        Some(key.to_string())
+    } else {
+        map.keys().find(|k| k.eq_ignore_ascii_case(key)).cloned()
+    }
+}
+
+/// sourceをtargetに再帰的にマージする\
+/// concatが真なら配列同士は連結、偽なら置き換える
+pub fn merge(target: &mut JsonValue, source: JsonValue, concat: bool) {
+    match (target, source) {
+        (JsonValue::Object(t), JsonValue::Object(s)) => {
+            for (key, value) in s {
+                match find_key(t, &key).and_then(|k| t.get_mut(&k)) {
+                    Some(old) => merge(old, value, concat),
+                    None => {
+                        t.insert(key, value);
+                    },
+                }
+            }
+        },
+        (JsonValue::Array(t), JsonValue::Array(s)) if concat => t.extend(s),
+        (target, source) => *target = source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_and_apply() {
+        let from = json!({"a": 1, "b": [1, 2, 3], "c": {"d/e": true}, "x": null});
+        let to = json!({"a": 2, "b": [1], "c": {"d/e": false, "f": "g"}});
+        let ops = diff(&from, &to);
+        let mut target = from.clone();
+        apply(&mut target, &ops).unwrap();
+        assert_eq!(target, to);
+        assert_eq!(ops[0], json!({"op": "remove", "path": "/x"}));
+        assert!(ops.contains(&json!({"op": "replace", "path": "/c/d~1e", "value": false})));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut target = json!({"foo": [1, 2], "bar": {"baz": 1}});
+        let ops = vec![
+            json!({"op": "add", "path": "/foo/1", "value": 9}),
+            json!({"op": "add", "path": "/foo/-", "value": 3}),
+            json!({"op": "move", "from": "/bar/baz", "path": "/qux"}),
+            json!({"op": "copy", "from": "/foo", "path": "/bar/foo"}),
+            json!({"op": "test", "path": "/qux", "value": 1}),
+        ];
+        apply(&mut target, &ops).unwrap();
+        assert_eq!(target, json!({"foo": [1, 9, 2, 3], "bar": {"foo": [1, 9, 2, 3]}, "qux": 1}));
+
+        // 失敗したら何も変わらない
+        let ops = vec![
+            json!({"op": "remove", "path": "/qux"}),
+            json!({"op": "test", "path": "/foo/0", "value": 2}),
+        ];
+        let before = target.clone();
+        assert_eq!(apply(&mut target, &ops).unwrap_err().0, 1);
+        assert_eq!(target, before);
+        assert!(apply(&mut target, &[json!({"op": "remove", "path": "/foo/4"})]).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut target = json!({"Name": "a", "list": [1], "nested": {"x": 1}});
+        merge(&mut target, json!({"name": "b", "list": [2], "nested": {"y": 2}}), false);
+        assert_eq!(target, json!({"Name": "b", "list": [2], "nested": {"x": 1, "y": 2}}));
+        merge(&mut target, json!({"list": [3]}), true);
+        assert_eq!(target["list"], json!([2, 3]));
+    }
+}
@@ -0,0 +1,645 @@
+//! JSON Schemaによる検証
+//!
+//! draft-07 および 2020-12 の主なキーワードに対応する\
+//! $ref はスキーマ内の参照 (`#`, `#/$defs/name` 等) のみ\
+//! 外部スキーマへの $ref や未対応のキーワードは検証結果が不正確になるためエラーとする
+
+use super::jsonpath::escape_token;
+use crate::error::{write_locale, CURRENT_LOCALE, Locale};
+
+use serde_json::{Map, Value as JsonValue};
+use regex::Regex;
+
+/// $ref の循環を防ぐための上限
+const MAX_DEPTH: usize = 64;
+/// 未対応のキーワード、無視すると違反を見逃すためエラーとする
+const UNSUPPORTED_KEYWORDS: &[&str] = &[
+    "$dynamicRef",
+    "$recursiveRef",
+    "unevaluatedItems",
+    "unevaluatedProperties",
+];
+
+pub type SchemaResult<T> = Result<T, String>;
+
+/// スキーマ違反
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// 違反した値のJSON Pointer
+    pub pointer: String,
+    /// 違反したキーワード
+    pub keyword: &'static str,
+    pub message: ViolationMessage,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViolationMessage {
+    /// 期待する型, 実際の型
+    Type(String, &'static str),
+    Enum,
+    Const,
+    Required(String),
+    AdditionalProperty(String),
+    PropertyName(String),
+    MinProperties(usize),
+    MaxProperties(usize),
+    MinItems(usize),
+    MaxItems(usize),
+    UniqueItems,
+    Contains,
+    MinLength(usize),
+    MaxLength(usize),
+    Pattern(String),
+    Format(String),
+    Minimum(f64),
+    Maximum(f64),
+    ExclusiveMinimum(f64),
+    ExclusiveMaximum(f64),
+    MultipleOf(f64),
+    AnyOf,
+    /// 一致したスキーマの数
+    OneOf(usize),
+    Not,
+    False,
+    /// 存在するプロパティ, 必要なプロパティ
+    DependentRequired(String, String),
+}
+
+impl std::fmt::Display for ViolationMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Type(expected, actual) => write_locale!(f,
+                "型が不正です (期待値: {expected}, 実際: {actual})",
+                "Invalid type (expected: {expected}, actual: {actual})",
+            ),
+            Self::Enum => write_locale!(f,
+                "列挙された値のいずれでもありません",
+                "Value is not one of the allowed values",
+            ),
+            Self::Const => write_locale!(f,
+                "値が定数と一致しません",
+                "Value does not match the constant",
+            ),
+            Self::Required(name) => write_locale!(f,
+                "必須プロパティ {name} がありません",
+                "Required property {name} is missing",
+            ),
+            Self::AdditionalProperty(name) => write_locale!(f,
+                "プロパティ {name} は許可されていません",
+                "Property {name} is not allowed",
+            ),
+            Self::PropertyName(name) => write_locale!(f,
+                "プロパティ名 {name} が不正です",
+                "Invalid property name: {name}",
+            ),
+            Self::MinProperties(n) => write_locale!(f,
+                "プロパティは{n}個以上必要です",
+                "At least {n} properties are required",
+            ),
+            Self::MaxProperties(n) => write_locale!(f,
+                "プロパティは{n}個までです",
+                "At most {n} properties are allowed",
+            ),
+            Self::MinItems(n) => write_locale!(f,
+                "要素は{n}個以上必要です",
+                "At least {n} items are required",
+            ),
+            Self::MaxItems(n) => write_locale!(f,
+                "要素は{n}個までです",
+                "At most {n} items are allowed",
+            ),
+            Self::UniqueItems => write_locale!(f,
+                "要素が重複しています",
+                "Items are not unique",
+            ),
+            Self::Contains => write_locale!(f,
+                "条件に一致する要素がありません",
+                "No items match the contains schema",
+            ),
+            Self::MinLength(n) => write_locale!(f,
+                "{n}文字以上必要です",
+                "At least {n} characters are required",
+            ),
+            Self::MaxLength(n) => write_locale!(f,
+                "{n}文字までです",
+                "At most {n} characters are allowed",
+            ),
+            Self::Pattern(pattern) => write_locale!(f,
+                "パターン {pattern} に一致しません",
+                "Does not match the pattern {pattern}",
+            ),
+            Self::Format(format) => write_locale!(f,
+                "{format} 形式ではありません",
+                "Not a valid {format}",
+            ),
+            Self::Minimum(n) => write_locale!(f,
+                "{n}以上である必要があります",
+                "Must be greater than or equal to {n}",
+            ),
+            Self::Maximum(n) => write_locale!(f,
+                "{n}以下である必要があります",
+                "Must be less than or equal to {n}",
+            ),
+            Self::ExclusiveMinimum(n) => write_locale!(f,
+                "{n}より大きい必要があります",
+                "Must be greater than {n}",
+            ),
+            Self::ExclusiveMaximum(n) => write_locale!(f,
+                "{n}未満である必要があります",
+                "Must be less than {n}",
+            ),
+            Self::MultipleOf(n) => write_locale!(f,
+                "{n}の倍数である必要があります",
+                "Must be a multiple of {n}",
+            ),
+            Self::AnyOf => write_locale!(f,
+                "anyOfのいずれのスキーマにも一致しません",
+                "Does not match any schema of anyOf",
+            ),
+            Self::OneOf(n) => write_locale!(f,
+                "oneOfのスキーマにちょうど1つ一致する必要があります (一致数: {n})",
+                "Must match exactly one schema of oneOf (matched: {n})",
+            ),
+            Self::Not => write_locale!(f,
+                "notのスキーマに一致してはいけません",
+                "Must not match the schema of not",
+            ),
+            Self::False => write_locale!(f,
+                "値は許可されていません",
+                "Value is not allowed",
+            ),
+            Self::DependentRequired(name, required) => write_locale!(f,
+                "{name} がある場合は {required} が必要です",
+                "{required} is required when {name} is present",
+            ),
+        }
+    }
+}
+
+/// 値をスキーマで検証し、違反の一覧を返す\
+/// スキーマ自体が不正な場合はエラー
+pub fn validate(value: &JsonValue, schema: &JsonValue) -> SchemaResult<Vec<Violation>> {
+    let validator = Validator { root: schema };
+    let mut violations = vec![];
+    validator.validate(schema, value, "", &mut violations, 0)?;
+    Ok(violations)
+}
+
+struct Validator<'a> {
+    root: &'a JsonValue,
+}
+
+impl Validator<'_> {
+    fn is_valid(&self, schema: &JsonValue, value: &JsonValue, depth: usize) -> SchemaResult<bool> {
+        let mut violations = vec![];
+        self.validate(schema, value, "", &mut violations, depth)?;
+        Ok(violations.is_empty())
+    }
+    fn resolve(&self, reference: &str) -> SchemaResult<&JsonValue> {
+        // 外部スキーマ (URI) やアンカー名による参照は未対応
+        let pointer = reference.strip_prefix('#')
+            .filter(|pointer| pointer.is_empty() || pointer.starts_with('/'))
+            .ok_or(format!("unsupported $ref: {reference}"))?;
+        self.root.pointer(pointer)
+            .ok_or(format!("unresolved $ref: {reference}"))
+    }
+    fn validate(&self, schema: &JsonValue, value: &JsonValue, pointer: &str, out: &mut Vec<Violation>, depth: usize) -> SchemaResult<()> {
+        if depth > MAX_DEPTH {
+            return Err("$ref nesting is too deep".into());
+        }
+        let schema = match schema {
+            JsonValue::Bool(true) => return Ok(()),
+            JsonValue::Bool(false) => {
+                out.push(violation(pointer, "false", ViolationMessage::False));
+                return Ok(());
+            },
+            JsonValue::Object(schema) => schema,
+            _ => return Err(format!("schema must be an object or a boolean: {pointer}")),
+        };
+        if let Some(keyword) = UNSUPPORTED_KEYWORDS.iter().find(|keyword| schema.contains_key(**keyword)) {
+            return Err(format!("unsupported keyword: {keyword}"));
+        }
+        if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+            let target = self.resolve(reference)?;
+            self.validate(target, value, pointer, out, depth + 1)?;
+        }
+        self.validate_any(schema, value, pointer, out)?;
+        match value {
+            JsonValue::Number(n) => self.validate_number(schema, n.as_f64().unwrap_or_default(), pointer, out),
+            JsonValue::String(s) => self.validate_string(schema, s, pointer, out)?,
+            JsonValue::Array(arr) => self.validate_array(schema, arr, pointer, out, depth)?,
+            JsonValue::Object(map) => self.validate_object(schema, map, pointer, out, depth)?,
+            _ => {},
+        }
+        self.validate_combinators(schema, value, pointer, out, depth)
+    }
+    fn validate_any(&self, schema: &Map<String, JsonValue>, value: &JsonValue, pointer: &str, out: &mut Vec<Violation>) -> SchemaResult<()> {
+        if let Some(types) = schema.get("type") {
+            let names = match types {
+                JsonValue::String(name) => vec![name.as_str()],
+                JsonValue::Array(names) => names.iter().filter_map(|n| n.as_str()).collect(),
+                _ => return Err("'type' must be a string or an array".into()),
+            };
+            if ! names.iter().any(|name| type_matches(name, value)) {
+                let message = ViolationMessage::Type(names.join(", "), type_name(value));
+                out.push(violation(pointer, "type", message));
+            }
+        }
+        if let Some(JsonValue::Array(values)) = schema.get("enum") && ! values.iter().any(|v| json_equal(v, value)) {
+            out.push(violation(pointer, "enum", ViolationMessage::Enum));
+        }
+        if let Some(expected) = schema.get("const") && ! json_equal(expected, value) {
+            out.push(violation(pointer, "const", ViolationMessage::Const));
+        }
+        Ok(())
+    }
+    fn validate_number(&self, schema: &Map<String, JsonValue>, n: f64, pointer: &str, out: &mut Vec<Violation>) {
+        let get = |key: &str| schema.get(key).and_then(|v| v.as_f64());
+        // draft-04 では exclusiveMinimum/exclusiveMaximum が真偽値
+        let exclusive = |key: &str| schema.get(key).and_then(|v| v.as_bool()).unwrap_or(false);
+        if let Some(min) = get("minimum") {
+            if exclusive("exclusiveMinimum") && n <= min {
+                out.push(violation(pointer, "exclusiveMinimum", ViolationMessage::ExclusiveMinimum(min)));
+            } else if n < min {
+                out.push(violation(pointer, "minimum", ViolationMessage::Minimum(min)));
+            }
+        }
+        if let Some(max) = get("maximum") {
+            if exclusive("exclusiveMaximum") && n >= max {
+                out.push(violation(pointer, "exclusiveMaximum", ViolationMessage::ExclusiveMaximum(max)));
+            } else if n > max {
+                out.push(violation(pointer, "maximum", ViolationMessage::Maximum(max)));
+            }
+        }
+        if let Some(min) = get("exclusiveMinimum") && n <= min {
+            out.push(violation(pointer, "exclusiveMinimum", ViolationMessage::ExclusiveMinimum(min)));
+        }
+        if let Some(max) = get("exclusiveMaximum") && n >= max {
+            out.push(violation(pointer, "exclusiveMaximum", ViolationMessage::ExclusiveMaximum(max)));
+        }
+        if let Some(m) = get("multipleOf").filter(|m| *m > 0.0) {
+            let q = n / m;
+            if (q - q.round()).abs() > 1e-9 {
+                out.push(violation(pointer, "multipleOf", ViolationMessage::MultipleOf(m)));
+            }
+        }
+    }
+    fn validate_string(&self, schema: &Map<String, JsonValue>, s: &str, pointer: &str, out: &mut Vec<Violation>) -> SchemaResult<()> {
+        let len = s.chars().count();
+        if let Some(min) = get_usize(schema, "minLength") && len < min {
+            out.push(violation(pointer, "minLength", ViolationMessage::MinLength(min)));
+        }
+        if let Some(max) = get_usize(schema, "maxLength") && len > max {
+            out.push(violation(pointer, "maxLength", ViolationMessage::MaxLength(max)));
+        }
+        if let Some(pattern) = schema.get("pattern").and_then(|p| p.as_str()) && ! compile(pattern)?.is_match(s) {
+            out.push(violation(pointer, "pattern", ViolationMessage::Pattern(pattern.into())));
+        }
+        if let Some(format) = schema.get("format").and_then(|f| f.as_str()) && ! format_matches(format, s) {
+            out.push(violation(pointer, "format", ViolationMessage::Format(format.into())));
+        }
+        Ok(())
+    }
+    fn validate_array(&self, schema: &Map<String, JsonValue>, arr: &[JsonValue], pointer: &str, out: &mut Vec<Violation>, depth: usize) -> SchemaResult<()> {
+        if let Some(min) = get_usize(schema, "minItems") && arr.len() < min {
+            out.push(violation(pointer, "minItems", ViolationMessage::MinItems(min)));
+        }
+        if let Some(max) = get_usize(schema, "maxItems") && arr.len() > max {
+            out.push(violation(pointer, "maxItems", ViolationMessage::MaxItems(max)));
+        }
+        if schema.get("uniqueItems").and_then(|u| u.as_bool()).unwrap_or(false) {
+            let duplicated = arr.iter().enumerate()
+                .any(|(i, a)| arr[i + 1..].iter().any(|b| json_equal(a, b)));
+            if duplicated {
+                out.push(violation(pointer, "uniqueItems", ViolationMessage::UniqueItems));
+            }
+        }
+        // 先頭から個別のスキーマを適用する要素数
+        let prefix = match (schema.get("prefixItems"), schema.get("items")) {
+            (Some(JsonValue::Array(schemas)), _) |
+            (None, Some(JsonValue::Array(schemas))) => {
+                for (i, (item_schema, item)) in schemas.iter().zip(arr).enumerate() {
+                    self.validate(item_schema, item, &format!("{pointer}/{i}"), out, depth)?;
+                }
+                schemas.len()
+            },
+            _ => 0,
+        };
+        let rest = match schema.get("items") {
+            Some(JsonValue::Array(_)) => schema.get("additionalItems"),
+            items => items,
+        };
+        if let Some(rest) = rest {
+            for (i, item) in arr.iter().enumerate().skip(prefix) {
+                self.validate(rest, item, &format!("{pointer}/{i}"), out, depth)?;
+            }
+        }
+        if let Some(contains) = schema.get("contains") {
+            let mut matched = 0;
+            for item in arr {
+                if self.is_valid(contains, item, depth)? {
+                    matched += 1;
+                }
+            }
+            let min = get_usize(schema, "minContains").unwrap_or(1);
+            let max = get_usize(schema, "maxContains").unwrap_or(usize::MAX);
+            if matched < min || matched > max {
+                out.push(violation(pointer, "contains", ViolationMessage::Contains));
+            }
+        }
+        Ok(())
+    }
+    fn validate_object(&self, schema: &Map<String, JsonValue>, map: &Map<String, JsonValue>, pointer: &str, out: &mut Vec<Violation>, depth: usize) -> SchemaResult<()> {
+        if let Some(JsonValue::Array(required)) = schema.get("required") {
+            for name in required.iter().filter_map(|r| r.as_str()) {
+                if ! map.contains_key(name) {
+                    out.push(violation(pointer, "required", ViolationMessage::Required(name.into())));
+                }
+            }
+        }
+        if let Some(min) = get_usize(schema, "minProperties") && map.len() < min {
+            out.push(violation(pointer, "minProperties", ViolationMessage::MinProperties(min)));
+        }
+        if let Some(max) = get_usize(schema, "maxProperties") && map.len() > max {
+            out.push(violation(pointer, "maxProperties", ViolationMessage::MaxProperties(max)));
+        }
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        let patterns = match schema.get("patternProperties").and_then(|p| p.as_object()) {
+            Some(patterns) => patterns.iter()
+                .map(|(pattern, s)| compile(pattern).map(|re| (re, s)))
+                .collect::<SchemaResult<Vec<_>>>()?,
+            None => vec![],
+        };
+        let additional = schema.get("additionalProperties");
+        for (key, value) in map {
+            let child = format!("{pointer}/{}", escape_token(key));
+            let mut matched = false;
+            if let Some(s) = properties.and_then(|p| p.get(key)) {
+                matched = true;
+                self.validate(s, value, &child, out, depth)?;
+            }
+            for (_, s) in patterns.iter().filter(|(re, _)| re.is_match(key)) {
+                matched = true;
+                self.validate(s, value, &child, out, depth)?;
+            }
+            match additional {
+                Some(JsonValue::Bool(false)) if ! matched => {
+                    out.push(violation(&child, "additionalProperties", ViolationMessage::AdditionalProperty(key.clone())));
+                },
+                Some(s) if ! matched => self.validate(s, value, &child, out, depth)?,
+                _ => {},
+            }
+            if let Some(names) = schema.get("propertyNames") && ! self.is_valid(names, &JsonValue::String(key.clone()), depth)? {
+                out.push(violation(&child, "propertyNames", ViolationMessage::PropertyName(key.clone())));
+            }
+        }
+        // dependencies は draft-07 以前、dependentRequired/dependentSchemas は 2019-09 以降
+        let dependencies = ["dependencies", "dependentRequired", "dependentSchemas"].into_iter()
+            .filter_map(|keyword| schema.get(keyword).and_then(|d| d.as_object()));
+        for deps in dependencies {
+            for (name, dep) in deps.iter().filter(|(name, _)| map.contains_key(*name)) {
+                match dep {
+                    JsonValue::Array(required) => {
+                        for r in required.iter().filter_map(|r| r.as_str()).filter(|r| ! map.contains_key(*r)) {
+                            let message = ViolationMessage::DependentRequired(name.clone(), r.into());
+                            out.push(violation(pointer, "dependentRequired", message));
+                        }
+                    },
+                    dep => self.validate(dep, &JsonValue::Object(map.clone()), pointer, out, depth)?,
+                }
+            }
+        }
+        Ok(())
+    }
+    fn validate_combinators(&self, schema: &Map<String, JsonValue>, value: &JsonValue, pointer: &str, out: &mut Vec<Violation>, depth: usize) -> SchemaResult<()> {
+        if let Some(JsonValue::Array(schemas)) = schema.get("allOf") {
+            for s in schemas {
+                self.validate(s, value, pointer, out, depth)?;
+            }
+        }
+        if let Some(JsonValue::Array(schemas)) = schema.get("anyOf") {
+            let mut any = false;
+            for s in schemas {
+                if self.is_valid(s, value, depth)? {
+                    any = true;
+                    break;
+                }
+            }
+            if ! any {
+                out.push(violation(pointer, "anyOf", ViolationMessage::AnyOf));
+            }
+        }
+        if let Some(JsonValue::Array(schemas)) = schema.get("oneOf") {
+            let mut matched = 0;
+            for s in schemas {
+                if self.is_valid(s, value, depth)? {
+                    matched += 1;
+                }
+            }
+            if matched != 1 {
+                out.push(violation(pointer, "oneOf", ViolationMessage::OneOf(matched)));
+            }
+        }
+        if let Some(not) = schema.get("not") && self.is_valid(not, value, depth)? {
+            out.push(violation(pointer, "not", ViolationMessage::Not));
+        }
+        if let Some(condition) = schema.get("if") {
+            let branch = if self.is_valid(condition, value, depth)? {
+                schema.get("then")
+            } else {
+                schema.get("else")
+            };
+            if let Some(branch) = branch {
+                self.validate(branch, value, pointer, out, depth)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn violation(pointer: &str, keyword: &'static str, message: ViolationMessage) -> Violation {
+    Violation { pointer: pointer.to_string(), keyword, message }
+}
+
+fn get_usize(schema: &Map<String, JsonValue>, key: &str) -> Option<usize> {
+    schema.get(key).and_then(|v| v.as_f64()).map(|n| n as usize)
+}
+
+fn compile(pattern: &str) -> SchemaResult<Regex> {
+    Regex::new(pattern).map_err(|e| format!("invalid pattern {pattern}: {e}"))
+}
+
+fn type_matches(name: &str, value: &JsonValue) -> bool {
+    match (name, value) {
+        ("null", JsonValue::Null) |
+        ("boolean", JsonValue::Bool(_)) |
+        ("number", JsonValue::Number(_)) |
+        ("string", JsonValue::String(_)) |
+        ("array", JsonValue::Array(_)) |
+        ("object", JsonValue::Object(_)) => true,
+        ("integer", JsonValue::Number(n)) => n.as_f64().is_some_and(|f| f.fract() == 0.0),
+        _ => false,
+    }
+}
+
+fn type_name(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(n) if n.as_f64().is_some_and(|f| f.fract() == 0.0) => "integer",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+/// 数値は1と1.0を同じ値とみなして比較する
+fn json_equal(a: &JsonValue, b: &JsonValue) -> bool {
+    match (a, b) {
+        (JsonValue::Number(a), JsonValue::Number(b)) => a.as_f64() == b.as_f64(),
+        (JsonValue::Array(a), JsonValue::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_equal(a, b))
+        },
+        (JsonValue::Object(a), JsonValue::Object(b)) => {
+            a.len() == b.len() && a.iter().all(|(k, v)| b.get(k).is_some_and(|w| json_equal(v, w)))
+        },
+        (a, b) => a == b,
+    }
+}
+
+/// 未知のformatは検証しない
+fn format_matches(format: &str, s: &str) -> bool {
+    match format {
+        "date-time" => chrono::DateTime::parse_from_rfc3339(s).is_ok(),
+        "date" => chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok(),
+        "time" => chrono::DateTime::parse_from_rfc3339(&format!("1970-01-01T{s}")).is_ok(),
+        "email" => match s.split_once('@') {
+            Some((local, domain)) => ! local.is_empty()
+                && ! domain.contains('@')
+                && domain.split('.').count() > 1
+                && domain.split('.').all(|label| ! label.is_empty()),
+            None => false,
+        },
+        "ipv4" => s.parse::<std::net::Ipv4Addr>().is_ok(),
+        "ipv6" => s.parse::<std::net::Ipv6Addr>().is_ok(),
+        "uri" => match s.split_once(':') {
+            Some((scheme, _)) => scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')),
+            None => false,
+        },
+        "uuid" => {
+            let parts = s.split('-').collect::<Vec<_>>();
+            parts.iter().map(|p| p.len()).eq([8, 4, 4, 4, 12])
+                && parts.iter().all(|p| p.chars().all(|c| c.is_ascii_hexdigit()))
+        },
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use rstest::rstest;
+
+    fn failures(value: JsonValue, schema: JsonValue) -> Vec<(String, &'static str)> {
+        validate(&value, &schema).unwrap()
+            .into_iter()
+            .map(|v| (v.pointer, v.keyword))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name", "port"],
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "port": {"type": "integer", "minimum": 1, "maximum": 65535},
+                "mail": {"type": "string", "format": "email"},
+                "tags": {"type": "array", "items": {"$ref": "#/$defs/tag"}, "uniqueItems": true}
+            },
+            "additionalProperties": false,
+            "$defs": {
+                "tag": {"type": "string", "pattern": "^[a-z]+$"}
+            }
+        });
+        let valid = json!({"name": "server", "port": 8080, "tags": ["a", "b"]});
+        assert!(failures(valid, schema.clone()).is_empty());
+
+        let invalid = json!({"name": "", "port": 70000.0, "mail": "foo", "tags": ["a", "B", "a"], "x/y": 1});
+        assert_eq!(failures(invalid, schema.clone()), vec![
+            ("/name".to_string(), "minLength"),
+            ("/port".to_string(), "maximum"),
+            ("/mail".to_string(), "format"),
+            ("/tags".to_string(), "uniqueItems"),
+            ("/tags/1".to_string(), "pattern"),
+            ("/x~1y".to_string(), "additionalProperties"),
+        ]);
+        assert_eq!(failures(json!({"port": 1.5}), schema), vec![
+            ("".to_string(), "required"),
+            ("/port".to_string(), "type"),
+        ]);
+    }
+
+    #[test]
+    fn test_combinators() {
+        let schema = json!({
+            "oneOf": [{"type": "integer"}, {"type": "number", "multipleOf": 0.5}],
+            "not": {"const": 3}
+        });
+        assert!(failures(json!(2.5), schema.clone()).is_empty());
+        assert_eq!(failures(json!(1), schema.clone()), vec![("".to_string(), "oneOf")]);
+        assert_eq!(failures(json!(3), schema), vec![("".to_string(), "oneOf"), ("".to_string(), "not")]);
+
+        let schema = json!({
+            "if": {"properties": {"kind": {"const": "file"}}},
+            "then": {"required": ["path"]},
+            "else": {"required": ["url"]}
+        });
+        assert_eq!(failures(json!({"kind": "file"}), schema.clone()), vec![("".to_string(), "required")]);
+        assert!(failures(json!({"kind": "web", "url": "x"}), schema).is_empty());
+
+        assert!(validate(&json!(1), &json!({"$ref": "#/nothing"})).is_err());
+        assert!(validate(&json!("a"), &json!({"pattern": "("})).is_err());
+    }
+
+    #[rstest]
+    #[case::reference(json!({"$defs": {"n": {"type": "number"}}, "$ref": "#/$defs/n"}), json!(1), json!("a"), "", "type")]
+    #[case::root_reference(json!({"properties": {"child": {"$ref": "#"}}, "maxProperties": 1}), json!({"child": {}}), json!({"child": {"a": 1, "b": 2}}), "/child", "maxProperties")]
+    #[case::all_of(json!({"allOf": [{"minimum": 1}, {"maximum": 3}]}), json!(2), json!(5), "", "maximum")]
+    #[case::any_of(json!({"anyOf": [{"type": "string"}, {"type": "boolean"}]}), json!(true), json!(1), "", "anyOf")]
+    #[case::one_of(json!({"oneOf": [{"minimum": 0}, {"maximum": 0}]}), json!(1), json!(0), "", "oneOf")]
+    #[case::enumeration(json!({"enum": ["a", 1]}), json!(1.0), json!("b"), "", "enum")]
+    #[case::constant(json!({"const": {"a": [1]}}), json!({"a": [1.0]}), json!({"a": [2]}), "", "const")]
+    #[case::pattern(json!({"pattern": "^\\d+$"}), json!("123"), json!("12a"), "", "pattern")]
+    #[case::minimum(json!({"minimum": 1}), json!(1), json!(0.5), "", "minimum")]
+    #[case::maximum(json!({"maximum": 1}), json!(1), json!(1.5), "", "maximum")]
+    #[case::exclusive_minimum(json!({"exclusiveMinimum": 1}), json!(1.5), json!(1), "", "exclusiveMinimum")]
+    #[case::exclusive_maximum(json!({"exclusiveMaximum": 1}), json!(0.5), json!(1), "", "exclusiveMaximum")]
+    #[case::min_length(json!({"minLength": 2}), json!("あい"), json!("a"), "", "minLength")]
+    #[case::max_length(json!({"maxLength": 2}), json!("あい"), json!("abc"), "", "maxLength")]
+    #[case::min_items(json!({"minItems": 1}), json!([1]), json!([]), "", "minItems")]
+    #[case::max_items(json!({"maxItems": 1}), json!([1]), json!([1, 2]), "", "maxItems")]
+    #[case::required(json!({"required": ["a"]}), json!({"a": null}), json!({"b": 1}), "", "required")]
+    #[case::no_additional_properties(json!({"properties": {"a": {}}, "additionalProperties": false}), json!({"a": 1}), json!({"a": 1, "b": 2}), "/b", "additionalProperties")]
+    #[case::additional_properties_schema(json!({"properties": {"a": {}}, "additionalProperties": {"type": "string"}}), json!({"a": 1, "b": "x"}), json!({"a": 1, "b": 2}), "/b", "type")]
+    fn test_keyword(#[case] schema: JsonValue, #[case] valid: JsonValue, #[case] invalid: JsonValue, #[case] pointer: &str, #[case] keyword: &str) {
+        assert!(failures(valid, schema.clone()).is_empty());
+        assert_eq!(failures(invalid, schema), vec![(pointer.to_string(), keyword)]);
+    }
+
+    #[rstest]
+    #[case::remote_ref(json!({"$ref": "https://example.com/schema.json"}), "unsupported $ref")]
+    #[case::remote_pointer(json!({"$ref": "other.json#/$defs/a"}), "unsupported $ref")]
+    #[case::anchor(json!({"$ref": "#node"}), "unsupported $ref")]
+    #[case::nested_remote_ref(json!({"items": {"$ref": "https://example.com/item.json"}}), "unsupported $ref")]
+    #[case::unevaluated_properties(json!({"unevaluatedProperties": false}), "unsupported keyword")]
+    #[case::dynamic_ref(json!({"$dynamicRef": "#node"}), "unsupported keyword")]
+    fn test_unsupported(#[case] schema: JsonValue, #[case] expected: &str) {
+        let err = validate(&json!([1]), &schema).unwrap_err();
+        assert!(err.starts_with(expected), "{err}");
+    }
+}
@@ -1,16 +1,34 @@
+mod cache;
+mod cassette;
+mod cookie;
+mod form;
+mod html;
+
 use crate::error::{UError, UErrorKind, UErrorMessage};
+use crate::{Evaluator, EvalResult};
+use crate::builtins::browser_control::WebMatchConst;
+use crate::sandbox::SandboxPolicy;
 use super::{Object, UObject, HashTbl};
+use cache::HttpCache;
+use cassette::{Cassette, Interaction, MatchRule, RecordedRequest, RecordedResponse};
+use cookie::{Cookie, CookieJar};
+use form::{RequestBody, FormPart};
 
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use reqwest::{
-    StatusCode,
-    header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderName, InvalidHeaderValue},
+    StatusCode, Url, Proxy, Certificate, Identity,
+    header::{self, HeaderMap, HeaderName, HeaderValue, InvalidHeaderName, InvalidHeaderValue},
     blocking::{Client, Response, RequestBuilder},
+    redirect::Policy,
     Method,
 };
+use serde_json::Value as JsonValue;
 
 impl From<InvalidHeaderValue> for UError {
     fn from(e: InvalidHeaderValue) -> Self {
@@ -24,6 +42,56 @@ impl From<InvalidHeaderName> for UError {
 }
 
 type WebResult<T> = Result<T, UError>;
+/// Content-Typeと送信するバイト列
+type EncodedBody = (Option<String>, Vec<u8>);
+
+fn web_error<E: std::fmt::Display>(e: E) -> UError {
+    UError::new(UErrorKind::WebRequestError, UErrorMessage::Any(e.to_string()))
+}
+fn cassette_error(e: String) -> UError {
+    UError::new(UErrorKind::WebRequestError, UErrorMessage::InvalidCassette(e))
+}
+
+/// リダイレクトを追う回数の既定値
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+/// リトライ対象とするステータスの既定値
+const DEFAULT_RETRY_STATUS: [u16; 5] = [429, 500, 502, 503, 504];
+/// ダウンロード時に一度に読み込むサイズ
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+enum ProxySetting {
+    Server { url: String, auth: Option<(String, String)> },
+    /// OSのプロキシ設定も使わない
+    Disabled,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct TlsSetting {
+    /// 追加で信頼するCA証明書ファイル
+    ca: Option<PathBuf>,
+    /// クライアント証明書 (PKCS#12) とパスワード
+    identity: Option<(PathBuf, String)>,
+    /// 証明書を検証しない
+    insecure: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RetrySetting {
+    count: u32,
+    /// 初回の待機時間、以降はリトライごとに倍になる
+    wait: Duration,
+    statuses: Vec<u16>,
+}
+impl Default for RetrySetting {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            wait: Duration::from_secs(1),
+            statuses: DEFAULT_RETRY_STATUS.to_vec(),
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct WebRequest {
@@ -31,9 +99,18 @@ pub struct WebRequest {
     user_agent: Option<String>,
     headers: HeaderMap,
     timeout: Option<Duration>,
-    body: Option<String>,
+    body: Option<RequestBody>,
     basic: Option<(String, Option<String>)>,
     bearer: Option<String>,
+    cookies: CookieJar,
+    max_redirects: Option<usize>,
+    proxy: Option<ProxySetting>,
+    tls: TlsSetting,
+    retry: RetrySetting,
+    cassette: Option<Cassette>,
+    cache: Option<HttpCache>,
+    /// サンドボックス有効時はファイルの読み書きを許可フォルダ内に限る
+    sandbox: Option<Arc<SandboxPolicy>>,
 }
 impl std::fmt::Display for WebRequest {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -44,6 +121,17 @@ impl WebRequest {
     pub fn new() -> Self {
         Self::default()
     }
+    /// サンドボックスのポリシーを設定する
+    pub fn with_sandbox(mut self, sandbox: Option<Arc<SandboxPolicy>>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+    fn check_path(&self, path: &str) -> WebResult<()> {
+        match &self.sandbox {
+            Some(sandbox) => sandbox.check_path(path),
+            None => Ok(()),
+        }
+    }
     fn add_header(&mut self, key: &str, value: &str) -> WebResult<()> {
         let key = HeaderName::from_str(key)?;
         let val = HeaderValue::from_str(value)?;
@@ -57,7 +145,7 @@ impl WebRequest {
         let duration = Duration::from_secs_f64(secs);
         self.timeout = Some(duration);
     }
-    fn set_body(&mut self, body: String) {
+    fn set_body(&mut self, body: RequestBody) {
         self.body = Some(body);
     }
     fn set_basic_auth(&mut self, name: String, password: Option<String>) {
@@ -67,31 +155,244 @@ impl WebRequest {
         self.bearer = Some(token);
     }
     fn client(&self) -> WebResult<Client> {
-        let builder = Client::builder();
-        let builder = if let Some(ua) = &self.user_agent {
-            builder.user_agent(ua)
-        } else {
-            builder
-        };
+        // Cookieを受け渡すためリダイレクトは自前で追う
+        let mut builder = Client::builder().redirect(Policy::none());
+        if let Some(ua) = &self.user_agent {
+            builder = builder.user_agent(ua);
+        }
+        match &self.proxy {
+            Some(ProxySetting::Server { url, auth }) => {
+                let mut proxy = Proxy::all(url)?;
+                if let Some((user, password)) = auth {
+                    proxy = proxy.basic_auth(user, password);
+                }
+                builder = builder.proxy(proxy);
+            },
+            Some(ProxySetting::Disabled) => builder = builder.no_proxy(),
+            None => {},
+        }
+        if let Some(path) = &self.tls.ca {
+            let pem = std::fs::read(path)?;
+            let certs = if pem.starts_with(b"-----BEGIN") {
+                Certificate::from_pem_bundle(&pem)?
+            } else {
+                vec![Certificate::from_der(&pem)?]
+            };
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some((path, password)) = &self.tls.identity {
+            let der = std::fs::read(path)?;
+            let identity = Identity::from_pkcs12_der(&der, password)?;
+            builder = builder.identity(identity);
+        }
+        if self.tls.insecure {
+            builder = builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
         let client = builder.build()?;
         Ok(client)
     }
-    fn request(&self, method: Method, url: &str) -> WebResult<WebResponse> {
+    /// リトライ時や記録時にも同じ本文を使うため先に変換しておく
+    fn encode_body(&self) -> WebResult<Option<EncodedBody>> {
+        let body = self.body.as_ref()
+            .map(|body| body.encode())
+            .transpose()?;
+        Ok(body)
+    }
+    /// リトライ設定に従いリクエストを送信する
+    fn send(&mut self, method: Method, url: &str, headers: &HeaderMap, body: Option<&EncodedBody>) -> WebResult<Response> {
         let client = self.client()?;
-        let builder = client.request(method, url)
-            .set_header(&self.headers)
-            .set_timeout(self.timeout)
-            .set_body(&self.body)
-            .set_basic_auth(&self.basic)
-            .set_bearer_auth(&self.bearer);
+        let url = Url::parse(url).map_err(web_error)?;
+        let mut attempt = 0;
+        loop {
+            let result = self.send_following(&client, method.clone(), url.clone(), headers, body);
+            if attempt >= self.retry.count {
+                return Ok(result?);
+            }
+            let wait = self.retry.wait * 2u32.pow(attempt.min(16));
+            let wait = match &result {
+                Ok(res) if self.retry.statuses.contains(&res.status().as_u16()) => {
+                    // Retry-Afterが秒数で指定されていればそちらが長ければ従う
+                    let retry_after = res.headers().get(header::RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.trim().parse::<u64>().ok())
+                        .map(Duration::from_secs)
+                        .unwrap_or_default();
+                    wait.max(retry_after)
+                },
+                Err(e) if e.is_timeout() || e.is_connect() => wait,
+                _ => return Ok(result?),
+            };
+            std::thread::sleep(wait);
+            attempt += 1;
+        }
+    }
+    /// リダイレクトを追いつつCookieの送受信を行う
+    fn send_following(&mut self, client: &Client, method: Method, url: Url, headers: &HeaderMap, body: Option<&EncodedBody>) -> reqwest::Result<Response> {
+        let max_redirects = self.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS);
+        let origin = url.origin();
+        let mut method = method;
+        let mut url = url;
+        let mut with_body = true;
+        let mut redirects = 0;
+        loop {
+            let mut builder = client.request(method.clone(), url.clone())
+                .set_header(headers)
+                .set_timeout(self.timeout);
+            if let Some(cookie) = self.cookies.header(&url) {
+                builder = builder.header(header::COOKIE, cookie);
+            }
+            if with_body {
+                builder = builder.set_body(body, headers);
+            }
+            // 認証情報は別のオリジンに送らない
+            if url.origin() == origin {
+                builder = builder
+                    .set_basic_auth(&self.basic)
+                    .set_bearer_auth(&self.bearer);
+            }
+            let res = builder.send()?;
+            let set_cookies = res.headers().get_all(header::SET_COOKIE).iter()
+                .filter_map(|v| v.to_str().ok());
+            self.cookies.store(&url, set_cookies);
 
-        let res = builder.send()?;
-        Ok(res.into())
+            let status = res.status();
+            if ! status.is_redirection() || redirects >= max_redirects {
+                return Ok(res);
+            }
+            let Some(next) = res.headers().get(header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|location| url.join(location).ok())
+            else {
+                return Ok(res);
+            };
+            // 303および POST の 301/302 は本文なしの GET に変わる
+            if status == StatusCode::SEE_OTHER
+                || (method == Method::POST && matches!(status, StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND))
+            {
+                if method != Method::HEAD {
+                    method = Method::GET;
+                }
+                with_body = false;
+            }
+            url = next;
+            redirects += 1;
+        }
     }
-    pub fn get(&self, url: &str) -> WebResult<WebResponse> {
+    /// 記録・再生およびキャッシュを経てリクエストを行う
+    fn request(&mut self, method: Method, url: &str) -> WebResult<WebResponse> {
+        let body = self.encode_body()?;
+        let recorded = RecordedRequest::new(method.as_str(), url, body.as_ref().map(|(_, bytes)| bytes.as_slice()));
+        let use_cache = method == Method::GET;
+        let cached = match &mut self.cache {
+            Some(cache) if use_cache => cache.get(url).cloned(),
+            _ => None,
+        };
+
+        let response = match &mut self.cassette {
+            Some(cassette) if cassette.is_replay() => {
+                cassette.find(&recorded)
+                    .ok_or(UError::new(
+                        UErrorKind::WebRequestError,
+                        UErrorMessage::NoRecordedResponse(recorded.method.clone(), recorded.url.clone())
+                    ))?
+            },
+            _ => {
+                let mut headers = self.headers.clone();
+                if let Some(entry) = &cached {
+                    if let Some(etag) = &entry.etag {
+                        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(etag)?);
+                    }
+                    if let Some(last_modified) = &entry.last_modified {
+                        headers.insert(header::IF_MODIFIED_SINCE, HeaderValue::from_str(last_modified)?);
+                    }
+                }
+                let response = RecordedResponse::from(self.send(method, url, &headers, body.as_ref())?);
+                if let Some(cassette) = &mut self.cassette {
+                    let interaction = Interaction { request: recorded, response: response.clone() };
+                    cassette.push(interaction).map_err(cassette_error)?;
+                }
+                response
+            },
+        };
+
+        if let Some(cache) = &mut self.cache && use_cache {
+            if response.status == StatusCode::NOT_MODIFIED.as_u16() && let Some(entry) = cached {
+                let mut res = WebResponse::from(entry.response);
+                res.from_cache = true;
+                return Ok(res);
+            }
+            cache.store(url, &response)?;
+        }
+        Ok(response.into())
+    }
+    pub fn get(&mut self, url: &str) -> WebResult<WebResponse> {
         self.request(Method::GET, url)
     }
-    pub fn invoke_method(&mut self, name: &str, args: Vec<Object>) -> WebResult<Option<Object>> {
+    /// レスポンス本文をファイルに書き出す
+    ///
+    /// progressには受信済みのバイト数と (分かれば) 全体のバイト数が渡される
+    pub fn download<F>(&mut self, url: &str, path: &str, mut progress: F) -> WebResult<WebResponse>
+    where F: FnMut(u64, Option<u64>) -> WebResult<()>
+    {
+        self.check_path(path)?;
+        let body = self.encode_body()?;
+        let headers = self.headers.clone();
+        let mut res = self.send(Method::GET, url, &headers, body.as_ref())?;
+        if ! res.status().is_success() {
+            return Ok(res.into());
+        }
+        let total = res.content_length();
+        let mut file = BufWriter::new(File::create(path)?);
+        let mut buf = vec![0; DOWNLOAD_CHUNK_SIZE];
+        let mut received = 0;
+        loop {
+            let size = res.read(&mut buf).map_err(web_error)?;
+            if size == 0 {
+                break;
+            }
+            file.write_all(&buf[..size])?;
+            received += size as u64;
+            progress(received, total)?;
+        }
+        file.flush()?;
+        Ok(WebResponse::without_body(&res))
+    }
+    /// コールバックを受けるメソッドのため評価器を受ける
+    pub fn invoke_method(mutex: &Arc<Mutex<WebRequest>>, evaluator: &mut Evaluator, name: &str, args: Vec<Object>) -> EvalResult<Object> {
+        if name.eq_ignore_ascii_case("download") {
+            let url = args.as_string(0)?;
+            let path = args.as_string(1)?;
+            let func = match args.get(2) {
+                Some(Object::Function(f)) |
+                Some(Object::AnonFunc(f)) => Some(f.clone()),
+                None |
+                Some(Object::EmptyParam) => None,
+                Some(o) => return Err(UError::new(UErrorKind::WebRequestError, UErrorMessage::NotAFunction(o.clone()))),
+            };
+            // コールバック中に自身を操作できるようロックを解放しておく
+            let mut req = mutex.lock().unwrap().clone();
+            let result = req.download(&url, &path, |received, total| {
+                if let Some(func) = &func {
+                    let total = total.map(|n| Object::Num(n as f64)).unwrap_or_default();
+                    func.invoke_with_values(evaluator, vec![Object::Num(received as f64), total])?;
+                }
+                Ok(())
+            });
+            mutex.lock().unwrap().cookies = req.cookies;
+            return result.map(Object::WebResponse);
+        }
+        let maybe_obj = {
+            let mut req = mutex.lock().unwrap();
+            req.invoke(name, args)?
+        };
+        let obj = maybe_obj.unwrap_or_else(|| Object::WebRequest(mutex.clone()));
+        Ok(obj)
+    }
+    fn invoke(&mut self, name: &str, args: Vec<Object>) -> WebResult<Option<Object>> {
         let obj = match name.to_ascii_lowercase().as_str() {
             "header" => {
                 let key = args.as_string(0)?;
@@ -110,10 +411,35 @@ impl WebRequest {
                 None
             },
             "body" => {
-                let body = args.as_string(0)?;
+                let body = match args.first() {
+                    Some(Object::ByteArray(bytes)) => RequestBody::Bytes(bytes.clone()),
+                    Some(Object::UObject(uo)) => RequestBody::Json(uo.to_string()),
+                    _ => RequestBody::Text(args.as_string(0)?),
+                };
                 self.set_body(body);
                 None
             },
+            "form" => {
+                for (name, value) in args.as_pairs()? {
+                    RequestBody::push_field(&mut self.body, name, value);
+                }
+                None
+            },
+            "multipart" => {
+                for (name, value) in args.as_pairs()? {
+                    RequestBody::push_part(&mut self.body, FormPart::Text { name, value });
+                }
+                None
+            },
+            "multipartfile" => {
+                let name = args.as_string(0)?;
+                let path = args.as_string(1)?;
+                self.check_path(&path)?;
+                let path = path.into();
+                let mime = args.as_optional_string(2);
+                RequestBody::push_part(&mut self.body, FormPart::File { name, path, mime });
+                None
+            },
             "basic" => {
                 let name = args.as_string(0)?;
                 let password = args.as_string(1).ok();
@@ -125,6 +451,117 @@ impl WebRequest {
                 self.set_bearer_auth(token);
                 None
             },
+            "cookie" => {
+                let name = args.as_string(0)?;
+                let value = args.as_string(1)?;
+                let domain = args.as_optional_string(2);
+                let path = args.as_optional_string(3);
+                self.cookies.insert(Cookie::new(name, value, domain, path));
+                None
+            },
+            "clearcookies" => {
+                self.cookies.clear();
+                None
+            },
+            "savecookies" => {
+                let path = args.as_string(0)?;
+                self.check_path(&path)?;
+                let json = self.cookies.to_json().map_err(web_error)?;
+                std::fs::write(path, json)?;
+                None
+            },
+            "loadcookies" => {
+                let path = args.as_string(0)?;
+                self.check_path(&path)?;
+                let json = std::fs::read_to_string(path)?;
+                self.cookies.load_json(&json).map_err(web_error)?;
+                None
+            },
+            "redirect" => {
+                let max = args.as_f64(0)?;
+                self.max_redirects = Some(max.max(0.0) as usize);
+                None
+            },
+            "proxy" => {
+                let url = args.as_string(0)?;
+                let auth = args.as_optional_string(1)
+                    .map(|user| (user, args.as_optional_string(2).unwrap_or_default()));
+                self.proxy = Some(ProxySetting::Server { url, auth });
+                None
+            },
+            "noproxy" => {
+                self.proxy = Some(ProxySetting::Disabled);
+                None
+            },
+            "cacert" => {
+                let path = args.as_string(0)?;
+                self.check_path(&path)?;
+                self.tls.ca = Some(path.into());
+                None
+            },
+            "clientcert" => {
+                let path = args.as_string(0)?;
+                self.check_path(&path)?;
+                let password = args.as_optional_string(1).unwrap_or_default();
+                self.tls.identity = Some((path.into(), password));
+                None
+            },
+            "insecure" => {
+                self.tls.insecure = args.as_bool_or(0, true);
+                None
+            },
+            "retry" => {
+                let count = args.as_f64(0)?;
+                self.retry.count = count.max(0.0) as u32;
+                if let Some(secs) = args.as_optional_f64(1)? {
+                    self.retry.wait = Duration::from_secs_f64(secs.max(0.0));
+                }
+                if let Some(Object::Array(statuses)) = args.get(2) {
+                    self.retry.statuses = statuses.iter()
+                        .filter_map(|o| o.as_f64(false))
+                        .map(|n| n as u16)
+                        .collect();
+                }
+                None
+            },
+            "record" => {
+                let path = args.as_string(0)?;
+                self.check_path(&path)?;
+                self.cassette = Some(Cassette::record(&path));
+                None
+            },
+            "replay" => {
+                let path = args.as_string(0)?;
+                self.check_path(&path)?;
+                let flags = args.as_optional_f64(1)?
+                    .map(|n| n as u32)
+                    .unwrap_or(WebMatchConst::REPLAY_MATCH_METHOD as u32 | WebMatchConst::REPLAY_MATCH_URL as u32);
+                let rule = MatchRule {
+                    method: flags & WebMatchConst::REPLAY_MATCH_METHOD as u32 > 0,
+                    url: flags & WebMatchConst::REPLAY_MATCH_URL as u32 > 0,
+                    body: flags & WebMatchConst::REPLAY_MATCH_BODY as u32 > 0,
+                };
+                let cassette = Cassette::replay(&path, rule).map_err(cassette_error)?;
+                self.cassette = Some(cassette);
+                None
+            },
+            "eject" => {
+                self.cassette = None;
+                None
+            },
+            "cache" => {
+                let dir = args.as_optional_string(0);
+                if let Some(dir) = &dir {
+                    self.check_path(dir)?;
+                }
+                let dir = dir.map(PathBuf::from);
+                self.cache = Some(HttpCache::new(dir)?);
+                None
+            },
+            "nocache" => {
+                self.cache = None;
+                None
+            },
             "get" => {
                 let url = args.as_string(0)?;
                 let res = self.request(Method::GET, &url)?;
@@ -163,10 +600,16 @@ impl WebRequest {
         Ok(obj)
     }
     pub fn get_property(&self, name: &str) -> WebResult<Object> {
-        Err(UError::new(
-            UErrorKind::WebRequestError,
-            UErrorMessage::InvalidMember(name.to_string())
-        ))?
+        match name.to_ascii_lowercase().as_str() {
+            "cookies" => {
+                let cookies = serde_json::to_value(self.cookies.cookies()).map_err(web_error)?;
+                Ok(Object::UObject(UObject::from(cookies)))
+            },
+            _ => Err(UError::new(
+                UErrorKind::WebRequestError,
+                UErrorMessage::InvalidMember(name.to_string())
+            ))
+        }
     }
 }
 
@@ -176,6 +619,8 @@ pub struct WebResponse {
     status: StatusCode,
     body: Option<String>,
     header: HashTbl,
+    /// キャッシュから返したかどうか
+    from_cache: bool,
 }
 impl std::fmt::Display for WebResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -188,19 +633,32 @@ impl std::fmt::Display for WebResponse {
 
 impl From<Response> for WebResponse {
     fn from(response: Response) -> Self {
+        RecordedResponse::from(response).into()
+    }
+}
+
+impl From<RecordedResponse> for WebResponse {
+    fn from(response: RecordedResponse) -> Self {
+        let status = StatusCode::from_u16(response.status).unwrap_or_default();
+        let mut header = HashTbl::new(false, false);
+        for (k, v) in response.headers {
+            header.insert(k, Object::String(v));
+        }
+        Self { status, body: response.body, header, from_cache: false }
+    }
+}
+
+impl WebResponse {
+    /// 本文を読まずにステータスとヘッダのみを得る
+    fn without_body(response: &Response) -> Self {
         let status = response.status();
         let mut header = HashTbl::new(false, false);
         for (k, v) in response.headers() {
             let value = v.to_str().ok();
             header.insert(k.to_string(), value.into());
         }
-
-        let body = response.text().ok();
-        Self { status, body, header }
+        Self { status, body: None, header, from_cache: false }
     }
-}
-
-impl WebResponse {
     fn status(&self) -> Object {
         let n = self.status.as_u16();
         n.into()
@@ -238,6 +696,7 @@ impl WebResponse {
             "header" => self.header(),
             "body" => self.body(),
             "json" => self.json(),
+            "fromcache" => self.from_cache.into(),
             _ => Err(UError::new(
                 UErrorKind::WebRequestError,
                 UErrorMessage::InvalidMember(name.to_string())
@@ -255,8 +714,11 @@ impl WebResponse {
 
 trait WebArg {
     fn as_string(&self, index: usize) -> WebResult<String>;
-    fn _as_bool(&self, index: usize) -> WebResult<bool>;
+    fn as_bool_or(&self, index: usize, default: bool) -> bool;
     fn as_f64(&self, index: usize) -> WebResult<f64>;
+    fn as_optional_string(&self, index: usize) -> Option<String>;
+    fn as_optional_f64(&self, index: usize) -> WebResult<Option<f64>>;
+    fn as_pairs(&self) -> WebResult<Vec<(String, String)>>;
 }
 impl WebArg for Vec<Object> {
     fn as_string(&self, index: usize) -> WebResult<String> {
@@ -268,9 +730,12 @@ impl WebArg for Vec<Object> {
         }
     }
 
-    fn _as_bool(&self, index: usize) -> WebResult<bool> {
-        let obj = self.get(index).ok_or(UError::new(UErrorKind::WebRequestError, UErrorMessage::BuiltinArgRequiredAt(index+1)))?;
-        Ok(obj.is_truthy())
+    fn as_bool_or(&self, index: usize, default: bool) -> bool {
+        match self.get(index) {
+            None |
+            Some(Object::EmptyParam) => default,
+            Some(obj) => obj.is_truthy(),
+        }
     }
 
     fn as_f64(&self, index: usize) -> WebResult<f64> {
@@ -278,12 +743,65 @@ impl WebArg for Vec<Object> {
         obj.as_f64(false)
             .ok_or(UError::new(UErrorKind::WebRequestError, UErrorMessage::ArgumentIsNotNumber(index+1, obj.to_string())))
     }
+
+    fn as_optional_string(&self, index: usize) -> Option<String> {
+        match self.get(index)? {
+            Object::EmptyParam |
+            Object::Empty => None,
+            obj => Some(obj.to_string()),
+        }
+    }
+
+    fn as_optional_f64(&self, index: usize) -> WebResult<Option<f64>> {
+        match self.get(index) {
+            None |
+            Some(Object::EmptyParam) |
+            Some(Object::Empty) => Ok(None),
+            Some(_) => self.as_f64(index).map(Some),
+        }
+    }
+
+    /// キーと値、または連想配列かUObjectを受ける
+    fn as_pairs(&self) -> WebResult<Vec<(String, String)>> {
+        match self.first() {
+            Some(Object::HashTbl(hash)) => {
+                let hash = hash.lock().unwrap();
+                let pairs = hash.keys().into_iter()
+                    .zip(hash.values())
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect();
+                Ok(pairs)
+            },
+            Some(Object::UObject(uo)) => {
+                match JsonValue::try_from(Object::UObject(uo.clone()))? {
+                    JsonValue::Object(map) => {
+                        let to_string = |value: JsonValue| match value {
+                            JsonValue::String(s) => s,
+                            value => value.to_string(),
+                        };
+                        // 配列は同名の項目を複数送る
+                        let pairs = map.into_iter()
+                            .flat_map(|(key, value)| match value {
+                                JsonValue::Array(arr) => arr.into_iter()
+                                    .map(|value| (key.clone(), to_string(value)))
+                                    .collect::<Vec<_>>(),
+                                value => vec![(key, to_string(value))],
+                            })
+                            .collect();
+                        Ok(pairs)
+                    },
+                    _ => Err(UError::new(UErrorKind::WebRequestError, UErrorMessage::InvalidArgument(Object::UObject(uo.clone())))),
+                }
+            },
+            _ => Ok(vec![(self.as_string(0)?, self.as_string(1)?)]),
+        }
+    }
 }
 
 trait RequestBuilderExt {
     fn set_header(self, headers: &HeaderMap<HeaderValue>) -> Self;
     fn set_timeout(self, timeout: Option<Duration>) -> Self;
-    fn set_body(self, body: &Option<String>) -> Self;
+    fn set_body(self, body: Option<&EncodedBody>, headers: &HeaderMap<HeaderValue>) -> Self;
     fn set_basic_auth(self, basic: &Option<(String, Option<String>)>) -> Self;
     fn set_bearer_auth(self, token: &Option<String>) -> Self;
 }
@@ -303,11 +821,19 @@ impl RequestBuilderExt for RequestBuilder {
             self
         }
     }
-    fn set_body(self, body: &Option<String>) -> Self {
-        if let Some(body) = body {
-            self.body(body.to_string())
-        } else {
-            self
+    fn set_body(self, body: Option<&EncodedBody>, headers: &HeaderMap<HeaderValue>) -> Self {
+        match body {
+            Some((content_type, bytes)) => {
+                // Content-Typeが明示されていればそちらを優先する
+                let builder = match content_type {
+                    Some(content_type) if ! headers.contains_key(header::CONTENT_TYPE) => {
+                        self.header(header::CONTENT_TYPE, content_type)
+                    },
+                    _ => self,
+                };
+                builder.body(bytes.clone())
+            },
+            None => self,
         }
     }
     fn set_basic_auth(self, basic: &Option<(String, Option<String>)>) -> Self {
@@ -327,7 +853,9 @@ impl RequestBuilderExt for RequestBuilder {
 }
 
 /* ParseHTML */
-use scraper::{Html, ElementRef, element_ref::Select, Selector, error::SelectorErrorKind};
+use scraper::{Html, ElementRef, Selector, error::SelectorErrorKind};
+use ego_tree::NodeId;
+use super::xml::xpath::{self, XPathItem};
 
 impl From<SelectorErrorKind<'_>> for UError {
     fn from(e: SelectorErrorKind) -> Self {
@@ -341,37 +869,30 @@ impl From<SelectorErrorKind<'_>> for UError {
 pub enum Accessor {
     Selector(Selector),
     Index(usize),
+    /// XPathや親子・兄弟の参照で得たエレメントのコレクション
+    Nodes(Vec<NodeId>),
 }
-impl Accessor {
-    fn as_selector(&self) -> Option<&Selector> {
-        match self {
-            Accessor::Selector(selector) => Some(selector),
-            Accessor::Index(_) => None,
-        }
-    }
-    fn as_index(&self) -> Option<usize> {
-        match self {
-            Accessor::Selector(_) => None,
-            Accessor::Index(index) => Some(*index),
-        }
-    }
-}
-enum Accessed<'a, 'b> {
-    Select(Box<Select<'a, 'b>>),
+enum Accessed<'a> {
+    Collection(Vec<ElementRef<'a>>),
     ElementRef(ElementRef<'a>),
 }
-#[derive(Debug, Clone, PartialEq)]
+/// 同じ文書から得たHtmlNodeは文書を共有するため、変更はすべてのHtmlNodeに反映される
+#[derive(Debug, Clone)]
 pub struct HtmlNode {
-    html: Html,
+    html: Arc<Mutex<Html>>,
     accessors: Vec<Accessor>,
 }
+impl PartialEq for HtmlNode {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.html, &other.html) && self.accessors == other.accessors
+    }
+}
 impl std::fmt::Display for HtmlNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-
-        match self.access() {
+        self.with_access(|accessed| match accessed {
             Some(a) => match a {
-                Accessed::Select(select) => {
-                    let names = select.map(|e| e.value().name()).collect::<Vec<_>>();
+                Accessed::Collection(elements) => {
+                    let names = elements.iter().map(|e| e.value().name()).collect::<Vec<_>>();
                     write!(f, "Collection: {names:?}")
                 },
                 Accessed::ElementRef(element_ref) => {
@@ -384,7 +905,7 @@ impl std::fmt::Display for HtmlNode {
                 },
             },
             None => write!(f, ""),
-        }
+        })
     }
 }
 
@@ -392,53 +913,85 @@ impl HtmlNode {
     pub fn new(html: &str) -> Self {
         let html = Html::parse_fragment(html);
         Self {
-            html,
+            html: Arc::new(Mutex::new(html)),
             accessors: Vec::new()
         }
     }
-    fn access(&self) -> Option<Accessed> {
-        let root = Accessed::ElementRef(self.html.root_element());
+    fn access<'a>(&self, html: &'a Html) -> Option<Accessed<'a>> {
+        let root = Accessed::ElementRef(html.root_element());
         let accessed = self.accessors.iter()
             .try_fold(root, |accessed, accessor| {
-                match accessed {
-                    Accessed::Select(mut select) => {
-                        select.nth(accessor.as_index()?)
+                match (accessed, accessor) {
+                    (_, Accessor::Nodes(ids)) => {
+                        let elements = ids.iter()
+                            .filter_map(|id| html.tree.get(*id).and_then(ElementRef::wrap))
+                            .collect();
+                        Some(Accessed::Collection(elements))
+                    },
+                    (Accessed::Collection(elements), Accessor::Index(index)) => {
+                        elements.get(*index).copied()
                             .map(Accessed::ElementRef)
                     },
-                    Accessed::ElementRef(element_ref) => {
-                        let select = element_ref.select(accessor.as_selector()?);
-                        Some(Accessed::Select(Box::new(select)))
+                    (Accessed::ElementRef(element_ref), Accessor::Selector(selector)) => {
+                        let elements = element_ref.select(selector).collect();
+                        Some(Accessed::Collection(elements))
                     },
+                    _ => None,
                 }
             });
         accessed
     }
+    fn with_access<R>(&self, f: impl FnOnce(Option<Accessed>) -> R) -> R {
+        let html = self.html.lock().unwrap();
+        f(self.access(&html))
+    }
     fn push_accessor(&mut self, accessor: Accessor) {
         self.accessors.push(accessor);
     }
     pub fn set_index(&mut self, index: usize) {
         self.push_accessor(Accessor::Index(index));
     }
-    pub fn len(&self) -> Option<usize> {
-        match self.access()? {
-            Accessed::Select(select) => Some(select.count()),
-            Accessed::ElementRef(_) => None,
+    /// 同じ文書の指定エレメントを示すHtmlNodeを作る
+    fn nodes(&self, ids: Vec<NodeId>) -> Self {
+        Self {
+            html: Arc::clone(&self.html),
+            accessors: vec![Accessor::Nodes(ids)],
         }
     }
-    pub fn into_vec(self) -> Option<Vec<Object>> {
-        match self.access()? {
-            Accessed::Select(select) => {
-                let vec = select.enumerate().map(|(index, _)| {
-                    let mut node = self.clone();
-                    node.set_index(index);
-                    Object::HtmlNode(node)
-                })
-                .collect();
-                Some(vec)
+    fn node_or_empty(&self, id: Option<NodeId>) -> Object {
+        match id {
+            Some(id) => {
+                let mut node = self.nodes(vec![id]);
+                node.set_index(0);
+                Object::HtmlNode(node)
             },
-            Accessed::ElementRef(_) => None,
+            None => Object::Empty,
         }
     }
+    /// 対象となるエレメント、コレクションならそのすべて
+    fn target_ids(&self) -> Vec<NodeId> {
+        self.with_access(|accessed| match accessed {
+            Some(Accessed::Collection(elements)) => elements.iter().map(|e| e.id()).collect(),
+            Some(Accessed::ElementRef(element_ref)) => vec![element_ref.id()],
+            None => vec![],
+        })
+    }
+    pub fn len(&self) -> Option<usize> {
+        self.with_access(|accessed| match accessed? {
+            Accessed::Collection(elements) => Some(elements.len()),
+            Accessed::ElementRef(_) => None,
+        })
+    }
+    pub fn into_vec(self) -> Option<Vec<Object>> {
+        let len = self.len()?;
+        let vec = (0..len).map(|index| {
+            let mut node = self.clone();
+            node.set_index(index);
+            Object::HtmlNode(node)
+        })
+        .collect();
+        Some(vec)
+    }
 
     pub fn get_property(&self, name: &str) -> WebResult<Object> {
         match name.to_ascii_lowercase().as_str() {
@@ -449,7 +1002,8 @@ impl HtmlNode {
             "iselement" => {
                 let obj = match self.accessors.last() {
                     Some(a) => match a {
-                        Accessor::Selector(_) => false,
+                        Accessor::Selector(_) |
+                        Accessor::Nodes(_) => false,
                         Accessor::Index(_) => true,
                     },
                     // 空の場合はルートなのでtrue
@@ -460,7 +1014,8 @@ impl HtmlNode {
             "iscollection" => {
                 let obj = match self.accessors.last() {
                     Some(a) => match a {
-                        Accessor::Selector(_) => true,
+                        Accessor::Selector(_) |
+                        Accessor::Nodes(_) => true,
                         Accessor::Index(_) => false,
                     },
                     None => false,
@@ -468,6 +1023,26 @@ impl HtmlNode {
                 Ok(obj)
             },
             "textcontent" => self.text_content(None, true),
+            "name" => Ok(self.with_access(|accessed| match accessed {
+                Some(Accessed::ElementRef(e)) => e.value().name().into(),
+                _ => Object::Empty,
+            })),
+            "parent" => Ok(self.relative(|e| {
+                e.parent().and_then(ElementRef::wrap).map(|p| p.id())
+            })),
+            "next" | "nextsibling" => Ok(self.relative(|e| {
+                e.next_siblings().find_map(ElementRef::wrap).map(|s| s.id())
+            })),
+            "prev" | "prevsibling" => Ok(self.relative(|e| {
+                e.prev_siblings().find_map(ElementRef::wrap).map(|s| s.id())
+            })),
+            "children" => {
+                let ids = self.with_access(|accessed| match accessed {
+                    Some(Accessed::ElementRef(e)) => Some(e.child_elements().map(|c| c.id()).collect::<Vec<_>>()),
+                    _ => None,
+                });
+                Ok(ids.map(|ids| Object::HtmlNode(self.nodes(ids))).unwrap_or_default())
+            },
             _ => Err(UError::new(
                 UErrorKind::HtmlNodeError,
                 UErrorMessage::InvalidMember(name.to_string())
@@ -484,13 +1059,21 @@ impl HtmlNode {
                 let selectors = args.as_string(0)?;
                 self.first(&selectors)
             },
+            "xpath" => {
+                let path = args.as_string(0)?;
+                self.xpath(&path, false)
+            },
+            "xpathfirst" => {
+                let path = args.as_string(0)?;
+                self.xpath(&path, true)
+            },
             "attr" | "attribute" => {
                 let name = args.as_string(0)?;
                 self.attr(&name)
             },
             "textcontent" => {
                 let sep = args.as_string(0).ok();
-                let trim = args._as_bool(1).unwrap_or(true);
+                let trim = args.as_bool_or(1, true);
                 self.text_content(sep.as_deref(), trim)
             }
             "count" => {
@@ -499,6 +1082,51 @@ impl HtmlNode {
                     .unwrap_or(Object::Empty);
                 Ok(obj)
             }
+            "formdata" => Ok(self.form_data()),
+            "totable" => {
+                let repeat = args.as_bool_or(0, true);
+                let obj = self.table_grid(repeat)
+                    .map(|grid| {
+                        let rows = grid.into_iter()
+                            .map(|row| Object::Array(row.into_iter().map(Object::from).collect()))
+                            .collect();
+                        Object::Array(rows)
+                    })
+                    .unwrap_or_default();
+                Ok(obj)
+            },
+            "tocsv" => {
+                let delimiter = args.as_optional_string(0)
+                    .and_then(|s| s.bytes().next())
+                    .unwrap_or(b',');
+                let repeat = args.as_bool_or(1, true);
+                match self.table_grid(repeat) {
+                    Some(grid) => {
+                        let csv = html::grid_to_csv(&grid, delimiter)
+                            .map_err(|e| UError::new(UErrorKind::HtmlNodeError, UErrorMessage::Any(e)))?;
+                        Ok(csv.into())
+                    },
+                    None => Ok(Object::Empty),
+                }
+            },
+            "setattr" => {
+                let name = args.as_string(0)?;
+                let value = args.as_optional_string(1).unwrap_or_default();
+                self.mutate(|doc, id| html::set_attr(doc, id, &name, &value));
+                Ok(Object::HtmlNode(self))
+            },
+            "removeattr" => {
+                let name = args.as_string(0)?;
+                Ok(self.mutate(|doc, id| html::remove_attr(doc, id, &name)).into())
+            },
+            "settext" => {
+                let text = args.as_optional_string(0).unwrap_or_default();
+                self.mutate(|doc, id| html::set_text(doc, id, &text));
+                Ok(Object::HtmlNode(self))
+            },
+            "remove" => {
+                Ok(self.mutate(html::remove).into())
+            },
             _ => Err(UError::new(
                 UErrorKind::HtmlNodeError,
                 UErrorMessage::InvalidMember(name.to_string())
@@ -519,10 +1147,10 @@ impl HtmlNode {
                 None => t1 + t2,
             }
         };
-        let obj = match self.access() {
+        let obj = self.with_access(|accessed| match accessed {
             Some(a) => match a {
-                Accessed::Select(select) => {
-                    let arr = select
+                Accessed::Collection(elements) => {
+                    let arr = elements.into_iter()
                         .map(|e| {
                             let text_content = e.text()
                                 .map(|t| if trim {t.trim()} else {t})
@@ -540,40 +1168,40 @@ impl HtmlNode {
                 }
             },
             None => Object::Empty,
-        };
+        });
         Ok(obj)
     }
     fn outer_html(&self) -> WebResult<Object> {
-        let obj = match self.access() {
+        let obj = self.with_access(|accessed| match accessed {
             Some(a) => match a {
-                Accessed::Select(select) => {
-                    let arr = select.map(|e| e.html().into()).collect();
+                Accessed::Collection(elements) => {
+                    let arr = elements.into_iter().map(|e| e.html().into()).collect();
                     Object::Array(arr)
                 },
                 Accessed::ElementRef(element_ref) => element_ref.html().into(),
             },
             None => Object::Empty,
-        };
+        });
         Ok(obj)
     }
     fn inner_html(&self) -> WebResult<Object> {
-        let obj = match self.access() {
+        let obj = self.with_access(|accessed| match accessed {
             Some(a) => match a {
-                Accessed::Select(select) => {
-                    let arr = select.map(|e| e.inner_html().into()).collect();
+                Accessed::Collection(elements) => {
+                    let arr = elements.into_iter().map(|e| e.inner_html().into()).collect();
                     Object::Array(arr)
                 },
                 Accessed::ElementRef(element_ref) => element_ref.inner_html().into(),
             },
             None => Object::Empty,
-        };
+        });
         Ok(obj)
     }
     fn texts(&self) -> WebResult<Object> {
-        let obj = match self.access() {
+        let obj = self.with_access(|accessed| match accessed {
             Some(a) => match a {
-                Accessed::Select(select) => {
-                    let arr = select
+                Accessed::Collection(elements) => {
+                    let arr = elements.into_iter()
                         .map(|e| {
                             let arr = e.text().map(|t| t.into()).collect();
                             Object::Array(arr)
@@ -587,43 +1215,71 @@ impl HtmlNode {
                 }
             },
             None => Object::Empty,
-        };
+        });
         Ok(obj)
     }
+    fn is_element(&self) -> bool {
+        self.with_access(|accessed| matches!(accessed, Some(Accessed::ElementRef(_))))
+    }
     fn find(mut self, selectors: &str) -> WebResult<Object> {
-        let obj = match self.access() {
-            Some(a) => match a {
-                Accessed::Select(_) => Object::Empty,
-                Accessed::ElementRef(_) => {
-                    let selector = Selector::parse(selectors)?;
-                    self.push_accessor(Accessor::Selector(selector));
-                    Object::HtmlNode(self)
-                }
-            },
-            None => Object::Empty,
+        let obj = if self.is_element() {
+            let selector = Selector::parse(selectors)?;
+            self.push_accessor(Accessor::Selector(selector));
+            Object::HtmlNode(self)
+        } else {
+            Object::Empty
         };
         Ok(obj)
     }
     fn first(mut self, selectors: &str) -> WebResult<Object> {
-        let obj = match self.access() {
-            Some(a) => match a {
-                Accessed::Select(_) => Object::Empty,
-                Accessed::ElementRef(_) => {
-                    let selector = Selector::parse(selectors)?;
-                    self.push_accessor(Accessor::Selector(selector));
-                    self.push_accessor(Accessor::Index(0));
-                    Object::HtmlNode(self)
-                }
-            },
-            None => Object::Empty,
+        let obj = if self.is_element() {
+            let selector = Selector::parse(selectors)?;
+            self.push_accessor(Accessor::Selector(selector));
+            self.push_accessor(Accessor::Index(0));
+            Object::HtmlNode(self)
+        } else {
+            Object::Empty
+        };
+        Ok(obj)
+    }
+    /// XPathに該当するエレメントのコレクション、属性やテキストを得た場合は文字列の配列
+    fn xpath(&self, path: &str, first: bool) -> WebResult<Object> {
+        let items = {
+            let html = self.html.lock().unwrap();
+            let Some(Accessed::ElementRef(context)) = self.access(&html) else {
+                return Ok(Object::Empty);
+            };
+            xpath::evaluate(&*html, context.id(), path)
+                .map_err(|e| UError::new(UErrorKind::HtmlNodeError, UErrorMessage::InvalidXPath(path.to_string(), e)))?
+        };
+        let mut ids = vec![];
+        let mut values = vec![];
+        for item in items {
+            match item {
+                XPathItem::Node(id) => ids.push(id),
+                XPathItem::Value(s) => values.push(Object::from(s)),
+            }
+        }
+        // テキストノードなどエレメント以外はコレクションに含まれない
+        let obj = if values.is_empty() {
+            let node = self.nodes(ids);
+            if first {
+                node.into_vec().and_then(|v| v.into_iter().next()).unwrap_or_default()
+            } else {
+                Object::HtmlNode(node)
+            }
+        } else if first {
+            values.into_iter().next().unwrap_or_default()
+        } else {
+            Object::Array(values)
         };
         Ok(obj)
     }
     fn attr(&self, attr: &str) -> WebResult<Object> {
-        let obj = match self.access() {
+        let obj = self.with_access(|accessed| match accessed {
             Some(a) => match a {
-                Accessed::Select(s) => {
-                    let arr = s.map(|e| e.attr(attr).into()).collect();
+                Accessed::Collection(elements) => {
+                    let arr = elements.into_iter().map(|e| e.attr(attr).into()).collect();
                     Object::Array(arr)
                 },
                 Accessed::ElementRef(e) => {
@@ -631,7 +1287,194 @@ impl HtmlNode {
                 }
             },
             None => Object::Empty,
-        };
+        });
         Ok(obj)
     }
-}
\ No newline at end of file
+    /// エレメントからの相対位置にあるエレメント
+    fn relative(&self, f: impl FnOnce(ElementRef) -> Option<NodeId>) -> Object {
+        let id = self.with_access(|accessed| match accessed {
+            Some(Accessed::ElementRef(e)) => f(e),
+            _ => None,
+        });
+        self.node_or_empty(id)
+    }
+    /// フォームの送信項目をUObjectにする、同名の項目が複数あれば配列にする
+    fn form_data(&self) -> Object {
+        let fields = self.with_access(|accessed| match accessed {
+            Some(Accessed::ElementRef(e)) => Some(html::form_fields(e)),
+            _ => None,
+        });
+        let Some(fields) = fields else {
+            return Object::Empty;
+        };
+        let mut map = serde_json::Map::new();
+        for (name, value) in fields {
+            let value = JsonValue::String(value);
+            match map.get_mut(&name) {
+                Some(JsonValue::Array(arr)) => arr.push(value),
+                Some(prev) => *prev = JsonValue::Array(vec![prev.take(), value]),
+                None => {
+                    map.insert(name, value);
+                },
+            }
+        }
+        Object::UObject(UObject::from(JsonValue::Object(map)))
+    }
+    /// tableエレメントの内容、table以外ならNone
+    fn table_grid(&self, repeat: bool) -> Option<Vec<Vec<String>>> {
+        self.with_access(|accessed| match accessed {
+            Some(Accessed::ElementRef(e)) if e.value().name() == "table" => Some(html::table_grid(e, repeat)),
+            _ => None,
+        })
+    }
+    /// 対象のエレメントそれぞれを変更する、ひとつでも変更された場合true
+    fn mutate(&self, mut f: impl FnMut(&mut Html, NodeId) -> bool) -> bool {
+        let ids = self.target_ids();
+        let mut html = self.html.lock().unwrap();
+        let mut changed = false;
+        for id in ids {
+            changed |= f(&mut html, id);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    /// 用意した応答を順に返すサーバー、受信したリクエストを返す
+    fn serve(responses: Vec<&'static str>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            responses.into_iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request = String::new();
+                    let mut length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line == "\r\n" {
+                            break;
+                        }
+                        if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                            length = value.trim().parse().unwrap();
+                        }
+                        request.push_str(&line);
+                    }
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    request.push_str(&String::from_utf8_lossy(&body));
+                    stream.write_all(response.as_bytes()).unwrap();
+                    request
+                })
+                .collect()
+        });
+        (base, handle)
+    }
+
+    fn s(s: &str) -> Object {
+        Object::String(s.to_string())
+    }
+
+    #[test]
+    fn test_redirect_and_cookie() {
+        let (base, handle) = serve(vec![
+            "HTTP/1.1 302 Found\r\nLocation: /next\r\nSet-Cookie: sid=abc; Path=/\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ]);
+        let mut req = WebRequest::new();
+        req.invoke("form", vec![s("name"), s("a b")]).unwrap();
+        let res = req.request(Method::POST, &format!("{base}/login")).unwrap();
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body.as_deref(), Some("ok"));
+
+        let requests = handle.join().unwrap();
+        let first = requests[0].to_ascii_lowercase();
+        assert!(first.starts_with("post /login "));
+        assert!(first.contains("content-type: application/x-www-form-urlencoded"));
+        assert!(first.ends_with("name=a+b"));
+        // 302 の後は本文なしの GET で、受け取ったCookieを送る
+        let second = requests[1].to_ascii_lowercase();
+        assert!(second.starts_with("get /next "));
+        assert!(second.contains("cookie: sid=abc"));
+        assert!(! second.contains("name=a+b"));
+    }
+
+    #[test]
+    fn test_cache_and_replay() {
+        let (base, handle) = serve(vec![
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 4\r\nConnection: close\r\n\r\nbody",
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n",
+        ]);
+        let url = format!("{base}/data");
+        let path = std::env::temp_dir().join("uwscr_replay_test.json");
+        let mut req = WebRequest::new();
+        req.invoke("cache", vec![]).unwrap();
+        req.invoke("record", vec![s(&path.to_string_lossy())]).unwrap();
+        let res = req.get(&url).unwrap();
+        assert!(! res.from_cache);
+        let res = req.get(&url).unwrap();
+        assert!(res.from_cache);
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.body.as_deref(), Some("body"));
+        let requests = handle.join().unwrap();
+        assert!(requests[1].to_ascii_lowercase().contains("if-none-match: \"v1\""));
+
+        // サーバーなしで再生する
+        let mut req = WebRequest::new();
+        req.invoke("replay", vec![s(&path.to_string_lossy())]).unwrap();
+        let res = req.get(&url).unwrap();
+        assert_eq!(res.body.as_deref(), Some("body"));
+        let res = req.get(&url).unwrap();
+        assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+        assert!(req.get(&format!("{base}/other")).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let root = std::env::temp_dir().join("uwscr_web_sandbox");
+        let outside = std::env::temp_dir().join("uwscr_web_outside.txt");
+        let outside = outside.to_string_lossy();
+        let policy = SandboxPolicy::new().allow_root(&root);
+        let mut req = WebRequest::new().with_sandbox(Some(Arc::new(policy)));
+        let denied = |r: WebResult<Option<Object>>| matches!(r, Err(e) if e.kind == UErrorKind::SandboxError);
+        assert!(denied(req.invoke("cacert", vec![s(&outside)])));
+        assert!(denied(req.invoke("clientcert", vec![s(&outside), s("pass")])));
+        assert!(denied(req.invoke("multipartfile", vec![s("file"), s(&outside)])));
+        assert!(denied(req.invoke("savecookies", vec![s(&outside)])));
+        assert!(denied(req.invoke("record", vec![s(&outside)])));
+        // 接続する前に拒否される
+        let err = req.download("http://127.0.0.1:1/", &outside, |_, _| Ok(())).unwrap_err();
+        assert_eq!(err.kind, UErrorKind::SandboxError);
+        let inside = root.join("ca.pem");
+        assert!(req.invoke("cacert", vec![s(&inside.to_string_lossy())]).is_ok());
+    }
+
+    #[test]
+    fn test_retry_and_download() {
+        let (base, handle) = serve(vec![
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n0123456789",
+        ]);
+        let mut req = WebRequest::new();
+        req.invoke("retry", vec![Object::Num(2.0), Object::Num(0.01)]).unwrap();
+        let path = std::env::temp_dir().join("uwscr_download_test.txt");
+        let mut progress = vec![];
+        let res = req.download(&format!("{base}/file"), &path.to_string_lossy(), |received, total| {
+            progress.push((received, total));
+            Ok(())
+        }).unwrap();
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(progress.last(), Some(&(10, Some(10))));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "0123456789");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(handle.join().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,114 @@
+//! ETag/Last-Modifiedによる条件付きリクエストのためのHTTPキャッシュ
+
+use super::cassette::RecordedResponse;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub response: RecordedResponse,
+}
+
+/// GETのレスポンスをURLごとに保持する
+///
+/// ディレクトリを指定した場合はファイルにも保存し、スクリプトの実行をまたいで使う
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HttpCache {
+    dir: Option<PathBuf>,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl HttpCache {
+    pub fn new(dir: Option<PathBuf>) -> std::io::Result<Self> {
+        if let Some(dir) = &dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self { dir, entries: HashMap::new() })
+    }
+    pub fn get(&mut self, url: &str) -> Option<&CacheEntry> {
+        if ! self.entries.contains_key(url) {
+            let path = self.file_path(url)?;
+            let text = std::fs::read_to_string(path).ok()?;
+            let entry: CacheEntry = serde_json::from_str(&text).ok()?;
+            // ハッシュが衝突した別URLのエントリは使わない
+            if entry.url != url {
+                return None;
+            }
+            self.entries.insert(url.to_string(), entry);
+        }
+        self.entries.get(url)
+    }
+    /// ETagかLast-Modifiedを持つレスポンスを保存する、それ以外は破棄する
+    pub fn store(&mut self, url: &str, response: &RecordedResponse) -> std::io::Result<()> {
+        let no_store = response.header("cache-control")
+            .is_some_and(|cc| cc.to_ascii_lowercase().contains("no-store"));
+        let etag = response.header("etag").map(|s| s.to_string());
+        let last_modified = response.header("last-modified").map(|s| s.to_string());
+        if no_store || (etag.is_none() && last_modified.is_none()) {
+            self.entries.remove(url);
+            if let Some(path) = self.file_path(url) && path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        let entry = CacheEntry {
+            url: url.to_string(),
+            etag,
+            last_modified,
+            response: response.clone(),
+        };
+        if let Some(path) = self.file_path(url) {
+            let json = serde_json::to_string(&entry)?;
+            std::fs::write(path, json)?;
+        }
+        self.entries.insert(url.to_string(), entry);
+        Ok(())
+    }
+    fn file_path(&self, url: &str) -> Option<PathBuf> {
+        self.dir.as_deref().map(|dir| cache_file(dir, url))
+    }
+}
+
+/// URLのハッシュ値 (FNV-1a) をファイル名にする
+fn cache_file(dir: &Path, url: &str) -> PathBuf {
+    let hash = url.bytes().fold(0xcbf29ce484222325_u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    dir.join(format!("{hash:016x}.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http_cache() {
+        let dir = std::env::temp_dir().join("uwscr_http_cache_test");
+        let url = "http://example.com/data";
+        let response = RecordedResponse {
+            status: 200,
+            headers: vec![("ETag".into(), "\"v1\"".into())],
+            body: Some("cached".into()),
+        };
+        let mut cache = HttpCache::new(Some(dir.clone())).unwrap();
+        cache.store(url, &response).unwrap();
+        assert_eq!(cache.get(url).unwrap().etag.as_deref(), Some("\"v1\""));
+
+        // ファイルから読み込める
+        let mut cache = HttpCache::new(Some(dir.clone())).unwrap();
+        assert_eq!(cache.get(url).unwrap().response.body.as_deref(), Some("cached"));
+        assert!(cache.get("http://example.com/other").is_none());
+
+        // 検証子を持たないレスポンスは破棄する
+        let no_validator = RecordedResponse { status: 200, headers: vec![], body: None };
+        cache.store(url, &no_validator).unwrap();
+        assert!(cache.get(url).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+//! WebRequestの送受信内容を記録し、ネットワークに接続せず再生する
+
+use serde::{Deserialize, Serialize};
+use reqwest::{Url, blocking::Response};
+
+use std::path::{Path, PathBuf};
+
+pub type CassetteResult<T> = Result<T, String>;
+
+/// 照合に使うリクエスト内容
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub body: Option<String>,
+}
+impl RecordedRequest {
+    pub fn new(method: &str, url: &str, body: Option<&[u8]>) -> Self {
+        // 末尾の / などの表記揺れを吸収する
+        let url = Url::parse(url)
+            .map(|url| url.to_string())
+            .unwrap_or(url.to_string());
+        Self {
+            method: method.to_ascii_uppercase(),
+            url,
+            body: body.map(|b| String::from_utf8_lossy(b).to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub body: Option<String>,
+}
+impl RecordedResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+impl From<Response> for RecordedResponse {
+    fn from(response: Response) -> Self {
+        let status = response.status().as_u16();
+        let headers = response.headers().iter()
+            .filter_map(|(key, value)| {
+                let value = value.to_str().ok()?;
+                Some((key.to_string(), value.to_string()))
+            })
+            .collect();
+        let body = response.text().ok();
+        Self { status, headers, body }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    #[serde(default)]
+    interactions: Vec<Interaction>,
+}
+
+/// 再生時にリクエストのどの部分を照合するか
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchRule {
+    pub method: bool,
+    pub url: bool,
+    pub body: bool,
+}
+impl MatchRule {
+    fn matches(&self, recorded: &RecordedRequest, request: &RecordedRequest) -> bool {
+        (! self.method || recorded.method == request.method)
+            && (! self.url || recorded.url == request.url)
+            && (! self.body || recorded.body == request.body)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CassetteMode {
+    Record,
+    Replay(MatchRule),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Vec<Interaction>,
+    /// 再生済みのもの
+    played: Vec<bool>,
+}
+
+impl Cassette {
+    /// 新たに記録する、既存のファイルは最初の記録時に上書きされる
+    pub fn record(path: &str) -> Self {
+        Self {
+            path: path.into(),
+            mode: CassetteMode::Record,
+            interactions: Vec::new(),
+            played: Vec::new(),
+        }
+    }
+    pub fn replay(path: &str, rule: MatchRule) -> CassetteResult<Self> {
+        let path = PathBuf::from(path);
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        let file: CassetteFile = if is_yaml(&path) {
+            serde_yml::from_str(&text).map_err(|e| e.to_string())?
+        } else {
+            serde_json::from_str(&text).map_err(|e| e.to_string())?
+        };
+        let played = vec![false; file.interactions.len()];
+        Ok(Self {
+            path,
+            mode: CassetteMode::Replay(rule),
+            interactions: file.interactions,
+            played,
+        })
+    }
+    pub fn is_replay(&self) -> bool {
+        matches!(self.mode, CassetteMode::Replay(_))
+    }
+    /// 一致するレスポンスを返す
+    ///
+    /// 同じリクエストが複数記録されていれば記録順に返し、使い切った後は最後のものを返し続ける
+    pub fn find(&mut self, request: &RecordedRequest) -> Option<RecordedResponse> {
+        let CassetteMode::Replay(rule) = self.mode else {
+            return None;
+        };
+        let matched = self.interactions.iter()
+            .enumerate()
+            .filter(|(_, i)| rule.matches(&i.request, request))
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        let index = matched.iter()
+            .find(|index| ! self.played[**index])
+            .or(matched.last())
+            .copied()?;
+        self.played[index] = true;
+        Some(self.interactions[index].response.clone())
+    }
+    /// 記録してファイルに保存する
+    pub fn push(&mut self, interaction: Interaction) -> CassetteResult<()> {
+        self.interactions.push(interaction);
+        self.save()
+    }
+    fn save(&self) -> CassetteResult<()> {
+        let file = CassetteFile { interactions: self.interactions.clone() };
+        let text = if is_yaml(&self.path) {
+            serde_yml::to_string(&file).map_err(|e| e.to_string())?
+        } else {
+            serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?
+        };
+        std::fs::write(&self.path, text)
+            .map_err(|e| format!("{}: {e}", self.path.display()))
+    }
+}
+
+fn is_yaml(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("yml") || ext.eq_ignore_ascii_case("yaml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str) -> RecordedResponse {
+        RecordedResponse { status: 200, headers: vec![("content-type".into(), "text/plain".into())], body: Some(body.into()) }
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        for name in ["uwscr_cassette_test.json", "uwscr_cassette_test.yml"] {
+            let path = std::env::temp_dir().join(name);
+            let path = path.to_string_lossy();
+            let mut cassette = Cassette::record(&path);
+            let get = RecordedRequest::new("get", "http://example.com", None);
+            let post = RecordedRequest::new("POST", "http://example.com/", Some(b"a=1"));
+            cassette.push(Interaction { request: get.clone(), response: response("first") }).unwrap();
+            cassette.push(Interaction { request: get.clone(), response: response("second") }).unwrap();
+            cassette.push(Interaction { request: post.clone(), response: response("posted") }).unwrap();
+
+            let rule = MatchRule { method: true, url: true, body: false };
+            let mut cassette = Cassette::replay(&path, rule).unwrap();
+            assert_eq!(get.url, "http://example.com/");
+            assert_eq!(cassette.find(&get).unwrap().body.as_deref(), Some("first"));
+            assert_eq!(cassette.find(&get).unwrap().body.as_deref(), Some("second"));
+            assert_eq!(cassette.find(&get).unwrap().body.as_deref(), Some("second"));
+            let other_body = RecordedRequest::new("POST", "http://example.com/", Some(b"a=2"));
+            assert_eq!(cassette.find(&other_body).unwrap().body.as_deref(), Some("posted"));
+            assert!(cassette.find(&RecordedRequest::new("GET", "http://example.org/", None)).is_none());
+
+            let rule = MatchRule { method: true, url: true, body: true };
+            let mut cassette = Cassette::replay(&path, rule).unwrap();
+            assert!(cassette.find(&other_body).is_none());
+            assert_eq!(cassette.find(&post).unwrap().header("Content-Type"), Some("text/plain"));
+            std::fs::remove_file(&*path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! WebRequestが保持するCookie (RFC 6265 のサブセット)
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// 空文字ならすべてのホストに送る
+    pub domain: String,
+    /// Domain属性がない場合は発行元のホストにのみ送る
+    #[serde(default)]
+    pub host_only: bool,
+    pub path: String,
+    #[serde(default)]
+    pub secure: bool,
+    /// 有効期限 (UNIX時間)、Noneならセッション中のみ有効
+    #[serde(default)]
+    pub expires: Option<i64>,
+}
+
+impl Cookie {
+    pub fn new(name: String, value: String, domain: Option<String>, path: Option<String>) -> Self {
+        let domain = domain
+            .map(|d| d.trim_start_matches('.').to_ascii_lowercase())
+            .unwrap_or_default();
+        Self {
+            name,
+            value,
+            domain,
+            host_only: false,
+            path: path.unwrap_or("/".into()),
+            secure: false,
+            expires: None,
+        }
+    }
+    /// Set-Cookieヘッダを解釈する、不正な値や発行元と無関係なドメインの場合はNone
+    pub fn parse(header: &str, url: &Url) -> Option<Self> {
+        let mut attrs = header.split(';');
+        let (name, value) = attrs.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let host = url.host_str()?.to_ascii_lowercase();
+        let mut cookie = Self {
+            name: name.to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            secure: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        for attr in attrs {
+            let (key, val) = match attr.split_once('=') {
+                Some((key, val)) => (key.trim(), val.trim()),
+                None => (attr.trim(), ""),
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "domain" => {
+                    let domain = val.trim_start_matches('.').to_ascii_lowercase();
+                    if ! domain.is_empty() {
+                        if ! domain_match(&host, &domain) {
+                            return None;
+                        }
+                        cookie.domain = domain;
+                        cookie.host_only = false;
+                    }
+                },
+                "path" if val.starts_with('/') => cookie.path = val.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = val.parse::<i64>().ok(),
+                "expires" => cookie.expires = parse_date(val),
+                _ => {},
+            }
+        }
+        // Max-AgeはExpiresより優先される
+        if let Some(age) = max_age {
+            cookie.expires = Some(Utc::now().timestamp().saturating_add(age));
+        }
+        Some(cookie)
+    }
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+    fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_ok = if self.domain.is_empty() {
+            true
+        } else if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(&host, &self.domain)
+        };
+        domain_ok
+            && (! self.secure || url.scheme() == "https")
+            && path_match(url.path(), &self.path)
+    }
+    fn is_same(&self, other: &Cookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+/// 受信したCookieを保持し、送信先に応じたCookieヘッダを作る
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// 同名のCookieは置き換える、期限切れのCookieは削除される
+    pub fn insert(&mut self, cookie: Cookie) {
+        self.cookies.retain(|c| ! c.is_same(&cookie));
+        if ! cookie.is_expired(Utc::now().timestamp()) {
+            self.cookies.push(cookie);
+        }
+    }
+    /// レスポンスのSet-Cookieヘッダを取り込む
+    pub fn store<'a>(&mut self, url: &Url, headers: impl Iterator<Item = &'a str>) {
+        for header in headers {
+            if let Some(cookie) = Cookie::parse(header, url) {
+                self.insert(cookie);
+            }
+        }
+    }
+    /// 送信先に該当するCookieヘッダの値、該当なしならNone
+    pub fn header(&self, url: &Url) -> Option<String> {
+        let now = Utc::now().timestamp();
+        let mut cookies = self.cookies.iter()
+            .filter(|c| ! c.is_expired(now) && c.matches(url))
+            .collect::<Vec<_>>();
+        if cookies.is_empty() {
+            return None;
+        }
+        // パスが長いものから送る
+        cookies.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        let header = cookies.into_iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Some(header)
+    }
+    pub fn cookies(&self) -> Vec<&Cookie> {
+        let now = Utc::now().timestamp();
+        self.cookies.iter()
+            .filter(|c| ! c.is_expired(now))
+            .collect()
+    }
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&self.cookies())
+    }
+    /// 保存済みのCookieを読み込む、同名のものは上書きする
+    pub fn load_json(&mut self, json: &str) -> serde_json::Result<()> {
+        let cookies: Vec<Cookie> = serde_json::from_str(json)?;
+        for cookie in cookies {
+            self.insert(cookie);
+        }
+        Ok(())
+    }
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || host.strip_suffix(domain).is_some_and(|rest| rest.ends_with('.'))
+}
+
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    match path.strip_prefix(cookie_path) {
+        Some(rest) => cookie_path.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Path属性がない場合のパス (リクエストパスの最後の / より前)
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".into(),
+        Some(index) => path[..index].into(),
+    }
+}
+
+fn parse_date(date: &str) -> Option<i64> {
+    DateTime::parse_from_rfc2822(date)
+        .map(|d| d.timestamp())
+        .or_else(|_| {
+            // 旧形式: Wed, 21-Oct-2015 07:28:00 GMT
+            NaiveDateTime::parse_from_str(date, "%a, %d-%b-%Y %H:%M:%S GMT")
+                .map(|d| d.and_utc().timestamp())
+        })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_set_cookie() {
+        let cookie = Cookie::parse("sid=abc; Path=/app; Secure; HttpOnly", &url("https://example.com/app/login")).unwrap();
+        assert_eq!(cookie.name, "sid");
+        assert_eq!(cookie.value, "abc");
+        assert_eq!(cookie.domain, "example.com");
+        assert!(cookie.host_only);
+        assert_eq!(cookie.path, "/app");
+        assert!(cookie.secure);
+
+        let cookie = Cookie::parse("a=1; Domain=.example.com; Expires=Wed, 21 Oct 2015 07:28:00 GMT", &url("http://www.example.com/a/b")).unwrap();
+        assert_eq!(cookie.domain, "example.com");
+        assert!(! cookie.host_only);
+        assert_eq!(cookie.path, "/a");
+        assert_eq!(cookie.expires, Some(1445412480));
+
+        assert!(Cookie::parse("a=1; Domain=other.com", &url("http://example.com/")).is_none());
+        assert!(Cookie::parse("novalue", &url("http://example.com/")).is_none());
+    }
+
+    #[test]
+    fn test_cookie_jar() {
+        let mut jar = CookieJar::default();
+        let origin = url("http://www.example.com/");
+        jar.store(&origin, [
+            "a=1",
+            "b=2; Domain=example.com",
+            "c=3; Path=/admin",
+            "d=4; Secure",
+            "old=x; Max-Age=0",
+        ].into_iter());
+
+        assert_eq!(jar.header(&url("http://www.example.com/")), Some("a=1; b=2".into()));
+        assert_eq!(jar.header(&url("http://api.example.com/")), Some("b=2".into()));
+        assert_eq!(jar.header(&url("http://www.example.com/admin/users")), Some("c=3; a=1; b=2".into()));
+        assert_eq!(jar.header(&url("http://www.example.com/administrator")), Some("a=1; b=2".into()));
+        assert_eq!(jar.header(&url("https://www.example.com/")), Some("a=1; b=2; d=4".into()));
+        assert_eq!(jar.header(&url("http://example.org/")), None);
+
+        // 上書きと削除
+        jar.store(&origin, ["a=10", "b=; Domain=example.com; Max-Age=0"].into_iter());
+        assert_eq!(jar.header(&origin), Some("a=10".into()));
+
+        let json = jar.to_json().unwrap();
+        let mut loaded = CookieJar::default();
+        loaded.load_json(&json).unwrap();
+        assert_eq!(loaded.header(&url("https://www.example.com/admin")), Some("c=3; d=4; a=10".into()));
+    }
+}
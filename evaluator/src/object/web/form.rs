@@ -0,0 +1,135 @@
+//! WebRequestのリクエスト本文
+
+use crate::builtins::socket::content_type;
+
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestBody {
+    Text(String),
+    Bytes(Vec<u8>),
+    Json(String),
+    /// application/x-www-form-urlencoded
+    Form(Vec<(String, String)>),
+    /// multipart/form-data
+    Multipart(Vec<FormPart>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormPart {
+    Text { name: String, value: String },
+    File { name: String, path: PathBuf, mime: Option<String> },
+}
+
+impl RequestBody {
+    /// Content-Typeと送信するバイト列に変換する
+    ///
+    /// ファイルはこの時点で読み込まれる
+    pub fn encode(&self) -> std::io::Result<(Option<String>, Vec<u8>)> {
+        let encoded = match self {
+            RequestBody::Text(text) => (None, text.as_bytes().to_vec()),
+            RequestBody::Bytes(bytes) => (Some("application/octet-stream".into()), bytes.clone()),
+            RequestBody::Json(json) => (Some("application/json".into()), json.as_bytes().to_vec()),
+            RequestBody::Form(fields) => {
+                let form = url::form_urlencoded::Serializer::new(String::new())
+                    .extend_pairs(fields)
+                    .finish();
+                (Some("application/x-www-form-urlencoded".into()), form.into_bytes())
+            },
+            RequestBody::Multipart(parts) => {
+                let boundary = format!("----UWSCRFormBoundary{:016x}", rand::random::<u64>());
+                let body = encode_multipart(parts, &boundary)?;
+                (Some(format!("multipart/form-data; boundary={boundary}")), body)
+            },
+        };
+        Ok(encoded)
+    }
+    /// フォームの項目を追加する、他の形式だった場合は置き換える
+    pub fn push_field(body: &mut Option<Self>, name: String, value: String) {
+        match body {
+            Some(RequestBody::Form(fields)) => fields.push((name, value)),
+            _ => *body = Some(RequestBody::Form(vec![(name, value)])),
+        }
+    }
+    /// マルチパートの項目を追加する、他の形式だった場合は置き換える
+    pub fn push_part(body: &mut Option<Self>, part: FormPart) {
+        match body {
+            Some(RequestBody::Multipart(parts)) => parts.push(part),
+            _ => *body = Some(RequestBody::Multipart(vec![part])),
+        }
+    }
+}
+
+fn encode_multipart(parts: &[FormPart], boundary: &str) -> std::io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    for part in parts {
+        body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+        match part {
+            FormPart::Text { name, value } => {
+                let header = format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", escape_quoted(name));
+                body.extend_from_slice(header.as_bytes());
+                body.extend_from_slice(value.as_bytes());
+            },
+            FormPart::File { name, path, mime } => {
+                let data = std::fs::read(path)?;
+                let filename = path.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let mime = mime.as_deref().unwrap_or(content_type(Path::new(&filename)));
+                let header = format!(
+                    "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {mime}\r\n\r\n",
+                    escape_quoted(name), escape_quoted(&filename)
+                );
+                body.extend_from_slice(header.as_bytes());
+                body.extend_from_slice(&data);
+            },
+        }
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    Ok(body)
+}
+
+/// Content-Dispositionの引用符内で使えない文字をエスケープする
+fn escape_quoted(s: &str) -> String {
+    s.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_form() {
+        let body = RequestBody::Form(vec![
+            ("name".into(), "山田 太郎".into()),
+            ("q".into(), "a&b=c".into()),
+        ]);
+        let (content_type, bytes) = body.encode().unwrap();
+        assert_eq!(content_type.as_deref(), Some("application/x-www-form-urlencoded"));
+        assert_eq!(String::from_utf8(bytes).unwrap(), "name=%E5%B1%B1%E7%94%B0+%E5%A4%AA%E9%83%8E&q=a%26b%3Dc");
+    }
+
+    #[test]
+    fn test_encode_multipart() {
+        let path = std::env::temp_dir().join("uwscr_multipart_test.txt");
+        std::fs::write(&path, "file body").unwrap();
+        let parts = vec![
+            FormPart::Text { name: "title".into(), value: "hello".into() },
+            FormPart::File { name: "upload".into(), path: path.clone(), mime: None },
+        ];
+        let body = encode_multipart(&parts, "XYZ").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let expected = "--XYZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            hello\r\n\
+            --XYZ\r\n\
+            Content-Disposition: form-data; name=\"upload\"; filename=\"uwscr_multipart_test.txt\"\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\r\n\
+            file body\r\n\
+            --XYZ--\r\n";
+        assert_eq!(String::from_utf8(body).unwrap(), expected);
+    }
+}
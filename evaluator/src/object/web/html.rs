@@ -0,0 +1,324 @@
+//! HtmlNodeの文書に対するXPath、フォーム・テーブルの抽出と変更
+
+use crate::object::xml::xpath::XPathTree;
+
+use scraper::{Html, ElementRef, Node, node::{Element, Text}};
+use ego_tree::NodeId;
+use html5ever::{Attribute, LocalName, Namespace, QualName};
+
+/// colspanの上限 (ブラウザと同じ)
+const MAX_COLSPAN: usize = 1000;
+
+impl XPathTree for Html {
+    type Id = NodeId;
+
+    fn document(&self) -> NodeId {
+        self.tree.root().id()
+    }
+    fn parent_of(&self, id: NodeId) -> Option<NodeId> {
+        self.tree.get(id)?.parent().map(|p| p.id())
+    }
+    fn children_of(&self, id: NodeId) -> Vec<NodeId> {
+        self.tree.get(id)
+            .map(|node| node.children().map(|c| c.id()).collect())
+            .unwrap_or_default()
+    }
+    fn element_name(&self, id: NodeId) -> Option<&str> {
+        self.tree.get(id)?.value().as_element().map(|e| e.name())
+    }
+    fn matches_name(&self, id: NodeId, test: &str) -> bool {
+        self.element_name(id).is_some_and(|name| name.eq_ignore_ascii_case(test))
+    }
+    fn text_of(&self, id: NodeId) -> Option<&str> {
+        self.tree.get(id)?.value().as_text().map(|t| &**t)
+    }
+    fn attributes(&self, id: NodeId) -> Vec<(&str, &str)> {
+        self.tree.get(id)
+            .and_then(|node| node.value().as_element())
+            .map(|e| e.attrs().collect())
+            .unwrap_or_default()
+    }
+    fn string_value(&self, id: NodeId) -> String {
+        let Some(node) = self.tree.get(id) else {
+            return String::new();
+        };
+        match node.value() {
+            Node::Text(text) => text.to_string(),
+            _ => node.descendants()
+                .filter_map(|d| d.value().as_text())
+                .map(|t| &**t)
+                .collect(),
+        }
+    }
+}
+
+/// フォーム内の送信対象となる項目を文書順で返す
+///
+/// name属性がないもの、disabled、未チェックのcheckbox/radio、ボタンとファイルは含まない
+pub fn form_fields(form: ElementRef) -> Vec<(String, String)> {
+    let mut fields = vec![];
+    for element in form.descendent_elements() {
+        let e = element.value();
+        let Some(name) = e.attr("name").filter(|n| ! n.is_empty()) else {
+            continue;
+        };
+        if e.attr("disabled").is_some() {
+            continue;
+        }
+        match e.name() {
+            "input" => {
+                let input_type = e.attr("type").unwrap_or("text").to_ascii_lowercase();
+                match input_type.as_str() {
+                    "submit" | "button" | "reset" | "image" | "file" => {},
+                    "checkbox" | "radio" => if e.attr("checked").is_some() {
+                        fields.push((name.to_string(), e.attr("value").unwrap_or("on").to_string()));
+                    },
+                    _ => fields.push((name.to_string(), e.attr("value").unwrap_or_default().to_string())),
+                }
+            },
+            "textarea" => fields.push((name.to_string(), element.text().collect())),
+            "select" => {
+                let options = element.descendent_elements()
+                    .filter(|o| o.value().name() == "option" && o.value().attr("disabled").is_none())
+                    .collect::<Vec<_>>();
+                let selected = options.iter()
+                    .copied()
+                    .filter(|o| o.value().attr("selected").is_some())
+                    .collect::<Vec<_>>();
+                let values = if e.attr("multiple").is_some() {
+                    selected
+                } else {
+                    // 単一選択で選択がなければ先頭が選ばれる
+                    selected.last().or(options.first()).copied().into_iter().collect()
+                };
+                for option in values {
+                    let value = match option.value().attr("value") {
+                        Some(value) => value.to_string(),
+                        None => option.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" "),
+                    };
+                    fields.push((name.to_string(), value));
+                }
+            },
+            _ => {},
+        }
+    }
+    fields
+}
+
+/// テーブルを二次元配列にする
+///
+/// rowspan/colspanで結合されたセルは、repeatならすべての位置に同じ値を入れ、そうでなければ先頭以外を空にする\
+/// 入れ子のテーブルの行は含まない
+pub fn table_grid(table: ElementRef, repeat: bool) -> Vec<Vec<String>> {
+    let table_id = table.id();
+    let rows = table.descendent_elements()
+        .filter(|e| e.value().name() == "tr")
+        .filter(|tr| {
+            tr.ancestors()
+                .find(|a| a.value().as_element().is_some_and(|e| e.name() == "table"))
+                .is_some_and(|a| a.id() == table_id)
+        })
+        .collect::<Vec<_>>();
+    let mut grid: Vec<Vec<Option<String>>> = vec![vec![]; rows.len()];
+    for (r, tr) in rows.iter().enumerate() {
+        let mut col = 0;
+        for cell in tr.child_elements().filter(|e| matches!(e.value().name(), "td" | "th")) {
+            while grid[r].get(col).is_some_and(|c| c.is_some()) {
+                col += 1;
+            }
+            let span = |name: &str| cell.value().attr(name)
+                .and_then(|s| s.trim().parse::<usize>().ok());
+            // rowspan=0 は残りすべての行
+            let rowspan = match span("rowspan") {
+                Some(0) => rows.len() - r,
+                Some(n) => n.min(rows.len() - r),
+                None => 1,
+            };
+            let colspan = span("colspan").unwrap_or(1).clamp(1, MAX_COLSPAN);
+            let text = cell.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ");
+            for (dr, row) in grid[r..r + rowspan].iter_mut().enumerate() {
+                if row.len() < col + colspan {
+                    row.resize(col + colspan, None);
+                }
+                for (dc, value) in row[col..col + colspan].iter_mut().enumerate() {
+                    let origin = dr == 0 && dc == 0;
+                    *value = Some(if origin || repeat { text.clone() } else { String::new() });
+                }
+            }
+            col += colspan;
+        }
+    }
+    let width = grid.iter().map(|row| row.len()).max().unwrap_or_default();
+    grid.into_iter()
+        .map(|row| {
+            let mut row = row.into_iter().map(|c| c.unwrap_or_default()).collect::<Vec<_>>();
+            row.resize(width, String::new());
+            row
+        })
+        .collect()
+}
+
+/// 二次元配列をCSV文字列にする
+pub fn grid_to_csv(grid: &[Vec<String>], delimiter: u8) -> Result<String, String> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+    for row in grid {
+        writer.write_record(row).map_err(|e| e.to_string())?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+}
+
+fn rebuild_element(html: &mut Html, id: NodeId, f: impl FnOnce(&mut Vec<(QualName, String)>)) -> bool {
+    let Some(mut node) = html.tree.get_mut(id) else {
+        return false;
+    };
+    let Node::Element(element) = node.value() else {
+        return false;
+    };
+    let mut attrs = element.attrs.iter()
+        .map(|(name, value)| (name.clone(), value.to_string()))
+        .collect();
+    f(&mut attrs);
+    // idとclassはElementがキャッシュしているため作り直す
+    let attrs = attrs.into_iter()
+        .map(|(name, value)| Attribute { name, value: value.into() })
+        .collect();
+    *element = Element::new(element.name.clone(), attrs);
+    true
+}
+
+/// 属性を設定する、要素でなければfalse
+pub fn set_attr(html: &mut Html, id: NodeId, name: &str, value: &str) -> bool {
+    rebuild_element(html, id, |attrs| {
+        match attrs.iter_mut().find(|(n, _)| n.local.as_ref().eq_ignore_ascii_case(name)) {
+            Some((_, v)) => *v = value.to_string(),
+            None => {
+                let name = QualName::new(None, Namespace::from(""), LocalName::from(name.to_ascii_lowercase()));
+                attrs.push((name, value.to_string()));
+            },
+        }
+    })
+}
+
+/// 属性を削除する、削除した場合true
+pub fn remove_attr(html: &mut Html, id: NodeId, name: &str) -> bool {
+    let mut removed = false;
+    rebuild_element(html, id, |attrs| {
+        let len = attrs.len();
+        attrs.retain(|(n, _)| ! n.local.as_ref().eq_ignore_ascii_case(name));
+        removed = attrs.len() != len;
+    });
+    removed
+}
+
+/// 子ノードをすべて取り除きテキストに置き換える
+pub fn set_text(html: &mut Html, id: NodeId, text: &str) -> bool {
+    let Some(mut node) = html.tree.get_mut(id) else {
+        return false;
+    };
+    if ! node.value().is_element() {
+        return false;
+    }
+    while let Some(mut child) = node.first_child() {
+        child.detach();
+    }
+    node.append(Node::Text(Text { text: text.into() }));
+    true
+}
+
+/// ノードを文書から取り除く、親がなければfalse
+pub fn remove(html: &mut Html, id: NodeId) -> bool {
+    let Some(mut node) = html.tree.get_mut(id) else {
+        return false;
+    };
+    if node.parent().is_none() {
+        return false;
+    }
+    node.detach();
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::xml::xpath::{evaluate, XPathItem};
+    use scraper::Selector;
+
+    fn first<'a>(html: &'a Html, selector: &str) -> ElementRef<'a> {
+        html.select(&Selector::parse(selector).unwrap()).next().unwrap()
+    }
+
+    #[test]
+    fn test_xpath() {
+        let html = Html::parse_fragment(r#"<div id="a"><p class="x">one</p><p>two <b>bold</b></p></div><a href="/next">next</a>"#);
+        let found = evaluate(&html, html.document(), "//p[@class='x']/text()").unwrap().into_iter()
+            .map(|item| match item {
+                XPathItem::Node(_) => "node".to_string(),
+                XPathItem::Value(s) => s,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec!["one"]);
+        let nodes = evaluate(&html, html.document(), "//DIV/p[contains(., 'bold')]").unwrap();
+        let [XPathItem::Node(id)] = nodes.as_slice() else { panic!() };
+        assert_eq!(html.string_value(*id), "two bold");
+        let Ok(hrefs) = evaluate(&html, html.document(), "//a/@href") else { panic!() };
+        assert!(matches!(hrefs.as_slice(), [XPathItem::Value(s)] if s == "/next"));
+    }
+
+    #[test]
+    fn test_form_fields() {
+        let html = Html::parse_fragment(r#"<form>
+            <input name="user" value="taro"><input type="password" name="pass">
+            <input type="checkbox" name="opt" value="a" checked><input type="checkbox" name="opt" value="b">
+            <input type="radio" name="r" value="1"><input type="radio" name="r" value="2" checked>
+            <input type="hidden" name="token" value="xyz" disabled><input type="submit" name="go" value="Go">
+            <select name="s"><option value="1">one</option><option>  two  </option></select>
+            <select name="m" multiple><option selected>x</option><option value="y" selected>Y</option></select>
+            <textarea name="memo">hello</textarea>
+        </form>"#);
+        let fields = form_fields(first(&html, "form"));
+        let expected = [
+            ("user", "taro"), ("pass", ""), ("opt", "a"), ("r", "2"),
+            ("s", "1"), ("m", "x"), ("m", "y"), ("memo", "hello"),
+        ];
+        assert_eq!(fields, expected.map(|(n, v)| (n.to_string(), v.to_string())));
+    }
+
+    #[test]
+    fn test_table_grid() {
+        let html = Html::parse_fragment(r#"<table>
+            <tr><th>a</th><th colspan="2">b</th></tr>
+            <tr><td rowspan="2">1</td><td>2</td><td>3</td></tr>
+            <tr><td>4</td><td><table><tr><td>n</td></tr></table></td></tr>
+        </table>"#);
+        let table = first(&html, "table");
+        assert_eq!(table_grid(table, true), vec![
+            vec!["a", "b", "b"],
+            vec!["1", "2", "3"],
+            vec!["1", "4", "n"],
+        ]);
+        let grid = table_grid(table, false);
+        assert_eq!(grid[0], vec!["a", "b", ""]);
+        assert_eq!(grid[2], vec!["", "4", "n"]);
+        assert_eq!(grid_to_csv(&grid, b',').unwrap(), "a,b,\n1,2,3\n,4,n\n");
+    }
+
+    #[test]
+    fn test_mutation() {
+        let mut html = Html::parse_fragment(r#"<div><p id="x">old</p><span>gone</span></div>"#);
+        let p = first(&html, "p").id();
+        let span = first(&html, "span").id();
+        assert!(set_attr(&mut html, p, "id", "y"));
+        assert!(set_text(&mut html, p, "a < b"));
+        assert!(remove(&mut html, span));
+        assert_eq!(first(&html, "div").html(), r#"<div><p id="y">a &lt; b</p></div>"#);
+        // キャッシュされたidとclassも更新される
+        assert!(set_attr(&mut html, p, "class", "new"));
+        assert!(html.select(&Selector::parse("#y.new").unwrap()).next().is_some());
+        assert!(remove_attr(&mut html, p, "class"));
+        assert!(! remove_attr(&mut html, p, "class"));
+        assert_eq!(first(&html, "p").html(), r#"<p id="y">a &lt; b</p>"#);
+    }
+}
@@ -0,0 +1,910 @@
+//! XMLの読み書き
+//!
+//! パース結果はノードの配列として保持し、XmlNodeはそのインデックスを指す\
+//! 同じ文書から得たXmlNodeは文書を共有するため、変更はすべてのXmlNodeに反映される
+
+mod css;
+pub(crate) mod xpath;
+
+use super::{Object, UObject};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+
+use std::sync::{Arc, RwLock};
+use serde_json::{Map, Value as JsonValue};
+
+pub type XmlResult<T> = Result<T, UError>;
+
+/// 要素の入れ子の上限
+const MAX_DEPTH: usize = 256;
+
+fn xml_error(message: UErrorMessage) -> UError {
+    UError::new(UErrorKind::XmlError, message)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum XmlKind {
+    Document,
+    Element {
+        name: String,
+        attrs: Vec<(String, String)>,
+    },
+    Text(String),
+    CData(String),
+    Comment(String),
+    /// 処理命令のターゲット, 内容
+    Pi(String, String),
+}
+
+#[derive(Debug, Clone)]
+struct XmlData {
+    kind: XmlKind,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct XmlTree {
+    /// 0番目は常に文書ノード
+    nodes: Vec<XmlData>,
+    /// <?xml ...?> の内容
+    declaration: Option<String>,
+    /// <!DOCTYPE ...> の内容
+    doctype: Option<String>,
+}
+
+impl XmlTree {
+    const DOCUMENT: usize = 0;
+
+    fn new() -> Self {
+        let document = XmlData { kind: XmlKind::Document, parent: None, children: vec![] };
+        Self { nodes: vec![document], declaration: None, doctype: None }
+    }
+    fn push(&mut self, parent: usize, kind: XmlKind) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(XmlData { kind, parent: Some(parent), children: vec![] });
+        self.nodes[parent].children.push(id);
+        id
+    }
+    fn kind(&self, id: usize) -> &XmlKind {
+        &self.nodes[id].kind
+    }
+    fn parent(&self, id: usize) -> Option<usize> {
+        self.nodes[id].parent
+    }
+    fn children(&self, id: usize) -> &[usize] {
+        &self.nodes[id].children
+    }
+    fn is_element(&self, id: usize) -> bool {
+        matches!(self.kind(id), XmlKind::Element { .. })
+    }
+    fn name(&self, id: usize) -> Option<&str> {
+        match self.kind(id) {
+            XmlKind::Element { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+    /// 名前の比較、接頭辞のない名前は接頭辞を除いた名前とも比較する
+    fn name_matches(&self, id: usize, test: &str) -> bool {
+        match self.name(id) {
+            Some(name) => name == test || (! test.contains(':') && name.split_once(':').is_some_and(|(_, local)| local == test)),
+            None => false,
+        }
+    }
+    fn attrs(&self, id: usize) -> &[(String, String)] {
+        match self.kind(id) {
+            XmlKind::Element { attrs, .. } => attrs,
+            _ => &[],
+        }
+    }
+    fn attr(&self, id: usize, name: &str) -> Option<&str> {
+        self.attrs(id).iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+    fn child_elements(&self, id: usize) -> impl Iterator<Item = usize> + '_ {
+        self.children(id).iter().copied().filter(|c| self.is_element(*c))
+    }
+    /// 子孫ノードを文書順で返す
+    fn descendants(&self, id: usize, include_self: bool) -> Vec<usize> {
+        let mut out = vec![];
+        if include_self {
+            out.push(id);
+        }
+        let mut stack = self.children(id).iter().rev().copied().collect::<Vec<_>>();
+        while let Some(next) = stack.pop() {
+            out.push(next);
+            stack.extend(self.children(next).iter().rev());
+        }
+        out
+    }
+    fn document_element(&self) -> Option<usize> {
+        self.child_elements(Self::DOCUMENT).next()
+    }
+    /// 子孫のテキストを連結する
+    fn text_content(&self, id: usize) -> String {
+        match self.kind(id) {
+            XmlKind::Text(s) |
+            XmlKind::CData(s) |
+            XmlKind::Comment(s) => s.clone(),
+            XmlKind::Pi(_, s) => s.clone(),
+            _ => self.descendants(id, false).into_iter()
+                .filter_map(|d| match self.kind(d) {
+                    XmlKind::Text(s) |
+                    XmlKind::CData(s) => Some(s.as_str()),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+    fn detach(&mut self, id: usize) -> bool {
+        match self.nodes[id].parent.take() {
+            Some(parent) => {
+                self.nodes[parent].children.retain(|c| *c != id);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /* 書き出し */
+    fn write(&self, id: usize, out: &mut String, indent: Option<&str>, depth: usize) {
+        match self.kind(id) {
+            XmlKind::Document => {
+                let mut items = vec![];
+                if let Some(decl) = &self.declaration {
+                    items.push(format!("<?xml {decl}?>"));
+                }
+                if let Some(doctype) = &self.doctype {
+                    items.push(format!("<!DOCTYPE {doctype}>"));
+                }
+                for child in self.children(id) {
+                    let mut s = String::new();
+                    self.write(*child, &mut s, indent, depth);
+                    items.push(s);
+                }
+                out.push_str(&items.join("\n"));
+            },
+            XmlKind::Element { name, attrs } => {
+                out.push('<');
+                out.push_str(name);
+                for (n, v) in attrs {
+                    out.push_str(&format!(" {n}=\"{}\"", escape(v, true)));
+                }
+                let children = self.children(id);
+                if children.is_empty() {
+                    out.push_str("/>");
+                    return;
+                }
+                out.push('>');
+                let inline = children.iter()
+                    .all(|c| matches!(self.kind(*c), XmlKind::Text(_) | XmlKind::CData(_)));
+                match indent {
+                    Some(indent) if ! inline => {
+                        for child in children {
+                            if let XmlKind::Text(s) = self.kind(*child) && s.trim().is_empty() {
+                                continue;
+                            }
+                            out.push('\n');
+                            out.push_str(&indent.repeat(depth + 1));
+                            self.write(*child, out, Some(indent), depth + 1);
+                        }
+                        out.push('\n');
+                        out.push_str(&indent.repeat(depth));
+                    },
+                    _ => {
+                        for child in children {
+                            self.write(*child, out, indent, depth + 1);
+                        }
+                    },
+                }
+                out.push_str(&format!("</{name}>"));
+            },
+            XmlKind::Text(s) => match indent {
+                Some(_) if self.parent(id).is_some_and(|p| self.child_elements(p).next().is_some()) => {
+                    out.push_str(&escape(s.trim(), false));
+                },
+                _ => out.push_str(&escape(s, false)),
+            },
+            XmlKind::CData(s) => out.push_str(&format!("<![CDATA[{s}]]>")),
+            XmlKind::Comment(s) => out.push_str(&format!("<!--{s}-->")),
+            XmlKind::Pi(target, s) if s.is_empty() => out.push_str(&format!("<?{target}?>")),
+            XmlKind::Pi(target, s) => out.push_str(&format!("<?{target} {s}?>")),
+        }
+    }
+    fn to_xml(&self, id: usize, pretty: bool) -> String {
+        let mut out = String::new();
+        let indent = pretty.then_some("  ");
+        self.write(id, &mut out, indent, 0);
+        out
+    }
+    fn inner_xml(&self, id: usize) -> String {
+        self.children(id).iter()
+            .map(|c| self.to_xml(*c, false))
+            .collect()
+    }
+
+    /* UObjectとの変換 */
+    /// 属性は @名前、テキストは #text、同名の子要素は配列になる
+    fn to_json(&self, id: usize) -> JsonValue {
+        match self.kind(id) {
+            XmlKind::Document => {
+                let mut map = Map::new();
+                if let Some(root) = self.document_element() {
+                    map.insert(self.name(root).unwrap_or_default().to_string(), self.to_json(root));
+                }
+                JsonValue::Object(map)
+            },
+            XmlKind::Element { attrs, .. } => {
+                let text = self.children(id).iter()
+                    .filter_map(|c| match self.kind(*c) {
+                        XmlKind::Text(s) |
+                        XmlKind::CData(s) => Some(s.as_str()),
+                        _ => None,
+                    })
+                    .collect::<String>();
+                let text = text.trim();
+                if attrs.is_empty() && self.child_elements(id).next().is_none() {
+                    return JsonValue::String(text.to_string());
+                }
+                let mut map = Map::new();
+                for (n, v) in attrs {
+                    map.insert(format!("@{n}"), JsonValue::String(v.clone()));
+                }
+                for child in self.child_elements(id) {
+                    let name = self.name(child).unwrap_or_default().to_string();
+                    let value = self.to_json(child);
+                    match map.get_mut(&name) {
+                        Some(JsonValue::Array(arr)) => arr.push(value),
+                        Some(existing) => {
+                            let first = existing.take();
+                            *existing = JsonValue::Array(vec![first, value]);
+                        },
+                        None => {
+                            map.insert(name, value);
+                        },
+                    }
+                }
+                if ! text.is_empty() {
+                    map.insert("#text".into(), JsonValue::String(text.to_string()));
+                }
+                JsonValue::Object(map)
+            },
+            _ => JsonValue::String(self.text_content(id)),
+        }
+    }
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        let mut tree = Self::new();
+        match value {
+            JsonValue::Object(map) if map.len() == 1 => {
+                let (name, value) = map.iter().next().unwrap_or_else(|| unreachable!());
+                match value {
+                    JsonValue::Array(_) => return Err("root element must not be an array".into()),
+                    value => tree.build(Self::DOCUMENT, name, value),
+                }
+                tree.declaration = Some(r#"version="1.0" encoding="UTF-8""#.into());
+                Ok(tree)
+            },
+            _ => Err("object with a single root element is required".into()),
+        }
+    }
+    fn build(&mut self, parent: usize, name: &str, value: &JsonValue) {
+        let id = self.push(parent, XmlKind::Element { name: name.to_string(), attrs: vec![] });
+        match value {
+            JsonValue::Object(map) => {
+                for (key, value) in map {
+                    if let Some(attr) = key.strip_prefix('@') {
+                        let value = json_to_text(value);
+                        if let XmlKind::Element { attrs, .. } = &mut self.nodes[id].kind {
+                            attrs.push((attr.to_string(), value));
+                        }
+                    } else if key == "#text" {
+                        self.push(id, XmlKind::Text(json_to_text(value)));
+                    } else if let JsonValue::Array(arr) = value {
+                        for item in arr {
+                            self.build(id, key, item);
+                        }
+                    } else {
+                        self.build(id, key, value);
+                    }
+                }
+            },
+            JsonValue::Null => {},
+            value => {
+                self.push(id, XmlKind::Text(json_to_text(value)));
+            },
+        }
+    }
+}
+
+impl xpath::XPathTree for XmlTree {
+    type Id = usize;
+
+    fn document(&self) -> usize {
+        Self::DOCUMENT
+    }
+    fn parent_of(&self, id: usize) -> Option<usize> {
+        self.parent(id)
+    }
+    fn children_of(&self, id: usize) -> Vec<usize> {
+        self.children(id).to_vec()
+    }
+    fn element_name(&self, id: usize) -> Option<&str> {
+        self.name(id)
+    }
+    fn matches_name(&self, id: usize, test: &str) -> bool {
+        self.name_matches(id, test)
+    }
+    fn text_of(&self, id: usize) -> Option<&str> {
+        match self.kind(id) {
+            XmlKind::Text(s) |
+            XmlKind::CData(s) => Some(s),
+            _ => None,
+        }
+    }
+    fn attributes(&self, id: usize) -> Vec<(&str, &str)> {
+        self.attrs(id).iter()
+            .map(|(n, v)| (n.as_str(), v.as_str()))
+            .collect()
+    }
+    fn string_value(&self, id: usize) -> String {
+        self.text_content(id)
+    }
+    fn descendants_of(&self, id: usize, include_self: bool) -> Vec<usize> {
+        self.descendants(id, include_self)
+    }
+}
+
+fn json_to_text(value: &JsonValue) -> String {
+    match value {
+        JsonValue::Null => String::new(),
+        JsonValue::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn escape(s: &str, attr: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if attr => out.push_str("&quot;"),
+            '\n' if attr => out.push_str("&#10;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// 文字参照と定義済み実体参照を展開する、未知の実体参照はそのまま残す
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';')
+            .filter(|end| *end <= 12)
+            .and_then(|end| {
+                let entity = &rest[1..end];
+                let c = match entity {
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "amp" => Some('&'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    _ => match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                        None => entity.strip_prefix('#')
+                            .and_then(|dec| dec.parse().ok())
+                            .and_then(char::from_u32),
+                    },
+                };
+                c.map(|c| (c, end))
+            });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    tree: XmlTree,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(src: &'a str) -> Result<XmlTree, String> {
+        let mut parser = Self {
+            src: src.strip_prefix('\u{feff}').unwrap_or(src),
+            pos: 0,
+            tree: XmlTree::new(),
+        };
+        parser.document()?;
+        Ok(parser.tree)
+    }
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+    fn starts(&self, s: &str) -> bool {
+        self.rest().starts_with(s)
+    }
+    fn error(&self, message: &str) -> String {
+        let line = self.src[..self.pos].matches('\n').count() + 1;
+        format!("{message} (line {line})")
+    }
+    fn skip_ws(&mut self) -> bool {
+        let trimmed = self.rest().trim_start();
+        let skipped = self.rest().len() - trimmed.len();
+        self.pos += skipped;
+        skipped > 0
+    }
+    /// endまでを返し、endの後ろに進む
+    fn take_until(&mut self, end: &str) -> Result<&'a str, String> {
+        match self.rest().find(end) {
+            Some(i) => {
+                let s = &self.rest()[..i];
+                self.pos += i + end.len();
+                Ok(s)
+            },
+            None => Err(self.error(&format!("'{end}' expected"))),
+        }
+    }
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.rest()
+            .find(|c: char| c.is_whitespace() || "/>=<'\"?".contains(c))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err(self.error("name expected"));
+        }
+        let name = self.rest()[..len].to_string();
+        self.pos += len;
+        Ok(name)
+    }
+    /// コメントまたは処理命令であれば読み込む
+    fn misc(&mut self, parent: usize) -> Result<bool, String> {
+        if self.starts("<!--") {
+            self.pos += 4;
+            let comment = self.take_until("-->")?;
+            self.tree.push(parent, XmlKind::Comment(comment.to_string()));
+            Ok(true)
+        } else if self.starts("<?") {
+            self.pos += 2;
+            let target = self.name()?;
+            self.skip_ws();
+            let content = self.take_until("?>")?;
+            self.tree.push(parent, XmlKind::Pi(target, content.trim_end().to_string()));
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+    fn document(&mut self) -> Result<(), String> {
+        if self.starts("<?xml") && self.rest()[5..].starts_with(|c: char| c.is_whitespace() || c == '?') {
+            self.pos += 5;
+            let decl = self.take_until("?>")?;
+            self.tree.declaration = Some(decl.trim().to_string());
+        }
+        loop {
+            self.skip_ws();
+            if self.rest().is_empty() {
+                break;
+            }
+            if self.misc(XmlTree::DOCUMENT)? {
+                continue;
+            }
+            if self.starts("<!DOCTYPE") {
+                self.pos += 9;
+                self.doctype()?;
+            } else if self.starts("<") {
+                if self.tree.document_element().is_some() {
+                    return Err(self.error("multiple root elements"));
+                }
+                self.element(XmlTree::DOCUMENT, 1)?;
+            } else {
+                return Err(self.error("text outside of the root element"));
+            }
+        }
+        match self.tree.document_element() {
+            Some(_) => Ok(()),
+            None => Err(self.error("root element not found")),
+        }
+    }
+    /// 内部サブセットの引用符やコメント内の [ ] > は無視する
+    fn doctype(&mut self) -> Result<(), String> {
+        let rest = self.rest();
+        let bytes = rest.as_bytes();
+        let mut depth = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                q @ (b'"' | b'\'') => match rest[i + 1..].find(q as char) {
+                    Some(end) => i += end + 1,
+                    None => break,
+                },
+                b'<' if rest[i..].starts_with("<!--") => match rest[i + 4..].find("-->") {
+                    Some(end) => i += end + 6,
+                    None => break,
+                },
+                b'[' => depth += 1,
+                b']' => depth -= 1,
+                b'>' if depth == 0 => {
+                    self.tree.doctype = Some(rest[..i].trim().to_string());
+                    self.pos += i + 1;
+                    return Ok(());
+                },
+                _ => {},
+            }
+            i += 1;
+        }
+        Err(self.error("'>' expected"))
+    }
+    /// - depth: この要素の入れ子の深さ、ルート要素が1
+    fn element(&mut self, parent: usize, depth: usize) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error(&format!("elements are nested deeper than {MAX_DEPTH} levels")));
+        }
+        self.pos += 1;
+        let name = self.name()?;
+        let mut attrs: Vec<(String, String)> = vec![];
+        let empty = loop {
+            let has_ws = self.skip_ws();
+            if self.starts("/>") {
+                self.pos += 2;
+                break true;
+            }
+            if self.starts(">") {
+                self.pos += 1;
+                break false;
+            }
+            if ! has_ws {
+                return Err(self.error(&format!("invalid character in <{name}>")));
+            }
+            let attr = self.name()?;
+            self.skip_ws();
+            if ! self.starts("=") {
+                return Err(self.error(&format!("'=' expected after {attr}")));
+            }
+            self.pos += 1;
+            self.skip_ws();
+            let quote = match self.rest().chars().next() {
+                Some(q @ ('"' | '\'')) => q,
+                _ => return Err(self.error(&format!("quoted value expected for {attr}"))),
+            };
+            self.pos += 1;
+            let value = self.take_until(&quote.to_string())?;
+            if value.contains('<') {
+                return Err(self.error(&format!("'<' in value of {attr}")));
+            }
+            if attrs.iter().any(|(n, _)| *n == attr) {
+                return Err(self.error(&format!("duplicated attribute {attr}")));
+            }
+            attrs.push((attr, unescape(value)));
+        };
+        let id = self.tree.push(parent, XmlKind::Element { name: name.clone(), attrs });
+        if empty {
+            return Ok(());
+        }
+        loop {
+            if self.rest().is_empty() {
+                return Err(self.error(&format!("<{name}> is not closed")));
+            }
+            if self.starts("</") {
+                self.pos += 2;
+                let close = self.name()?;
+                self.skip_ws();
+                if ! self.starts(">") || close != name {
+                    return Err(self.error(&format!("</{name}> expected")));
+                }
+                self.pos += 1;
+                return Ok(());
+            }
+            if self.misc(id)? {
+                continue;
+            }
+            if self.starts("<![CDATA[") {
+                self.pos += 9;
+                let data = self.take_until("]]>")?;
+                self.tree.push(id, XmlKind::CData(data.to_string()));
+            } else if self.starts("<") {
+                self.element(id, depth + 1)?;
+            } else {
+                let len = self.rest().find('<').unwrap_or(self.rest().len());
+                let text = unescape(&self.rest()[..len]);
+                self.pos += len;
+                self.tree.push(id, XmlKind::Text(text));
+            }
+        }
+    }
+}
+
+/// XML文書内のノード
+#[derive(Debug, Clone)]
+pub struct XmlNode {
+    tree: Arc<RwLock<XmlTree>>,
+    id: usize,
+}
+
+impl PartialEq for XmlNode {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.tree, &other.tree) && self.id == other.id
+    }
+}
+
+impl std::fmt::Display for XmlNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_xml(false))
+    }
+}
+
+impl XmlNode {
+    fn new(tree: XmlTree) -> Self {
+        Self { tree: Arc::new(RwLock::new(tree)), id: XmlTree::DOCUMENT }
+    }
+    /// XML文字列をパースし文書ノードを返す
+    pub fn parse(xml: &str) -> XmlResult<Self> {
+        let tree = Parser::parse(xml)
+            .map_err(|e| xml_error(UErrorMessage::XmlParseError(e)))?;
+        Ok(Self::new(tree))
+    }
+    /// UObjectの値からXML文書を作る
+    pub fn from_json(value: &JsonValue) -> XmlResult<Self> {
+        let tree = XmlTree::from_json(value)
+            .map_err(|e| xml_error(UErrorMessage::XmlParseError(e)))?;
+        Ok(Self::new(tree))
+    }
+    fn node(&self, id: usize) -> Self {
+        Self { tree: Arc::clone(&self.tree), id }
+    }
+    fn node_or_empty(&self, id: Option<usize>) -> Object {
+        id.map(|id| Object::XmlNode(self.node(id))).unwrap_or_default()
+    }
+    pub fn to_xml(&self, pretty: bool) -> String {
+        let tree = self.tree.read().unwrap();
+        tree.to_xml(self.id, pretty)
+    }
+    fn child_objects(&self, tree: &XmlTree) -> Vec<Object> {
+        tree.child_elements(self.id)
+            .map(|id| Object::XmlNode(self.node(id)))
+            .collect()
+    }
+    /// 子要素
+    pub fn to_vec(&self) -> Vec<Object> {
+        let tree = self.tree.read().unwrap();
+        self.child_objects(&tree)
+    }
+    pub fn to_json(&self) -> JsonValue {
+        let tree = self.tree.read().unwrap();
+        tree.to_json(self.id)
+    }
+    /// XPathに該当するノードまたは値
+    pub fn find(&self, path: &str) -> XmlResult<Vec<Object>> {
+        let items = {
+            let tree = self.tree.read().unwrap();
+            xpath::evaluate(&*tree, self.id, path)
+                .map_err(|e| xml_error(UErrorMessage::InvalidXPath(path.to_string(), e)))?
+        };
+        let objects = items.into_iter()
+            .map(|item| match item {
+                xpath::XPathItem::Node(id) => Object::XmlNode(self.node(id)),
+                xpath::XPathItem::Value(s) => s.into(),
+            })
+            .collect();
+        Ok(objects)
+    }
+    /// CSSセレクタに該当する子孫要素
+    pub fn select(&self, selector: &str) -> XmlResult<Vec<Object>> {
+        let ids = {
+            let tree = self.tree.read().unwrap();
+            css::select(&tree, self.id, selector)
+                .map_err(|e| xml_error(UErrorMessage::InvalidXmlSelector(selector.to_string(), e)))?
+        };
+        Ok(ids.into_iter().map(|id| Object::XmlNode(self.node(id))).collect())
+    }
+    fn set_attr(&self, name: String, value: String) -> bool {
+        let mut tree = self.tree.write().unwrap();
+        match &mut tree.nodes[self.id].kind {
+            XmlKind::Element { attrs, .. } => {
+                match attrs.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, v)) => *v = value,
+                    None => attrs.push((name, value)),
+                }
+                true
+            },
+            _ => false,
+        }
+    }
+    fn remove_attr(&self, name: &str) -> bool {
+        let mut tree = self.tree.write().unwrap();
+        match &mut tree.nodes[self.id].kind {
+            XmlKind::Element { attrs, .. } => {
+                let len = attrs.len();
+                attrs.retain(|(n, _)| n != name);
+                attrs.len() != len
+            },
+            _ => false,
+        }
+    }
+    fn set_text(&self, text: String) -> bool {
+        let mut tree = self.tree.write().unwrap();
+        if ! tree.is_element(self.id) {
+            return false;
+        }
+        for child in tree.children(self.id).to_vec() {
+            tree.nodes[child].parent = None;
+        }
+        tree.nodes[self.id].children.clear();
+        tree.push(self.id, XmlKind::Text(text));
+        true
+    }
+    fn append(&self, name: String, text: Option<String>) -> Option<Self> {
+        let mut tree = self.tree.write().unwrap();
+        let is_root = self.id == XmlTree::DOCUMENT && tree.document_element().is_some();
+        if ! (tree.is_element(self.id) || self.id == XmlTree::DOCUMENT) || is_root {
+            return None;
+        }
+        let id = tree.push(self.id, XmlKind::Element { name, attrs: vec![] });
+        if let Some(text) = text {
+            tree.push(id, XmlKind::Text(text));
+        }
+        Some(self.node(id))
+    }
+    fn remove(&self) -> bool {
+        let mut tree = self.tree.write().unwrap();
+        tree.detach(self.id)
+    }
+
+    pub fn get_property(&self, name: &str) -> XmlResult<Object> {
+        let tree = self.tree.read().unwrap();
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "name" => match tree.kind(self.id) {
+                XmlKind::Document => "#document".into(),
+                XmlKind::Element { name, .. } => name.as_str().into(),
+                XmlKind::Text(_) => "#text".into(),
+                XmlKind::CData(_) => "#cdata-section".into(),
+                XmlKind::Comment(_) => "#comment".into(),
+                XmlKind::Pi(target, _) => target.as_str().into(),
+            },
+            "text" => tree.text_content(self.id).into(),
+            "attributes" => {
+                let map = tree.attrs(self.id).iter()
+                    .map(|(n, v)| (n.clone(), JsonValue::String(v.clone())))
+                    .collect::<Map<_, _>>();
+                Object::UObject(UObject::from(JsonValue::Object(map)))
+            },
+            "children" => Object::Array(self.child_objects(&tree)),
+            "parent" => self.node_or_empty(tree.parent(self.id)),
+            "root" => self.node_or_empty(tree.document_element()),
+            "outerxml" => tree.to_xml(self.id, false).into(),
+            "innerxml" => tree.inner_xml(self.id).into(),
+            _ => return Err(xml_error(UErrorMessage::InvalidMember(name.to_string()))),
+        };
+        Ok(obj)
+    }
+    pub fn invoke_method(&self, name: &str, args: Vec<Object>) -> XmlResult<Object> {
+        let string_arg = |i: usize| match args.get(i) {
+            None |
+            Some(Object::Empty) |
+            Some(Object::EmptyParam) => Err(xml_error(UErrorMessage::BuiltinArgRequiredAt(i + 1))),
+            Some(o) => Ok(o.to_string()),
+        };
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "find" => Object::Array(self.find(&string_arg(0)?)?),
+            "first" | "findfirst" => self.find(&string_arg(0)?)?
+                .into_iter().next().unwrap_or_default(),
+            "select" => Object::Array(self.select(&string_arg(0)?)?),
+            "selectfirst" => self.select(&string_arg(0)?)?
+                .into_iter().next().unwrap_or_default(),
+            "attr" | "attribute" => {
+                let name = string_arg(0)?;
+                let tree = self.tree.read().unwrap();
+                tree.attr(self.id, &name).map(Object::from).unwrap_or_default()
+            },
+            "setattr" => {
+                self.set_attr(string_arg(0)?, string_arg(1).unwrap_or_default());
+                Object::XmlNode(self.clone())
+            },
+            "removeattr" => self.remove_attr(&string_arg(0)?).into(),
+            "settext" => {
+                self.set_text(string_arg(0).unwrap_or_default());
+                Object::XmlNode(self.clone())
+            },
+            "append" => {
+                let text = string_arg(1).ok();
+                let child = self.append(string_arg(0)?, text);
+                child.map(Object::XmlNode).unwrap_or_default()
+            },
+            "remove" => self.remove().into(),
+            "toxml" => {
+                let pretty = args.first().is_some_and(|o| o.is_truthy());
+                self.to_xml(pretty).into()
+            },
+            "toobject" => Object::UObject(UObject::from(self.to_json())),
+            _ => return Err(xml_error(UErrorMessage::InvalidMember(name.to_string()))),
+        };
+        Ok(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE catalog [ <!ENTITY x "y"> ]>
+<catalog xmlns:m="urn:m">
+  <!-- books -->
+  <book id="1" class="new sale"><title>A &amp; B</title><price>10</price></book>
+  <book id="2"><title><![CDATA[<C>]]></title><price>25.5</price><m:tag>x</m:tag></book>
+  <magazine id="3"/>
+</catalog>"#;
+
+    #[test]
+    fn test_parse_and_write() {
+        let tree = Parser::parse(XML).unwrap();
+        let root = tree.document_element().unwrap();
+        assert_eq!(tree.name(root), Some("catalog"));
+        assert_eq!(tree.child_elements(root).count(), 3);
+        let book = tree.child_elements(root).next().unwrap();
+        assert_eq!(tree.attr(book, "class"), Some("new sale"));
+        assert_eq!(tree.text_content(book), "A & B10");
+        // 再パースしても同じ内容になる
+        let written = tree.to_xml(XmlTree::DOCUMENT, false);
+        assert_eq!(Parser::parse(&written).unwrap().to_xml(XmlTree::DOCUMENT, false), written);
+        assert!(written.contains("<title>A &amp; B</title>"));
+        assert!(written.contains("<![CDATA[<C>]]>"));
+
+        let pretty = tree.to_xml(root, true);
+        assert!(pretty.starts_with("<catalog xmlns:m=\"urn:m\">\n  <!-- books -->\n  <book"));
+        assert!(pretty.contains("\n    <title>A &amp; B</title>\n"));
+        assert!(pretty.ends_with("  <magazine id=\"3\"/>\n</catalog>"));
+
+        assert!(Parser::parse("<a><b></a>").is_err());
+        assert!(Parser::parse("<a x='1' x='2'/>").is_err());
+        assert!(Parser::parse("<a/><b/>").is_err());
+        assert!(Parser::parse("text").is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        let tree = Parser::parse(XML).unwrap();
+        let json = tree.to_json(XmlTree::DOCUMENT);
+        assert_eq!(json["catalog"]["book"][0]["@id"], "1");
+        assert_eq!(json["catalog"]["book"][1]["title"], "<C>");
+        assert_eq!(json["catalog"]["magazine"]["@id"], "3");
+
+        let value = serde_json::json!({"root": {"@v": 1, "item": ["a", {"@k": "x", "#text": "b"}], "empty": null}});
+        let tree = XmlTree::from_json(&value).unwrap();
+        let root = tree.document_element().unwrap();
+        assert_eq!(tree.to_xml(root, false), r#"<root v="1"><item>a</item><item k="x">b</item><empty/></root>"#);
+        assert!(XmlTree::from_json(&serde_json::json!({"a": 1, "b": 2})).is_err());
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let nested = |n: usize| "<a>".repeat(n) + &"</a>".repeat(n);
+        assert!(Parser::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Parser::parse(&nested(MAX_DEPTH + 1)).is_err());
+
+        let xml = r#"<!DOCTYPE r [ <!ENTITY a "]>"> <!-- it's ]> --> <!ENTITY b '[>'> ]><r/>"#;
+        let tree = Parser::parse(xml).unwrap();
+        assert_eq!(tree.doctype.as_deref(), Some(r#"r [ <!ENTITY a "]>"> <!-- it's ]> --> <!ENTITY b '[>'> ]"#));
+        assert_eq!(tree.name(tree.document_element().unwrap()), Some("r"));
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("&lt;&#65;&#x42;&unknown; & x"), "<AB&unknown; & x");
+    }
+}
@@ -0,0 +1,296 @@
+//! CSSセレクタのサブセット
+//!
+//! 対応する構文
+//! - 要素名 `*` `#id` `.class` `[attr]` `[attr=v]` `[attr^=v]` `[attr$=v]` `[attr*=v]` `[attr~=v]`
+//! - 結合子: 子孫(空白) `>` `+` `~`、複数セレクタ `,`
+//! - 疑似クラス: `:first-child` `:last-child` `:nth-child(n)`
+
+use super::XmlTree;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+    Adjacent,
+    Sibling,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AttrOp {
+    Exists,
+    Equals,
+    Prefix,
+    Suffix,
+    Contains,
+    Word,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Pseudo {
+    First,
+    Last,
+    Nth(usize),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Compound {
+    tag: Option<String>,
+    attrs: Vec<(String, AttrOp, String)>,
+    pseudo: Vec<Pseudo>,
+}
+
+/// 複合セレクタと、その左側の複合セレクタとの関係
+type Complex = Vec<(Compound, Option<Combinator>)>;
+
+fn parse(selector: &str) -> Result<Vec<Complex>, String> {
+    selector.split(',')
+        .map(|s| parse_complex(s.trim()))
+        .collect()
+}
+
+fn parse_complex(selector: &str) -> Result<Complex, String> {
+    if selector.is_empty() {
+        return Err("empty selector".into());
+    }
+    let mut parts: Complex = vec![];
+    let mut chars = selector.chars().peekable();
+    let mut combinator = None;
+    loop {
+        let mut had_ws = false;
+        while chars.next_if(|c| c.is_whitespace()).is_some() {
+            had_ws = true;
+        }
+        let Some(&c) = chars.peek() else {
+            break;
+        };
+        let explicit = match c {
+            '>' => Some(Combinator::Child),
+            '+' => Some(Combinator::Adjacent),
+            '~' => Some(Combinator::Sibling),
+            _ => None,
+        };
+        if let Some(explicit) = explicit {
+            if parts.is_empty() || combinator.is_some() {
+                return Err(format!("unexpected '{c}'"));
+            }
+            chars.next();
+            combinator = Some(explicit);
+            continue;
+        }
+        if had_ws && ! parts.is_empty() && combinator.is_none() {
+            combinator = Some(Combinator::Descendant);
+        }
+        if ! parts.is_empty() && combinator.is_none() {
+            return Err(format!("unexpected '{c}'"));
+        }
+        let compound = parse_compound(&mut chars)?;
+        parts.push((compound, combinator.take()));
+    }
+    if combinator.is_some() {
+        return Err("selector ends with a combinator".into());
+    }
+    Ok(parts)
+}
+
+fn ident(chars: &mut std::iter::Peekable<std::str::Chars>, colon: bool) -> String {
+    let mut s = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || "-_".contains(*c) || (colon && *c == ':')) {
+        s.push(c);
+    }
+    s
+}
+
+fn parse_compound(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Compound, String> {
+    let mut compound = Compound::default();
+    let universal = chars.next_if_eq(&'*').is_some();
+    if ! universal {
+        let tag = ident(chars, false);
+        if ! tag.is_empty() {
+            compound.tag = Some(tag);
+        }
+    }
+    loop {
+        match chars.peek() {
+            Some('#') => {
+                chars.next();
+                compound.attrs.push(("id".into(), AttrOp::Equals, ident(chars, false)));
+            },
+            Some('.') => {
+                chars.next();
+                compound.attrs.push(("class".into(), AttrOp::Word, ident(chars, false)));
+            },
+            Some('[') => {
+                chars.next();
+                let attr = ident(chars, true);
+                let op = match chars.next() {
+                    Some(']') => {
+                        compound.attrs.push((attr, AttrOp::Exists, String::new()));
+                        continue;
+                    },
+                    Some('=') => AttrOp::Equals,
+                    Some(c) if chars.next_if_eq(&'=').is_some() => match c {
+                        '^' => AttrOp::Prefix,
+                        '$' => AttrOp::Suffix,
+                        '*' => AttrOp::Contains,
+                        '~' => AttrOp::Word,
+                        _ => return Err(format!("unsupported attribute operator: {c}=")),
+                    },
+                    _ => return Err(format!("invalid attribute selector: [{attr}")),
+                };
+                let value = match chars.next_if(|c| *c == '"' || *c == '\'') {
+                    Some(quote) => {
+                        let value = chars.by_ref().take_while(|c| *c != quote).collect();
+                        if chars.next() != Some(']') {
+                            return Err(format!("']' expected after [{attr}"));
+                        }
+                        value
+                    },
+                    None => chars.by_ref().take_while(|c| *c != ']').collect::<String>().trim().to_string(),
+                };
+                compound.attrs.push((attr, op, value));
+            },
+            Some(':') => {
+                chars.next();
+                let name = ident(chars, false);
+                let pseudo = match name.as_str() {
+                    "first-child" => Pseudo::First,
+                    "last-child" => Pseudo::Last,
+                    "nth-child" if chars.next_if_eq(&'(').is_some() => {
+                        let n = chars.by_ref().take_while(|c| *c != ')').collect::<String>();
+                        let n = n.trim().parse().map_err(|_| format!("invalid nth-child: {n}"))?;
+                        Pseudo::Nth(n)
+                    },
+                    _ => return Err(format!("unsupported pseudo class: :{name}")),
+                };
+                compound.pseudo.push(pseudo);
+            },
+            _ => break,
+        }
+    }
+    let empty = compound.tag.is_none() && compound.attrs.is_empty() && compound.pseudo.is_empty();
+    match chars.peek() {
+        Some(c) if empty && ! universal => Err(format!("unexpected '{c}'")),
+        _ => Ok(compound),
+    }
+}
+
+fn sibling_elements(tree: &XmlTree, id: usize) -> Vec<usize> {
+    match tree.parent(id) {
+        Some(parent) => tree.child_elements(parent).collect(),
+        None => vec![id],
+    }
+}
+
+fn compound_matches(tree: &XmlTree, id: usize, compound: &Compound) -> bool {
+    if ! tree.is_element(id) {
+        return false;
+    }
+    if let Some(tag) = &compound.tag && ! tree.name_matches(id, tag) {
+        return false;
+    }
+    let attrs_match = compound.attrs.iter().all(|(name, op, value)| {
+        let Some(attr) = tree.attr(id, name) else {
+            return false;
+        };
+        match op {
+            AttrOp::Exists => true,
+            AttrOp::Equals => attr == value,
+            AttrOp::Prefix => attr.starts_with(value.as_str()),
+            AttrOp::Suffix => attr.ends_with(value.as_str()),
+            AttrOp::Contains => attr.contains(value.as_str()),
+            AttrOp::Word => attr.split_whitespace().any(|w| w == value),
+        }
+    });
+    if ! attrs_match {
+        return false;
+    }
+    compound.pseudo.iter().all(|pseudo| {
+        let siblings = sibling_elements(tree, id);
+        let index = siblings.iter().position(|s| *s == id);
+        match pseudo {
+            Pseudo::First => index == Some(0),
+            Pseudo::Last => index == Some(siblings.len() - 1),
+            Pseudo::Nth(n) => index.map(|i| i + 1) == Some(*n),
+        }
+    })
+}
+
+fn complex_matches(tree: &XmlTree, id: usize, parts: &[(Compound, Option<Combinator>)]) -> bool {
+    let Some(((compound, combinator), rest)) = parts.split_last() else {
+        return true;
+    };
+    if ! compound_matches(tree, id, compound) {
+        return false;
+    }
+    let Some(combinator) = combinator else {
+        return true;
+    };
+    let element_parent = tree.parent(id).filter(|p| tree.is_element(*p));
+    let previous_siblings = || {
+        let siblings = sibling_elements(tree, id);
+        let index = siblings.iter().position(|s| *s == id).unwrap_or(0);
+        siblings[..index].to_vec()
+    };
+    match combinator {
+        Combinator::Child => element_parent.is_some_and(|p| complex_matches(tree, p, rest)),
+        Combinator::Descendant => {
+            let mut ancestor = element_parent;
+            while let Some(a) = ancestor {
+                if complex_matches(tree, a, rest) {
+                    return true;
+                }
+                ancestor = tree.parent(a).filter(|p| tree.is_element(*p));
+            }
+            false
+        },
+        Combinator::Adjacent => previous_siblings().last().is_some_and(|s| complex_matches(tree, *s, rest)),
+        Combinator::Sibling => previous_siblings().iter().any(|s| complex_matches(tree, *s, rest)),
+    }
+}
+
+/// contextの子孫からセレクタに一致する要素を文書順で返す
+pub(super) fn select(tree: &XmlTree, context: usize, selector: &str) -> Result<Vec<usize>, String> {
+    let selectors = parse(selector)?;
+    let found = tree.descendants(context, false).into_iter()
+        .filter(|id| selectors.iter().any(|parts| complex_matches(tree, *id, parts)))
+        .collect();
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Parser as XmlParser;
+
+    fn ids(tree: &XmlTree, selector: &str) -> Vec<String> {
+        select(tree, XmlTree::DOCUMENT, selector).unwrap().into_iter()
+            .map(|id| tree.attr(id, "id").unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_select() {
+        let xml = r#"<root>
+            <list id="l1" class="main">
+                <item id="i1" class="a b" lang="en-US"/>
+                <item id="i2" class="b"/>
+                <sub id="s1"><item id="i3"/></sub>
+            </list>
+            <list id="l2"><item id="i4" lang="ja"/></list>
+        </root>"#;
+        let tree = XmlParser::parse(xml).unwrap();
+        assert_eq!(ids(&tree, "item"), vec!["i1", "i2", "i3", "i4"]);
+        assert_eq!(ids(&tree, "list > item"), vec!["i1", "i2", "i4"]);
+        assert_eq!(ids(&tree, ".main item"), vec!["i1", "i2", "i3"]);
+        assert_eq!(ids(&tree, "item.b"), vec!["i1", "i2"]);
+        assert_eq!(ids(&tree, "#i1 + item, #s1"), vec!["i2", "s1"]);
+        assert_eq!(ids(&tree, "#i1 ~ *"), vec!["i2", "s1"]);
+        assert_eq!(ids(&tree, "[lang^=en]"), vec!["i1"]);
+        assert_eq!(ids(&tree, "item[lang='ja']"), vec!["i4"]);
+        assert_eq!(ids(&tree, "list:first-child > :last-child"), vec!["s1"]);
+        assert_eq!(ids(&tree, "item:nth-child(2)"), vec!["i2"]);
+        assert!(select(&tree, 0, "list >").is_err());
+        assert!(select(&tree, 0, "item:hover").is_err());
+        assert!(select(&tree, 0, "a,,b").is_err());
+    }
+}
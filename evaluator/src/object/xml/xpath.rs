@@ -0,0 +1,573 @@
+//! XPathのサブセット
+//!
+//! 対応する構文
+//! - `/` `//` による絶対パスと相対パス、`.` `..` `*` 要素名 `@属性` `@*` `text()` `node()`
+//! - 述語: 位置 `[1]` `[last()]`、比較 `= != < <= > >=`、`and` `or` `not()`
+//! - 関数: `position()` `last()` `count()` `contains()` `starts-with()` `ends-with()` `string()` `number()` `normalize-space()` `name()` `local-name()`
+
+/// XPathで辿る木構造
+///
+/// XmlNodeとHtmlNodeがそれぞれの文書に対して実装する
+pub(crate) trait XPathTree {
+    type Id: Copy + PartialEq;
+    /// 絶対パスの起点となるノード
+    fn document(&self) -> Self::Id;
+    fn parent_of(&self, id: Self::Id) -> Option<Self::Id>;
+    fn children_of(&self, id: Self::Id) -> Vec<Self::Id>;
+    /// 要素名、要素でなければNone
+    fn element_name(&self, id: Self::Id) -> Option<&str>;
+    fn matches_name(&self, id: Self::Id, test: &str) -> bool;
+    /// テキストノードの内容
+    fn text_of(&self, id: Self::Id) -> Option<&str>;
+    fn attributes(&self, id: Self::Id) -> Vec<(&str, &str)>;
+    /// ノードの文字列値 (子孫のテキストの連結)
+    fn string_value(&self, id: Self::Id) -> String;
+    /// 子孫ノードを文書順で返す
+    fn descendants_of(&self, id: Self::Id, include_self: bool) -> Vec<Self::Id> {
+        let mut out = vec![];
+        if include_self {
+            out.push(id);
+        }
+        let mut stack = self.children_of(id).into_iter().rev().collect::<Vec<_>>();
+        while let Some(next) = stack.pop() {
+            out.push(next);
+            stack.extend(self.children_of(next).into_iter().rev());
+        }
+        out
+    }
+}
+
+pub(crate) enum XPathItem<Id = usize> {
+    Node(Id),
+    Value(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum NodeTest {
+    SelfNode,
+    Parent,
+    Any,
+    Name(String),
+    Attr(Option<String>),
+    Text,
+    Node,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    /// 直前が `//` であれば真
+    descendant: bool,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+#[derive(Debug, Clone)]
+struct Path {
+    absolute: bool,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp(Box<Expr>, CmpOp, Box<Expr>),
+    Func(String, Vec<Expr>),
+    Path(Path),
+    Str(String),
+    Num(f64),
+}
+
+enum Val {
+    Set(Vec<String>),
+    Str(String),
+    Num(f64),
+    Bool(bool),
+}
+
+impl Val {
+    fn truthy(&self) -> bool {
+        match self {
+            Val::Set(v) => ! v.is_empty(),
+            Val::Str(s) => ! s.is_empty(),
+            Val::Num(n) => *n != 0.0 && ! n.is_nan(),
+            Val::Bool(b) => *b,
+        }
+    }
+    fn strings(self) -> Vec<String> {
+        match self {
+            Val::Set(v) => v,
+            Val::Str(s) => vec![s],
+            Val::Num(n) => vec![n.to_string()],
+            Val::Bool(b) => vec![b.to_string()],
+        }
+    }
+    fn string(self) -> String {
+        self.strings().into_iter().next().unwrap_or_default()
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+    fn skip_ws(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.src.len() - trimmed.len();
+    }
+    fn eat(&mut self, s: &str) -> bool {
+        self.skip_ws();
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, s: &str) -> Result<(), String> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(format!("'{s}' expected at {}", self.pos))
+        }
+    }
+    fn name(&mut self) -> Result<String, String> {
+        self.skip_ws();
+        let len = self.rest()
+            .find(|c: char| ! (c.is_alphanumeric() || "_-.:".contains(c)))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err(format!("name expected at {}", self.pos));
+        }
+        let name = self.rest()[..len].to_string();
+        self.pos += len;
+        Ok(name)
+    }
+
+    fn path(&mut self) -> Result<Path, String> {
+        self.skip_ws();
+        let absolute = self.rest().starts_with('/');
+        let mut steps = vec![];
+        let mut descendant = false;
+        loop {
+            if self.eat("//") {
+                descendant = true;
+            } else if self.eat("/") {
+                descendant = false;
+            } else if ! steps.is_empty() || absolute {
+                break;
+            }
+            self.skip_ws();
+            // 絶対パスの "/" のみ
+            if steps.is_empty() && absolute && ! descendant && self.step_end() {
+                break;
+            }
+            steps.push(self.step(descendant)?);
+            descendant = false;
+        }
+        Ok(Path { absolute, steps })
+    }
+    fn step_end(&self) -> bool {
+        self.rest().is_empty() || self.rest().starts_with([']', ')', ',', '=', '!', '<', '>', '|', ' '])
+    }
+    fn step(&mut self, descendant: bool) -> Result<Step, String> {
+        let test = if self.eat("..") {
+            NodeTest::Parent
+        } else if self.eat(".") {
+            NodeTest::SelfNode
+        } else if self.eat("@") {
+            if self.eat("*") {
+                NodeTest::Attr(None)
+            } else {
+                NodeTest::Attr(Some(self.name()?))
+            }
+        } else if self.eat("*") {
+            NodeTest::Any
+        } else {
+            let name = self.name()?;
+            if self.eat("(") {
+                self.expect(")")?;
+                match name.as_str() {
+                    "text" => NodeTest::Text,
+                    "node" => NodeTest::Node,
+                    _ => return Err(format!("unsupported node test: {name}()")),
+                }
+            } else {
+                NodeTest::Name(name)
+            }
+        };
+        let mut predicates = vec![];
+        while self.eat("[") {
+            predicates.push(self.or()?);
+            self.expect("]")?;
+        }
+        Ok(Step { descendant, test, predicates })
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.keyword("or") {
+            let right = self.and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.cmp()?;
+        while self.keyword("and") {
+            let right = self.cmp()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+    fn keyword(&mut self, word: &str) -> bool {
+        self.skip_ws();
+        let rest = self.rest();
+        let followed = rest[word.len().min(rest.len())..].chars().next();
+        if rest.starts_with(word) && followed.is_some_and(|c| c.is_whitespace() || c == '(') {
+            self.pos += word.len();
+            true
+        } else {
+            false
+        }
+    }
+    fn cmp(&mut self) -> Result<Expr, String> {
+        let left = self.operand()?;
+        let op = if self.eat("!=") {
+            CmpOp::Ne
+        } else if self.eat("<=") {
+            CmpOp::Le
+        } else if self.eat(">=") {
+            CmpOp::Ge
+        } else if self.eat("=") {
+            CmpOp::Eq
+        } else if self.eat("<") {
+            CmpOp::Lt
+        } else if self.eat(">") {
+            CmpOp::Gt
+        } else {
+            return Ok(left);
+        };
+        let right = self.operand()?;
+        Ok(Expr::Cmp(Box::new(left), op, Box::new(right)))
+    }
+    fn operand(&mut self) -> Result<Expr, String> {
+        self.skip_ws();
+        let rest = self.rest();
+        if self.eat("(") {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if let Some(quote) = rest.chars().next().filter(|c| *c == '\'' || *c == '"') {
+            let end = rest[1..].find(quote).ok_or("unterminated string".to_string())?;
+            self.pos += end + 2;
+            return Ok(Expr::Str(rest[1..end + 1].to_string()));
+        }
+        if rest.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+            let len = rest[1..].find(|c: char| ! (c.is_ascii_digit() || c == '.'))
+                .map(|i| i + 1)
+                .unwrap_or(rest.len());
+            let n = rest[..len].parse().map_err(|_| format!("invalid number: {}", &rest[..len]))?;
+            self.pos += len;
+            return Ok(Expr::Num(n));
+        }
+        // 関数呼び出しかどうか
+        let start = self.pos;
+        if let Ok(name) = self.name()
+            && self.eat("(")
+            && ! matches!(name.as_str(), "text" | "node")
+        {
+            let mut args = vec![];
+            if ! self.eat(")") {
+                loop {
+                    args.push(self.or()?);
+                    if self.eat(")") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            return match name.as_str() {
+                "not" if args.len() == 1 => Ok(Expr::Not(Box::new(args.remove(0)))),
+                "position" | "last" | "contains" | "starts-with" | "ends-with" |
+                "string" | "number" | "count" | "name" | "local-name" | "normalize-space" => Ok(Expr::Func(name, args)),
+                _ => Err(format!("unsupported function: {name}()")),
+            };
+        }
+        self.pos = start;
+        Ok(Expr::Path(self.path()?))
+    }
+}
+
+fn parse(path: &str) -> Result<Path, String> {
+    let mut parser = Parser { src: path, pos: 0 };
+    let parsed = parser.path()?;
+    parser.skip_ws();
+    if parsed.steps.is_empty() && ! parsed.absolute {
+        return Err("empty path".into());
+    }
+    if ! parser.rest().is_empty() {
+        return Err(format!("unexpected '{}'", parser.rest()));
+    }
+    Ok(parsed)
+}
+
+/// contextを起点にパスを評価する
+pub(crate) fn evaluate<T: XPathTree>(tree: &T, context: T::Id, path: &str) -> Result<Vec<XPathItem<T::Id>>, String> {
+    let path = parse(path)?;
+    select(tree, context, &path)
+}
+
+fn select<T: XPathTree>(tree: &T, context: T::Id, path: &Path) -> Result<Vec<XPathItem<T::Id>>, String> {
+    let mut current = vec![XPathItem::Node(if path.absolute { tree.document() } else { context })];
+    for step in &path.steps {
+        let mut next: Vec<XPathItem<T::Id>> = vec![];
+        for item in &current {
+            let XPathItem::Node(node) = item else {
+                return Err("attribute or text() must be the last step".into());
+            };
+            let contexts = if step.descendant {
+                tree.descendants_of(*node, true)
+            } else {
+                vec![*node]
+            };
+            for ctx in contexts {
+                let candidates = candidates(tree, ctx, &step.test);
+                let filtered = apply_predicates(tree, candidates, &step.predicates)?;
+                for item in filtered {
+                    let dup = match &item {
+                        XPathItem::Node(id) => next.iter().any(|n| matches!(n, XPathItem::Node(other) if other == id)),
+                        XPathItem::Value(_) => false,
+                    };
+                    if ! dup {
+                        next.push(item);
+                    }
+                }
+            }
+        }
+        current = next;
+    }
+    Ok(current)
+}
+
+fn candidates<T: XPathTree>(tree: &T, ctx: T::Id, test: &NodeTest) -> Vec<XPathItem<T::Id>> {
+    let nodes = |f: &dyn Fn(T::Id) -> bool| -> Vec<XPathItem<T::Id>> {
+        tree.children_of(ctx).into_iter()
+            .filter(|c| f(*c))
+            .map(XPathItem::Node)
+            .collect()
+    };
+    match test {
+        NodeTest::SelfNode => vec![XPathItem::Node(ctx)],
+        NodeTest::Parent => tree.parent_of(ctx).map(XPathItem::Node).into_iter().collect(),
+        NodeTest::Any => nodes(&|c| tree.element_name(c).is_some()),
+        NodeTest::Name(name) => nodes(&|c| tree.matches_name(c, name)),
+        NodeTest::Node => nodes(&|_| true),
+        NodeTest::Text => tree.children_of(ctx).into_iter()
+            .filter_map(|c| tree.text_of(c).map(|s| XPathItem::Value(s.to_string())))
+            .collect(),
+        NodeTest::Attr(name) => tree.attributes(ctx).into_iter()
+            .filter(|(n, _)| name.as_deref().is_none_or(|name| *n == name || n.split_once(':').is_some_and(|(_, l)| l == name)))
+            .map(|(_, v)| XPathItem::Value(v.to_string()))
+            .collect(),
+    }
+}
+
+fn apply_predicates<T: XPathTree>(tree: &T, mut items: Vec<XPathItem<T::Id>>, predicates: &[Expr]) -> Result<Vec<XPathItem<T::Id>>, String> {
+    for predicate in predicates {
+        let size = items.len();
+        let mut kept = vec![];
+        for (i, item) in items.into_iter().enumerate() {
+            let position = i + 1;
+            let matched = match &item {
+                XPathItem::Node(node) => {
+                    let ctx = Context { node: *node, position, size };
+                    match eval(tree, &ctx, predicate)? {
+                        Val::Num(n) => n == position as f64,
+                        val => val.truthy(),
+                    }
+                },
+                // 値に対しては位置指定のみ有効
+                XPathItem::Value(_) => match predicate {
+                    Expr::Num(n) => *n == position as f64,
+                    Expr::Func(name, _) if name == "last" => position == size,
+                    _ => true,
+                },
+            };
+            if matched {
+                kept.push(item);
+            }
+        }
+        items = kept;
+    }
+    Ok(items)
+}
+
+struct Context<Id> {
+    node: Id,
+    position: usize,
+    size: usize,
+}
+
+fn eval<T: XPathTree>(tree: &T, ctx: &Context<T::Id>, expr: &Expr) -> Result<Val, String> {
+    let val = match expr {
+        Expr::Or(l, r) => Val::Bool(eval(tree, ctx, l)?.truthy() || eval(tree, ctx, r)?.truthy()),
+        Expr::And(l, r) => Val::Bool(eval(tree, ctx, l)?.truthy() && eval(tree, ctx, r)?.truthy()),
+        Expr::Not(e) => Val::Bool(! eval(tree, ctx, e)?.truthy()),
+        Expr::Cmp(l, op, r) => {
+            let l = eval(tree, ctx, l)?;
+            let r = eval(tree, ctx, r)?;
+            Val::Bool(compare(l, *op, r))
+        },
+        Expr::Str(s) => Val::Str(s.clone()),
+        Expr::Num(n) => Val::Num(*n),
+        Expr::Path(path) => {
+            let values = select(tree, ctx.node, path)?.into_iter()
+                .map(|item| match item {
+                    XPathItem::Node(id) => tree.string_value(id),
+                    XPathItem::Value(s) => s,
+                })
+                .collect();
+            Val::Set(values)
+        },
+        Expr::Func(name, args) => {
+            let mut strings = vec![];
+            for arg in args {
+                strings.push(eval(tree, ctx, arg)?);
+            }
+            let mut strings = strings.into_iter();
+            let mut next = || strings.next().map(|v| v.string()).unwrap_or_default();
+            match name.as_str() {
+                "position" => Val::Num(ctx.position as f64),
+                "last" => Val::Num(ctx.size as f64),
+                "contains" => {
+                    let (s, sub) = (next(), next());
+                    Val::Bool(s.contains(&sub))
+                },
+                "starts-with" => {
+                    let (s, sub) = (next(), next());
+                    Val::Bool(s.starts_with(&sub))
+                },
+                "ends-with" => {
+                    let (s, sub) = (next(), next());
+                    Val::Bool(s.ends_with(&sub))
+                },
+                "string" if args.is_empty() => Val::Str(tree.string_value(ctx.node)),
+                "string" => Val::Str(next()),
+                "normalize-space" => {
+                    let s = if args.is_empty() { tree.string_value(ctx.node) } else { next() };
+                    Val::Str(s.split_whitespace().collect::<Vec<_>>().join(" "))
+                },
+                "number" => Val::Num(next().trim().parse().unwrap_or(f64::NAN)),
+                "count" => match args.first() {
+                    Some(arg) => match eval(tree, ctx, arg)? {
+                        Val::Set(v) => Val::Num(v.len() as f64),
+                        _ => Val::Num(1.0),
+                    },
+                    None => Val::Num(0.0),
+                },
+                "name" => Val::Str(tree.element_name(ctx.node).unwrap_or_default().to_string()),
+                "local-name" => {
+                    let name = tree.element_name(ctx.node).unwrap_or_default();
+                    let local = name.split_once(':').map(|(_, l)| l).unwrap_or(name);
+                    Val::Str(local.to_string())
+                },
+                _ => return Err(format!("unsupported function: {name}()")),
+            }
+        },
+    };
+    Ok(val)
+}
+
+/// どちらかがノード集合であればいずれかの要素が条件を満たせば真
+fn compare(left: Val, op: CmpOp, right: Val) -> bool {
+    let numeric = matches!(left, Val::Num(_)) || matches!(right, Val::Num(_))
+        || ! matches!(op, CmpOp::Eq | CmpOp::Ne);
+    let boolean = matches!(left, Val::Bool(_)) || matches!(right, Val::Bool(_));
+    if boolean {
+        let (l, r) = (left.truthy(), right.truthy());
+        return match op {
+            CmpOp::Eq => l == r,
+            CmpOp::Ne => l != r,
+            _ => false,
+        };
+    }
+    let left = left.strings();
+    let right = right.strings();
+    left.iter().any(|l| right.iter().any(|r| {
+        if numeric {
+            let (Ok(l), Ok(r)) = (l.trim().parse::<f64>(), r.trim().parse::<f64>()) else {
+                return false;
+            };
+            match op {
+                CmpOp::Eq => l == r,
+                CmpOp::Ne => l != r,
+                CmpOp::Lt => l < r,
+                CmpOp::Le => l <= r,
+                CmpOp::Gt => l > r,
+                CmpOp::Ge => l >= r,
+            }
+        } else {
+            match op {
+                CmpOp::Eq => l == r,
+                _ => l != r,
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Parser as XmlParser, XmlTree};
+
+    fn find(tree: &XmlTree, path: &str) -> Vec<String> {
+        evaluate(tree, XmlTree::DOCUMENT, path).unwrap().into_iter()
+            .map(|item| match item {
+                XPathItem::Node(id) => format!("<{}>", tree.attr(id, "id").or(tree.name(id)).unwrap_or("?")),
+                XPathItem::Value(s) => s,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_xpath() {
+        let xml = r#"<root>
+            <item id="a" type="x"><name>foo</name><price>10</price></item>
+            <item id="b" type="y"><name>bar</name><price>30</price></item>
+            <group><item id="c" type="x"><name>baz</name><price>20</price></item></group>
+        </root>"#;
+        let tree = XmlParser::parse(xml).unwrap();
+        assert_eq!(find(&tree, "/root/item"), vec!["<a>", "<b>"]);
+        assert_eq!(find(&tree, "//item/@id"), vec!["a", "b", "c"]);
+        assert_eq!(find(&tree, "//item[@type='x']/name/text()"), vec!["foo", "baz"]);
+        assert_eq!(find(&tree, "//item[price > 15]"), vec!["<b>", "<c>"]);
+        assert_eq!(find(&tree, "/root/item[last()]"), vec!["<b>"]);
+        assert_eq!(find(&tree, "/root/item[2]/name"), vec!["<name>"]);
+        assert_eq!(find(&tree, "//item[contains(name, 'a') and not(@type = 'y')]"), vec!["<c>"]);
+        assert_eq!(find(&tree, "//name[starts-with(., 'b')]/.."), vec!["<b>", "<c>"]);
+        assert_eq!(find(&tree, "/root/*"), vec!["<a>", "<b>", "<group>"]);
+        assert_eq!(find(&tree, "//item[@id='a' or @id='c']/price[. = 20]"), vec!["<price>"]);
+        assert_eq!(find(&tree, "/"), vec!["<?>"]);
+        assert!(evaluate(&tree, 0, "//item[").is_err());
+        assert!(evaluate(&tree, 0, "//item[foo()]").is_err());
+        assert!(evaluate(&tree, 0, "//@id/name").is_err());
+    }
+}
@@ -0,0 +1,576 @@
+//! ZipArchiveオブジェクト
+//!
+//! エントリの追加・削除はメモリ上で行い、saveでまとめて書き出す\
+//! 既存のエントリは展開せずにそのままコピーする\
+//! ただし暗号化されたエントリは一度復号し、設定されたパスワードで暗号化し直す
+
+use super::{Object, UObject};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+use crate::sandbox::SandboxPolicy;
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use serde_json::{Map, Value as JsonValue};
+use zip::{AesMode, CompressionMethod, DateTime, ZipArchive as ZipReader, ZipWriter};
+use zip::write::{FileOptions, SimpleFileOptions};
+
+pub type ArchiveResult<T> = Result<T, UError>;
+
+fn zip_error(message: UErrorMessage) -> UError {
+    UError::new(UErrorKind::ZipError, message)
+}
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// 読み出しのたびに複製しないよう共有する
+#[derive(Debug, Clone)]
+struct SharedBytes(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for SharedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Source {
+    /// 新規作成
+    None,
+    File(PathBuf),
+    Bytes(SharedBytes),
+}
+
+#[derive(Debug, Clone)]
+enum Entry {
+    /// 元のアーカイブのエントリ
+    Original { index: usize, name: String },
+    /// メモリ上のデータから追加
+    Data { name: String, data: Vec<u8> },
+    /// ファイルから追加、保存時に読み込む
+    File { name: String, path: PathBuf },
+}
+
+impl Entry {
+    fn name(&self) -> &str {
+        match self {
+            Entry::Original { name, .. } |
+            Entry::Data { name, .. } |
+            Entry::File { name, .. } => name,
+        }
+    }
+    fn is_dir(&self) -> bool {
+        self.name().ends_with('/')
+    }
+}
+
+#[derive(Debug)]
+pub struct ZipArchive {
+    source: Source,
+    /// 保存先
+    path: Option<PathBuf>,
+    entries: Vec<Entry>,
+    /// 暗号化エントリの読み出しと、追加エントリの暗号化 (AES-256) に使う
+    password: Option<String>,
+    /// 圧縮レベル (0-9)、0なら無圧縮、Noneなら既定値
+    level: Option<i64>,
+    /// サンドボックス有効時はファイルの読み書きを許可フォルダ内に限る
+    sandbox: Option<Arc<SandboxPolicy>>,
+}
+
+impl fmt::Display for ZipArchive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "ZipArchive({})", path.to_string_lossy()),
+            None => write!(f, "ZipArchive"),
+        }
+    }
+}
+
+impl ZipArchive {
+    /// zipファイルを開く、ファイルがなければ新規作成として扱う
+    pub fn open(path: &str, password: Option<String>) -> ArchiveResult<Self> {
+        let path = PathBuf::from(path);
+        let source = if path.exists() {
+            Source::File(path.clone())
+        } else {
+            Source::None
+        };
+        let mut archive = Self { source, path: Some(path), entries: vec![], password, level: None, sandbox: None };
+        archive.load_entries()?;
+        Ok(archive)
+    }
+    /// バイト配列からzipを読む、空なら新規作成として扱う
+    pub fn from_bytes(bytes: Vec<u8>, password: Option<String>) -> ArchiveResult<Self> {
+        let source = if bytes.is_empty() {
+            Source::None
+        } else {
+            Source::Bytes(SharedBytes(Arc::new(bytes)))
+        };
+        let mut archive = Self { source, path: None, entries: vec![], password, level: None, sandbox: None };
+        archive.load_entries()?;
+        Ok(archive)
+    }
+    /// サンドボックスのポリシーを設定する
+    pub fn with_sandbox(mut self, sandbox: Option<Arc<SandboxPolicy>>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+    fn check_path(&self, path: &Path) -> ArchiveResult<()> {
+        match &self.sandbox {
+            Some(sandbox) => sandbox.check_path(&path.to_string_lossy()),
+            None => Ok(()),
+        }
+    }
+
+    fn reader(&self) -> ArchiveResult<Option<ZipReader<Box<dyn ReadSeek>>>> {
+        let reader: Box<dyn ReadSeek> = match &self.source {
+            Source::None => return Ok(None),
+            Source::File(path) => Box::new(File::open(path)?),
+            Source::Bytes(bytes) => Box::new(Cursor::new(bytes.clone())),
+        };
+        Ok(Some(ZipReader::new(reader)?))
+    }
+    fn load_entries(&mut self) -> ArchiveResult<()> {
+        self.entries = match self.reader()? {
+            Some(reader) => (0..reader.len())
+                .filter_map(|index| {
+                    let name = reader.name_for_index(index)?.to_string();
+                    Some(Entry::Original { index, name })
+                })
+                .collect(),
+            None => vec![],
+        };
+        Ok(())
+    }
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name() == name)
+    }
+    fn entry(&self, name: &str) -> ArchiveResult<&Entry> {
+        self.position(name)
+            .map(|i| &self.entries[i])
+            .ok_or(zip_error(UErrorMessage::ZipEntryNotFound(name.to_string())))
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.entries.iter().map(|entry| entry.name().to_string()).collect()
+    }
+    /// エントリの内容を書き出す
+    fn read_entry(&self, name: &str, out: &mut dyn Write) -> ArchiveResult<u64> {
+        let size = match self.entry(name)? {
+            Entry::Original { index, .. } => {
+                let Some(mut reader) = self.reader()? else {
+                    return Err(zip_error(UErrorMessage::ZipEntryNotFound(name.to_string())));
+                };
+                let mut file = match &self.password {
+                    Some(password) => reader.by_index_decrypt(*index, password.as_bytes())?,
+                    None => reader.by_index(*index)?,
+                };
+                io::copy(&mut file, out)?
+            },
+            Entry::Data { data, .. } => {
+                out.write_all(data)?;
+                data.len() as u64
+            },
+            Entry::File { path, .. } => io::copy(&mut File::open(path)?, out)?,
+        };
+        Ok(size)
+    }
+    pub fn read_bytes(&self, name: &str) -> ArchiveResult<Vec<u8>> {
+        let mut buf = vec![];
+        self.read_entry(name, &mut buf)?;
+        Ok(buf)
+    }
+    pub fn read_string(&self, name: &str) -> ArchiveResult<String> {
+        let bytes = self.read_bytes(name)?;
+        let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+        Ok(String::from_utf8_lossy(bytes).to_string())
+    }
+    /// エントリの情報
+    pub fn info(&self, name: &str) -> ArchiveResult<JsonValue> {
+        let mut map = Map::new();
+        map.insert("name".into(), name.into());
+        match self.entry(name)? {
+            Entry::Original { index, .. } => {
+                if let Some(mut reader) = self.reader()? {
+                    let file = reader.by_index_raw(*index)?;
+                    let modified = file.last_modified().map(format_datetime);
+                    map.insert("size".into(), file.size().into());
+                    map.insert("compressed".into(), file.compressed_size().into());
+                    map.insert("modified".into(), modified.into());
+                    map.insert("isdir".into(), file.is_dir().into());
+                    map.insert("encrypted".into(), file.encrypted().into());
+                }
+            },
+            entry => {
+                let size = match entry {
+                    Entry::Data { data, .. } => Some(data.len() as u64),
+                    Entry::File { path, .. } => fs::metadata(path).ok().map(|m| m.len()),
+                    Entry::Original { .. } => None,
+                };
+                map.insert("size".into(), size.into());
+                map.insert("compressed".into(), JsonValue::Null);
+                map.insert("modified".into(), JsonValue::Null);
+                map.insert("isdir".into(), entry.is_dir().into());
+                map.insert("encrypted".into(), self.password.is_some().into());
+            },
+        }
+        Ok(JsonValue::Object(map))
+    }
+
+    /// 同名のエントリがあれば置き換える
+    fn push(&mut self, entry: Entry) {
+        match self.position(entry.name()) {
+            Some(i) => self.entries[i] = entry,
+            None => self.entries.push(entry),
+        }
+    }
+    pub fn add_data(&mut self, name: String, data: Vec<u8>) {
+        self.push(Entry::Data { name, data });
+    }
+    pub fn add_file(&mut self, path: &str, name: Option<String>) -> ArchiveResult<()> {
+        let path = PathBuf::from(path);
+        self.check_path(&path)?;
+        // 存在しなければここでエラーにする
+        File::open(&path)?;
+        let name = match name {
+            Some(name) => name,
+            None => path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        };
+        self.push(Entry::File { name, path });
+        Ok(())
+    }
+    pub fn remove(&mut self, name: &str) -> bool {
+        match self.position(name) {
+            Some(i) => {
+                self.entries.remove(i);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// 展開先のパスを得る、フォルダ外を指す名前は拒否する
+    fn enclosed_path(dir: &Path, name: &str) -> ArchiveResult<PathBuf> {
+        let relative = Path::new(name);
+        let is_safe = relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if is_safe {
+            Ok(dir.join(relative))
+        } else {
+            Err(zip_error(UErrorMessage::InvalidZipEntryName(name.to_string())))
+        }
+    }
+    /// エントリをファイルに書き出す
+    pub fn extract(&self, name: &str, out: &str) -> ArchiveResult<()> {
+        let out = PathBuf::from(out);
+        self.check_path(&out)?;
+        if let Some(parent) = out.parent().filter(|p| ! p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(out)?;
+        self.read_entry(name, &mut file)?;
+        Ok(())
+    }
+    /// すべてのエントリをフォルダに展開する
+    pub fn extract_all(&self, dir: &str) -> ArchiveResult<()> {
+        let dir = PathBuf::from(dir);
+        self.check_path(&dir)?;
+        for entry in &self.entries {
+            let path = Self::enclosed_path(&dir, entry.name())?;
+            if entry.is_dir() {
+                fs::create_dir_all(path)?;
+            } else {
+                self.extract(entry.name(), &path.to_string_lossy())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn file_options(&self) -> FileOptions<'_, ()> {
+        let method = match self.level {
+            Some(0) => CompressionMethod::Stored,
+            _ => CompressionMethod::Deflated,
+        };
+        let options = SimpleFileOptions::default()
+            .compression_method(method)
+            .compression_level(self.level.filter(|level| *level > 0));
+        match &self.password {
+            Some(password) => options.with_aes_encryption(AesMode::Aes256, password),
+            None => options,
+        }
+    }
+    fn write_to<W: Write + Seek>(&self, writer: W) -> ArchiveResult<W> {
+        let mut zip = ZipWriter::new(writer);
+        let mut reader = self.reader()?;
+        let options = self.file_options();
+        for entry in &self.entries {
+            match entry {
+                Entry::Original { index, name } => {
+                    let Some(reader) = reader.as_mut() else {
+                        continue;
+                    };
+                    let file = reader.by_index_raw(*index)?;
+                    if ! file.encrypted() {
+                        zip.raw_copy_file(file)?;
+                        continue;
+                    }
+                    // 暗号化情報はそのままコピーできないため復号して書き直す
+                    let modified = file.last_modified();
+                    drop(file);
+                    let password = self.password.as_deref()
+                        .ok_or(zip_error(UErrorMessage::ZipPasswordRequired(name.to_string())))?;
+                    let mut file = reader.by_index_decrypt(*index, password.as_bytes())?;
+                    let options = match modified {
+                        Some(modified) => options.last_modified_time(modified),
+                        None => options,
+                    };
+                    zip.start_file(name, options)?;
+                    io::copy(&mut file, &mut zip)?;
+                },
+                entry if entry.is_dir() => zip.add_directory(entry.name(), options)?,
+                Entry::Data { name, data } => {
+                    zip.start_file(name, options)?;
+                    zip.write_all(data)?;
+                },
+                Entry::File { name, path } => {
+                    zip.start_file(name, options)?;
+                    io::copy(&mut File::open(path)?, &mut zip)?;
+                },
+            }
+        }
+        Ok(zip.finish()?)
+    }
+    /// ファイルに保存する、一時ファイルに書き出してから置き換える
+    pub fn save(&mut self, path: Option<String>) -> ArchiveResult<()> {
+        let path = path.map(PathBuf::from)
+            .or_else(|| self.path.clone())
+            .ok_or(zip_error(UErrorMessage::ZipSavePathRequired))?;
+        self.check_path(&path)?;
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let result = File::create(&temp)
+            .map_err(UError::from)
+            .and_then(|file| self.write_to(file));
+        match result {
+            Ok(file) => {
+                drop(file);
+                fs::rename(&temp, &path)?;
+            },
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                return Err(e);
+            },
+        }
+        self.source = Source::File(path.clone());
+        self.path = Some(path);
+        self.load_entries()
+    }
+    pub fn to_bytes(&self) -> ArchiveResult<Vec<u8>> {
+        let cursor = self.write_to(Cursor::new(vec![]))?;
+        Ok(cursor.into_inner())
+    }
+
+    pub fn get_property(&self, name: &str) -> ArchiveResult<Object> {
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "path" => match &self.path {
+                Some(path) => path.to_string_lossy().to_string().into(),
+                None => Object::Empty,
+            },
+            "count" => self.entries.len().into(),
+            "items" => Object::Array(self.names().into_iter().map(Object::from).collect()),
+            _ => return Err(zip_error(UErrorMessage::InvalidMember(name.to_string()))),
+        };
+        Ok(obj)
+    }
+    /// 設定用メソッドは自身を返すためNoneを返す
+    pub fn invoke_method(&mut self, name: &str, args: Vec<Object>) -> ArchiveResult<Option<Object>> {
+        let obj = match name.to_ascii_lowercase().as_str() {
+            "read" => Some(self.read_string(&args.as_string(0)?)?.into()),
+            "readbytes" => Some(Object::ByteArray(self.read_bytes(&args.as_string(0)?)?)),
+            "info" => Some(Object::UObject(UObject::from(self.info(&args.as_string(0)?)?))),
+            "exists" => Some(self.position(&args.as_string(0)?).is_some().into()),
+            "add" => {
+                let name = args.as_string(0)?;
+                let data = match args.get(1) {
+                    Some(Object::ByteArray(bytes)) => bytes.clone(),
+                    Some(o) => o.to_string().into_bytes(),
+                    None => vec![],
+                };
+                self.add_data(name, data);
+                None
+            },
+            "addfile" => {
+                let path = args.as_string(0)?;
+                let name = args.as_string_or_empty(1)?;
+                self.add_file(&path, name)?;
+                None
+            },
+            "remove" => Some(self.remove(&args.as_string(0)?).into()),
+            "extract" => {
+                self.extract(&args.as_string(0)?, &args.as_string(1)?)?;
+                Some(Object::Empty)
+            },
+            "extractall" => {
+                self.extract_all(&args.as_string(0)?)?;
+                Some(Object::Empty)
+            },
+            "setlevel" => {
+                self.level = match args.first().and_then(|o| o.as_f64(false)) {
+                    Some(n) if (0.0..=9.0).contains(&n) => Some(n as i64),
+                    Some(n) => return Err(zip_error(UErrorMessage::InvalidArgument(n.into()))),
+                    None => None,
+                };
+                None
+            },
+            "setpassword" => {
+                self.password = args.as_string_or_empty(0)?;
+                None
+            },
+            "save" => {
+                self.save(args.as_string_or_empty(0)?)?;
+                Some(Object::Empty)
+            },
+            "tobytes" => Some(Object::ByteArray(self.to_bytes()?)),
+            _ => return Err(zip_error(UErrorMessage::InvalidMember(name.to_string()))),
+        };
+        Ok(obj)
+    }
+}
+
+fn format_datetime(dt: DateTime) -> String {
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        dt.year(), dt.month(), dt.day(), dt.hour(), dt.minute(), dt.second()
+    )
+}
+
+trait ZipArg {
+    fn as_string(&self, index: usize) -> ArchiveResult<String>;
+    fn as_string_or_empty(&self, index: usize) -> ArchiveResult<Option<String>>;
+}
+impl ZipArg for Vec<Object> {
+    fn as_string(&self, index: usize) -> ArchiveResult<String> {
+        self.as_string_or_empty(index)?
+            .ok_or(zip_error(UErrorMessage::BuiltinArgRequiredAt(index + 1)))
+    }
+    fn as_string_or_empty(&self, index: usize) -> ArchiveResult<Option<String>> {
+        match self.get(index) {
+            None |
+            Some(Object::Empty) |
+            Some(Object::EmptyParam) => Ok(None),
+            Some(o) => Ok(Some(o.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_archive() {
+        let mut zip = ZipArchive::from_bytes(vec![], None).unwrap();
+        zip.add_data("a.txt".into(), b"hello".to_vec());
+        zip.add_data("dir/".into(), vec![]);
+        zip.add_data("dir/b.bin".into(), vec![0, 1, 2]);
+        zip.add_data("a.txt".into(), b"\xEF\xBB\xBFreplaced".to_vec());
+        assert_eq!(zip.names(), vec!["a.txt", "dir/", "dir/b.bin"]);
+        assert_eq!(zip.read_string("a.txt").unwrap(), "replaced");
+
+        let bytes = zip.to_bytes().unwrap();
+        let mut zip = ZipArchive::from_bytes(bytes, None).unwrap();
+        assert_eq!(zip.names(), vec!["a.txt", "dir/", "dir/b.bin"]);
+        assert_eq!(zip.read_bytes("dir/b.bin").unwrap(), vec![0, 1, 2]);
+        assert!(zip.remove("a.txt"));
+        assert!(! zip.remove("a.txt"));
+        assert!(zip.read_bytes("a.txt").is_err());
+
+        let zip = ZipArchive::from_bytes(zip.to_bytes().unwrap(), None).unwrap();
+        assert_eq!(zip.names(), vec!["dir/", "dir/b.bin"]);
+        assert_eq!(zip.info("dir/b.bin").unwrap()["size"], 3);
+    }
+
+    #[test]
+    fn test_password() {
+        let mut zip = ZipArchive::from_bytes(vec![], Some("secret".into())).unwrap();
+        zip.level = Some(9);
+        zip.add_data("secret.txt".into(), b"data".to_vec());
+        let bytes = zip.to_bytes().unwrap();
+
+        let zip = ZipArchive::from_bytes(bytes.clone(), None).unwrap();
+        assert_eq!(zip.info("secret.txt").unwrap()["encrypted"], true);
+        assert!(zip.read_bytes("secret.txt").is_err());
+        let zip = ZipArchive::from_bytes(bytes.clone(), Some("wrong".into())).unwrap();
+        assert!(zip.read_bytes("secret.txt").is_err());
+        let mut zip = ZipArchive::from_bytes(bytes, Some("secret".into())).unwrap();
+        assert_eq!(zip.read_string("secret.txt").unwrap(), "data");
+
+        // 暗号化エントリを書き直すにはパスワードが必要
+        zip.add_data("added.txt".into(), b"added".to_vec());
+        let bytes = zip.to_bytes().unwrap();
+        let zip = ZipArchive::from_bytes(bytes.clone(), Some("secret".into())).unwrap();
+        assert_eq!(zip.read_string("secret.txt").unwrap(), "data");
+        assert_eq!(zip.read_string("added.txt").unwrap(), "added");
+        let zip = ZipArchive::from_bytes(bytes, None).unwrap();
+        assert!(zip.to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_save_file() {
+        let dir = std::env::temp_dir().join("uwscr_ziparchive_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.zip").to_string_lossy().to_string();
+
+        let mut zip = ZipArchive::open(&path, None).unwrap();
+        assert!(zip.names().is_empty());
+        zip.add_data("a.txt".into(), b"a".to_vec());
+        zip.save(None).unwrap();
+        zip.add_data("sub/b.txt".into(), b"b".to_vec());
+        zip.save(None).unwrap();
+
+        let zip = ZipArchive::open(&path, None).unwrap();
+        assert_eq!(zip.names(), vec!["a.txt", "sub/b.txt"]);
+        let out = dir.join("out");
+        zip.extract_all(&out.to_string_lossy()).unwrap();
+        assert_eq!(fs::read(out.join("sub").join("b.txt")).unwrap(), b"b");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_enclosed_path() {
+        let dir = Path::new("out");
+        assert!(ZipArchive::enclosed_path(dir, "a/b.txt").is_ok());
+        assert!(ZipArchive::enclosed_path(dir, "../b.txt").is_err());
+        assert!(ZipArchive::enclosed_path(dir, "/b.txt").is_err());
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let root = std::env::temp_dir().join("uwscr_ziparchive_sandbox");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let outside = std::env::temp_dir().join("uwscr_ziparchive_outside");
+        let policy = SandboxPolicy::new().allow_root(&root);
+        let mut zip = ZipArchive::from_bytes(vec![], None).unwrap()
+            .with_sandbox(Some(Arc::new(policy)));
+        zip.add_data("a.txt".into(), b"a".to_vec());
+
+        let denied = |r: ArchiveResult<()>| matches!(r, Err(e) if e.kind == UErrorKind::SandboxError);
+        assert!(denied(zip.extract("a.txt", &outside.join("a.txt").to_string_lossy())));
+        assert!(denied(zip.extract_all(&outside.to_string_lossy())));
+        assert!(denied(zip.save(Some(outside.join("a.zip").to_string_lossy().to_string()))));
+        assert!(denied(zip.add_file(&outside.join("b.txt").to_string_lossy(), None)));
+        assert!(! outside.exists());
+
+        zip.extract_all(&root.to_string_lossy()).unwrap();
+        zip.save(Some(root.join("a.zip").to_string_lossy().to_string())).unwrap();
+        assert!(root.join("a.txt").exists() && root.join("a.zip").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
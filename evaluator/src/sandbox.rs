@@ -0,0 +1,365 @@
+//! サンドボックスモードによる機能制限
+
+use crate::Evaluator;
+use crate::object::{Object, MemberCaller};
+use crate::error::{UError, UErrorKind, UErrorMessage};
+use crate::EvalResult;
+
+use util::settings::{Sandbox, SandboxCapability, normalize_path, is_inside};
+use parser::ast::Expression;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// サンドボックスの許可設定
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SandboxPolicy {
+    allow: Vec<SandboxCapability>,
+    roots: Vec<PathBuf>,
+    /// 許可された埋め込み側の関数名
+    functions: Vec<String>,
+}
+
+/// ビルトイン関数のパス引数の位置
+enum PathArgs {
+    None,
+    /// 指定位置の引数
+    At(&'static [usize]),
+    /// 指定位置以降すべての引数
+    From(usize),
+}
+
+impl SandboxPolicy {
+    /// すべての機能を禁止するポリシー
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// 設定ファイルから作成、サンドボックスが無効ならNone
+    pub fn from_settings(sandbox: &Sandbox) -> Option<Self> {
+        sandbox.enabled.then(|| {
+            let mut policy = Self::new();
+            for cap in &sandbox.allow {
+                policy = policy.allow(*cap);
+            }
+            for root in &sandbox.allowed_roots {
+                policy = policy.allow_root(root);
+            }
+            policy
+        })
+    }
+    /// 機能を許可する
+    pub fn allow(mut self, capability: SandboxCapability) -> Self {
+        if ! self.allow.contains(&capability) {
+            self.allow.push(capability);
+        }
+        self
+    }
+    /// ファイル操作を許可するフォルダを追加する
+    pub fn allow_root<P: AsRef<Path>>(mut self, root: P) -> Self {
+        let root = normalize_path(root.as_ref());
+        self.roots.push(root);
+        self
+    }
+    /// 埋め込み側で登録した関数の呼び出しを許可する
+    pub fn allow_function(mut self, name: &str) -> Self {
+        self.functions.push(name.to_ascii_uppercase());
+        self
+    }
+    pub fn is_allowed(&self, capability: SandboxCapability) -> bool {
+        self.allow.contains(&capability)
+    }
+    /// 機能が許可されていなければエラー
+    pub fn check(&self, capability: SandboxCapability, name: &str) -> EvalResult<()> {
+        if self.is_allowed(capability) {
+            Ok(())
+        } else {
+            Err(UError::new(
+                UErrorKind::SandboxError,
+                UErrorMessage::DeniedBySandbox(capability, name.to_string())
+            ))
+        }
+    }
+    /// パスが許可フォルダ内になければエラー
+    pub fn check_path(&self, path: &str) -> EvalResult<()> {
+        if self.is_allowed(SandboxCapability::Filesystem) {
+            return Ok(());
+        }
+        let normalized = normalize_path(Path::new(path));
+        if self.roots.iter().any(|root| is_inside(&normalized, root)) {
+            Ok(())
+        } else {
+            Err(UError::new(
+                UErrorKind::SandboxError,
+                UErrorMessage::PathDeniedBySandbox(path.to_string())
+            ))
+        }
+    }
+    /// ビルトイン関数の呼び出し可否を確認する
+    ///
+    /// 分類されていない関数は許可されたもの以外すべて拒否する
+    pub fn check_builtin(&self, name: &str, arguments: &[(Option<Expression>, Object)]) -> EvalResult<()> {
+        let Some((capabilities, path_args)) = builtin_capabilities(name) else {
+            return if self.functions.iter().any(|f| f.eq_ignore_ascii_case(name)) {
+                Ok(())
+            } else {
+                Err(UError::new(
+                    UErrorKind::SandboxError,
+                    UErrorMessage::FunctionDeniedBySandbox(name.to_string())
+                ))
+            };
+        };
+        for cap in capabilities {
+            self.check(*cap, name)?;
+        }
+        let paths = match path_args {
+            PathArgs::None => return Ok(()),
+            PathArgs::At(indexes) => indexes.iter()
+                .filter_map(|i| arguments.get(*i))
+                .map(|(_, o)| o)
+                .collect::<Vec<_>>(),
+            PathArgs::From(index) => arguments.iter()
+                .skip(index)
+                .map(|(_, o)| o)
+                .collect(),
+        };
+        for obj in paths {
+            match obj {
+                Object::String(path) => self.check_path(path)?,
+                Object::Array(arr) => {
+                    for o in arr {
+                        if let Object::String(path) = o {
+                            self.check_path(path)?;
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+    /// オブジェクトのメソッド呼び出し可否を確認する
+    pub fn check_member(&self, caller: &MemberCaller, member: &str) -> EvalResult<()> {
+        for cap in member_capabilities(caller) {
+            self.check(*cap, member)?;
+        }
+        Ok(())
+    }
+}
+
+impl Evaluator {
+    /// サンドボックスのポリシーを設定する、Noneならサンドボックスを無効にする
+    pub fn set_sandbox(&mut self, policy: Option<SandboxPolicy>) {
+        self.sandbox = policy.map(Arc::new);
+    }
+    pub fn is_sandboxed(&self) -> bool {
+        self.sandbox.is_some()
+    }
+    /// ファイル操作などを行うオブジェクトに渡すポリシー
+    pub(crate) fn sandbox(&self) -> Option<Arc<SandboxPolicy>> {
+        self.sandbox.clone()
+    }
+    /// サンドボックス有効時に機能が許可されていなければエラー
+    pub(crate) fn check_sandbox(&self, capability: SandboxCapability, name: &str) -> EvalResult<()> {
+        match &self.sandbox {
+            Some(sandbox) => sandbox.check(capability, name),
+            None => Ok(()),
+        }
+    }
+}
+
+/// ビルトイン関数が必要とする機能とパス引数の位置、分類されていない関数はNone
+fn builtin_capabilities(name: &str) -> Option<(&'static [SandboxCapability], PathArgs)> {
+    use SandboxCapability::*;
+    let capabilities: (&'static [SandboxCapability], PathArgs) = match name.to_ascii_lowercase().as_str() {
+        "exec" | "shexec" | "doscmd" | "powershell" | "pwsh" | "poff" => (&[Process], PathArgs::None),
+        "browsercontrol" | "browserbuilder" => (&[Process, Network], PathArgs::None),
+        "webrequest" | "webrequestbuilder" |
+        "udpclient" | "udpsend" | "udprecv" | "udpjoin" | "udpleave" | "udponrecv" |
+        "tcpsend" | "tcplistener" | "tcpserver" |
+        "tcprecv" | "tcprecvuntil" | "tcprecvline" | "tcptimeout" |
+        "websocket" | "wssend" | "wsrecv" | "wsonmessage" | "wsserver" | "wsbroadcast" |
+        "mqttclient" | "mqttpublish" | "mqttsubscribe" | "mqttunsubscribe" | "mqttrecv" |
+        "mailclient" => (&[Network], PathArgs::None),
+        "mailsend" => (&[Network], PathArgs::At(&[6])),
+        "mailfetch" | "tcpclient" => (&[Network], PathArgs::At(&[4])),
+        "httpserver" => (&[Network], PathArgs::At(&[3])),
+        "createoleobj" | "getactiveoleobj" | "getoleitem" | "oleevent" |
+        "xlopen" | "xlactivate" | "xlsheet" | "xlgetdata" | "xlsetdata" | "wmi" |
+        "brgetdata" | "brsetdata" | "brgetsrc" | "brlink" => (&[Com], PathArgs::None),
+        "xlclose" => (&[Com], PathArgs::At(&[1])),
+        "mmv" | "btn" | "kbd" | "sckey" | "clkitem" | "sendstr" |
+        "setslider" | "lockhard" | "lockhardex" | "dropfile" |
+        "getkeystate" | "sethotkey" | "recostate" | "dictate" |
+        // 他のアプリケーションのウィンドウを操作する
+        "ctrlwin" | "acw" => (&[Input], PathArgs::None),
+        "fopen" | "deletefile" | "getdir" | "zipitems" | "csvopen" | "csvreader" | "csvwriter" |
+        "saveimg" | "searchimage" | "chkimg" | "loadini" | "createform" | "sound" => (&[], PathArgs::At(&[0])),
+        "readini" | "deleteini" => (&[], PathArgs::At(&[2])),
+        "writeini" => (&[], PathArgs::At(&[3])),
+        "saveini" | "validatejson" => (&[], PathArgs::At(&[1])),
+        "ziparchive" | "taritems" => (&[], PathArgs::At(&[0])),
+        "unzip" | "untar" => (&[], PathArgs::At(&[0, 1])),
+        "zip" | "tar" => (&[], PathArgs::From(0)),
+        // 評価器内部
+        "eval" | "list_env" | "list_module_member" | "name_of" | "const_as_string" |
+        "assert_equal" | "raise" | "type_of" | "get_settings" | "__p_a_n_i_c__" | "get_struct_layout" |
+        // 配列
+        "join" | "qsort" | "reverse" | "resize" | "slice" | "split" | "calcarray" | "setclear" |
+        "shiftarray" | "map" | "filter" | "reduce" | "find" | "findindex" | "any" | "all" |
+        "groupby" | "uniq" | "flatten" | "ziparray" | "sort" | "chunk" |
+        // 数値
+        "isnan" | "random" | "rng" | "randseed" | "abs" | "zcut" | "int" | "ceil" | "round" |
+        "sqrt" | "power" | "exp" | "ln" | "logn" | "sin" | "cos" | "tan" |
+        "arcsin" | "arccos" | "arctan" | "median" | "mode" | "variance" | "stddev" |
+        "percentile" | "histogram" | "linreg" | "movavg" | "cumsum" | "bigcalc" | "deccalc" |
+        // 文字列
+        "copy" | "length" | "lengthb" | "lengthu" | "lengths" | "lengthw" | "as_string" |
+        "newre" | "regex" | "testre" | "match" | "replace" | "chgmoj" |
+        "tojson" | "fromjson" | "tohashtbl" | "toyaml" | "fromyaml" | "totoml" | "fromtoml" |
+        "parsexml" | "toxml" | "pos" | "betweenstr" | "chknum" | "val" | "trim" |
+        "chr" | "asc" | "chrb" | "ascb" | "isunicode" | "strconv" | "format" | "token" |
+        "encode" | "decode" |
+        // 生成済みのハンドルやデータのみを扱う
+        "fclose" | "fget" | "fput" | "fdelline" | "gzip" | "gunzip" | "deflate" | "inflate" |
+        "csvclose" | "csvread" | "csvwrite" | "fromcsv" | "tocsv" |
+        "remoteobjecttype" | "parsehtml" | "vartype" | "safearray" | "sclose" | "httpresponse" |
+        // ダイアログ
+        "msgbox" | "input" | "logprint" | "slctbox" | "popupmenu" | "balloon" | "fukidasi" |
+        "getformdata" | "setformdata" |
+        // システム情報など
+        "sleep" | "kindofos" | "env" | "setenv" | "task" | "waittask" | "cpuuserate" | "sensor" |
+        "beep" | "gettime" | "datetime" | "speak" | "attachconsole" |
+        // ウィンドウ情報の取得
+        "getid" | "idtohnd" | "hndtoid" | "status" | "getallwin" |
+        "getctlhnd" | "getitem" | "posacc" | "muscur" | "peekcolor" | "getslider" | "chkbtn" |
+        "getstr" | "getslctlst" | "monitor" | "mouseorg" | "chkmorg" | "chkclr" | "enum_acc" => (&[], PathArgs::None),
+        _ => return None,
+    };
+    Some(capabilities)
+}
+
+/// メソッド呼び出しが必要とする機能
+///
+/// オブジェクトを追加した際に分類漏れがないようワイルドカードを使わない
+fn member_capabilities(caller: &MemberCaller) -> &'static [SandboxCapability] {
+    use SandboxCapability::*;
+    match caller {
+        MemberCaller::ComObject(_) => &[Com],
+        MemberCaller::BrowserBuilder(_) |
+        MemberCaller::Browser(_) |
+        MemberCaller::TabWindow(_) |
+        MemberCaller::RemoteObject(_) => &[Process, Network],
+        MemberCaller::WebRequest(_) => &[Network],
+        // ファイル操作は生成時の関数やオブジェクト自身が許可フォルダを確認する
+        MemberCaller::ZipArchive(_) |
+        MemberCaller::CsvStream(_) |
+        MemberCaller::Module(_) |
+        MemberCaller::ClassInstance(_) |
+        MemberCaller::WebResponse(_) |
+        MemberCaller::HtmlNode(_) |
+        MemberCaller::UStruct(_) |
+        MemberCaller::WebViewForm(_) |
+        MemberCaller::WebViewRemoteObject(_) |
+        MemberCaller::UObject(_) |
+        MemberCaller::SafeArray(_) |
+        MemberCaller::DateTime(_) |
+        MemberCaller::XmlNode(_) |
+        MemberCaller::HashTbl(_) |
+        MemberCaller::Rng(_) |
+        MemberCaller::HttpRequest(_) |
+        MemberCaller::HttpResponse(_) => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::Interpreter;
+
+    #[test]
+    fn test_sandbox_path() {
+        let root = std::env::temp_dir().join("uwscr_sandbox");
+        let policy = SandboxPolicy::new().allow_root(&root);
+        let inside = root.join("sub").join("file.txt");
+        let escaped = root.join("..").join("file.txt");
+        assert!(policy.check_path(&inside.to_string_lossy()).is_ok());
+        assert!(policy.check_path(&escaped.to_string_lossy()).is_err());
+        let policy = policy.allow(SandboxCapability::Filesystem);
+        assert!(policy.check_path(&escaped.to_string_lossy()).is_ok());
+    }
+
+    #[test]
+    fn test_sandbox_denied() {
+        let mut uwscr = Interpreter::new();
+        uwscr.set_sandbox(Some(SandboxPolicy::new().allow(SandboxCapability::Network)));
+        let err = uwscr.eval_str("exec(\"notepad\")").unwrap_err();
+        assert_eq!(err.kind, UErrorKind::SandboxError);
+        assert_eq!(err.message, UErrorMessage::DeniedBySandbox(SandboxCapability::Process, "EXEC".into()));
+        let err = uwscr.eval_str("def_dll MessageBoxW(hwnd, wstring, wstring, uint):int:user32.dll").unwrap_err();
+        assert_eq!(err.kind, UErrorKind::SandboxError);
+        let err = uwscr.eval_str("deletefile(\"C:\\foo.txt\")").unwrap_err();
+        assert_eq!(err.message, UErrorMessage::PathDeniedBySandbox("C:\\foo.txt".into()));
+        assert!(uwscr.eval_str("a = length(\"abc\")").is_ok());
+        // 静的ファイルのフォルダも許可フォルダ内に限る
+        let err = uwscr.eval_str("httpserver(\"127.0.0.1\", 0, [], \"C:\\www\")").unwrap_err();
+        assert_eq!(err.message, UErrorMessage::PathDeniedBySandbox("C:\\www".into()));
+        // CA証明書ファイルも許可フォルダ内に限る
+        let err = uwscr.eval_str("tcpclient(\"127.0.0.1\", 443, 1, TLS_VERIFY, \"C:\\ca.pem\")").unwrap_err();
+        assert_eq!(err.message, UErrorMessage::PathDeniedBySandbox("C:\\ca.pem".into()));
+    }
+
+    #[test]
+    fn test_sandbox_network() {
+        let mut uwscr = Interpreter::new();
+        uwscr.set_sandbox(Some(SandboxPolicy::new()));
+        let denied = |uwscr: &mut Interpreter, script: &str, name: &str| {
+            let err = uwscr.eval_str(script).unwrap_err();
+            assert_eq!(err.message, UErrorMessage::DeniedBySandbox(SandboxCapability::Network, name.into()));
+        };
+        denied(&mut uwscr, "tcpclient(\"127.0.0.1\", 80)", "TCPCLIENT");
+        denied(&mut uwscr, "tcprecv(EMPTY)", "TCPRECV");
+        denied(&mut uwscr, "tcpserver(\"127.0.0.1\", 0, EMPTY)", "TCPSERVER");
+        denied(&mut uwscr, "wsserver(\"127.0.0.1\", 0, EMPTY)", "WSSERVER");
+        denied(&mut uwscr, "wsonmessage(EMPTY)", "WSONMESSAGE");
+        denied(&mut uwscr, "wsbroadcast(EMPTY, \"hello\")", "WSBROADCAST");
+    }
+
+    #[test]
+    fn test_sandbox_window() {
+        let mut uwscr = Interpreter::new();
+        uwscr.set_sandbox(Some(SandboxPolicy::new()));
+        let denied = |uwscr: &mut Interpreter, script: &str, name: &str| {
+            let err = uwscr.eval_str(script).unwrap_err();
+            assert_eq!(err.message, UErrorMessage::DeniedBySandbox(SandboxCapability::Input, name.into()));
+        };
+        // 他のアプリケーションのウィンドウを閉じたり動かしたりできない
+        denied(&mut uwscr, "ctrlwin(0, CLOSE2)", "CTRLWIN");
+        denied(&mut uwscr, "acw(0, 0, 0)", "ACW");
+        let err = uwscr.eval_str("sound(\"C:\\foo.wav\")").unwrap_err();
+        assert_eq!(err.message, UErrorMessage::PathDeniedBySandbox("C:\\foo.wav".into()));
+    }
+
+    #[test]
+    fn test_sandbox_builtins_classified() {
+        // 新しいビルトイン関数が分類されないまま拒否されないようにする
+        let unclassified = crate::builtins::init_builtins().into_iter()
+            .filter_map(|obj| match obj.object {
+                Object::BuiltinFunction(name, _, _) => Some(name),
+                _ => None,
+            })
+            .filter(|name| builtin_capabilities(name).is_none())
+            .collect::<Vec<_>>();
+        assert!(unclassified.is_empty(), "{unclassified:?}");
+    }
+
+    #[test]
+    fn test_sandbox_member() {
+        let mut uwscr = Interpreter::new();
+        // サンドボックス有効化前に作られたオブジェクトでもメソッド呼び出しは確認される
+        uwscr.eval_str("public req = webrequest()").unwrap();
+        uwscr.set_sandbox(Some(SandboxPolicy::new()));
+        let err = uwscr.eval_str("req.header(\"a\", \"b\")").unwrap_err();
+        assert_eq!(err.message, UErrorMessage::DeniedBySandbox(SandboxCapability::Network, "header".into()));
+        assert!(uwscr.eval_str("dt = datetime()\r\ns = dt.format(\"%Y\")").is_ok());
+    }
+}
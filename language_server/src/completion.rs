@@ -1091,6 +1091,68 @@ endif
 if "hoge" then // 数値変換できないためエラー
 endif
 ```
+"#
+        ),
+        new_snippet(
+            "OPTION TIMELIMIT", "OPTION TIMELIMIT",
+r#"OPTION TIMELIMIT=${1:sec}
+$0"#,
+"OPTION設定: 実行時間の上限",
+r#"スクリプトの実行時間の上限を秒で指定する  
+0なら無制限
+
+```uwscr
+OPTION TIMELIMIT=60
+
+while true // 60秒経過するとエラー
+wend
+```
+"#
+        ),
+        new_snippet(
+            "OPTION STEPLIMIT", "OPTION STEPLIMIT",
+r#"OPTION STEPLIMIT=${1:n}
+$0"#,
+"OPTION設定: 実行文数の上限",
+r#"実行できる文の数の上限を指定する  
+ループは繰り返しごとに数えられる  
+0なら無制限
+"#
+        ),
+        new_snippet(
+            "OPTION DEPTHLIMIT", "OPTION DEPTHLIMIT",
+r#"OPTION DEPTHLIMIT=${1:n}
+$0"#,
+"OPTION設定: 関数呼び出しの深さの上限",
+r#"関数呼び出しの深さの上限を指定する  
+0なら無制限
+
+```uwscr
+OPTION DEPTHLIMIT=100
+
+function f(n)
+    result = f(n + 1) // 101回目の呼び出しでエラー
+fend
+f(1)
+```
+"#
+        ),
+        new_snippet(
+            "OPTION MEMLIMIT", "OPTION MEMLIMIT",
+r#"OPTION MEMLIMIT=${1:MB}
+$0"#,
+"OPTION設定: 配列・文字列サイズの上限",
+r#"配列や文字列のおおよそのサイズ上限をMB単位で指定する  
+0なら無制限
+"#
+        ),
+        new_snippet(
+            "OPTION RANDSEED", "OPTION RANDSEED",
+r#"OPTION RANDSEED=${1:seed}
+$0"#,
+"OPTION設定: 乱数のシード",
+r#"random関数が使う乱数生成器のシードを指定する  
+同じシードであれば毎回同じ乱数列になる
 "#
         ),
     ]
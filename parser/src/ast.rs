@@ -355,7 +355,7 @@ pub enum Statement {
     },
     Select {
         expression: Expression,
-        cases: Vec<(Vec<Expression>, BlockStatement)>,
+        cases: Vec<SelectCase>,
         default: Option<BlockStatement>
     },
     Function {
@@ -426,6 +426,41 @@ impl std::fmt::Display for Statement {
     }
 }
 
+/// selectのcase節
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct SelectCase {
+    /// カンマ区切りのパターン、いずれかに一致すれば該当
+    pub patterns: Vec<CasePattern>,
+    /// case ～ if 条件 のガード節
+    pub guard: Option<Expression>,
+    pub body: BlockStatement,
+}
+impl SelectCase {
+    pub fn new(patterns: Vec<CasePattern>, guard: Option<Expression>, body: BlockStatement) -> Self {
+        Self { patterns, guard, body }
+    }
+}
+
+/// caseに記述するパターン
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum CasePattern {
+    /// 値の一致、正規表現の場合はマッチするかどうか
+    Value(Expression),
+    /// 式 to 式
+    Range(Expression, Expression),
+    /// is 比較演算子 式
+    Compare(Infix, Expression),
+    /// is 型
+    Type(Expression),
+    /// [パターン, ...]
+    Array(Vec<CasePattern>),
+    /// {キー: パターン, ...}
+    Object(Vec<(String, CasePattern)>),
+    /// 配列・オブジェクトパターン内の変数への束縛
+    Bind(Identifier),
+    /// 配列・オブジェクトパターン内の _
+    Wildcard,
+}
 
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct UEnum {
@@ -2479,6 +2514,11 @@ pub enum OptionSetting {
     GuiPrint(bool),
     ForceBool(bool),
     CondUwsc(bool),
+    TimeLimit(u64),
+    StepLimit(u64),
+    DepthLimit(u32),
+    MemLimit(u64),
+    RandSeed(u64),
     AllowIEObj(bool),
 }
 
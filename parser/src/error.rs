@@ -99,6 +99,10 @@ pub enum ParseErrorKind {
     /// 連想配列定義が不正
     InvalidHashMemberDefinition(Option<Expression>),
     InvalidCallUri(String),
+    /// サンドボックスによりURIからのcallが禁止されている
+    CallUriDeniedBySandbox(String),
+    /// サンドボックスにより許可フォルダ外のスクリプトのcallが禁止されている
+    CallPathDeniedBySandbox(String),
     ExplicitError(String),
     DefinitionStatementNotAllowed,
     OptionStatementNotAllowed,
@@ -341,6 +345,14 @@ impl fmt::Display for ParseErrorKind {
                 "不正なスクリプト ({uri})",
                 "Invalid script uri: {uri}",
             ),
+            ParseErrorKind::CallUriDeniedBySandbox(uri) => write_locale!(f,
+                "サンドボックスモードではURIからのcallは許可されていません ({uri})",
+                "Calling script from uri is not allowed in sandbox mode: {uri}",
+            ),
+            ParseErrorKind::CallPathDeniedBySandbox(path) => write_locale!(f,
+                "サンドボックスモードでは許可フォルダ外のスクリプトのcallは許可されていません ({path})",
+                "Calling script outside of allowed folders is not allowed in sandbox mode: {path}",
+            ),
             ParseErrorKind::ExplicitError(ident) => write_locale!(f,
                 "未宣言の変数 {ident} への代入は禁止されています (OPTION EXPLICIT)",
                 "Assigment to undeclared variable '{ident}' is prohibited by OPTION EXPLICIT",
@@ -88,6 +88,8 @@ pub struct Lexer {
     is_textblock: bool,
     is_comment_textblock: bool,
     is_call: bool,
+    /// caseパターンの解析中であればtrue、単体の _ をワイルドカードとする
+    is_case_pattern: bool,
     /// ( と ) のペアのそれぞれの位置を示す
     pub paren_pairs: Option<Pairs>,
     /// [ と ] のペアのそれぞれの位置を示す
@@ -110,6 +112,7 @@ impl std::fmt::Debug for Lexer {
             .field("is_textblock", &self.is_textblock)
             .field("is_comment_textblock", &self.is_comment_textblock)
             .field("is_call", &self.is_call)
+            .field("is_case_pattern", &self.is_case_pattern)
             .field("def_dll", &self.def_dll)
             .finish()
     }
@@ -129,6 +132,7 @@ impl Lexer {
             is_textblock: false,
             is_comment_textblock: true,
             is_call: false,
+            is_case_pattern: false,
             def_dll: None,
             paren_pairs: None,
             bracket_pairs: None,
@@ -228,6 +232,22 @@ impl Lexer {
             .map(|slice| slice.iter().collect())
     }
 
+    /// caseパターンの解析開始・終了時にパーサーから呼ばれる
+    pub fn set_case_pattern(&mut self, is_case_pattern: bool) {
+        self.is_case_pattern = is_case_pattern;
+    }
+
+    /// 現在の _ 以降が空白を除いて行末またはコメントであればtrue
+    fn is_line_continuation(&self) -> bool {
+        let mut rest = self.input.iter().skip(self.next_pos)
+            .skip_while(|c| matches!(c, ' ' | '\t' | '　'));
+        match rest.next() {
+            None | Some('\r' | '\n' | ';') => true,
+            Some('/') => rest.next() == Some(&'/'),
+            _ => false,
+        }
+    }
+
     fn skip_whitespace(&mut self) -> bool {
         let mut skipped = false;
         while let ' ' | '\t' | '　' = self.ch {
@@ -432,6 +452,8 @@ impl Lexer {
                     'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '#' => {
                         return TokenInfo::new_with_pos(self.consume_identifier(), p, skipped);
                     },
+                    // caseパターン内で行末にない _ はワイルドカード
+                    _ if self.is_case_pattern && ! self.is_line_continuation() => Token::Identifier("_".into()),
                     _ => {
                         self.read_char();
                         let tp = self.next_token();
@@ -1159,6 +1181,48 @@ mod test {
         test_next_token(input, tokens);
     }
 
+    #[test]
+    fn test_underscore() {
+        // caseパターン外では従来通り _ を読み飛ばす
+        let input = "a = 1 + _ // comment\r\n2\r\nb = [_, x]";
+        let tokens = vec![
+            Token::Identifier("a".to_string()),
+            Token::EqualOrAssign,
+            Token::Num(1_f64),
+            Token::Plus,
+            Token::Num(2_f64),
+            Token::Eol,
+            Token::Identifier("b".to_string()),
+            Token::EqualOrAssign,
+            Token::Lbracket,
+            Token::Comma,
+            Token::Identifier("x".to_string()),
+            Token::Rbracket,
+        ];
+        test_next_token(input, tokens);
+    }
+
+    #[test]
+    fn test_underscore_in_case_pattern() {
+        // caseパターン内では行末の _ のみ行継続、それ以外は識別子
+        let input = "[_, x, _\r\n]";
+        let tokens = vec![
+            Token::Lbracket,
+            Token::Identifier("_".to_string()),
+            Token::Comma,
+            Token::Identifier("x".to_string()),
+            Token::Comma,
+            Token::Rbracket,
+            Token::Eof,
+        ];
+        let mut lexer = Lexer::new(input);
+        lexer.set_case_pattern(true);
+        for expected in tokens {
+            let t = lexer.next_token();
+            assert_eq!(t.token, expected);
+        }
+    }
+
     #[test]
     fn test_public() {
         let input = "public fuga = 123";
@@ -10,7 +10,7 @@ use token::{Token, BlockEnd};
 use error::{ParseError, ParseErrorKind};
 use util::{
     get_script, get_utf8,
-    settings::USETTINGS,
+    settings::{USETTINGS, SandboxCapability},
 };
 
 use std::path::PathBuf;
@@ -286,6 +286,10 @@ impl Parser {
         self.next_token.token == *token
     }
 
+    fn is_next_token_in(&self, tokens: &[Token]) -> bool {
+        tokens.contains(&self.next_token.token)
+    }
+
     /// 次のトークンが期待通りであればbumpする
     ///
     /// 異なる場合はエラーを積む
@@ -1165,6 +1169,11 @@ impl Parser {
                 } else {
                     path
                 };
+                if USETTINGS.lock().unwrap().sandbox.denies_path(&path) {
+                    let kind = ParseErrorKind::CallPathDeniedBySandbox(path.to_string_lossy().to_string());
+                    self.error_on_current_token(kind);
+                    return None;
+                }
 
                 match path.extension() {
                     Some(os_str) => {
@@ -1223,6 +1232,12 @@ impl Parser {
                 (script, builder, args)
             },
             Token::Uri(uri) => {
+                let denied = USETTINGS.lock().unwrap().sandbox.denies(SandboxCapability::Network);
+                if denied {
+                    let kind = ParseErrorKind::CallUriDeniedBySandbox(uri);
+                    self.error_on_next_token(kind);
+                    return None;
+                }
                 let maybe_script = match reqwest::blocking::get(&uri) {
                     Ok(response) => if response.status().is_success() {
                         match response.text() {
@@ -2267,6 +2282,66 @@ impl Parser {
                     Statement::Option(OptionSetting::CondUwsc(true))
                 }
             },
+            "timelimit" => {
+                if ! self.bump_to_next_expected_token(Token::EqualOrAssign)? {
+                    return None;
+                }
+                self.bump()?;
+                if let Token::Num(n) = self.current_token.token {
+                    Statement::Option(OptionSetting::TimeLimit(n as u64))
+                } else {
+                    self.error_current_token_is_invalid();
+                    return None;
+                }
+            },
+            "steplimit" => {
+                if ! self.bump_to_next_expected_token(Token::EqualOrAssign)? {
+                    return None;
+                }
+                self.bump()?;
+                if let Token::Num(n) = self.current_token.token {
+                    Statement::Option(OptionSetting::StepLimit(n as u64))
+                } else {
+                    self.error_current_token_is_invalid();
+                    return None;
+                }
+            },
+            "depthlimit" => {
+                if ! self.bump_to_next_expected_token(Token::EqualOrAssign)? {
+                    return None;
+                }
+                self.bump()?;
+                if let Token::Num(n) = self.current_token.token {
+                    Statement::Option(OptionSetting::DepthLimit(n as u32))
+                } else {
+                    self.error_current_token_is_invalid();
+                    return None;
+                }
+            },
+            "memlimit" => {
+                if ! self.bump_to_next_expected_token(Token::EqualOrAssign)? {
+                    return None;
+                }
+                self.bump()?;
+                if let Token::Num(n) = self.current_token.token {
+                    Statement::Option(OptionSetting::MemLimit(n as u64))
+                } else {
+                    self.error_current_token_is_invalid();
+                    return None;
+                }
+            },
+            "randseed" => {
+                if ! self.bump_to_next_expected_token(Token::EqualOrAssign)? {
+                    return None;
+                }
+                self.bump()?;
+                if let Token::Num(n) = self.current_token.token {
+                    Statement::Option(OptionSetting::RandSeed(n as u64))
+                } else {
+                    self.error_current_token_is_invalid();
+                    return None;
+                }
+            },
             "__allow_ie_object__" => {
                 if ! self.is_next_token(&Token::EqualOrAssign) {
                     Statement::Option(OptionSetting::AllowIEObj(true))
@@ -3141,11 +3216,8 @@ impl Parser {
         while self.is_current_token_in(vec![Token::Eol, Token::BlockEnd(BlockEnd::Case), Token::BlockEnd(BlockEnd::Default)]) {
             match self.current_token.token {
                 Token::BlockEnd(BlockEnd::Case) => {
-                    let case_values = self.parse_expression_list(Token::Eol, true)?;
-                    cases.push((
-                        case_values,
-                        self.parse_block_statement()
-                    ));
+                    let case = self.parse_select_case()?;
+                    cases.push(case);
                 },
                 Token::BlockEnd(BlockEnd::Default) => {
                     self.bump()?;
@@ -3163,6 +3235,183 @@ impl Parser {
         Some(Statement::Select {expression, cases, default})
     }
 
+    /// case行の解析
+    ///
+    /// `case パターン, パターン if ガード条件`
+    fn parse_select_case(&mut self) -> Option<SelectCase> {
+        // パターン部分でのみ単体の _ をワイルドカードとして字句解析させる
+        self.lexer.set_case_pattern(true);
+        let patterns = self.parse_case_patterns();
+        self.lexer.set_case_pattern(false);
+        let patterns = patterns?;
+        let guard = if self.is_next_token(&Token::If) || self.is_next_token(&Token::IfB) {
+            self.bump()?;
+            self.bump()?;
+            match self.parse_expression(Precedence::Lowest, ExpressionState::Default) {
+                Some(e) => Some(e),
+                None => {
+                    self.error_on_current_token(ParseErrorKind::ExpressionIsExpected);
+                    return None;
+                }
+            }
+        } else {
+            None
+        };
+        if ! self.is_next_token(&Token::Eol) && ! self.is_next_token(&Token::Eof) {
+            self.error_next_token_is_invalid();
+            return None;
+        }
+        let body = self.parse_block_statement();
+        Some(SelectCase::new(patterns, guard, body))
+    }
+
+    /// caseのパターンをカンマ区切りで解析する
+    fn parse_case_patterns(&mut self) -> Option<Vec<CasePattern>> {
+        let mut patterns = vec![];
+        loop {
+            self.bump()?;
+            let pattern = self.parse_case_pattern(false)?;
+            patterns.push(pattern);
+            if self.is_next_token(&Token::Comma) {
+                self.bump()?;
+                // selectのcaseでは末尾カンマをエラーとする
+                if self.is_next_token(&Token::Eol) || self.is_next_token(&Token::Eof) {
+                    self.error_on_current_token(ParseErrorKind::CommaNotAllowedOnEndOfList);
+                    return None;
+                }
+            } else {
+                break;
+            }
+        }
+        Some(patterns)
+    }
+
+    /// caseのパターンを解析する
+    ///
+    /// - nested: 配列・オブジェクトパターンの要素であればtrue、識別子単体は変数への束縛となる
+    fn parse_case_pattern(&mut self, nested: bool) -> Option<CasePattern> {
+        match &self.current_token.token {
+            Token::Lbracket => return self.parse_case_array_pattern(),
+            Token::Lbrace => return self.parse_case_object_pattern(),
+            Token::Identifier(name) if nested && self.is_next_token_in(&[Token::Comma, Token::Rbracket, Token::Rbrace]) => {
+                return if name == "_" {
+                    Some(CasePattern::Wildcard)
+                } else {
+                    self.parse_identifier(IdentifierType::Assignment)
+                        .map(CasePattern::Bind)
+                };
+            },
+            Token::Identifier(name) if name.eq_ignore_ascii_case("is") => {
+                let infix = match self.next_token.token {
+                    Token::EqualOrAssign |
+                    Token::Equal => Some(Infix::Equal),
+                    Token::NotEqual => Some(Infix::NotEqual),
+                    Token::LessThan => Some(Infix::LessThan),
+                    Token::LessThanEqual => Some(Infix::LessThanEqual),
+                    Token::GreaterThan => Some(Infix::GreaterThan),
+                    Token::GreaterThanEqual => Some(Infix::GreaterThanEqual),
+                    _ => None,
+                };
+                match infix {
+                    // is > 式
+                    Some(infix) => {
+                        self.bump()?;
+                        self.bump()?;
+                        let expression = self.parse_expression(Precedence::Lowest, ExpressionState::Default)?;
+                        return Some(CasePattern::Compare(infix, expression));
+                    },
+                    // is 型
+                    None => if self.is_next_token_in(&[Token::Eol, Token::Eof, Token::Comma, Token::To, Token::If, Token::IfB]) {
+                        /* is という名前の変数とみなす */
+                    } else {
+                        self.bump()?;
+                        let expression = self.parse_expression(Precedence::Lowest, ExpressionState::Default)?;
+                        return Some(CasePattern::Type(expression));
+                    },
+                }
+            },
+            _ => {},
+        }
+        let expression = self.parse_expression(Precedence::Lowest, ExpressionState::Default)?;
+        if self.is_next_token(&Token::To) {
+            self.bump()?;
+            self.bump()?;
+            let to = self.parse_expression(Precedence::Lowest, ExpressionState::Default)?;
+            Some(CasePattern::Range(expression, to))
+        } else {
+            Some(CasePattern::Value(expression))
+        }
+    }
+
+    /// `[パターン, ...]`
+    fn parse_case_array_pattern(&mut self) -> Option<CasePattern> {
+        let mut patterns = vec![];
+        if self.is_next_token(&Token::Rbracket) {
+            self.bump()?;
+            return Some(CasePattern::Array(patterns));
+        }
+        loop {
+            self.bump()?;
+            let pattern = self.parse_case_pattern(true)?;
+            patterns.push(pattern);
+            match self.next_token.token {
+                Token::Comma => {
+                    self.bump()?;
+                },
+                Token::Rbracket => {
+                    self.bump()?;
+                    break;
+                },
+                _ => {
+                    self.error_next_token_is_invalid();
+                    return None;
+                }
+            }
+        }
+        Some(CasePattern::Array(patterns))
+    }
+
+    /// `{キー: パターン, ...}`
+    fn parse_case_object_pattern(&mut self) -> Option<CasePattern> {
+        let mut members = vec![];
+        if self.is_next_token(&Token::Rbrace) {
+            self.bump()?;
+            return Some(CasePattern::Object(members));
+        }
+        loop {
+            self.bump()?;
+            let key = match self.current_token.token() {
+                Token::String(key) |
+                Token::ExpandableString(key) |
+                Token::Identifier(key) => key,
+                _ => {
+                    self.error_current_token_is_invalid();
+                    return None;
+                }
+            };
+            if ! self.bump_to_next_expected_token(Token::Colon)? {
+                return None;
+            }
+            self.bump()?;
+            let pattern = self.parse_case_pattern(true)?;
+            members.push((key, pattern));
+            match self.next_token.token {
+                Token::Comma => {
+                    self.bump()?;
+                },
+                Token::Rbrace => {
+                    self.bump()?;
+                    break;
+                },
+                _ => {
+                    self.error_next_token_is_invalid();
+                    return None;
+                }
+            }
+        }
+        Some(CasePattern::Object(members))
+    }
+
     fn parse_async_function_statement(&mut self) -> Option<Statement> {
         self.bump()?;
         match self.current_token.token {
@@ -4190,11 +4439,12 @@ selend
                         Statement::Select {
                             expression: Expression::Literal(Literal::Num(1.0)),
                             cases: vec![
-                                (
+                                SelectCase::new(
                                     vec![
-                                        Expression::Literal(Literal::Num(1.0)),
-                                        Expression::Literal(Literal::Num(2.0))
+                                        CasePattern::Value(Expression::Literal(Literal::Num(1.0))),
+                                        CasePattern::Value(Expression::Literal(Literal::Num(2.0)))
                                     ],
+                                    None,
                                     vec![
                                         StatementWithRow::new_expected(
                                             Statement::Print(Expression::Identifier(Identifier("a".to_string()))),
@@ -4202,10 +4452,11 @@ selend
                                         )
                                     ]
                                 ),
-                                (
+                                SelectCase::new(
                                     vec![
-                                        Expression::Literal(Literal::Num(3.0))
+                                        CasePattern::Value(Expression::Literal(Literal::Num(3.0)))
                                     ],
+                                    None,
                                     vec![
                                         StatementWithRow::new_expected(
                                             Statement::Print(Expression::Identifier(Identifier("b".to_string()))),
@@ -4260,10 +4511,11 @@ selend
                         Statement::Select {
                             expression: Expression::Literal(Literal::Num(1.0)),
                             cases: vec![
-                                (
+                                SelectCase::new(
                                     vec![
-                                        Expression::Literal(Literal::Num(1.0)),
+                                        CasePattern::Value(Expression::Literal(Literal::Num(1.0))),
                                     ],
+                                    None,
                                     vec![
                                         StatementWithRow::new_expected(
                                             Statement::Print(Expression::Identifier(Identifier("a".to_string()))),
@@ -4283,6 +4535,82 @@ selend
         }
     }
 
+    #[test]
+    fn test_select_pattern() {
+        let input = r#"
+select x
+    case 1 to 10
+        print 1
+    case is > 5, is TYPE_STRING
+        print 2
+    case [a, _] if a = 1
+        print a
+    case {"name": n, age: [b, 1]}
+        print n
+selend
+"#;
+        let print = |e: Expression, row: usize| vec![
+            StatementWithRow::new_expected(Statement::Print(e), row)
+        ];
+        let ident = |name: &str| Expression::Identifier(Identifier(name.into()));
+        parser_test(input, vec![
+            StatementWithRow::new_expected(
+                Statement::Select {
+                    expression: ident("x"),
+                    cases: vec![
+                        SelectCase::new(
+                            vec![
+                                CasePattern::Range(
+                                    Expression::Literal(Literal::Num(1.0)),
+                                    Expression::Literal(Literal::Num(10.0)),
+                                ),
+                            ],
+                            None,
+                            print(Expression::Literal(Literal::Num(1.0)), 4)
+                        ),
+                        SelectCase::new(
+                            vec![
+                                CasePattern::Compare(Infix::GreaterThan, Expression::Literal(Literal::Num(5.0))),
+                                CasePattern::Type(ident("TYPE_STRING")),
+                            ],
+                            None,
+                            print(Expression::Literal(Literal::Num(2.0)), 6)
+                        ),
+                        SelectCase::new(
+                            vec![
+                                CasePattern::Array(vec![
+                                    CasePattern::Bind(Identifier("a".into())),
+                                    CasePattern::Wildcard,
+                                ]),
+                            ],
+                            Some(Expression::Infix(
+                                Infix::Equal,
+                                Box::new(ident("a")),
+                                Box::new(Expression::Literal(Literal::Num(1.0)))
+                            )),
+                            print(ident("a"), 8)
+                        ),
+                        SelectCase::new(
+                            vec![
+                                CasePattern::Object(vec![
+                                    ("name".into(), CasePattern::Bind(Identifier("n".into()))),
+                                    ("age".into(), CasePattern::Array(vec![
+                                        CasePattern::Bind(Identifier("b".into())),
+                                        CasePattern::Value(Expression::Literal(Literal::Num(1.0))),
+                                    ])),
+                                ]),
+                            ],
+                            None,
+                            print(ident("n"), 10)
+                        ),
+                    ],
+                    default: None
+                },
+                2
+            )
+        ], vec![]);
+    }
+
     #[test]
     fn test_prefix() {
         let input = r#"
@@ -7043,4 +7371,32 @@ endmodule
             parser_error_test(input, expected);
         }
     }
+
+    #[test]
+    fn test_call_sandbox() {
+        use util::settings::USETTINGS;
+        let root = std::env::temp_dir().join("uwscr_call_sandbox");
+        {
+            let mut usettings = USETTINGS.lock().unwrap();
+            usettings.sandbox.enabled = true;
+            usettings.sandbox.allowed_roots = vec![root.to_string_lossy().to_string()];
+        }
+        let parse_errors = |path: std::path::PathBuf| {
+            let input = format!("call {}", path.display());
+            Parser::new(Lexer::new(&input), None, None).parse()
+                .err()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|e| e.kind)
+                .collect::<Vec<_>>()
+        };
+        let outside = root.join("..").join("outside.uws");
+        let expected = ParseErrorKind::CallPathDeniedBySandbox(outside.to_string_lossy().to_string());
+        assert!(parse_errors(outside).contains(&expected));
+        // 許可フォルダ内なら読み込みを試みる
+        let errors = parse_errors(root.join("inside.uws"));
+        assert!(matches!(errors.first(), Some(ParseErrorKind::CanNotCallScript(_, _))));
+
+        USETTINGS.lock().unwrap().sandbox = Default::default();
+    }
 }
\ No newline at end of file
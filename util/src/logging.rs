@@ -1,4 +1,5 @@
 use crate::settings::USETTINGS;
+use crate::winapi::is_headless;
 
 use std::env;
 use std::path::{PathBuf, Path};
@@ -6,6 +7,7 @@ use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::io::{BufReader, SeekFrom};
 use std::fmt;
+use std::sync::Mutex;
 
 use chrono::Local;
 
@@ -44,10 +46,30 @@ pub fn init(dir: &Path) {
     }
 }
 
+/// ヘッドレスモードで最後に標準エラー出力へ書き出したエラー
+static ROUTED_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// 直前にout_logが標準エラー出力へ書き出したエラーならtrue
+pub fn is_routed_error(message: &str) -> bool {
+    ROUTED_ERROR.lock().unwrap().take().is_some_and(|routed| routed == message)
+}
+
 pub fn out_log(log: &String, log_type: LogType) {
     if log.is_empty() {
         return;
     }
+    if is_headless() {
+        // ヘッドレスモードではprint文の出力は標準出力へ、それ以外は標準エラー出力へ
+        match log_type {
+            LogType::Print => println!("{log}"),
+            LogType::Error |
+            LogType::Panic => {
+                eprintln!("{log_type} {log}");
+                *ROUTED_ERROR.lock().unwrap() = Some(log.clone());
+            },
+            LogType::Info => eprintln!("{log_type} {log}"),
+        }
+    }
     let log_option = env::var("UWSCR_LOG_TYPE").ok().and_then(|t| t.parse::<u8>().ok());
     if log_option.is_none() && log_type != LogType::Panic {
         return;
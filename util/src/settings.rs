@@ -1,4 +1,6 @@
 use crate::winapi::{get_special_directory, shell_execute};
+use crate::write_locale;
+use crate::error::{CURRENT_LOCALE, Locale};
 
 use std::{
     fs::{
@@ -8,7 +10,7 @@ use std::{
     },
     fmt,
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf, Component},
     sync::Mutex,
     str::FromStr,
     marker::PhantomData,
@@ -68,6 +70,12 @@ pub struct USettings {
     /// print窓のフォント設定
     #[serde(default, deserialize_with = "string_or_struct")]
     pub logfont: LogFont,
+    /// サンドボックス設定
+    #[serde(default)]
+    pub sandbox: Sandbox,
+    /// ヘッドレスモード設定
+    #[serde(default)]
+    pub headless: Headless,
     /// この設定ファイルのschemaファイルのパス
     #[serde(default = "get_schema_url", skip_deserializing, rename(serialize = "$schema"))]
     pub schema: String,
@@ -112,6 +120,8 @@ impl Default for USettings {
             browser: Browser::default(),
             chkimg: Chkimg::default(),
             logfont: LogFont::default(),
+            sandbox: Sandbox::default(),
+            headless: Headless::default(),
             schema
         }
     }
@@ -173,6 +183,18 @@ pub struct UOption {
     /// 条件式の判定をUWSCと同等にする
     #[serde(default)]
     pub cond_uwsc: bool,
+    /// 実行時間の上限 (秒)、0なら無制限
+    #[serde(default)]
+    pub time_limit: u64,
+    /// 実行できる文の数の上限、0なら無制限
+    #[serde(default)]
+    pub step_limit: u64,
+    /// 関数呼び出しの深さの上限、0なら無制限
+    #[serde(default)]
+    pub depth_limit: u32,
+    /// 配列や文字列のおおよそのサイズ上限 (MB)、0なら無制限
+    #[serde(default)]
+    pub mem_limit: u64,
     /// IEオブジェクトを許可 (非公開)
     #[serde(skip_serializing, default)]
     #[schemars(skip)]
@@ -199,6 +221,10 @@ impl Default for UOption {
             gui_print: false,
             force_bool: false,
             cond_uwsc: false,
+            time_limit: 0,
+            step_limit: 0,
+            depth_limit: 0,
+            mem_limit: 0,
             allow_ie_object: false,
         }
     }
@@ -338,6 +364,101 @@ pub struct Chkimg {
     pub save_ss: bool,
 }
 
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Headless {
+    /// ウィンドウを使わず標準入出力のみで実行する
+    #[serde(default)]
+    pub enabled: bool,
+    /// msgbox, input, slctboxの応答を記述したファイル、nullなら標準入力から読む
+    #[serde(default)]
+    pub answer_file: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Sandbox {
+    /// サンドボックスを有効にする
+    #[serde(default)]
+    pub enabled: bool,
+    /// サンドボックス有効時に許可する機能
+    #[serde(default)]
+    pub allow: Vec<SandboxCapability>,
+    /// filesystemが許可されていない場合でもファイル操作を許可するフォルダ
+    #[serde(default)]
+    pub allowed_roots: Vec<String>,
+}
+impl Sandbox {
+    /// サンドボックスが有効かつ機能が許可されていなければtrue
+    pub fn denies(&self, capability: SandboxCapability) -> bool {
+        self.enabled && ! self.allow.contains(&capability)
+    }
+    /// サンドボックスが有効かつパスが許可フォルダ外ならtrue
+    pub fn denies_path(&self, path: &Path) -> bool {
+        if ! self.denies(SandboxCapability::Filesystem) {
+            return false;
+        }
+        let path = normalize_path(path);
+        ! self.allowed_roots.iter()
+            .any(|root| is_inside(&path, &normalize_path(Path::new(root))))
+    }
+}
+
+/// カレントディレクトリを基準に絶対パス化し . と .. を取り除く
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => { normalized.pop(); },
+            c => normalized.push(c.as_os_str()),
+        }
+    }
+    normalized
+}
+
+/// 正規化済みのパスがrootかその配下ならtrue
+pub fn is_inside(path: &Path, root: &Path) -> bool {
+    // Windowsのパスは大文字小文字を区別しない
+    let mut target = path.components();
+    root.components().all(|c| {
+        target.next().is_some_and(|t| t.as_os_str().eq_ignore_ascii_case(c.as_os_str()))
+    })
+}
+
+/// サンドボックスで制限される機能
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxCapability {
+    /// 外部プロセスの実行や電源操作
+    Process,
+    /// 許可フォルダ外のファイル操作
+    Filesystem,
+    /// ネットワーク通信
+    Network,
+    /// COMオブジェクトの利用
+    Com,
+    /// DLL関数の呼び出し
+    Ffi,
+    /// キー入力やマウス操作の送信
+    Input,
+}
+impl fmt::Display for SandboxCapability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SandboxCapability::Process => write_locale!(f, "プロセス実行", "process"),
+            SandboxCapability::Filesystem => write_locale!(f, "ファイル操作", "filesystem"),
+            SandboxCapability::Network => write_locale!(f, "ネットワーク", "network"),
+            SandboxCapability::Com => write_locale!(f, "COM", "COM"),
+            SandboxCapability::Ffi => write_locale!(f, "DLL呼び出し", "FFI"),
+            SandboxCapability::Input => write_locale!(f, "入力送信", "input injection"),
+        }
+    }
+}
+
 // pub fn usettings_singleton(usettings: Option<USettings>) -> Box<SingletonSettings> {
 //     static mut SINGLETON: Option<Box<SingletonSettings>> = None;
 //     static ONCE: Once = Once::new();
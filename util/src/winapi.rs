@@ -10,7 +10,7 @@ use windows::{
             SystemInformation::{
                 GetSystemDirectoryW, GetWindowsDirectoryW
             },
-            Console::GetConsoleWindow,
+            Console::{GetConsoleWindow, AttachConsole, ATTACH_PARENT_PROCESS},
         },
         UI::{
             WindowsAndMessaging::{
@@ -45,6 +45,29 @@ use std::path::{PathBuf, Path};
 use std::sync::OnceLock;
 
 pub static FORCE_WINDOW_MODE: OnceLock<bool> = OnceLock::new();
+/// ヘッドレスモード、ウィンドウを使わず標準入出力のみを使う
+static HEADLESS_MODE: OnceLock<bool> = OnceLock::new();
+
+/// ヘッドレスモードを有効にする
+pub fn enable_headless_mode() {
+    HEADLESS_MODE.get_or_init(|| {
+        if cfg!(feature="gui") {
+            // GUI版はコンソールを持たないため親プロセスのコンソールを使う
+            unsafe { let _ = AttachConsole(ATTACH_PARENT_PROCESS); }
+        }
+        true
+    });
+}
+pub fn is_headless() -> bool {
+    HEADLESS_MODE.get().copied().unwrap_or(false)
+}
+/// エラーで終了する際の終了コード、ヘッドレスモードなら1
+pub fn error_exit_code() -> i32 {
+    exit_code(is_headless())
+}
+fn exit_code(headless: bool) -> i32 {
+    if headless {1} else {0}
+}
 
 pub fn shell_execute(cmd: String, params: Option<String>) -> bool {
     unsafe {
@@ -215,14 +238,17 @@ pub fn message_box(message: &str, title: &str, utype: MESSAGEBOX_STYLE) {
 }
 
 pub fn show_message(message: &str, title: &str, is_error: bool) {
-    if cfg!(feature="gui") {
+    if cfg!(feature="gui") && ! is_headless() {
         match is_error {
             true => message_box(message, title, MB_ICONEXCLAMATION),
             false => message_box(message, title, MB_OK),
         }
     } else {
         match is_error {
-            true => eprintln!("{title}\n{message}"),
+            // ヘッドレスモードでout_logが書き出し済みなら繰り返さない
+            true => if ! crate::logging::is_routed_error(message) {
+                eprintln!("{title}\n{message}");
+            },
             false => println!("{}", message),
         }
     }
@@ -390,4 +416,15 @@ impl<T> WindowsResultExt<T> for windows::core::Result<T> {
     fn err_hint(self, hint: &str) -> std::result::Result<T, Win32Error> {
         self.map_err(|error| Win32Error::new(error, hint))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_exit_code() {
+        assert_eq!(exit_code(false), 0);
+        assert_eq!(exit_code(true), 1);
+    }
+}
@@ -17,10 +17,10 @@ use evaluator::builtins::get_builtin_string_names;
 use util::get_script;
 use util::logging::{out_log, LogType};
 use util::settings::{
-    FileMode,
+    FileMode, SandboxCapability, USETTINGS,
     out_default_setting_file, out_json_schema_file
 };
-use util::winapi::{show_message, shell_execute, FORCE_WINDOW_MODE};
+use util::winapi::{show_message, shell_execute, enable_headless_mode, error_exit_code, FORCE_WINDOW_MODE};
 use util::error::UWSCRErrorTitle;
 use language_server::UwscrLanguageServer;
 
@@ -56,6 +56,7 @@ fn main() {
             // attach_console();
             show_message(&err, &UWSCRErrorTitle::Panic.to_string(), true);
             // free_console();
+            std::process::exit(error_exit_code());
         }
     }
 }
@@ -76,10 +77,12 @@ fn start_uwscr() {
                     Err(script::ScriptError(title, err)) => {
                         out_log(&err, LogType::Error);
                         show_message(&err, &title.to_string(), true);
+                        std::process::exit(error_exit_code());
                     }
                 },
                 Err(e) => {
                     show_message(&e.to_string(), &UWSCRErrorTitle::InitializeError.to_string(), true);
+                    std::process::exit(error_exit_code());
                 }
             }
         },
@@ -89,6 +92,7 @@ fn start_uwscr() {
                 Err(errors) => {
                     let err = errors.join("\r\n");
                     show_message(&err, "uwscr --code", true);
+                    std::process::exit(error_exit_code());
                 }
             }
         }
@@ -255,6 +259,33 @@ impl Mode {
         let args = CommandArgs::parse();
         let ast = args.ast.then_some((args._continue, args.prettify));
 
+        if let Some(allow) = &args.sandbox {
+            let mut usettings = USETTINGS.lock().unwrap();
+            usettings.sandbox.enabled = true;
+            // 許可する機能が指定された場合は設定ファイルの内容を置き換える
+            if ! allow.is_empty() {
+                usettings.sandbox.allow = allow.iter().map(|a| a.into()).collect();
+            }
+            // スクリプト実行時にカレントディレクトリが変わるため絶対パスにしておく
+            let roots = args.allow_root.iter()
+                .map(|p| std::path::absolute(p).unwrap_or(p.clone()))
+                .map(|p| p.to_string_lossy().to_string());
+            usettings.sandbox.allowed_roots.extend(roots);
+        }
+
+        {
+            let mut usettings = USETTINGS.lock().unwrap();
+            if let Some(path) = &args.answer_file {
+                // スクリプト実行時にカレントディレクトリが変わるため絶対パスにしておく
+                let path = std::path::absolute(path).unwrap_or(path.clone());
+                usettings.headless.answer_file = Some(path.to_string_lossy().to_string());
+            }
+            if args.headless || usettings.headless.enabled {
+                usettings.headless.enabled = true;
+                enable_headless_mode();
+            }
+        }
+
         if args.language_server {
             Self::LanguageServer
         } else if let Some(code) = args.code {
@@ -345,7 +376,49 @@ struct CommandArgs {
 
     /// Language Serverを起動
     #[arg(long="language-server")]
-    language_server: bool
+    language_server: bool,
+
+    /// ウィンドウを使わず標準入出力のみで実行する
+    #[arg(long)]
+    headless: bool,
+    /// ヘッドレスモードでmsgbox, input, slctboxの応答を読むファイル
+    #[arg(long="answer-file", value_name="FILE")]
+    answer_file: Option<PathBuf>,
+
+    /// サンドボックスモードで実行する、許可する機能を --sandbox=network,com のように指定可
+    #[arg(long, value_name="ALLOW", num_args=0..=1, require_equals=true, value_delimiter=',')]
+    sandbox: Option<Vec<SandboxArg>>,
+    /// サンドボックスモードでファイル操作を許可するフォルダ
+    #[arg(long="allow-root", value_name="DIR", requires="sandbox")]
+    allow_root: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum SandboxArg {
+    /// 外部プロセスの実行
+    Process,
+    /// 許可フォルダ外のファイル操作
+    Filesystem,
+    /// ネットワーク通信
+    Network,
+    /// COMオブジェクト
+    Com,
+    /// DLL関数の呼び出し
+    Ffi,
+    /// キー入力やマウス操作の送信
+    Input,
+}
+impl From<&SandboxArg> for SandboxCapability {
+    fn from(arg: &SandboxArg) -> Self {
+        match arg {
+            SandboxArg::Process => SandboxCapability::Process,
+            SandboxArg::Filesystem => SandboxCapability::Filesystem,
+            SandboxArg::Network => SandboxCapability::Network,
+            SandboxArg::Com => SandboxCapability::Com,
+            SandboxArg::Ffi => SandboxCapability::Ffi,
+            SandboxArg::Input => SandboxCapability::Input,
+        }
+    }
 }
 
 
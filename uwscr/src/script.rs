@@ -73,11 +73,11 @@ pub fn run(script: String, script_path: PathBuf, params: Vec<String>, ast: Optio
 
     if ! errors.is_empty() {
         if cfg!(debug_assertions) {
-            println!("\u{001b}[90m[script::run] Parser Errors");
+            eprintln!("\u{001b}[90m[script::run] Parser Errors");
             for e in &errors {
-                println!("{e:?}");
+                eprintln!("{e:?}");
             }
-            println!("\u{001b}[0m");
+            eprintln!("\u{001b}[0m");
         }
         return Err(ScriptError::new(
             UWSCRErrorTitle::StatementError,
@@ -99,7 +99,7 @@ pub fn run(script: String, script_path: PathBuf, params: Vec<String>, ast: Optio
     let env = Environment::new(params);
     let mut evaluator = Evaluator::new(env);
     if let Err(e) = evaluator.eval(program, true) {
-        #[cfg(debug_assertions)] println!("\u{001b}[90m[script::run] Evaluator Error: {:#?}\u{001b}[0m", &e);
+        #[cfg(debug_assertions)] eprintln!("\u{001b}[90m[script::run] Evaluator Error: {:#?}\u{001b}[0m", &e);
         return Err(ScriptError::new(
             UWSCRErrorTitle::RuntimeError,
             e.errror_text_with_line()